@@ -1,6 +1,7 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_prost_build::configure()
         .extern_path(".google.protobuf.Struct", "::prost_wkt_types::Struct")
+        .extern_path(".google.protobuf.Value", "::prost_wkt_types::Value")
         .compile_protos(&["proto/service.proto"], &["proto"])?;
 
     Ok(())
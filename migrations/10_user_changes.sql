@@ -0,0 +1,13 @@
+-- History of the attributes of the users. The values are stored as JSON; NULL means the field was unset.
+-- There is no foreign key on user_id, so the history of merged users outlives them.
+CREATE TABLE IF NOT EXISTS User_Changes (
+    id bigserial PRIMARY KEY,
+    user_id bigint NOT NULL,
+    field varchar(32) NOT NULL,
+    old_value jsonb,
+    new_value jsonb,
+    actor_service_id integer REFERENCES Services(id) ON DELETE SET NULL,
+    changed_at timestamptz NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX IF NOT EXISTS user_changes_user_id_idx ON User_Changes (user_id, id);
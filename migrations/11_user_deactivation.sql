@@ -0,0 +1,2 @@
+-- Deactivated users keep all their data, but can't be fetched or get premium until reactivated
+ALTER TABLE Users ADD COLUMN IF NOT EXISTS deactivated_at timestamptz;
@@ -0,0 +1,13 @@
+-- Bans of users in a service, or in all of them when service_id is NULL
+CREATE TABLE IF NOT EXISTS User_Bans (
+    id bigserial PRIMARY KEY,
+    user_id bigint NOT NULL REFERENCES Users(id) ON DELETE CASCADE,
+    service_id int REFERENCES Services(id) ON DELETE CASCADE,
+    reason varchar(1024) NOT NULL,
+    issuer varchar(256) NOT NULL,
+    issued_at timestamptz NOT NULL DEFAULT current_timestamp,
+    expires_at timestamptz
+);
+
+-- a user has at most one ban per service and one global ban
+CREATE UNIQUE INDEX IF NOT EXISTS user_bans_scope_idx ON User_Bans (user_id, COALESCE(service_id, 0));
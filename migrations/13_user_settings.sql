@@ -0,0 +1,17 @@
+-- Arbitrary settings of the users in the scope of a service
+CREATE TABLE IF NOT EXISTS User_Settings (
+    user_id bigint NOT NULL REFERENCES Users(id) ON DELETE CASCADE,
+    service_id int NOT NULL REFERENCES Services(id) ON DELETE CASCADE,
+    key varchar(128) NOT NULL,
+    value jsonb NOT NULL,
+    updated_at timestamptz NOT NULL DEFAULT current_timestamp,
+    PRIMARY KEY (user_id, service_id, key)
+);
+
+-- Values of the settings for the users who haven't set them
+CREATE TABLE IF NOT EXISTS Service_Setting_Defaults (
+    service_id int NOT NULL REFERENCES Services(id) ON DELETE CASCADE,
+    key varchar(128) NOT NULL,
+    value jsonb NOT NULL,
+    PRIMARY KEY (service_id, key)
+);
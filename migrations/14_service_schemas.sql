@@ -0,0 +1,8 @@
+-- JSON Schemas the free-form documents sent by a service must conform to
+CREATE TABLE IF NOT EXISTS Service_Schemas (
+    service_id int NOT NULL REFERENCES Services(id) ON DELETE CASCADE,
+    kind varchar(32) NOT NULL,
+    schema jsonb NOT NULL,
+    updated_at timestamptz NOT NULL DEFAULT current_timestamp,
+    PRIMARY KEY (service_id, kind)
+);
@@ -0,0 +1,2 @@
+-- IANA name of the timezone, set directly or derived from the location
+ALTER TABLE Users ADD COLUMN IF NOT EXISTS timezone varchar(64);
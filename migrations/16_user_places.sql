@@ -0,0 +1,3 @@
+-- The nearest populated place to the location, looked up on its update
+ALTER TABLE Users ADD COLUMN IF NOT EXISTS city varchar(200);
+ALTER TABLE Users ADD COLUMN IF NOT EXISTS country_code char(2);
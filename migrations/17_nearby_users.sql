@@ -0,0 +1,6 @@
+CREATE EXTENSION IF NOT EXISTS cube;
+CREATE EXTENSION IF NOT EXISTS earthdistance;
+
+-- Serves the bounding box lookups of the nearby users; location[1] is the latitude, location[2] is the longitude
+CREATE INDEX IF NOT EXISTS users_location_earth_idx ON Users USING gist (ll_to_earth(location[1], location[2]))
+    WHERE location IS NOT NULL;
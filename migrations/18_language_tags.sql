@@ -0,0 +1,32 @@
+-- BCP 47 language tags like `pt-BR` or `zh-Hant-TW` instead of the two-letter codes
+ALTER TABLE Users ALTER COLUMN language_code TYPE varchar(64);
+
+-- Any two characters were accepted before, so the saved codes are brought to the canonical form of the tags:
+-- lowercase, the deprecated codes replaced with their successors, and the codes out of ISO 639-1 removed
+UPDATE Users SET language_code = lower(trim(language_code))
+WHERE language_code IS NOT NULL;
+
+UPDATE Users SET language_code = CASE language_code
+        WHEN 'iw' THEN 'he'
+        WHEN 'in' THEN 'id'
+        WHEN 'ji' THEN 'yi'
+        WHEN 'jw' THEN 'jv'
+        WHEN 'mo' THEN 'ro'
+    END
+WHERE language_code IN ('iw', 'in', 'ji', 'jw', 'mo');
+
+UPDATE Users SET language_code = NULL
+WHERE language_code NOT IN (
+    'aa', 'ab', 'ae', 'af', 'ak', 'am', 'an', 'ar', 'as', 'av', 'ay', 'az', 'ba', 'be', 'bg', 'bi',
+    'bm', 'bn', 'bo', 'br', 'bs', 'ca', 'ce', 'ch', 'co', 'cr', 'cs', 'cu', 'cv', 'cy', 'da', 'de',
+    'dv', 'dz', 'ee', 'el', 'en', 'eo', 'es', 'et', 'eu', 'fa', 'ff', 'fi', 'fj', 'fo', 'fr', 'fy',
+    'ga', 'gd', 'gl', 'gn', 'gu', 'gv', 'ha', 'he', 'hi', 'ho', 'hr', 'ht', 'hu', 'hy', 'hz', 'ia',
+    'id', 'ie', 'ig', 'ii', 'ik', 'io', 'is', 'it', 'iu', 'ja', 'jv', 'ka', 'kg', 'ki', 'kj', 'kk',
+    'kl', 'km', 'kn', 'ko', 'kr', 'ks', 'ku', 'kv', 'kw', 'ky', 'la', 'lb', 'lg', 'li', 'ln', 'lo',
+    'lt', 'lu', 'lv', 'mg', 'mh', 'mi', 'mk', 'ml', 'mn', 'mr', 'ms', 'mt', 'my', 'na', 'nb', 'nd',
+    'ne', 'ng', 'nl', 'nn', 'no', 'nr', 'nv', 'ny', 'oc', 'oj', 'om', 'or', 'os', 'pa', 'pi', 'pl',
+    'ps', 'pt', 'qu', 'rm', 'rn', 'ro', 'ru', 'rw', 'sa', 'sc', 'sd', 'se', 'sg', 'si', 'sk', 'sl',
+    'sm', 'sn', 'so', 'sq', 'sr', 'ss', 'st', 'su', 'sv', 'sw', 'ta', 'te', 'tg', 'th', 'ti', 'tk',
+    'tl', 'tn', 'to', 'tr', 'ts', 'tt', 'tw', 'ty', 'ug', 'uk', 'ur', 'uz', 've', 'vi', 'vo', 'wa',
+    'wo', 'xh', 'yi', 'yo', 'za', 'zh', 'zu'
+);
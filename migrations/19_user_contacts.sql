@@ -0,0 +1,15 @@
+-- Email and phone of the users, one of each kind; `value` is set only once the ownership is confirmed
+CREATE TABLE IF NOT EXISTS User_Contacts (
+    user_id bigint NOT NULL REFERENCES Users(id) ON DELETE CASCADE,
+    kind varchar(16) NOT NULL,
+    value varchar(320),
+    verified_at timestamptz,
+    pending_value varchar(320),
+    code varchar(16),
+    code_expires_at timestamptz,
+    failed_attempts int NOT NULL DEFAULT 0,
+    PRIMARY KEY (user_id, kind)
+);
+
+-- a verified contact belongs to a single user
+CREATE UNIQUE INDEX IF NOT EXISTS user_contacts_value_idx ON User_Contacts (kind, value);
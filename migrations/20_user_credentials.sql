@@ -0,0 +1,18 @@
+-- Passwords of the users of websites; a user logs in with either of the logins
+CREATE TABLE IF NOT EXISTS User_Credentials (
+    user_id bigint PRIMARY KEY REFERENCES Users(id) ON DELETE CASCADE,
+    username varchar(32) UNIQUE,
+    email varchar(320) UNIQUE,
+    password_hash varchar(256) NOT NULL,
+    failed_attempts int NOT NULL DEFAULT 0,
+    locked_until timestamptz,
+    updated_at timestamptz NOT NULL DEFAULT current_timestamp,
+    CHECK (username IS NOT NULL OR email IS NOT NULL)
+);
+
+-- only SHA-256 digests of the tokens are stored
+CREATE TABLE IF NOT EXISTS Password_Reset_Tokens (
+    token_hash bytea PRIMARY KEY,
+    user_id bigint NOT NULL REFERENCES Users(id) ON DELETE CASCADE,
+    expires_at timestamptz NOT NULL
+);
@@ -0,0 +1,15 @@
+-- only SHA-256 digests of the verification codes are stored; the codes pending at the moment have to be requested again
+ALTER TABLE User_Contacts DROP COLUMN IF EXISTS code;
+ALTER TABLE User_Contacts ADD COLUMN IF NOT EXISTS code_hash bytea;
+
+-- every code sent, to throttle the requests per user and per contact
+CREATE TABLE IF NOT EXISTS Contact_Verification_Requests (
+    user_id bigint NOT NULL REFERENCES Users(id) ON DELETE CASCADE,
+    kind varchar(16) NOT NULL,
+    value varchar(320) NOT NULL,
+    requested_at timestamptz NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX IF NOT EXISTS contact_verification_requests_user_idx ON Contact_Verification_Requests (user_id, requested_at);
+CREATE INDEX IF NOT EXISTS contact_verification_requests_value_idx ON Contact_Verification_Requests (kind, value, requested_at);
+CREATE INDEX IF NOT EXISTS contact_verification_requests_time_idx ON Contact_Verification_Requests (requested_at);
@@ -0,0 +1,8 @@
+-- Short-lived one-time codes to attach an account from another service to an existing user
+CREATE TABLE IF NOT EXISTS Link_Codes (
+    code varchar(16) PRIMARY KEY,
+    user_id bigint NOT NULL REFERENCES Users(id),
+    expires_at timestamptz NOT NULL
+);
+
+CREATE INDEX ON Link_Codes (expires_at);
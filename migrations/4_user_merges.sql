@@ -0,0 +1,8 @@
+-- Users merged into others; source users are deleted but lookups of their IDs are redirected to the targets
+CREATE TABLE IF NOT EXISTS User_Merges (
+    source_id bigint PRIMARY KEY,
+    target_id bigint NOT NULL REFERENCES Users(id),
+    merged_at timestamptz NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX ON User_Merges (target_id);
@@ -0,0 +1,3 @@
+-- Websites and applications identify their users by strings (UUIDs, vendor account IDs);
+-- Telegram IDs are kept as their decimal representations
+ALTER TABLE User_Service_Mappings ALTER COLUMN external_id TYPE varchar(256) USING external_id::text;
@@ -0,0 +1,20 @@
+-- Registry of service types, so new kinds can be added without a schema change
+CREATE TABLE IF NOT EXISTS Service_Types (
+    name varchar(64) PRIMARY KEY,
+    string_ids boolean NOT NULL DEFAULT false,
+    created_at timestamptz NOT NULL DEFAULT current_timestamp
+);
+
+INSERT INTO Service_Types (name, string_ids) VALUES
+    ('telegram-bot', false),
+    ('telegram-channel', false),
+    ('website', true),
+    ('application', true)
+ON CONFLICT (name) DO NOTHING;
+
+-- The unique (name, type) constraint survives the type change
+ALTER TABLE Services
+    ALTER COLUMN type TYPE varchar(64) USING type::text,
+    ADD CONSTRAINT services_type_fkey FOREIGN KEY (type) REFERENCES Service_Types(name);
+
+DROP TYPE IF EXISTS service_type;
@@ -0,0 +1,9 @@
+ALTER TABLE Users ADD COLUMN IF NOT EXISTS registered_at timestamptz NOT NULL DEFAULT current_timestamp;
+
+-- The earliest consent is the best guess for the users registered before the column existed
+UPDATE Users u SET registered_at = c.obtained_at
+FROM (SELECT uid, min(obtained_at) AS obtained_at FROM Consents GROUP BY uid) c
+WHERE c.uid = u.id;
+
+-- Used by the registered_after filter of the listing
+CREATE INDEX IF NOT EXISTS users_registered_at_idx ON Users (registered_at);
@@ -0,0 +1,4 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- Serves both the similarity operators and the word similarity ones used by the search
+CREATE INDEX IF NOT EXISTS users_name_trgm_idx ON Users USING gin (name gin_trgm_ops);
@@ -0,0 +1,2 @@
+-- Incremented by every change of the user; exposed as ETags for optimistic concurrency control
+ALTER TABLE Users ADD COLUMN IF NOT EXISTS version bigint NOT NULL DEFAULT 1;
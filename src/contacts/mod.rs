@@ -0,0 +1,55 @@
+//! Delivery of the verification codes to the contacts of the users
+
+mod smtp;
+
+#[cfg(test)]
+mod test;
+
+use std::pin::Pin;
+use std::sync::Arc;
+use derive_more::Display;
+use thiserror::Error;
+use crate::dto::{ContactKind, NewContact, VerificationCode};
+
+pub use smtp::*;
+
+pub type SendFuture<'a> = Pin<Box<dyn Future<Output = Result<(), SendError>> + Send + 'a>>;
+
+/// Delivers the codes to the contacts of some kinds; used as a trait object, so the future is boxed
+pub trait CodeSender: Send + Sync {
+    /// Checked before a code is issued, so that nothing is stored for a contact the code can't be delivered to
+    fn supports(&self, kind: ContactKind) -> bool;
+    fn send_code<'a>(&'a self, contact: &'a NewContact, verification: &'a VerificationCode) -> SendFuture<'a>;
+}
+
+#[derive(Debug, Display, Error)]
+pub enum SendError {
+    #[display("the codes can't be delivered to the {_0} contacts")]
+    Unsupported(ContactKind),
+    #[display("couldn't deliver the code: {_0}")]
+    Delivery(String),
+}
+
+/// Used when no delivery channel is configured, so the contacts can't be verified at all
+pub struct DisabledSender;
+
+impl CodeSender for DisabledSender {
+    fn supports(&self, _: ContactKind) -> bool {
+        false
+    }
+
+    fn send_code<'a>(&'a self, contact: &'a NewContact, _: &'a VerificationCode) -> SendFuture<'a> {
+        Box::pin(async move { Err(SendError::Unsupported(contact.kind)) })
+    }
+}
+
+/// The SMTP sender if `SMTP_HOST` is set, the disabled one otherwise
+pub fn sender_from_env() -> anyhow::Result<Arc<dyn CodeSender>> {
+    match SmtpConfig::from_env() {
+        Some(config) => Ok(Arc::new(SmtpSender::new(config)?)),
+        None => {
+            tracing::warn!("SMTP_HOST is not set, the contacts can't be verified");
+            Ok(Arc::new(DisabledSender))
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use lettre::message::Mailbox;
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use crate::contacts::{CodeSender, SendError, SendFuture};
+use crate::dto::{ContactKind, NewContact, VerificationCode};
+use crate::env::get_value_or_default;
+
+const DEFAULT_PORT: u16 = 587;
+const DEFAULT_FROM: &str = "User Service <noreply@localhost>";
+const SUBJECT: &str = "Verification code";
+
+#[derive(Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub credentials: Option<(String, String)>,
+    pub from: String,
+    /// STARTTLS is required unless disabled explicitly for a local relay
+    pub tls: bool,
+}
+
+impl SmtpConfig {
+    /// `None` if `SMTP_HOST` is not set
+    pub fn from_env() -> Option<Self> {
+        let host = std::env::var("SMTP_HOST").ok()?;
+        let credentials = std::env::var("SMTP_USERNAME").ok()
+            .zip(std::env::var("SMTP_PASSWORD").ok());
+        Some(Self {
+            host,
+            port: get_value_or_default("SMTP_PORT", DEFAULT_PORT),
+            credentials,
+            from: get_value_or_default("SMTP_FROM", DEFAULT_FROM.to_owned()),
+            tls: get_value_or_default("SMTP_TLS", true),
+        })
+    }
+}
+
+/// Sends the codes to the emails; phones are not supported
+pub struct SmtpSender {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpSender {
+    pub fn new(config: SmtpConfig) -> Result<Self, SendError> {
+        let builder = if config.tls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)
+                .map_err(|e| SendError::Delivery(e.to_string()))?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host)
+        };
+        let builder = match config.credentials {
+            Some((username, password)) => builder.credentials(Credentials::new(username, password)),
+            None => builder,
+        };
+        let from = config.from.parse()
+            .map_err(|e: lettre::address::AddressError| SendError::Delivery(e.to_string()))?;
+        Ok(Self {
+            transport: builder.port(config.port).build(),
+            from,
+        })
+    }
+
+    fn message(&self, email: &str, verification: &VerificationCode) -> Result<Message, SendError> {
+        let to: Mailbox = email.parse()
+            .map_err(|e: lettre::address::AddressError| SendError::Delivery(e.to_string()))?;
+        let minutes = (verification.expires_at - chrono::Utc::now()).num_minutes().max(1);
+        Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(SUBJECT)
+            .header(ContentType::TEXT_PLAIN)
+            .body(format!("Your verification code is {}. It expires in {minutes} minutes.", verification.code))
+            .map_err(|e| SendError::Delivery(e.to_string()))
+    }
+}
+
+impl CodeSender for SmtpSender {
+    fn supports(&self, kind: ContactKind) -> bool {
+        kind == ContactKind::Email
+    }
+
+    fn send_code<'a>(&'a self, contact: &'a NewContact, verification: &'a VerificationCode) -> SendFuture<'a> {
+        Box::pin(async move {
+            if !self.supports(contact.kind) {
+                return Err(SendError::Unsupported(contact.kind));
+            }
+            let message = self.message(&contact.value, verification)?;
+            self.transport.send(message).await
+                .map_err(|e| SendError::Delivery(e.to_string()))?;
+            tracing::info!(kind = %contact.kind, "Verification code sent");
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,89 @@
+use std::sync::Arc;
+use chrono::{TimeDelta, Utc};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+use crate::contacts::{CodeSender, DisabledSender, SendError, SmtpConfig, SmtpSender};
+use crate::dto::{ContactKind, NewContact, VerificationCode};
+
+/// Accepts any command of a single session and keeps the contents of the messages
+async fn start_smtp_stub() -> anyhow::Result<(u16, Arc<Mutex<Vec<String>>>)> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    let messages = Arc::new(Mutex::new(Vec::new()));
+    let received = messages.clone();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.expect("the client must connect");
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        writer.write_all(b"220 localhost ESMTP stub\r\n").await.expect("the greeting must be sent");
+        while let Ok(Some(line)) = lines.next_line().await {
+            let command = line.to_uppercase();
+            let reply: &[u8] = if command.starts_with("EHLO") || command.starts_with("HELO") {
+                b"250 localhost\r\n"
+            } else if command.starts_with("DATA") {
+                writer.write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n").await.expect("the reply must be sent");
+                let mut message = String::new();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if line == "." {
+                        break;
+                    }
+                    message.push_str(&line);
+                    message.push('\n');
+                }
+                received.lock().await.push(message);
+                b"250 OK\r\n"
+            } else if command.starts_with("QUIT") {
+                writer.write_all(b"221 Bye\r\n").await.expect("the reply must be sent");
+                break;
+            } else {
+                b"250 OK\r\n"
+            };
+            writer.write_all(reply).await.expect("the reply must be sent");
+        }
+    });
+    Ok((port, messages))
+}
+
+fn build_verification() -> VerificationCode {
+    VerificationCode {
+        code: "123456".to_owned(),
+        expires_at: Utc::now() + TimeDelta::minutes(15),
+    }
+}
+
+#[tokio::test]
+async fn test_smtp_sender() -> anyhow::Result<()> {
+    let (port, messages) = start_smtp_stub().await?;
+    let sender = SmtpSender::new(SmtpConfig {
+        host: "127.0.0.1".to_owned(),
+        port,
+        credentials: None,
+        from: "User Service <noreply@example.com>".to_owned(),
+        tls: false,
+    })?;
+
+    let email = NewContact::new(ContactKind::Email, "sad.bot@example.com")?;
+    sender.send_code(&email, &build_verification()).await?;
+
+    let messages = messages.lock().await;
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].contains("To: sad.bot@example.com"), "{}", messages[0]);
+    assert!(messages[0].contains("123456"), "{}", messages[0]);
+
+    assert!(sender.supports(ContactKind::Email));
+    assert!(!sender.supports(ContactKind::Phone));
+    let phone = NewContact::new(ContactKind::Phone, "+79001234567")?;
+    let result = sender.send_code(&phone, &build_verification()).await;
+    assert!(matches!(result, Err(SendError::Unsupported(ContactKind::Phone))));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_disabled_sender() -> anyhow::Result<()> {
+    assert!(!DisabledSender.supports(ContactKind::Email));
+    let email = NewContact::new(ContactKind::Email, "sad.bot@example.com")?;
+    let result = DisabledSender.send_code(&email, &build_verification()).await;
+    assert!(matches!(result, Err(SendError::Unsupported(ContactKind::Email))));
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+use crate::dto::error::BanError;
+use crate::dto::Service;
+
+const BAN_REASON_MAX_LENGTH: usize = 1024;
+const BAN_ISSUER_MAX_LENGTH: usize = 256;
+
+/// Ban of a user in a service, or in all of them if the service is not set
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Ban {
+    pub service: Option<Service>,
+    pub reason: String,
+    /// Who banned the user: a moderator, an anti-spam bot, etc.
+    pub issuer: String,
+    pub issued_at: DateTime<Utc>,
+    /// The ban is permanent if not set
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Ban yet to be issued, see [crate::repo::users::Users::ban]
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewBan {
+    pub reason: String,
+    pub issuer: String,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+
+// IMPLEMENTATIONS
+
+
+impl NewBan {
+    /// Trims the reason and the issuer and checks the ban isn't expired already
+    pub fn validated(self) -> Result<Self, BanError> {
+        let reason = self.reason.trim();
+        if reason.is_empty() || reason.chars().count() > BAN_REASON_MAX_LENGTH {
+            return Err(BanError::InvalidReason);
+        }
+        let issuer = self.issuer.trim();
+        if issuer.is_empty() || issuer.chars().count() > BAN_ISSUER_MAX_LENGTH {
+            return Err(BanError::InvalidIssuer);
+        }
+        if self.expires_at.is_some_and(|till| till <= Utc::now()) {
+            return Err(BanError::AlreadyExpired);
+        }
+        Ok(Self {
+            reason: reason.to_owned(),
+            issuer: issuer.to_owned(),
+            expires_at: self.expires_at,
+        })
+    }
+}
@@ -0,0 +1,93 @@
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use crate::dto::error::ChangedFieldError;
+use crate::dto::{SavedUser, Service};
+
+/// Attribute of a user whose changes are recorded in the `User_Changes` table
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangedField {
+    Name,
+    LanguageCode,
+    Location,
+    Timezone,
+    PremiumTill,
+    DeactivatedAt,
+}
+
+/// Entry of the change history of a user. The values are JSON representations of the field,
+/// `None` stands for an unset one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserChange {
+    pub id: i64,
+    pub field: ChangedField,
+    pub old_value: Option<serde_json::Value>,
+    pub new_value: Option<serde_json::Value>,
+    /// The service on whose behalf the change was made, if known
+    pub actor: Option<Service>,
+    pub changed_at: DateTime<Utc>,
+}
+
+/// Change of a single field yet to be recorded
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldChange {
+    pub field: ChangedField,
+    pub old_value: Option<serde_json::Value>,
+    pub new_value: Option<serde_json::Value>,
+}
+
+
+// IMPLEMENTATIONS
+
+
+impl ChangedField {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Name => "name",
+            Self::LanguageCode => "language_code",
+            Self::Location => "location",
+            Self::Timezone => "timezone",
+            Self::PremiumTill => "premium_till",
+            Self::DeactivatedAt => "deactivated_at",
+        }
+    }
+}
+
+impl SavedUser {
+    /// The tracked fields that differ between the old and the new state of the user
+    pub fn changes_since(&self, old: &SavedUser) -> Vec<FieldChange> {
+        old.tracked_values().into_iter()
+            .zip(self.tracked_values())
+            .filter(|((_, old_value), (_, new_value))| old_value != new_value)
+            .map(|((field, old_value), (_, new_value))| FieldChange { field, old_value, new_value })
+            .collect()
+    }
+
+    fn tracked_values(&self) -> [(ChangedField, Option<serde_json::Value>); 6] {
+        [
+            (ChangedField::Name, self.name.as_ref().map(|name| json!(name))),
+            (ChangedField::LanguageCode, self.language_code.as_ref().map(|code| json!(code.as_str()))),
+            (ChangedField::Location, self.location.as_ref().map(|location| json!(location))),
+            (ChangedField::Timezone, self.timezone.map(|timezone| json!(timezone))),
+            (ChangedField::PremiumTill, self.premium_till.map(|till| json!(till))),
+            (ChangedField::DeactivatedAt, self.deactivated_at.map(|at| json!(at))),
+        ]
+    }
+}
+
+impl TryFrom<&str> for ChangedField {
+    type Error = ChangedFieldError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "name" => Ok(Self::Name),
+            "language_code" => Ok(Self::LanguageCode),
+            "location" => Ok(Self::Location),
+            "timezone" => Ok(Self::Timezone),
+            "premium_till" => Ok(Self::PremiumTill),
+            "deactivated_at" => Ok(Self::DeactivatedAt),
+            unknown => Err(ChangedFieldError(unknown.to_owned())),
+        }
+    }
+}
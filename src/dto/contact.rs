@@ -0,0 +1,182 @@
+use chrono::{DateTime, Utc};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_derive::{Deserialize, Serialize};
+use crate::dto::error::{ContactError, ContactKindError};
+
+const EMAIL_MAX_LENGTH: usize = 254;
+const VERIFICATION_CODE_LENGTH: usize = 6;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContactKind {
+    Email,
+    /// E.164 phone number
+    Phone,
+}
+
+/// Contact of a user whose ownership is confirmed by a verification code
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Contact {
+    pub kind: ContactKind,
+    pub value: String,
+    pub verified_at: DateTime<Utc>,
+}
+
+/// Normalized contact yet to be verified, see [crate::repo::users::Users::request_contact_verification]
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewContact {
+    pub kind: ContactKind,
+    pub value: String,
+}
+
+/// Code sent to the contact to confirm its ownership
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerificationCode {
+    pub code: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContactRequestOutcome {
+    Requested(VerificationCode),
+    NotFound,
+    /// Too many codes have been requested lately or the current one has run out of attempts;
+    /// a new code may be requested after the time
+    TooManyRequests(DateTime<Utc>),
+    /// The contact is already verified by another user
+    TakenByAnotherUser,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContactConfirmation {
+    Confirmed(Contact),
+    /// Wrong or expired code; the pending contact is dropped after too many failed attempts
+    InvalidCode,
+    /// Nothing of this kind is pending verification
+    NotPending,
+    TakenByAnotherUser,
+}
+
+
+// IMPLEMENTATIONS
+
+
+impl ContactKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Email => "email",
+            Self::Phone => "phone",
+        }
+    }
+}
+
+impl std::fmt::Display for ContactKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TryFrom<&str> for ContactKind {
+    type Error = ContactKindError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "email" => Ok(Self::Email),
+            "phone" => Ok(Self::Phone),
+            _ => Err(ContactKindError(value.to_owned())),
+        }
+    }
+}
+
+impl NewContact {
+    /// Emails are lowercased; spaces, dashes, dots and parentheses are stripped from phone numbers
+    pub fn new(kind: ContactKind, value: &str) -> Result<Self, ContactError> {
+        let value = match kind {
+            ContactKind::Email => normalize_email(value)?,
+            ContactKind::Phone => normalize_phone(value)?,
+        };
+        Ok(Self { kind, value })
+    }
+}
+
+fn normalize_email(value: &str) -> Result<String, ContactError> {
+    let email = value.trim().to_lowercase();
+    let valid = email.len() <= EMAIL_MAX_LENGTH
+        && !email.chars().any(|c| c.is_whitespace() || c.is_control())
+        && email.split_once('@').is_some_and(|(local, domain)|
+            !local.is_empty() && !domain.contains('@')
+                && domain.split('.').count() > 1 && domain.split('.').all(|label| !label.is_empty()));
+    if valid {
+        Ok(email)
+    } else {
+        Err(ContactError::InvalidEmail)
+    }
+}
+
+fn normalize_phone(value: &str) -> Result<String, ContactError> {
+    let phone: String = value.trim().chars()
+        .filter(|c| !matches!(c, ' ' | '-' | '.' | '(' | ')'))
+        .collect();
+    let digits = phone.strip_prefix('+').unwrap_or_default();
+    let valid = (8..=15).contains(&digits.len())
+        && digits.chars().all(|c| c.is_ascii_digit())
+        && !digits.starts_with('0');
+    if valid {
+        Ok(phone)
+    } else {
+        Err(ContactError::InvalidPhone)
+    }
+}
+
+impl VerificationCode {
+    /// Numeric, so that it can be typed from an SMS as easily as from an email
+    pub fn generate_code() -> String {
+        let mut bytes = [0u8; 8];
+        SystemRandom::new()
+            .fill(&mut bytes)
+            .expect("the system random number generator must be available");
+        let number = u64::from_le_bytes(bytes) % 10u64.pow(VERIFICATION_CODE_LENGTH as u32);
+        format!("{number:0width$}", width = VERIFICATION_CODE_LENGTH)
+    }
+
+    /// Only the digests of the codes are stored, so the pending codes can't be read out of the table
+    pub fn digest(code: &str) -> Vec<u8> {
+        ring::digest::digest(&ring::digest::SHA256, code.trim().as_bytes())
+            .as_ref()
+            .to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emails() {
+        let contact = NewContact::new(ContactKind::Email, " Sad.Bot@Example.COM ").expect("the email must be valid");
+        assert_eq!(contact.value, "sad.bot@example.com");
+
+        for invalid in ["", "sadbot", "@example.com", "sad@bot@example.com", "sad@example", "sad@example..com", "sad bot@example.com"] {
+            assert!(NewContact::new(ContactKind::Email, invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_phones() {
+        let contact = NewContact::new(ContactKind::Phone, "+7 (900) 123-45-67").expect("the phone must be valid");
+        assert_eq!(contact.value, "+79001234567");
+
+        for invalid in ["", "89001234567", "+0123456789", "+7900", "+7900123456789012", "+7 900 CALL ME"] {
+            assert!(NewContact::new(ContactKind::Phone, invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_generate_code() {
+        let code = VerificationCode::generate_code();
+        assert_eq!(code.len(), VERIFICATION_CODE_LENGTH);
+        assert!(code.chars().all(|c| c.is_ascii_digit()));
+        assert_eq!(VerificationCode::digest(&code), VerificationCode::digest(&format!(" {code} ")));
+        assert_ne!(VerificationCode::digest(&code).as_slice(), code.as_bytes());
+    }
+}
@@ -0,0 +1,258 @@
+use std::sync::LazyLock;
+use argon2::{Argon2, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::{PasswordHash, SaltString};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, Utc};
+use ring::rand::{SecureRandom, SystemRandom};
+use crate::dto::error::{CredentialsError, PasswordHashError};
+use crate::dto::{ContactKind, NewContact};
+
+pub const PASSWORD_MIN_LENGTH: usize = 8;
+pub const PASSWORD_MAX_LENGTH: usize = 128;
+pub const USERNAME_MIN_LENGTH: usize = 3;
+pub const USERNAME_MAX_LENGTH: usize = 32;
+const SALT_LENGTH: usize = 16;
+const RESET_TOKEN_LENGTH: usize = 32;
+
+/// Hashed once with the same parameters as the real passwords, see [verify_dummy_password]
+static DUMMY_PASSWORD_HASH: LazyLock<String> = LazyLock::new(|| {
+    hash_password(&Password("not a password of anyone".to_owned()))
+        .expect("a constant password must be hashable")
+});
+
+/// Case-insensitive name to log in with: Latin letters (stored lowercased), digits, `_`, `.` and `-`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Username(String);
+
+/// Either a username or an email; told apart by the `@`, which usernames can't contain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Login {
+    Username(Username),
+    /// Normalized the same way as the email contacts
+    Email(String),
+}
+
+/// A new password that satisfies the length policy; never printed
+#[derive(Clone)]
+pub struct Password(String);
+
+#[derive(Debug, Clone)]
+pub struct NewCredentials {
+    pub username: Option<Username>,
+    pub email: Option<String>,
+    /// PHC string of Argon2id, see [hash_password]
+    pub password_hash: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Credentials {
+    pub user_id: i64,
+    pub username: Option<String>,
+    pub email: Option<String>,
+    pub password_hash: String,
+    /// Set for a while after too many failed attempts to log in
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CredentialsOutcome {
+    Created(Credentials),
+    NotFound,
+    /// The user has credentials already; only the password can be changed
+    AlreadyExists,
+    /// The username or the email belongs to another user
+    LoginTaken,
+    /// The email is not a verified contact of the user
+    EmailNotVerified,
+}
+
+/// The attempt to log in is counted as a failed one until the password is checked
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoginAttempt {
+    /// Holds the hash to check the password against
+    Started(String),
+    /// Too many failed attempts in a row, logging in is locked until the time
+    Locked(DateTime<Utc>),
+    NotFound,
+}
+
+/// One-time token to set a new password without the current one; sent to the verified email of the user
+#[derive(Debug, Clone)]
+pub struct PasswordReset {
+    pub user_id: i64,
+    pub email: String,
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+
+// IMPLEMENTATIONS
+
+
+impl Username {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<&str> for Username {
+    type Error = CredentialsError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let username = value.trim().to_lowercase();
+        let valid = (USERNAME_MIN_LENGTH..=USERNAME_MAX_LENGTH).contains(&username.len())
+            && username.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+        if valid {
+            Ok(Self(username))
+        } else {
+            Err(CredentialsError::InvalidUsername)
+        }
+    }
+}
+
+impl From<Username> for String {
+    fn from(value: Username) -> Self {
+        value.0
+    }
+}
+
+impl TryFrom<&str> for Login {
+    type Error = CredentialsError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        if value.contains('@') {
+            NewContact::new(ContactKind::Email, value)
+                .map(|contact| Self::Email(contact.value))
+                .map_err(|_| CredentialsError::InvalidEmail)
+        } else {
+            Username::try_from(value).map(Self::Username)
+        }
+    }
+}
+
+impl Login {
+    pub fn username(&self) -> Option<&str> {
+        match self {
+            Self::Username(username) => Some(username.as_str()),
+            Self::Email(_) => None,
+        }
+    }
+
+    pub fn email(&self) -> Option<&str> {
+        match self {
+            Self::Username(_) => None,
+            Self::Email(email) => Some(email),
+        }
+    }
+}
+
+impl TryFrom<String> for Password {
+    type Error = CredentialsError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if (PASSWORD_MIN_LENGTH..=PASSWORD_MAX_LENGTH).contains(&value.chars().count()) {
+            Ok(Self(value))
+        } else {
+            Err(CredentialsError::InvalidPassword)
+        }
+    }
+}
+
+impl std::fmt::Debug for Password {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Password(***)")
+    }
+}
+
+impl Credentials {
+    pub fn locked(&self) -> bool {
+        self.locked_until.is_some_and(|till| till > Utc::now())
+    }
+}
+
+impl PasswordReset {
+    pub fn generate_token() -> String {
+        let mut bytes = [0u8; RESET_TOKEN_LENGTH];
+        SystemRandom::new()
+            .fill(&mut bytes)
+            .expect("the system random number generator must be available");
+        URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    /// Only the digests of the tokens are stored, so a leaked table doesn't let anyone reset passwords
+    pub fn digest(token: &str) -> Vec<u8> {
+        ring::digest::digest(&ring::digest::SHA256, token.trim().as_bytes())
+            .as_ref()
+            .to_vec()
+    }
+}
+
+/// Argon2id with the default parameters of the `argon2` crate; CPU-bound, so call it off the async runtime
+pub fn hash_password(password: &Password) -> Result<String, PasswordHashError> {
+    let mut salt = [0u8; SALT_LENGTH];
+    SystemRandom::new()
+        .fill(&mut salt)
+        .expect("the system random number generator must be available");
+    let salt = SaltString::encode_b64(&salt)
+        .map_err(|e| PasswordHashError(e.to_string()))?;
+    Argon2::default()
+        .hash_password(password.0.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| PasswordHashError(e.to_string()))
+}
+
+/// The parameters are taken from the hash, so the old hashes stay valid if the defaults change
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash)
+        .is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+}
+
+/// Takes as long as [verify_password] with a real hash, so an unknown login can't be told by the response time
+pub fn verify_dummy_password(password: &str) {
+    verify_password(password, &DUMMY_PASSWORD_HASH);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_logins() {
+        assert_eq!(Login::try_from(" Sad.Bot ").ok(), Some(Login::Username(Username("sad.bot".to_owned()))));
+        assert_eq!(Login::try_from("Sad.Bot@Example.com").ok(), Some(Login::Email("sad.bot@example.com".to_owned())));
+
+        for invalid in ["", "sb", "sad bot", "sad+bot", "козало", &"s".repeat(33), "sad@bot"] {
+            assert!(Login::try_from(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_password_policy() {
+        assert!(Password::try_from("short".to_owned()).is_err());
+        assert!(Password::try_from("x".repeat(129)).is_err());
+        assert!(Password::try_from("пароль12".to_owned()).is_ok());
+        assert_eq!(format!("{:?}", Password::try_from("secret password".to_owned())), "Ok(Password(***))");
+    }
+
+    #[test]
+    fn test_hash_password() {
+        let password = Password::try_from("correct horse".to_owned()).expect("the password must be valid");
+        let hash = hash_password(&password).expect("the password must be hashed");
+        assert!(hash.starts_with("$argon2id$"));
+        assert_ne!(hash, hash_password(&password).expect("the password must be hashed"), "the salt must be random");
+
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("battery staple", &hash));
+        assert!(!verify_password("correct horse", "not a hash"));
+    }
+
+    #[test]
+    fn test_reset_token() {
+        let token = PasswordReset::generate_token();
+        assert_eq!(token.len(), 43);
+        assert_ne!(token, PasswordReset::generate_token());
+        assert_eq!(PasswordReset::digest(&token), PasswordReset::digest(&format!(" {token} ")));
+        assert_eq!(PasswordReset::digest(&token).len(), 32);
+    }
+}
@@ -1,8 +1,8 @@
 use std::fmt::Formatter;
 use derive_more::{Constructor, Display};
 use serde::Serializer;
-use serde_derive::{Deserialize, Serialize};
 use thiserror::Error;
+use crate::dto::{SchemaViolation, NEARBY_MAX_RADIUS_KM, PASSWORD_MAX_LENGTH, PASSWORD_MIN_LENGTH, USERNAME_MAX_LENGTH, USERNAME_MIN_LENGTH};
 
 #[derive(Debug, Error, Constructor)]
 pub struct VecLengthAssertionError<T> {
@@ -10,8 +10,19 @@ pub struct VecLengthAssertionError<T> {
     expected_length: usize,
 }
 
-#[derive(Debug, Display, Serialize, Deserialize, Error)]
-pub struct CodeStringLengthError;
+#[derive(Debug, Display, Error)]
+pub enum LanguageTagError {
+    #[display("the language tag must be at most 64 characters long")]
+    TooLong,
+    #[display("the language tag is not well-formed BCP 47: {_0}")]
+    Malformed(String),
+    #[display("the language tag has unregistered subtags: {_0}")]
+    Unregistered(String),
+    #[display("the language tag must start with a language subtag")]
+    NoLanguage,
+    #[display("the language is missing from ISO 639-1: {_0}")]
+    UnsupportedLanguage(String),
+}
 
 #[derive(Debug, Display, Error)]
 pub struct TypeConversionError(Box<dyn std::error::Error + Send + Sync + 'static>);
@@ -22,6 +33,100 @@ pub struct EnumUnspecifiedValue;
 #[derive(Debug, Display, Error)]
 pub struct LocationError(pub &'static str);
 
+#[derive(Debug, Display, Error)]
+#[display("the radius must be greater than 0 and at most {NEARBY_MAX_RADIUS_KM} km")]
+pub struct RadiusError;
+
+#[derive(Debug, Display, Error)]
+#[display("unknown IANA timezone: {_0}")]
+pub struct TimezoneError(pub String);
+
+#[derive(Debug, Display, Error)]
+#[display("the cursor is malformed")]
+pub struct CursorError;
+
+#[derive(Debug, Display, Error)]
+#[display("the service type must be a lowercase kebab-case name of at most 64 characters")]
+pub struct ServiceTypeNameError;
+
+#[derive(Debug, Display, Error)]
+#[display("unknown changed field: {_0}")]
+pub struct ChangedFieldError(pub String);
+
+#[derive(Debug, Display, Error)]
+pub enum NameError {
+    #[display("the name must contain printable characters")]
+    Empty,
+    #[display("the name must be at most 256 characters long")]
+    TooLong,
+}
+
+#[derive(Debug, Display, Error)]
+pub enum BanError {
+    #[display("the reason must be a non-empty string of at most 1024 characters")]
+    InvalidReason,
+    #[display("the issuer must be a non-empty string of at most 256 characters")]
+    InvalidIssuer,
+    #[display("the expiry of the ban must be in the future")]
+    AlreadyExpired,
+}
+
+#[derive(Debug, Display, Error)]
+pub enum SettingError {
+    #[display("the key must consist of at most 128 lowercase letters, digits, '_', '-' and '.'")]
+    InvalidKey,
+    #[display("the value must be at most 16 KiB long when serialized as JSON")]
+    ValueTooLarge,
+}
+
+#[derive(Debug, Display, Error)]
+#[display("unknown schema kind: {_0}")]
+pub struct SchemaKindError(pub String);
+
+#[derive(Debug, Display, Error)]
+pub enum SchemaError {
+    #[display("the schema is invalid: {_0}")]
+    InvalidSchema(String),
+    #[display("the document doesn't match the schema: {}", _0.iter().map(ToString::to_string).collect::<Vec<_>>().join("; "))]
+    Violations(Vec<SchemaViolation>),
+}
+
+#[derive(Debug, Display, Error)]
+#[display("unknown contact kind: {_0}")]
+pub struct ContactKindError(pub String);
+
+#[derive(Debug, Display, Error)]
+pub enum ContactError {
+    #[display("the email must look like user@example.com and be at most 254 characters long")]
+    InvalidEmail,
+    #[display("the phone number must be in the international format: '+' and 8 to 15 digits")]
+    InvalidPhone,
+}
+
+#[derive(Debug, Display, Error)]
+pub enum CredentialsError {
+    #[display("the username must be {USERNAME_MIN_LENGTH} to {USERNAME_MAX_LENGTH} characters long and consist of Latin letters, digits, '_', '.' and '-'")]
+    InvalidUsername,
+    #[display("the email must look like user@example.com and be at most 254 characters long")]
+    InvalidEmail,
+    #[display("either a username or an email must be set")]
+    NoLogin,
+    #[display("the password must be {PASSWORD_MIN_LENGTH} to {PASSWORD_MAX_LENGTH} characters long")]
+    InvalidPassword,
+}
+
+#[derive(Debug, Display, Error)]
+#[display("couldn't hash the password: {_0}")]
+pub struct PasswordHashError(pub String);
+
+#[derive(Debug, Display, Error)]
+pub enum ExternalIdError {
+    #[display("the type of the service requires numeric external IDs")]
+    NumericRequired,
+    #[display("the external ID must be a non-empty string of at most 256 characters")]
+    InvalidString,
+}
+
 
 // IMPLEMENTATIONS
 
@@ -0,0 +1,94 @@
+use std::sync::LazyLock;
+use reverse_geocoder::ReverseGeocoder;
+use serde_derive::{Deserialize, Serialize};
+use crate::dto::Location;
+use crate::dto::error::RadiusError;
+
+/// Places farther from the location are not considered to be nearby
+const MAX_PLACE_DISTANCE_KM: f64 = 100.0;
+const EARTH_RADIUS_KM: f64 = 6371.0;
+pub const NEARBY_MAX_RADIUS_KM: f64 = 1000.0;
+
+/// GeoNames dataset of the populated places with a population over 1000, embedded into the binary
+static GEOCODER: LazyLock<ReverseGeocoder> = LazyLock::new(ReverseGeocoder::new);
+
+/// The nearest populated place to a location
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Place {
+    pub city: String,
+    /// ISO 3166-1 alpha-2
+    pub country_code: String,
+}
+
+
+// IMPLEMENTATIONS
+
+
+/// Builds the search tree ahead of time, so that the first request doesn't have to wait for it
+pub fn load_geocoder() {
+    LazyLock::force(&GEOCODER);
+}
+
+/// The radius of the nearby users search must be positive and not larger than [NEARBY_MAX_RADIUS_KM]
+pub fn validate_radius(radius_km: f64) -> Result<(), RadiusError> {
+    if radius_km.is_finite() && radius_km > 0.0 && radius_km <= NEARBY_MAX_RADIUS_KM {
+        Ok(())
+    } else {
+        Err(RadiusError)
+    }
+}
+
+impl Location {
+    /// Looks the location up in the offline dataset; `None` in the middle of nowhere
+    pub fn place(&self) -> Option<Place> {
+        let record = GEOCODER.search((self.latitude, self.longitude)).record;
+        let nearest = Location { latitude: record.lat, longitude: record.lon };
+        (self.distance_km(&nearest) <= MAX_PLACE_DISTANCE_KM).then(|| Place {
+            city: record.name.clone(),
+            country_code: record.cc.clone(),
+        })
+    }
+
+    /// Great-circle distance by the haversine formula
+    pub fn distance_km(&self, other: &Location) -> f64 {
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let d_lat = lat2 - lat1;
+        let d_lon = (other.longitude - self.longitude).to_radians();
+        let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_place() {
+        let place = Location { latitude: 55.7558, longitude: 37.6173 }.place()
+            .expect("Moscow must be found");
+        assert_eq!(place.city, "Moscow");
+        assert_eq!(place.country_code, "RU");
+
+        let point_nemo = Location { latitude: -48.8767, longitude: -123.3933 };
+        assert_eq!(point_nemo.place(), None);
+    }
+
+    #[test]
+    fn test_distance() {
+        let moscow = Location { latitude: 55.7558, longitude: 37.6173 };
+        let saint_petersburg = Location { latitude: 59.9343, longitude: 30.3351 };
+        let distance = moscow.distance_km(&saint_petersburg);
+        assert!((630.0..640.0).contains(&distance), "{distance}");
+    }
+
+    #[test]
+    fn test_validate_radius() {
+        assert!(validate_radius(0.5).is_ok());
+        assert!(validate_radius(NEARBY_MAX_RADIUS_KM).is_ok());
+        assert!(validate_radius(0.0).is_err());
+        assert!(validate_radius(-1.0).is_err());
+        assert!(validate_radius(NEARBY_MAX_RADIUS_KM + 1.0).is_err());
+        assert!(validate_radius(f64::NAN).is_err());
+    }
+}
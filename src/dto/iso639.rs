@@ -0,0 +1,234 @@
+use serde_derive::Serialize;
+
+/// A language of the ISO 639-1 table embedded into the binary
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Language {
+    pub code: &'static str,
+    /// English name
+    pub name: &'static str,
+    /// Name of the language in itself
+    pub native_name: &'static str,
+}
+
+
+// IMPLEMENTATIONS
+
+
+impl Language {
+    pub fn by_code(code: &str) -> Option<&'static Language> {
+        ISO_639_1.binary_search_by(|language| language.code.cmp(code))
+            .ok()
+            .map(|index| &ISO_639_1[index])
+    }
+}
+
+/// All the languages which may be the primary language of a [crate::dto::LanguageTag], ordered by their codes
+pub fn supported_languages() -> &'static [Language] {
+    &ISO_639_1
+}
+
+/// The current edition of ISO 639-1 without the withdrawn codes (`sh`), sorted by code for the binary search
+static ISO_639_1: [Language; 183] = [
+    Language { code: "aa", name: "Afar", native_name: "Afaraf" },
+    Language { code: "ab", name: "Abkhazian", native_name: "аҧсуа бызшәа" },
+    Language { code: "ae", name: "Avestan", native_name: "avesta" },
+    Language { code: "af", name: "Afrikaans", native_name: "Afrikaans" },
+    Language { code: "ak", name: "Akan", native_name: "Akan" },
+    Language { code: "am", name: "Amharic", native_name: "አማርኛ" },
+    Language { code: "an", name: "Aragonese", native_name: "aragonés" },
+    Language { code: "ar", name: "Arabic", native_name: "العربية" },
+    Language { code: "as", name: "Assamese", native_name: "অসমীয়া" },
+    Language { code: "av", name: "Avaric", native_name: "авар мацӀ" },
+    Language { code: "ay", name: "Aymara", native_name: "aymar aru" },
+    Language { code: "az", name: "Azerbaijani", native_name: "azərbaycan dili" },
+    Language { code: "ba", name: "Bashkir", native_name: "башҡорт теле" },
+    Language { code: "be", name: "Belarusian", native_name: "беларуская мова" },
+    Language { code: "bg", name: "Bulgarian", native_name: "български език" },
+    Language { code: "bi", name: "Bislama", native_name: "Bislama" },
+    Language { code: "bm", name: "Bambara", native_name: "bamanankan" },
+    Language { code: "bn", name: "Bengali", native_name: "বাংলা" },
+    Language { code: "bo", name: "Tibetan", native_name: "བོད་ཡིག" },
+    Language { code: "br", name: "Breton", native_name: "brezhoneg" },
+    Language { code: "bs", name: "Bosnian", native_name: "bosanski jezik" },
+    Language { code: "ca", name: "Catalan", native_name: "català" },
+    Language { code: "ce", name: "Chechen", native_name: "нохчийн мотт" },
+    Language { code: "ch", name: "Chamorro", native_name: "Chamoru" },
+    Language { code: "co", name: "Corsican", native_name: "corsu" },
+    Language { code: "cr", name: "Cree", native_name: "ᓀᐦᐃᔭᐍᐏᐣ" },
+    Language { code: "cs", name: "Czech", native_name: "čeština" },
+    Language { code: "cu", name: "Church Slavic", native_name: "ѩзыкъ словѣньскъ" },
+    Language { code: "cv", name: "Chuvash", native_name: "чӑваш чӗлхи" },
+    Language { code: "cy", name: "Welsh", native_name: "Cymraeg" },
+    Language { code: "da", name: "Danish", native_name: "dansk" },
+    Language { code: "de", name: "German", native_name: "Deutsch" },
+    Language { code: "dv", name: "Divehi", native_name: "ދިވެހި" },
+    Language { code: "dz", name: "Dzongkha", native_name: "རྫོང་ཁ" },
+    Language { code: "ee", name: "Ewe", native_name: "Eʋegbe" },
+    Language { code: "el", name: "Greek", native_name: "Ελληνικά" },
+    Language { code: "en", name: "English", native_name: "English" },
+    Language { code: "eo", name: "Esperanto", native_name: "Esperanto" },
+    Language { code: "es", name: "Spanish", native_name: "español" },
+    Language { code: "et", name: "Estonian", native_name: "eesti" },
+    Language { code: "eu", name: "Basque", native_name: "euskara" },
+    Language { code: "fa", name: "Persian", native_name: "فارسی" },
+    Language { code: "ff", name: "Fulah", native_name: "Fulfulde" },
+    Language { code: "fi", name: "Finnish", native_name: "suomi" },
+    Language { code: "fj", name: "Fijian", native_name: "vosa Vakaviti" },
+    Language { code: "fo", name: "Faroese", native_name: "føroyskt" },
+    Language { code: "fr", name: "French", native_name: "français" },
+    Language { code: "fy", name: "Western Frisian", native_name: "Frysk" },
+    Language { code: "ga", name: "Irish", native_name: "Gaeilge" },
+    Language { code: "gd", name: "Scottish Gaelic", native_name: "Gàidhlig" },
+    Language { code: "gl", name: "Galician", native_name: "galego" },
+    Language { code: "gn", name: "Guarani", native_name: "Avañe'ẽ" },
+    Language { code: "gu", name: "Gujarati", native_name: "ગુજરાતી" },
+    Language { code: "gv", name: "Manx", native_name: "Gaelg" },
+    Language { code: "ha", name: "Hausa", native_name: "Hausa" },
+    Language { code: "he", name: "Hebrew", native_name: "עברית" },
+    Language { code: "hi", name: "Hindi", native_name: "हिन्दी" },
+    Language { code: "ho", name: "Hiri Motu", native_name: "Hiri Motu" },
+    Language { code: "hr", name: "Croatian", native_name: "hrvatski" },
+    Language { code: "ht", name: "Haitian Creole", native_name: "Kreyòl ayisyen" },
+    Language { code: "hu", name: "Hungarian", native_name: "magyar" },
+    Language { code: "hy", name: "Armenian", native_name: "Հայերեն" },
+    Language { code: "hz", name: "Herero", native_name: "Otjiherero" },
+    Language { code: "ia", name: "Interlingua", native_name: "Interlingua" },
+    Language { code: "id", name: "Indonesian", native_name: "Bahasa Indonesia" },
+    Language { code: "ie", name: "Interlingue", native_name: "Interlingue" },
+    Language { code: "ig", name: "Igbo", native_name: "Asụsụ Igbo" },
+    Language { code: "ii", name: "Sichuan Yi", native_name: "ꆈꌠ꒿" },
+    Language { code: "ik", name: "Inupiaq", native_name: "Iñupiaq" },
+    Language { code: "io", name: "Ido", native_name: "Ido" },
+    Language { code: "is", name: "Icelandic", native_name: "íslenska" },
+    Language { code: "it", name: "Italian", native_name: "italiano" },
+    Language { code: "iu", name: "Inuktitut", native_name: "ᐃᓄᒃᑎᑐᑦ" },
+    Language { code: "ja", name: "Japanese", native_name: "日本語" },
+    Language { code: "jv", name: "Javanese", native_name: "basa Jawa" },
+    Language { code: "ka", name: "Georgian", native_name: "ქართული" },
+    Language { code: "kg", name: "Kongo", native_name: "Kikongo" },
+    Language { code: "ki", name: "Kikuyu", native_name: "Gĩkũyũ" },
+    Language { code: "kj", name: "Kuanyama", native_name: "Kuanyama" },
+    Language { code: "kk", name: "Kazakh", native_name: "қазақ тілі" },
+    Language { code: "kl", name: "Kalaallisut", native_name: "kalaallisut" },
+    Language { code: "km", name: "Khmer", native_name: "ខ្មែរ" },
+    Language { code: "kn", name: "Kannada", native_name: "ಕನ್ನಡ" },
+    Language { code: "ko", name: "Korean", native_name: "한국어" },
+    Language { code: "kr", name: "Kanuri", native_name: "Kanuri" },
+    Language { code: "ks", name: "Kashmiri", native_name: "कॉशुर" },
+    Language { code: "ku", name: "Kurdish", native_name: "Kurdî" },
+    Language { code: "kv", name: "Komi", native_name: "коми кыв" },
+    Language { code: "kw", name: "Cornish", native_name: "Kernewek" },
+    Language { code: "ky", name: "Kyrgyz", native_name: "кыргызча" },
+    Language { code: "la", name: "Latin", native_name: "latine" },
+    Language { code: "lb", name: "Luxembourgish", native_name: "Lëtzebuergesch" },
+    Language { code: "lg", name: "Ganda", native_name: "Luganda" },
+    Language { code: "li", name: "Limburgish", native_name: "Limburgs" },
+    Language { code: "ln", name: "Lingala", native_name: "lingála" },
+    Language { code: "lo", name: "Lao", native_name: "ພາສາລາວ" },
+    Language { code: "lt", name: "Lithuanian", native_name: "lietuvių kalba" },
+    Language { code: "lu", name: "Luba-Katanga", native_name: "Kiluba" },
+    Language { code: "lv", name: "Latvian", native_name: "latviešu valoda" },
+    Language { code: "mg", name: "Malagasy", native_name: "fiteny malagasy" },
+    Language { code: "mh", name: "Marshallese", native_name: "Kajin M̧ajeļ" },
+    Language { code: "mi", name: "Maori", native_name: "te reo Māori" },
+    Language { code: "mk", name: "Macedonian", native_name: "македонски јазик" },
+    Language { code: "ml", name: "Malayalam", native_name: "മലയാളം" },
+    Language { code: "mn", name: "Mongolian", native_name: "монгол хэл" },
+    Language { code: "mr", name: "Marathi", native_name: "मराठी" },
+    Language { code: "ms", name: "Malay", native_name: "Bahasa Melayu" },
+    Language { code: "mt", name: "Maltese", native_name: "Malti" },
+    Language { code: "my", name: "Burmese", native_name: "ဗမာစာ" },
+    Language { code: "na", name: "Nauru", native_name: "Dorerin Naoero" },
+    Language { code: "nb", name: "Norwegian Bokmål", native_name: "norsk bokmål" },
+    Language { code: "nd", name: "North Ndebele", native_name: "isiNdebele" },
+    Language { code: "ne", name: "Nepali", native_name: "नेपाली" },
+    Language { code: "ng", name: "Ndonga", native_name: "Owambo" },
+    Language { code: "nl", name: "Dutch", native_name: "Nederlands" },
+    Language { code: "nn", name: "Norwegian Nynorsk", native_name: "norsk nynorsk" },
+    Language { code: "no", name: "Norwegian", native_name: "norsk" },
+    Language { code: "nr", name: "South Ndebele", native_name: "isiNdebele" },
+    Language { code: "nv", name: "Navajo", native_name: "Diné bizaad" },
+    Language { code: "ny", name: "Chichewa", native_name: "chiCheŵa" },
+    Language { code: "oc", name: "Occitan", native_name: "occitan" },
+    Language { code: "oj", name: "Ojibwa", native_name: "ᐊᓂᔑᓈᐯᒧᐎᓐ" },
+    Language { code: "om", name: "Oromo", native_name: "Afaan Oromoo" },
+    Language { code: "or", name: "Odia", native_name: "ଓଡ଼ିଆ" },
+    Language { code: "os", name: "Ossetian", native_name: "ирон æвзаг" },
+    Language { code: "pa", name: "Punjabi", native_name: "ਪੰਜਾਬੀ" },
+    Language { code: "pi", name: "Pali", native_name: "पाऴि" },
+    Language { code: "pl", name: "Polish", native_name: "polski" },
+    Language { code: "ps", name: "Pashto", native_name: "پښتو" },
+    Language { code: "pt", name: "Portuguese", native_name: "português" },
+    Language { code: "qu", name: "Quechua", native_name: "Runa Simi" },
+    Language { code: "rm", name: "Romansh", native_name: "rumantsch" },
+    Language { code: "rn", name: "Kirundi", native_name: "Ikirundi" },
+    Language { code: "ro", name: "Romanian", native_name: "română" },
+    Language { code: "ru", name: "Russian", native_name: "русский" },
+    Language { code: "rw", name: "Kinyarwanda", native_name: "Ikinyarwanda" },
+    Language { code: "sa", name: "Sanskrit", native_name: "संस्कृतम्" },
+    Language { code: "sc", name: "Sardinian", native_name: "sardu" },
+    Language { code: "sd", name: "Sindhi", native_name: "سنڌي" },
+    Language { code: "se", name: "Northern Sami", native_name: "davvisámegiella" },
+    Language { code: "sg", name: "Sango", native_name: "yângâ tî sängö" },
+    Language { code: "si", name: "Sinhala", native_name: "සිංහල" },
+    Language { code: "sk", name: "Slovak", native_name: "slovenčina" },
+    Language { code: "sl", name: "Slovenian", native_name: "slovenščina" },
+    Language { code: "sm", name: "Samoan", native_name: "gagana Sāmoa" },
+    Language { code: "sn", name: "Shona", native_name: "chiShona" },
+    Language { code: "so", name: "Somali", native_name: "Soomaaliga" },
+    Language { code: "sq", name: "Albanian", native_name: "shqip" },
+    Language { code: "sr", name: "Serbian", native_name: "српски језик" },
+    Language { code: "ss", name: "Swati", native_name: "SiSwati" },
+    Language { code: "st", name: "Southern Sotho", native_name: "Sesotho" },
+    Language { code: "su", name: "Sundanese", native_name: "Basa Sunda" },
+    Language { code: "sv", name: "Swedish", native_name: "svenska" },
+    Language { code: "sw", name: "Swahili", native_name: "Kiswahili" },
+    Language { code: "ta", name: "Tamil", native_name: "தமிழ்" },
+    Language { code: "te", name: "Telugu", native_name: "తెలుగు" },
+    Language { code: "tg", name: "Tajik", native_name: "тоҷикӣ" },
+    Language { code: "th", name: "Thai", native_name: "ไทย" },
+    Language { code: "ti", name: "Tigrinya", native_name: "ትግርኛ" },
+    Language { code: "tk", name: "Turkmen", native_name: "Türkmençe" },
+    Language { code: "tl", name: "Tagalog", native_name: "Wikang Tagalog" },
+    Language { code: "tn", name: "Tswana", native_name: "Setswana" },
+    Language { code: "to", name: "Tongan", native_name: "lea faka-Tonga" },
+    Language { code: "tr", name: "Turkish", native_name: "Türkçe" },
+    Language { code: "ts", name: "Tsonga", native_name: "Xitsonga" },
+    Language { code: "tt", name: "Tatar", native_name: "татар теле" },
+    Language { code: "tw", name: "Twi", native_name: "Twi" },
+    Language { code: "ty", name: "Tahitian", native_name: "Reo Tahiti" },
+    Language { code: "ug", name: "Uyghur", native_name: "ئۇيغۇرچە" },
+    Language { code: "uk", name: "Ukrainian", native_name: "українська" },
+    Language { code: "ur", name: "Urdu", native_name: "اردو" },
+    Language { code: "uz", name: "Uzbek", native_name: "oʻzbekcha" },
+    Language { code: "ve", name: "Venda", native_name: "Tshivenḓa" },
+    Language { code: "vi", name: "Vietnamese", native_name: "Tiếng Việt" },
+    Language { code: "vo", name: "Volapük", native_name: "Volapük" },
+    Language { code: "wa", name: "Walloon", native_name: "walon" },
+    Language { code: "wo", name: "Wolof", native_name: "Wolof" },
+    Language { code: "xh", name: "Xhosa", native_name: "isiXhosa" },
+    Language { code: "yi", name: "Yiddish", native_name: "ייִדיש" },
+    Language { code: "yo", name: "Yoruba", native_name: "Yorùbá" },
+    Language { code: "za", name: "Zhuang", native_name: "Vahcuengh" },
+    Language { code: "zh", name: "Chinese", native_name: "中文" },
+    Language { code: "zu", name: "Zulu", native_name: "isiZulu" },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_is_sorted() {
+        assert!(ISO_639_1.windows(2).all(|pair| pair[0].code < pair[1].code));
+    }
+
+    #[test]
+    fn test_by_code() {
+        let russian = Language::by_code("ru").expect("Russian must be supported");
+        assert_eq!(russian.name, "Russian");
+        assert_eq!(russian.native_name, "русский");
+        assert!(Language::by_code("zz").is_none());
+        assert!(Language::by_code("rus").is_none());
+    }
+}
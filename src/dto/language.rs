@@ -0,0 +1,123 @@
+use derive_more::Display;
+use crate::dto::error::LanguageTagError;
+use crate::dto::Language;
+
+/// Fits `Users.language_code`; long enough for a language, a script, a region and a couple of variants
+const LANGUAGE_TAG_MAX_LENGTH: usize = 64;
+
+/// BCP 47 language tag (`en`, `pt-BR`, `zh-Hant-TW`) in its canonical form.
+/// All the subtags are checked against the IANA registry; the primary language must be in the ISO 639-1 table.
+#[derive(Debug, Display, Clone, PartialEq, Eq, Hash)]
+pub struct LanguageTag(String);
+
+
+// IMPLEMENTATIONS
+
+
+impl LanguageTag {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The primary language subtag
+    pub fn language(&self) -> &'static Language {
+        let code = self.0.split('-').next().unwrap_or_default();
+        Language::by_code(code).expect("the language is checked on creation")
+    }
+
+    /// Lookup fallback chain of RFC 4647, from the tag itself to its primary language:
+    /// `zh-Hant-TW`, `zh-Hant`, `zh`
+    pub fn fallback_chain(&self) -> Vec<&str> {
+        let mut chain = vec![self.as_str()];
+        let mut tag = self.as_str();
+        while let Some((prefix, _)) = tag.rsplit_once('-') {
+            // a singleton introduces an extension or a private use section and means nothing alone
+            tag = match prefix.rsplit_once('-') {
+                Some((shorter, singleton)) if singleton.len() == 1 => shorter,
+                _ => prefix,
+            };
+            chain.push(tag);
+        }
+        chain
+    }
+}
+
+impl TryFrom<String> for LanguageTag {
+    type Error = LanguageTagError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.as_str().try_into()
+    }
+}
+
+impl TryFrom<&str> for LanguageTag {
+    type Error = LanguageTagError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        if value.len() > LANGUAGE_TAG_MAX_LENGTH {
+            return Err(LanguageTagError::TooLong);
+        }
+        let tag = language_tags::LanguageTag::parse(value)
+            .map_err(|e| LanguageTagError::Malformed(e.to_string()))?;
+        tag.validate()
+            .map_err(|e| LanguageTagError::Unregistered(e.to_string()))?;
+        let tag = tag.canonicalize()
+            .map_err(|e| LanguageTagError::Unregistered(e.to_string()))?;
+        // private use (`x-whatever`) and irregular grandfathered tags have no language at all
+        if tag.primary_language().is_empty() {
+            return Err(LanguageTagError::NoLanguage);
+        }
+        if Language::by_code(tag.primary_language()).is_none() {
+            return Err(LanguageTagError::UnsupportedLanguage(tag.primary_language().to_owned()));
+        }
+        Ok(Self(tag.as_str().to_owned()))
+    }
+}
+
+impl From<LanguageTag> for String {
+    fn from(value: LanguageTag) -> Self {
+        value.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let tag = LanguageTag::try_from("PT-br").expect("pt-BR must be valid");
+        assert_eq!(tag.as_str(), "pt-BR");
+        assert_ne!(tag, LanguageTag::try_from("pt-PT").expect("pt-PT must be valid"));
+        assert_eq!(LanguageTag::try_from("zh-hant").map(String::from).ok(), Some("zh-Hant".to_owned()));
+        assert_eq!(LanguageTag::try_from("ru").map(String::from).ok(), Some("ru".to_owned()));
+
+        for invalid in ["", "r", "zz", "12", "🦀🦀", "en--US", "x-private", "en-QQQ", "yue-HK", &"en-".repeat(30)] {
+            assert!(LanguageTag::try_from(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_language() {
+        let tag = LanguageTag::try_from("pt-BR").expect("pt-BR must be valid");
+        assert_eq!(tag.language().code, "pt");
+        assert_eq!(tag.language().name, "Portuguese");
+        assert_eq!(tag.language().native_name, "português");
+
+        // deprecated codes are replaced with the current ones
+        let tag = LanguageTag::try_from("iw").expect("iw must be valid");
+        assert_eq!(tag.language().name, "Hebrew");
+    }
+
+    #[test]
+    fn test_fallback_chain() {
+        let tag = LanguageTag::try_from("zh-Hant-TW").expect("zh-Hant-TW must be valid");
+        assert_eq!(tag.fallback_chain(), vec!["zh-Hant-TW", "zh-Hant", "zh"]);
+
+        let tag = LanguageTag::try_from("en-US-x-twain").expect("en-US-x-twain must be valid");
+        assert_eq!(tag.fallback_chain(), vec!["en-US-x-twain", "en-US", "en"]);
+
+        let tag = LanguageTag::try_from("ru").expect("ru must be valid");
+        assert_eq!(tag.fallback_chain(), vec!["ru"]);
+    }
+}
@@ -0,0 +1,39 @@
+use chrono::{DateTime, Utc};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_derive::{Deserialize, Serialize};
+
+/// Unambiguous characters only (no `0`/`O`, `1`/`I`); 32 symbols give no modulo bias for random bytes.
+const LINK_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const LINK_CODE_LENGTH: usize = 8;
+
+/// One-time code the user must enter in another service to link their account from it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkCode {
+    pub code: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LinkOutcome {
+    Linked(i64),
+    /// The external account is already mapped to another user; the users must be merged instead.
+    MappedToAnotherUser(i64),
+    InvalidCode,
+}
+
+impl LinkCode {
+    pub fn generate_code() -> String {
+        let mut bytes = [0u8; LINK_CODE_LENGTH];
+        SystemRandom::new()
+            .fill(&mut bytes)
+            .expect("the system random number generator must be available");
+        bytes.iter()
+            .map(|b| LINK_CODE_ALPHABET[*b as usize % LINK_CODE_ALPHABET.len()] as char)
+            .collect()
+    }
+
+    /// Codes are case-insensitive for the users
+    pub fn normalize(code: &str) -> String {
+        code.trim().to_uppercase()
+    }
+}
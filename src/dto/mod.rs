@@ -3,7 +3,31 @@ pub mod error;
 mod user;
 mod service;
 mod comresp;
+mod link;
+mod page;
+mod change;
+mod ban;
+mod setting;
+mod schema;
+mod timezone;
+mod geocoding;
+mod language;
+mod iso639;
+mod contact;
+mod credentials;
 
 pub use user::*;
 pub use service::*;
 pub use comresp::*;
+pub use link::*;
+pub use page::*;
+pub use change::*;
+pub use ban::*;
+pub use setting::*;
+pub use schema::*;
+pub use timezone::*;
+pub use geocoding::*;
+pub use language::*;
+pub use iso639::*;
+pub use contact::*;
+pub use credentials::*;
@@ -0,0 +1,29 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use crate::dto::error::CursorError;
+
+/// Opaque position in a list ordered by IDs (of users, changes, etc.): the ID of the last returned item
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Cursor(pub i64);
+
+#[derive(Debug, Clone)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// `None` on the last page
+    pub next: Option<Cursor>,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(self.0.to_string())
+    }
+
+    pub fn decode(value: &str) -> Result<Self, CursorError> {
+        let bytes = URL_SAFE_NO_PAD.decode(value)
+            .map_err(|_| CursorError)?;
+        String::from_utf8(bytes).ok()
+            .and_then(|id| id.parse().ok())
+            .map(Self)
+            .ok_or(CursorError)
+    }
+}
@@ -0,0 +1,110 @@
+use derive_more::Display;
+use serde_derive::{Deserialize, Serialize};
+use crate::dto::error::{SchemaError, SchemaKindError};
+
+/// Free-form JSON document a service may constrain with a JSON Schema
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaKind {
+    /// `consent_info` sent on registration and linking
+    Consent,
+    /// The settings of a user, as an object of the values by their keys
+    Settings,
+}
+
+/// Part of the document that doesn't match the schema
+#[derive(Debug, Clone, PartialEq, Display, Serialize, Deserialize)]
+#[display("{path}: {message}")]
+pub struct SchemaViolation {
+    /// JSON Pointer to the failing value, empty for the whole document
+    pub path: String,
+    pub message: String,
+}
+
+
+// IMPLEMENTATIONS
+
+
+impl SchemaKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Consent => "consent",
+            Self::Settings => "settings",
+        }
+    }
+}
+
+impl TryFrom<&str> for SchemaKind {
+    type Error = SchemaKindError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "consent" => Ok(Self::Consent),
+            "settings" => Ok(Self::Settings),
+            unknown => Err(SchemaKindError(unknown.to_owned())),
+        }
+    }
+}
+
+/// Ensures the schema itself is valid before saving it
+pub fn validate_schema(schema: &serde_json::Value) -> Result<(), SchemaError> {
+    jsonschema::validator_for(schema)
+        .map(|_| ())
+        .map_err(|e| SchemaError::InvalidSchema(e.to_string()))
+}
+
+/// Collects all the violations rather than stopping at the first one
+pub fn validate_document(schema: &serde_json::Value, document: &serde_json::Value) -> Result<(), SchemaError> {
+    let validator = jsonschema::validator_for(schema)
+        .map_err(|e| SchemaError::InvalidSchema(e.to_string()))?;
+    let violations: Vec<SchemaViolation> = validator.iter_errors(document)
+        .map(|error| SchemaViolation {
+            path: error.instance_path.to_string(),
+            message: error.to_string(),
+        })
+        .collect();
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(SchemaError::Violations(violations))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    #[test]
+    fn test_validate_document() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "version": {"type": "integer"},
+                "accepted": {"const": true}
+            },
+            "required": ["version", "accepted"]
+        });
+        assert!(validate_document(&schema, &json!({"version": 2, "accepted": true})).is_ok());
+
+        let Err(SchemaError::Violations(violations)) = validate_document(&schema, &json!({"version": "2", "accepted": false})) else {
+            panic!("the document must be rejected");
+        };
+        let mut paths: Vec<&str> = violations.iter().map(|v| v.path.as_str()).collect();
+        paths.sort();
+        assert_eq!(paths, vec!["/accepted", "/version"]);
+    }
+
+    #[test]
+    fn test_validate_schema() {
+        assert!(validate_schema(&json!({"type": "object"})).is_ok());
+        assert!(matches!(validate_schema(&json!({"type": "nonsense"})), Err(SchemaError::InvalidSchema(_))));
+    }
+
+    #[test]
+    fn test_schema_kind() {
+        assert_eq!(SchemaKind::try_from("consent").ok(), Some(SchemaKind::Consent));
+        assert_eq!(SchemaKind::try_from(SchemaKind::Settings.as_str()).ok(), Some(SchemaKind::Settings));
+        assert!(SchemaKind::try_from("profile").is_err());
+    }
+}
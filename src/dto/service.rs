@@ -1,21 +1,64 @@
-use derive_more::From;
+use std::borrow::Cow;
+use derive_more::{Display, From};
 use serde_derive::{Deserialize, Serialize};
+use crate::dto::error::ServiceTypeNameError;
 
-#[derive(sqlx::Type, Serialize, Deserialize, Debug, Hash, Eq, PartialEq, Copy, Clone)]
-#[sqlx(type_name = "service_type")]
-#[sqlx(rename_all = "kebab-case")]
-#[serde(rename_all = "kebab-case")]
-pub enum ServiceType {
-    TelegramBot,
-    TelegramChannel,
-    Website,
-    Application,
+const SERVICE_TYPE_MAX_LENGTH: usize = 64;
+
+/// Name of a kind of services registered in the `Service_Types` table (`telegram-bot`, `website`, etc.).
+/// Only lowercase kebab-case names are valid.
+#[derive(Debug, Display, Hash, Eq, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ServiceType(Cow<'static, str>);
+
+/// Entry of the service type registry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceTypeInfo {
+    pub name: ServiceType,
+    /// Whether the services of this type may identify their users by strings rather than numbers
+    #[serde(default)]
+    pub string_ids: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, From, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, From, PartialEq, Eq, Hash)]
 pub struct Service {
     pub name: String,
 
     #[serde(alias = "type")]
     pub service_type: ServiceType,
 }
+
+
+// IMPLEMENTATIONS
+
+
+impl ServiceType {
+    pub const TELEGRAM_BOT: Self = Self(Cow::Borrowed("telegram-bot"));
+    pub const TELEGRAM_CHANNEL: Self = Self(Cow::Borrowed("telegram-channel"));
+    pub const WEBSITE: Self = Self(Cow::Borrowed("website"));
+    pub const APPLICATION: Self = Self(Cow::Borrowed("application"));
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for ServiceType {
+    type Error = ServiceTypeNameError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let is_valid = value.len() <= SERVICE_TYPE_MAX_LENGTH && value.split('-')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit()));
+        if is_valid {
+            Ok(Self(Cow::Owned(value)))
+        } else {
+            Err(ServiceTypeNameError)
+        }
+    }
+}
+
+impl From<ServiceType> for String {
+    fn from(value: ServiceType) -> Self {
+        value.0.into_owned()
+    }
+}
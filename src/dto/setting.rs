@@ -0,0 +1,29 @@
+use std::collections::BTreeMap;
+use crate::dto::error::SettingError;
+
+const SETTING_KEY_MAX_LENGTH: usize = 128;
+const SETTING_VALUE_MAX_SIZE: usize = 16 * 1024;
+
+/// Settings of a user in a service, or the defaults of a service, by their keys
+pub type Settings = BTreeMap<String, serde_json::Value>;
+
+/// Keys consist of lowercase ASCII letters, digits, `_`, `-` and `.`
+pub fn validate_setting_key(key: &str) -> Result<(), SettingError> {
+    let valid = !key.is_empty()
+        && key.len() <= SETTING_KEY_MAX_LENGTH
+        && key.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '-' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(SettingError::InvalidKey)
+    }
+}
+
+/// Limits the size of the value serialized as JSON
+pub fn validate_setting_value(value: &serde_json::Value) -> Result<(), SettingError> {
+    if value.to_string().len() > SETTING_VALUE_MAX_SIZE {
+        Err(SettingError::ValueTooLarge)
+    } else {
+        Ok(())
+    }
+}
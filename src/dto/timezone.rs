@@ -0,0 +1,94 @@
+use std::sync::LazyLock;
+use chrono::{Offset, Utc};
+use chrono_tz::Tz;
+use serde_derive::{Deserialize, Serialize};
+use tzf_rs::DefaultFinder;
+use crate::dto::Location;
+use crate::dto::error::TimezoneError;
+
+/// The timezone boundaries are embedded into the binary and take a while to load, so it's done once
+static TIMEZONE_FINDER: LazyLock<DefaultFinder> = LazyLock::new(DefaultFinder::new);
+
+/// Timezone from the IANA database, like `Europe/Moscow`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Timezone(Tz);
+
+
+// IMPLEMENTATIONS
+
+
+/// Loads the timezone boundaries ahead of time, so that the first request doesn't have to wait for them
+pub fn load_timezone_finder() {
+    LazyLock::force(&TIMEZONE_FINDER);
+}
+
+impl Timezone {
+    pub fn name(&self) -> &'static str {
+        self.0.name()
+    }
+
+    /// Current offset from UTC in seconds, with DST taken into account
+    pub fn utc_offset(&self) -> i32 {
+        Utc::now()
+            .with_timezone(&self.0)
+            .offset()
+            .fix()
+            .local_minus_utc()
+    }
+}
+
+impl TryFrom<&str> for Timezone {
+    type Error = TimezoneError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        value.parse::<Tz>()
+            .map(Self)
+            .map_err(|_| TimezoneError(value.to_owned()))
+    }
+}
+
+impl TryFrom<String> for Timezone {
+    type Error = TimezoneError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.as_str().try_into()
+    }
+}
+
+impl From<Timezone> for String {
+    fn from(value: Timezone) -> Self {
+        value.name().to_owned()
+    }
+}
+
+impl Location {
+    /// Looks the location up in the offline dataset of the timezone boundaries
+    pub fn timezone(&self) -> Option<Timezone> {
+        TIMEZONE_FINDER.get_tz_name(self.longitude, self.latitude)
+            .try_into()
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let timezone = Timezone::try_from("Europe/Moscow").expect("the timezone must be valid");
+        assert_eq!(timezone.name(), "Europe/Moscow");
+        assert_eq!(timezone.utc_offset(), 3 * 3600);
+        assert!(Timezone::try_from("Europe/Atlantis").is_err());
+        assert!(Timezone::try_from("").is_err());
+    }
+
+    #[test]
+    fn test_derivation() {
+        let moscow = Location { latitude: 55.7558, longitude: 37.6173 };
+        assert_eq!(moscow.timezone().map(|tz| tz.name()), Some("Europe/Moscow"));
+        let tokyo = Location { latitude: 35.6762, longitude: 139.6503 };
+        assert_eq!(tokyo.timezone().map(|tz| tz.name()), Some("Asia/Tokyo"));
+    }
+}
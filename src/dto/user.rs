@@ -1,25 +1,56 @@
 use std::ops::Add;
 use chrono::{DateTime, Months, Utc};
-use derive_more::From;
+use derive_more::{Display, From};
 use serde_derive::{Deserialize, Serialize};
-use crate::dto::error::{CodeStringLengthError, LocationError, VecLengthAssertionError};
+use crate::dto::error::{ExternalIdError, LocationError, NameError, VecLengthAssertionError};
+use crate::dto::{Ban, Contact, LanguageTag, Place, ServiceTypeInfo, Timezone};
+
+const EXTERNAL_ID_MAX_LENGTH: usize = 256;
+const NAME_MAX_LENGTH: usize = 256;
 
 /// DTO for JSON request and `repo::Users::register()`
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ExternalUser {
-    pub external_id: i64,
+    pub external_id: ExternalId,
     pub name: Option<String>,
 }
 
+/// Identifier of the user in a service: numeric for Telegram, arbitrary strings (UUIDs,
+/// vendor account IDs, etc.) for websites and applications. Stored as text in the database.
+#[derive(Debug, Display, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, From)]
+#[serde(untagged)]
+pub enum ExternalId {
+    Numeric(i64),
+    String(String),
+}
+
 /// Public DTO for the users fetched from the database.
 /// See `crate::repo::users::UserInternal` to see the other, internal, side.
 #[derive(Clone)]
 pub struct SavedUser {
     pub id: i64,
     pub name: Option<String>,
-    pub language_code: Option<Code>,
+    pub language_code: Option<LanguageTag>,
     pub location: Option<Location>,
-    pub premium_till: Option<DateTime<Utc>>
+    /// The nearest place to the location, cached on its update
+    pub place: Option<Place>,
+    pub timezone: Option<Timezone>,
+    pub premium_till: Option<DateTime<Utc>>,
+    /// Incremented by every change of the user
+    pub version: i64,
+    /// Set while the account is deactivated by an admin
+    pub deactivated_at: Option<DateTime<Utc>>,
+    /// Active bans only
+    pub bans: Vec<Ban>,
+    /// Verified contacts only
+    pub contacts: Vec<Contact>,
+}
+
+/// A user found by `repo::Users::nearby()` along with its distance from the center of the search
+#[derive(Clone)]
+pub struct NearbyUser {
+    pub user: SavedUser,
+    pub distance_km: f64,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, From)]
@@ -28,16 +59,6 @@ pub struct Location {
     pub longitude: f64,
 }
 
-#[derive(Debug, Eq, PartialEq, Copy, Clone)]
-pub struct Code([char; 2]);
-
-#[cfg(test)]
-impl std::fmt::Display for Code {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_fmt(format_args!("{}{}", self.0[0], self.0[1]))
-    }
-}
-
 
 // IMPLEMENTATIONS
 
@@ -54,12 +75,59 @@ impl Location {
     }
 }
 
+/// Strips control characters and surrounding whitespace; the result must fit `Users.name`
+pub fn normalize_name(name: &str) -> Result<String, NameError> {
+    let name: String = name.chars()
+        .filter(|c| !c.is_control())
+        .collect();
+    let name = name.trim();
+    if name.is_empty() {
+        Err(NameError::Empty)
+    } else if name.chars().count() > NAME_MAX_LENGTH {
+        Err(NameError::TooLong)
+    } else {
+        Ok(name.to_owned())
+    }
+}
+
+impl ExternalUser {
+    pub fn normalized(self) -> Result<Self, NameError> {
+        Ok(Self {
+            name: self.name.as_deref().map(normalize_name).transpose()?,
+            ..self
+        })
+    }
+}
+
+impl ExternalId {
+    pub fn validate_for(&self, service_type: &ServiceTypeInfo) -> Result<(), ExternalIdError> {
+        match self {
+            Self::Numeric(_) => Ok(()),
+            Self::String(_) if !service_type.string_ids => Err(ExternalIdError::NumericRequired),
+            Self::String(id) if id.is_empty() || id.chars().count() > EXTERNAL_ID_MAX_LENGTH => Err(ExternalIdError::InvalidString),
+            Self::String(_) => Ok(()),
+        }
+    }
+}
+
+/// Path segments are kept as is: the IDs are compared as text in the database,
+/// so parsing `007` as a number would turn it into `7`, which matches nobody
+impl From<&str> for ExternalId {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_owned())
+    }
+}
+
 impl SavedUser {
     pub fn premium(&self) -> bool {
         self.premium_till
             .filter(|till| *till >= Utc::now())
             .is_some()
     }
+
+    pub fn deactivated(&self) -> bool {
+        self.deactivated_at.is_some()
+    }
 }
 
 impl From<[f64; 2]> for Location {
@@ -83,32 +151,6 @@ impl TryFrom<Vec<f64>> for Location {
     }
 }
 
-impl TryFrom<String> for Code {
-    type Error = CodeStringLengthError;
-
-    fn try_from(value: String) -> Result<Self, Self::Error> {
-        value.as_str().try_into()
-    }
-}
-
-impl TryFrom<&str> for Code {
-    type Error = CodeStringLengthError;
-
-    fn try_from(value: &str) -> Result<Self, Self::Error> {
-        let chars: [char; 2] = value.to_lowercase().chars()
-            .collect::<Vec<char>>()
-            .try_into()
-            .map_err(|_| CodeStringLengthError)?;
-        Ok(Self(chars))
-    }
-}
-
-impl Into<String> for Code {
-    fn into(self) -> String {
-        format!("{}{}", self.0[0], self.0[1])
-    }
-}
-
 #[repr(u32)]
 #[derive(Debug, Copy, Clone)]
 pub enum PremiumVariant {
@@ -118,9 +160,9 @@ pub enum PremiumVariant {
     Year = 12,
 }
 
-impl Into<DateTime<Utc>> for PremiumVariant {
-    fn into(self) -> DateTime<Utc> {
-        self + Utc::now()
+impl From<PremiumVariant> for DateTime<Utc> {
+    fn from(value: PremiumVariant) -> Self {
+        value + Utc::now()
     }
 }
 
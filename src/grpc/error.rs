@@ -1,4 +1,6 @@
-use tonic::Status;
+use tonic::{Code, Status};
+use tonic_types::{ErrorDetails, StatusExt};
+use crate::dto::error::SchemaError;
 
 /// Extension trait for converting Results into gRPC Status with automatic tracing
 pub trait IntoStatusExt<T> {
@@ -49,3 +51,20 @@ impl<T> IntoStatusOptionExt<T> for Option<T> {
         })
     }
 }
+
+/// The violations of a JSON Schema are attached as the field violations of the `BadRequest` details,
+/// so the clients don't have to parse the message to find the failing values
+pub fn schema_error_status(error: SchemaError) -> Status {
+    tracing::warn!(error = %error, "Invalid argument");
+    let message = error.to_string();
+    match error {
+        SchemaError::Violations(violations) => {
+            let mut details = ErrorDetails::new();
+            for violation in violations {
+                details.add_bad_request_violation(violation.path, violation.message);
+            }
+            Status::with_error_details(Code::InvalidArgument, message, details)
+        }
+        SchemaError::InvalidSchema(_) => Status::invalid_argument(message),
+    }
+}
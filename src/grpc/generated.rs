@@ -1,18 +1,28 @@
+use std::collections::HashMap;
 use derive_more::{Display, From};
 use thiserror::Error;
 use crate::dto;
-use crate::dto::error::{CodeStringLengthError, EnumUnspecifiedValue};
+use crate::dto::error::{EnumUnspecifiedValue, LanguageTagError, NameError, TimezoneError};
 use crate::grpc::generated::update_user_request::Target;
 use crate::grpc::generated::user::Options;
 use crate::repo::users::UpdateTarget;
 
 tonic::include_proto!("user_service");
 
-impl Into<dto::ExternalUser> for ExternalUser {
-    fn into(self) -> dto::ExternalUser {
-        dto::ExternalUser {
-            external_id: self.external_id,
-            name: self.name,
+/// String IDs take precedence over numeric ones when set
+pub fn to_external_id(numeric_id: i64, string_id: String) -> dto::ExternalId {
+    if string_id.is_empty() {
+        numeric_id.into()
+    } else {
+        string_id.into()
+    }
+}
+
+impl From<ExternalUser> for dto::ExternalUser {
+    fn from(value: ExternalUser) -> Self {
+        Self {
+            external_id: to_external_id(value.external_id, value.external_string_id),
+            name: value.name,
         }
     }
 }
@@ -20,14 +30,62 @@ impl Into<dto::ExternalUser> for ExternalUser {
 impl From<dto::SavedUser> for User {
     fn from(value: dto::SavedUser) -> Self {
         let is_premium = value.premium();
+        let deactivated = value.deactivated();
         Self {
             id: value.id,
             name: value.name,
             options: Some(Options {
                 language_code: value.language_code.map(Into::into),
                 location: value.location.map(Into::into),
+                timezone: value.timezone.map(Into::into),
             }),
             is_premium,
+            place: value.place.map(Into::into),
+            utc_offset: value.timezone.map(|timezone| timezone.utc_offset()),
+            version: value.version,
+            deactivated,
+            bans: value.bans.into_iter().map(Into::into).collect(),
+            contacts: value.contacts.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<dto::NearbyUser> for NearbyUser {
+    fn from(value: dto::NearbyUser) -> Self {
+        Self {
+            user: Some(value.user.into()),
+            distance_km: value.distance_km,
+        }
+    }
+}
+
+impl From<dto::Contact> for Contact {
+    fn from(value: dto::Contact) -> Self {
+        Self {
+            kind: value.kind.as_str().to_owned(),
+            value: value.value,
+            verified_at: Some(std::time::SystemTime::from(value.verified_at).into()),
+        }
+    }
+}
+
+impl From<dto::Ban> for Ban {
+    fn from(value: dto::Ban) -> Self {
+        Self {
+            service: value.service.map(Into::into),
+            reason: value.reason,
+            issuer: value.issuer,
+            issued_at: Some(std::time::SystemTime::from(value.issued_at).into()),
+            expires_at: value.expires_at.map(|till| std::time::SystemTime::from(till).into()),
+        }
+    }
+}
+
+impl From<dto::Place> for Place {
+    fn from(value: dto::Place) -> Self {
+        Self {
+            city: value.city,
+            country_code: value.country_code,
         }
     }
 }
@@ -50,17 +108,73 @@ impl TryInto<dto::ServiceType> for ServiceType {
     fn try_into(self) -> Result<dto::ServiceType, Self::Error> {
         match self {
             ServiceType::Unspecified => Err(UnspecifiedServiceType),
-            ServiceType::TelegramBot => Ok(dto::ServiceType::TelegramBot),
-            ServiceType::TelegramChannel => Ok(dto::ServiceType::TelegramChannel),
-            ServiceType::Website => Ok(dto::ServiceType::Website),
-            ServiceType::Application => Ok(dto::ServiceType::Application),
+            ServiceType::TelegramBot => Ok(dto::ServiceType::TELEGRAM_BOT),
+            ServiceType::TelegramChannel => Ok(dto::ServiceType::TELEGRAM_CHANNEL),
+            ServiceType::Website => Ok(dto::ServiceType::WEBSITE),
+            ServiceType::Application => Ok(dto::ServiceType::APPLICATION),
+        }
+    }
+}
+
+/// Types missing from the enum are passed by name in the `kind_name` field
+impl From<dto::Service> for Service {
+    fn from(value: dto::Service) -> Self {
+        let kind = [
+            (dto::ServiceType::TELEGRAM_BOT, ServiceType::TelegramBot),
+            (dto::ServiceType::TELEGRAM_CHANNEL, ServiceType::TelegramChannel),
+            (dto::ServiceType::WEBSITE, ServiceType::Website),
+            (dto::ServiceType::APPLICATION, ServiceType::Application),
+        ]
+            .into_iter()
+            .find(|(service_type, _)| *service_type == value.service_type)
+            .map_or(ServiceType::Unspecified, |(_, kind)| kind);
+        let kind_name = match kind {
+            ServiceType::Unspecified => value.service_type.into(),
+            _ => String::new(),
+        };
+        Self {
+            name: value.name,
+            kind: kind.into(),
+            kind_name,
         }
     }
 }
 
+#[derive(Debug, Error, Display)]
+#[display("{_0}")]
+pub struct ValueConversionError(serde_json::Error);
+
+impl TryFrom<dto::UserChange> for UserChange {
+    type Error = ValueConversionError;
+
+    fn try_from(value: dto::UserChange) -> Result<Self, Self::Error> {
+        let to_grpc_value = |value: Option<serde_json::Value>| value
+            .map(serde_json::from_value)
+            .transpose()
+            .map_err(ValueConversionError);
+        Ok(Self {
+            id: value.id,
+            field: value.field.as_str().to_owned(),
+            old_value: to_grpc_value(value.old_value)?,
+            new_value: to_grpc_value(value.new_value)?,
+            actor: value.actor.map(Into::into),
+            changed_at: Some(std::time::SystemTime::from(value.changed_at).into()),
+        })
+    }
+}
+
+/// Values of the settings are passed as `google.protobuf.Value`s
+pub fn settings_to_grpc(settings: dto::Settings) -> Result<HashMap<String, prost_wkt_types::Value>, ValueConversionError> {
+    settings.into_iter()
+        .map(|(key, value)| Ok((key, serde_json::from_value(value).map_err(ValueConversionError)?)))
+        .collect()
+}
+
 #[derive(Debug, Error, Display, From)]
 pub enum TargetConversionError {
-    LanguageCodeConversionError(CodeStringLengthError),
+    LanguageTag(LanguageTagError),
+    Name(NameError),
+    Timezone(TimezoneError),
 }
 
 impl TryInto<UpdateTarget> for Target {
@@ -68,8 +182,13 @@ impl TryInto<UpdateTarget> for Target {
 
     fn try_into(self) -> Result<UpdateTarget, Self::Error> {
         let target: UpdateTarget = match self {
-            Target::Language(code) => dto::Code::try_from(code)?.into(),
+            Target::Language(code) => dto::LanguageTag::try_from(code)?.into(),
             Target::Location(loc) => (loc.latitude, loc.longitude).into(),
+            Target::Name(name) => UpdateTarget::Name(dto::normalize_name(&name)?),
+            Target::ClearLanguage(()) => UpdateTarget::ClearLanguage,
+            Target::ClearLocation(()) => UpdateTarget::ClearLocation,
+            Target::Timezone(timezone) => dto::Timezone::try_from(timezone)?.into(),
+            Target::ClearTimezone(()) => UpdateTarget::ClearTimezone,
         };
         Ok(target)
     }
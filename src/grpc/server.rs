@@ -1,16 +1,20 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::SystemTime;
 use autometrics::autometrics;
 use derive_more::Constructor;
 use tonic::{Request, Response, Status};
 use crate::grpc::generated::user_service_server::UserService;
-use crate::grpc::generated::{ActivatePremiumRequest, ActivatePremiumResponse, GetUserRequest, PremiumVariant, RegistrationRequest, RegistrationResponse, ServiceType, UpdateUserRequest, User};
+use crate::grpc::generated::{ActivatePremiumRequest, ActivatePremiumResponse, Ban, BanRequest, BatchGetRequest, BatchGetResponse, CreateLinkCodeRequest, DeleteSchemaRequest, DeleteSettingDefaultRequest, DeleteSettingRequest, GetSchemaRequest, GetSettingsRequest, GetUserRequest, IssueTokenRequest, IssueTokenResponse, LinkRequest, LinkResponse, ListBansRequest, ListBansResponse, ListChangesRequest, ListChangesResponse, ListRequest, ListResponse, NearbyRequest, NearbyResponse, PatchUserRequest, PremiumVariant, RegistrationRequest, RegistrationResponse, Schema, SearchRequest, SearchResponse, ServiceType, SetSchemaRequest, SetSettingDefaultRequest, SetSettingRequest, Settings, UnbanRequest, UnlinkRequest, UpdateUserRequest, User};
+use crate::grpc::generated::batch_key::Key;
 use crate::grpc::generated::update_user_request::Target;
-use crate::dto::RegistrationStatus;
+use crate::dto::{LinkOutcome, RegistrationStatus};
 use crate::{dto, repo};
-use crate::repo::users::{UserId, Users};
+use crate::grpc::generated::{self, to_external_id};
+use crate::repo::users::{BatchKey, NearbyQuery, PatchOutcome, PremiumFilter, PremiumOutcome, UpdateTarget, UserFilter, UserId, UserPatch, Users, BATCH_MAX_SIZE, LIST_DEFAULT_LIMIT, LIST_MAX_LIMIT, SEARCH_DEFAULT_LIMIT, SEARCH_MAX_LIMIT, SEARCH_QUERY_MAX_LENGTH};
 use crate::repo::services::Services;
-use crate::grpc::error::{IntoStatusExt, IntoStatusOptionExt};
+use crate::grpc::error::{schema_error_status, IntoStatusExt, IntoStatusOptionExt};
+use crate::tokens::TokenIssuer;
 
 #[derive(Constructor)]
 pub struct GrpcServer<U, S>
@@ -18,7 +22,8 @@ where
     U: Users,
     S: Services,
 {
-    repos: Arc<repo::Repositories<U, S>>
+    repos: Arc<repo::Repositories<U, S>>,
+    issuer: Arc<TokenIssuer>,
 }
 
 #[tonic::async_trait]
@@ -27,51 +32,242 @@ where
     U: Users + Send + Sync + 'static,
     S: Services + Send + Sync + 'static,
 {
-    #[tracing::instrument(skip(self, request), fields(user_id = %request.get_ref().id, by_external_id = %request.get_ref().by_external_id))]
+    #[tracing::instrument(skip(self, request), fields(
+        user_id = %request.get_ref().id,
+        by_external_id = %request.get_ref().by_external_id,
+        external_string_id = %request.get_ref().external_string_id
+    ))]
     #[autometrics]
     async fn get(&self, request: Request<GetUserRequest>) -> Result<Response<User>, Status> {
         let req = request.into_inner();
-        let id = if req.by_external_id {
-            UserId::External(req.id)
+        let id = if req.by_external_id || !req.external_string_id.is_empty() {
+            let service_id = self.registered_service_id(req.service).await?;
+            UserId::External(service_id, to_external_id(req.id, req.external_string_id))
         } else {
             UserId::Internal(req.id)
         };
         let user = self.repos.users.get(id).await
             .into_status()?
-            .map(Into::into)
             .ok_or_not_found("The user is not found")?;
-        Ok(Response::new(user))
+        ensure_active(&user)?;
+        Ok(Response::new(user.into()))
     }
 
-    #[tracing::instrument(skip(self, request), fields(
-        external_id = request.get_ref().user.as_ref().map(|u| u.external_id).unwrap_or(0),
-        service_name = request.get_ref().service.as_ref().map(|s| s.name.as_str()).unwrap_or("")
-    ))]
+    #[tracing::instrument(skip(self, request), fields(keys_count = request.get_ref().keys.len()))]
     #[autometrics]
-    async fn register(&self, request: Request<RegistrationRequest>) -> Result<Response<RegistrationResponse>, Status> {
+    async fn batch_get(&self, request: Request<BatchGetRequest>) -> Result<Response<BatchGetResponse>, Status> {
         let req = request.into_inner();
+        if req.keys.len() > BATCH_MAX_SIZE {
+            tracing::warn!("Too many keys in the batch");
+            return Err(Status::invalid_argument(format!("At most {BATCH_MAX_SIZE} keys are allowed")));
+        }
+
+        // each service is looked up once; keys of unknown services can't match anyone
+        let mut service_ids: HashMap<dto::Service, Option<i32>> = HashMap::new();
+        let mut keys = Vec::with_capacity(req.keys.len());
+        for key in &req.keys {
+            let key = match key.key.as_ref().ok_or_invalid_argument("The 'key' field is not set")? {
+                Key::Id(id) => Some(BatchKey::Internal(*id)),
+                Key::External(external_key) => {
+                    let service = service_from_grpc(external_key.service.clone())?;
+                    let external_id = to_external_id(external_key.external_id, external_key.external_string_id.clone());
+                    let service_id = match service_ids.get(&service) {
+                        Some(service_id) => *service_id,
+                        None => {
+                            let service_id = self.repos.services.get_id(&service).await
+                                .into_status()?;
+                            service_ids.insert(service, service_id);
+                            service_id
+                        }
+                    };
+                    service_id.map(|service_id| BatchKey::External(service_id, external_id))
+                }
+            };
+            keys.push(key);
+        }
+        let known_keys: Vec<BatchKey> = keys.iter().flatten().cloned().collect();
+        let found = self.repos.users.get_many(&known_keys).await
+            .into_status()?;
+
+        let mut response = BatchGetResponse::default();
+        for (grpc_key, key) in req.keys.into_iter().zip(keys) {
+            match key.and_then(|key| found.get(&key)) {
+                Some(user) => response.users.push(generated::FoundUser {
+                    key: Some(grpc_key),
+                    user: Some(user.clone().into()),
+                }),
+                None => response.missing.push(grpc_key),
+            }
+        }
+        tracing::info!(found = response.users.len(), missing = response.missing.len(), "Batch lookup finished");
+        Ok(Response::new(response))
+    }
 
-        let (service_name, service_type_id) = req.service
-            .map(|s| (s.name, s.kind))
-            .ok_or_invalid_argument("The 'service' field is not set")?;
-        let grpc_service_type: ServiceType = service_type_id.try_into()
+    #[tracing::instrument(skip(self, request), fields(cursor = %request.get_ref().cursor, limit = %request.get_ref().limit))]
+    #[autometrics]
+    async fn list(&self, request: Request<ListRequest>) -> Result<Response<ListResponse>, Status> {
+        let req = request.into_inner();
+        let limit = validate_limit(req.limit, LIST_DEFAULT_LIMIT, LIST_MAX_LIMIT)?;
+        let after = Some(req.cursor.as_str())
+            .filter(|cursor| !cursor.is_empty())
+            .map(dto::Cursor::decode)
+            .transpose()
             .into_invalid_argument()?;
-        let service_type: dto::ServiceType = grpc_service_type.try_into()
+        let language_code = Some(req.language_code)
+            .filter(|code| !code.is_empty())
+            .map(dto::LanguageTag::try_from)
+            .transpose()
             .into_invalid_argument()?;
-        let service = (service_name.clone(), service_type).into();
+        let premium = match generated::PremiumFilter::try_from(req.premium).into_invalid_argument()? {
+            generated::PremiumFilter::Unspecified => None,
+            generated::PremiumFilter::Active => Some(PremiumFilter::Active),
+            generated::PremiumFilter::Expired => Some(PremiumFilter::Expired),
+        };
+        let registered_after = req.registered_after
+            .map(SystemTime::try_from)
+            .transpose()
+            .into_invalid_argument()?
+            .map(Into::into);
 
-        let maybe_service_id = self.repos.services.get_id(&service).await
+        let service_id = match req.service {
+            None => None,
+            Some(service) => {
+                let service = service_from_grpc(Some(service))?;
+                let service_id = self.repos.services.get_id(&service).await
+                    .into_status()?;
+                if service_id.is_none() {
+                    tracing::debug!("Unknown service, nobody to list");
+                    return Ok(Response::new(ListResponse::default()));
+                }
+                service_id
+            }
+        };
+
+        let filter = UserFilter {
+            service_id,
+            premium,
+            language_code,
+            registered_after,
+            has_location: req.has_location,
+        };
+        let page = self.repos.users.list(&filter, after, limit).await
             .into_status()?;
-        let service_id = match maybe_service_id {
-            None => self.repos.services.create(service_type, &service_name).await
-                .into_status()?,
-            Some(id) => id
+        Ok(Response::new(ListResponse {
+            users: page.items.into_iter().map(Into::into).collect(),
+            next_cursor: page.next.map(|cursor| cursor.encode()).unwrap_or_default(),
+        }))
+    }
+
+    #[tracing::instrument(skip(self, request), fields(query = %request.get_ref().query, limit = %request.get_ref().limit))]
+    #[autometrics]
+    async fn search(&self, request: Request<SearchRequest>) -> Result<Response<SearchResponse>, Status> {
+        let req = request.into_inner();
+        let limit = validate_limit(req.limit, SEARCH_DEFAULT_LIMIT, SEARCH_MAX_LIMIT)?;
+        let query = req.query.trim();
+        if query.is_empty() || query.chars().count() > SEARCH_QUERY_MAX_LENGTH {
+            tracing::warn!("Invalid search query");
+            return Err(Status::invalid_argument(format!("The query must be a non-empty string of at most {SEARCH_QUERY_MAX_LENGTH} characters")));
+        }
+
+        let service_id = match req.service {
+            None => None,
+            Some(service) => {
+                let service = service_from_grpc(Some(service))?;
+                let service_id = self.repos.services.get_id(&service).await
+                    .into_status()?;
+                if service_id.is_none() {
+                    tracing::debug!("Unknown service, nobody to find");
+                    return Ok(Response::new(SearchResponse::default()));
+                }
+                service_id
+            }
         };
+        let users = self.repos.users.search(query, service_id, limit).await
+            .into_status()?;
+        Ok(Response::new(SearchResponse {
+            users: users.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    #[tracing::instrument(skip(self, request), fields(radius_km = %request.get_ref().radius_km, cursor = %request.get_ref().cursor, limit = %request.get_ref().limit))]
+    #[autometrics]
+    async fn nearby(&self, request: Request<NearbyRequest>) -> Result<Response<NearbyResponse>, Status> {
+        let req = request.into_inner();
+        let limit = validate_limit(req.limit, LIST_DEFAULT_LIMIT, LIST_MAX_LIMIT)?;
+        let after = Some(req.cursor.as_str())
+            .filter(|cursor| !cursor.is_empty())
+            .map(dto::Cursor::decode)
+            .transpose()
+            .into_invalid_argument()?;
+        let center = location_from_grpc(req.center)?
+            .ok_or_invalid_argument("The center is required")?;
+        dto::validate_radius(req.radius_km)
+            .into_invalid_argument()?;
+
+        let service_id = match req.service {
+            None => None,
+            Some(service) => {
+                let service = service_from_grpc(Some(service))?;
+                let service_id = self.repos.services.get_id(&service).await
+                    .into_status()?;
+                if service_id.is_none() {
+                    tracing::debug!("Unknown service, nobody to find");
+                    return Ok(Response::new(NearbyResponse::default()));
+                }
+                service_id
+            }
+        };
+
+        let query = NearbyQuery { center, radius_km: req.radius_km, service_id };
+        let page = self.repos.users.nearby(&query, after, limit).await
+            .into_status()?;
+        Ok(Response::new(NearbyResponse {
+            users: page.items.into_iter().map(Into::into).collect(),
+            next_cursor: page.next.map(|cursor| cursor.encode()).unwrap_or_default(),
+        }))
+    }
 
-        let external_user: dto::ExternalUser = req.user
-            .map(|ext_usr| ext_usr.into())
-            .ok_or_invalid_argument("The 'user' field is not set")?;
-        let maybe_user_id = self.repos.users.get_user_id(service_id, external_user.external_id).await
+    #[tracing::instrument(skip(self, request), fields(user_id = %request.get_ref().id, cursor = %request.get_ref().cursor, limit = %request.get_ref().limit))]
+    #[autometrics]
+    async fn list_changes(&self, request: Request<ListChangesRequest>) -> Result<Response<ListChangesResponse>, Status> {
+        let req = request.into_inner();
+        let limit = validate_limit(req.limit, LIST_DEFAULT_LIMIT, LIST_MAX_LIMIT)?;
+        let after = Some(req.cursor.as_str())
+            .filter(|cursor| !cursor.is_empty())
+            .map(dto::Cursor::decode)
+            .transpose()
+            .into_invalid_argument()?;
+        // merged users are redirected, so their history is the one of the target
+        let user = self.repos.users.get(UserId::Internal(req.id)).await
+            .into_status()?
+            .ok_or_not_found("The user is not found")?;
+        let page = self.repos.users.changes(user.id, after, limit).await
+            .into_status()?;
+        let changes = page.items.into_iter()
+            .map(TryInto::try_into)
+            .collect::<Result<Vec<_>, _>>()
+            .into_status()?;
+        Ok(Response::new(ListChangesResponse {
+            changes,
+            next_cursor: page.next.map(|cursor| cursor.encode()).unwrap_or_default(),
+        }))
+    }
+
+    #[tracing::instrument(skip(self, request), fields(
+        external_id = request.get_ref().user.as_ref().map(|u| u.external_id).unwrap_or(0),
+        service_name = request.get_ref().service.as_ref().map(|s| s.name.as_str()).unwrap_or("")
+    ))]
+    #[autometrics]
+    async fn register(&self, request: Request<RegistrationRequest>) -> Result<Response<RegistrationResponse>, Status> {
+        let req = request.into_inner();
+        let service = service_from_grpc(req.service)?;
+        let service_type = self.get_service_type(&service.service_type).await?;
+        let external_user = external_user_from_grpc(req.user, &service_type)?
+            .normalized()
+            .into_invalid_argument()?;
+        let service_id = self.get_or_create_service(&service).await?;
+
+        let maybe_user_id = self.repos.users.get_user_id(service_id, &external_user.external_id).await
             .into_status()?;
 
         let resp = match maybe_user_id {
@@ -79,6 +275,7 @@ where
                 let consent_info = req.consent_info
                     .and_then(|info| serde_json::to_value(info).ok())
                     .ok_or_invalid_argument("The 'consent_info' field is not set or invalid")?;
+                self.check_schema(service_id, dto::SchemaKind::Consent, &consent_info).await?;
                 let id = self.repos.users.register(external_user, service_id, consent_info).await
                     .into_status()?;
                 tracing::info!(user_id = %id, "User registered successfully");
@@ -86,6 +283,11 @@ where
             }
             Some(id) => {
                 tracing::info!(user_id = %id, "User already registered");
+                if let Some(name) = external_user.name.filter(|_| req.refresh_name) {
+                    self.repos.users.update_value(id, UpdateTarget::Name(name), Some(service_id)).await
+                        .into_status()?;
+                    tracing::info!(user_id = %id, "Name refreshed");
+                }
                 RegistrationStatus::AlreadyPresent.with_id(id)
             }
         };
@@ -103,14 +305,31 @@ where
                 .validate()
                 .into_invalid_argument()?;
         }
-        let repo_target = grpc_target.try_into()
+        let repo_target: UpdateTarget = grpc_target.try_into()
             .into_invalid_argument()?;
-        self.repos.users.update_value(req.id, repo_target).await
-            .into_status()?;
+        let actor_service_id = self.optional_service_id(req.actor).await?;
+        self.patch_user(req.id, repo_target.into(), req.expected_version, actor_service_id).await?;
         tracing::info!("User updated successfully");
         Ok(Response::new(()))
     }
 
+    #[tracing::instrument(skip(self, request), fields(
+        user_id = %request.get_ref().id,
+        update_mask = ?request.get_ref().update_mask.as_ref().map(|mask| &mask.paths)
+    ))]
+    #[autometrics]
+    async fn patch(&self, request: Request<PatchUserRequest>) -> Result<Response<User>, Status> {
+        let req = request.into_inner();
+        let update_mask = req.update_mask
+            .filter(|mask| !mask.paths.is_empty())
+            .ok_or_invalid_argument("The 'update_mask' field is not set or empty")?;
+        let patch = patch_from_grpc(req.user.unwrap_or_default(), &update_mask.paths)?;
+        let actor_service_id = self.optional_service_id(req.actor).await?;
+        let user = self.patch_user(req.id, patch, req.expected_version, actor_service_id).await?;
+        tracing::info!("User patched successfully");
+        Ok(Response::new(user.into()))
+    }
+
     #[tracing::instrument(skip(self, request), fields(user_id = %request.get_ref().id, variant = %request.get_ref().variant))]
     #[autometrics]
     async fn activate_premium(&self, request: Request<ActivatePremiumRequest>) -> Result<Response<ActivatePremiumResponse>, Status> {
@@ -119,8 +338,13 @@ where
             .into_invalid_argument()?;
         let variant = grpc_variant.try_into()
             .into_invalid_argument()?;
-        let updated = self.repos.users.activate_premium(req.id, variant).await
-            .into_status()?;
+        let actor_service_id = self.optional_service_id(req.actor).await?;
+        let updated = match self.repos.users.activate_premium(req.id, variant, actor_service_id).await
+            .into_status()? {
+            PremiumOutcome::Activated(till) => Some(till),
+            PremiumOutcome::NotFound => None,
+            PremiumOutcome::Deactivated => return Err(Status::failed_precondition("The user is deactivated")),
+        };
 
         let response = updated
             .inspect(|till| tracing::info!(active_till = %till, "Premium activated successfully"))
@@ -137,4 +361,454 @@ where
             });
         Ok(Response::new(response))
     }
+
+    #[tracing::instrument(skip(self, request), fields(user_id = %request.get_ref().id))]
+    #[autometrics]
+    async fn issue_token(&self, request: Request<IssueTokenRequest>) -> Result<Response<IssueTokenResponse>, Status> {
+        let id = request.into_inner().id;
+        let user = self.repos.users.get(UserId::Internal(id)).await
+            .into_status()?
+            .ok_or_not_found("The user is not found")?;
+        ensure_active(&user)?;
+        // merged users are redirected, so the token is issued for the target
+        let services = self.repos.users.get_services(user.id).await
+            .into_status()?;
+        let issued = self.issuer.issue(&user, services)
+            .into_status()?;
+        Ok(Response::new(IssueTokenResponse {
+            token: issued.token,
+            expires_at: Some(SystemTime::from(issued.expires_at).into()),
+        }))
+    }
+
+    #[tracing::instrument(skip(self, request), fields(user_id = %request.get_ref().id))]
+    #[autometrics]
+    async fn create_link_code(&self, request: Request<CreateLinkCodeRequest>) -> Result<Response<generated::LinkCode>, Status> {
+        let id = request.into_inner().id;
+        let link_code = self.repos.users.create_link_code(id).await
+            .into_status()?
+            .ok_or_not_found("The user is not found")?;
+        tracing::info!(expires_at = %link_code.expires_at, "Link code created");
+        Ok(Response::new(generated::LinkCode {
+            code: link_code.code,
+            expires_at: Some(SystemTime::from(link_code.expires_at).into()),
+        }))
+    }
+
+    #[tracing::instrument(skip(self, request), fields(
+        external_id = request.get_ref().user.as_ref().map(|u| u.external_id).unwrap_or(0),
+        service_name = request.get_ref().service.as_ref().map(|s| s.name.as_str()).unwrap_or("")
+    ))]
+    #[autometrics]
+    async fn link(&self, request: Request<LinkRequest>) -> Result<Response<LinkResponse>, Status> {
+        let req = request.into_inner();
+        let service = service_from_grpc(req.service)?;
+        let service_type = self.get_service_type(&service.service_type).await?;
+        let external_user = external_user_from_grpc(req.user, &service_type)?;
+        let service_id = self.get_or_create_service(&service).await?;
+        let consent_info = req.consent_info
+            .and_then(|info| serde_json::to_value(info).ok())
+            .ok_or_invalid_argument("The 'consent_info' field is not set or invalid")?;
+        self.check_schema(service_id, dto::SchemaKind::Consent, &consent_info).await?;
+
+        let outcome = self.repos.users.redeem_link_code(&req.code, external_user, service_id, consent_info).await
+            .into_status()?;
+        match outcome {
+            LinkOutcome::Linked(id) => {
+                tracing::info!(user_id = %id, "Account linked successfully");
+                Ok(Response::new(LinkResponse { id }))
+            }
+            LinkOutcome::InvalidCode => {
+                tracing::warn!("The code is invalid or expired");
+                Err(Status::not_found("The code is invalid or expired"))
+            }
+            LinkOutcome::MappedToAnotherUser(id) => {
+                tracing::warn!(mapped_user_id = %id, "The account is already registered as another user");
+                Err(Status::already_exists("The account is already registered as another user"))
+            }
+        }
+    }
+
+    #[tracing::instrument(skip(self, request), fields(
+        external_id = %request.get_ref().external_id,
+        external_string_id = %request.get_ref().external_string_id,
+        service_name = request.get_ref().service.as_ref().map(|s| s.name.as_str()).unwrap_or(""),
+        withdraw_consent = %request.get_ref().withdraw_consent
+    ))]
+    #[autometrics]
+    async fn unlink(&self, request: Request<UnlinkRequest>) -> Result<Response<()>, Status> {
+        let req = request.into_inner();
+        let service = service_from_grpc(req.service)?;
+        let service_id = self.repos.services.get_id(&service).await
+            .into_status()?
+            .ok_or_not_found("The service is not found")?;
+        let external_id = to_external_id(req.external_id, req.external_string_id);
+        let user_id = self.repos.users.unlink(service_id, &external_id, req.withdraw_consent).await
+            .into_status()?
+            .ok_or_not_found("The account is not found")?;
+        tracing::info!(user_id = %user_id, "Account unlinked successfully");
+        Ok(Response::new(()))
+    }
+
+    #[tracing::instrument(skip(self, request), fields(user_id = %request.get_ref().id))]
+    #[autometrics]
+    async fn list_bans(&self, request: Request<ListBansRequest>) -> Result<Response<ListBansResponse>, Status> {
+        let user = self.repos.users.get(UserId::Internal(request.into_inner().id)).await
+            .into_status()?
+            .ok_or_not_found("The user is not found")?;
+        Ok(Response::new(ListBansResponse {
+            bans: user.bans.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    #[tracing::instrument(skip(self, request), fields(
+        user_id = %request.get_ref().id,
+        service_name = request.get_ref().service.as_ref().map(|s| s.name.as_str()).unwrap_or(""),
+        issuer = %request.get_ref().issuer
+    ))]
+    #[autometrics]
+    async fn ban(&self, request: Request<BanRequest>) -> Result<Response<Ban>, Status> {
+        let req = request.into_inner();
+        let expires_at = req.expires_at
+            .map(SystemTime::try_from)
+            .transpose()
+            .into_invalid_argument()?
+            .map(Into::into);
+        let ban = dto::NewBan {
+            reason: req.reason,
+            issuer: req.issuer,
+            expires_at,
+        }.validated().into_invalid_argument()?;
+        let service_id = self.optional_service_id(req.service).await?;
+        let ban = self.repos.users.ban(req.id, service_id, ban).await
+            .into_status()?
+            .ok_or_not_found("The user is not found")?;
+        tracing::info!(?service_id, "User banned");
+        Ok(Response::new(ban.into()))
+    }
+
+    #[tracing::instrument(skip(self, request), fields(
+        user_id = %request.get_ref().id,
+        service_name = request.get_ref().service.as_ref().map(|s| s.name.as_str()).unwrap_or("")
+    ))]
+    #[autometrics]
+    async fn unban(&self, request: Request<UnbanRequest>) -> Result<Response<()>, Status> {
+        let req = request.into_inner();
+        let service_id = self.optional_service_id(req.service).await?;
+        self.repos.users.unban(req.id, service_id).await
+            .into_status()?
+            .then_some(())
+            .ok_or_not_found("The ban is not found")?;
+        tracing::info!(?service_id, "User unbanned");
+        Ok(Response::new(()))
+    }
+
+    #[tracing::instrument(skip(self, request), fields(
+        user_id = %request.get_ref().id,
+        service_name = request.get_ref().service.as_ref().map(|s| s.name.as_str()).unwrap_or("")
+    ))]
+    #[autometrics]
+    async fn get_settings(&self, request: Request<GetSettingsRequest>) -> Result<Response<Settings>, Status> {
+        let req = request.into_inner();
+        let service_id = self.registered_service_id(req.service).await?;
+        let user = self.repos.users.get(UserId::Internal(req.id)).await
+            .into_status()?
+            .ok_or_not_found("The user is not found")?;
+        let settings = self.repos.users.settings(user.id, service_id).await
+            .into_status()?;
+        Ok(Response::new(Settings {
+            values: generated::settings_to_grpc(settings).into_status()?,
+        }))
+    }
+
+    #[tracing::instrument(skip(self, request), fields(
+        user_id = %request.get_ref().id,
+        service_name = request.get_ref().service.as_ref().map(|s| s.name.as_str()).unwrap_or(""),
+        key = %request.get_ref().key
+    ))]
+    #[autometrics]
+    async fn set_setting(&self, request: Request<SetSettingRequest>) -> Result<Response<()>, Status> {
+        let req = request.into_inner();
+        let value = setting_from_grpc(&req.key, req.value)?;
+        let service_id = self.registered_service_id(req.service).await?;
+        let mut settings = self.repos.users.settings(req.id, service_id).await
+            .into_status()?;
+        settings.insert(req.key.clone(), value.clone());
+        let settings = serde_json::Value::Object(settings.into_iter().collect());
+        self.check_schema(service_id, dto::SchemaKind::Settings, &settings).await?;
+        self.repos.users.set_setting(req.id, service_id, &req.key, value).await
+            .into_status()?
+            .then_some(())
+            .ok_or_not_found("The user is not found")?;
+        Ok(Response::new(()))
+    }
+
+    #[tracing::instrument(skip(self, request), fields(
+        user_id = %request.get_ref().id,
+        service_name = request.get_ref().service.as_ref().map(|s| s.name.as_str()).unwrap_or(""),
+        key = %request.get_ref().key
+    ))]
+    #[autometrics]
+    async fn delete_setting(&self, request: Request<DeleteSettingRequest>) -> Result<Response<()>, Status> {
+        let req = request.into_inner();
+        let service_id = self.registered_service_id(req.service).await?;
+        self.repos.users.delete_setting(req.id, service_id, &req.key).await
+            .into_status()?
+            .then_some(())
+            .ok_or_not_found("The setting is not found")?;
+        Ok(Response::new(()))
+    }
+
+    #[tracing::instrument(skip(self, request), fields(
+        service_name = request.get_ref().service.as_ref().map(|s| s.name.as_str()).unwrap_or(""),
+        key = %request.get_ref().key
+    ))]
+    #[autometrics]
+    async fn set_setting_default(&self, request: Request<SetSettingDefaultRequest>) -> Result<Response<()>, Status> {
+        let req = request.into_inner();
+        let value = setting_from_grpc(&req.key, req.value)?;
+        let service_id = self.registered_service_id(req.service).await?;
+        self.repos.services.set_setting_default(service_id, &req.key, value).await
+            .into_status()?;
+        Ok(Response::new(()))
+    }
+
+    #[tracing::instrument(skip(self, request), fields(
+        service_name = request.get_ref().service.as_ref().map(|s| s.name.as_str()).unwrap_or(""),
+        key = %request.get_ref().key
+    ))]
+    #[autometrics]
+    async fn delete_setting_default(&self, request: Request<DeleteSettingDefaultRequest>) -> Result<Response<()>, Status> {
+        let req = request.into_inner();
+        let service_id = self.registered_service_id(req.service).await?;
+        self.repos.services.delete_setting_default(service_id, &req.key).await
+            .into_status()?
+            .then_some(())
+            .ok_or_not_found("The default setting is not found")?;
+        Ok(Response::new(()))
+    }
+
+    #[tracing::instrument(skip(self, request), fields(
+        service_name = request.get_ref().service.as_ref().map(|s| s.name.as_str()).unwrap_or(""),
+        kind = %request.get_ref().kind
+    ))]
+    #[autometrics]
+    async fn get_schema(&self, request: Request<GetSchemaRequest>) -> Result<Response<Schema>, Status> {
+        let req = request.into_inner();
+        let kind = dto::SchemaKind::try_from(req.kind.as_str())
+            .into_invalid_argument()?;
+        let service_id = self.registered_service_id(req.service).await?;
+        let schema = self.repos.services.schema(service_id, kind).await
+            .into_status()?
+            .ok_or_not_found("The schema is not found")?;
+        Ok(Response::new(Schema {
+            schema: Some(serde_json::from_value(schema).into_status()?),
+        }))
+    }
+
+    #[tracing::instrument(skip(self, request), fields(
+        service_name = request.get_ref().service.as_ref().map(|s| s.name.as_str()).unwrap_or(""),
+        kind = %request.get_ref().kind
+    ))]
+    #[autometrics]
+    async fn set_schema(&self, request: Request<SetSchemaRequest>) -> Result<Response<()>, Status> {
+        let req = request.into_inner();
+        let kind = dto::SchemaKind::try_from(req.kind.as_str())
+            .into_invalid_argument()?;
+        let schema = req.schema
+            .and_then(|schema| serde_json::to_value(schema).ok())
+            .ok_or_invalid_argument("The 'schema' field is not set or invalid")?;
+        dto::validate_schema(&schema)
+            .map_err(schema_error_status)?;
+        let service_id = self.registered_service_id(req.service).await?;
+        self.repos.services.set_schema(service_id, kind, schema).await
+            .into_status()?;
+        Ok(Response::new(()))
+    }
+
+    #[tracing::instrument(skip(self, request), fields(
+        service_name = request.get_ref().service.as_ref().map(|s| s.name.as_str()).unwrap_or(""),
+        kind = %request.get_ref().kind
+    ))]
+    #[autometrics]
+    async fn delete_schema(&self, request: Request<DeleteSchemaRequest>) -> Result<Response<()>, Status> {
+        let req = request.into_inner();
+        let kind = dto::SchemaKind::try_from(req.kind.as_str())
+            .into_invalid_argument()?;
+        let service_id = self.registered_service_id(req.service).await?;
+        self.repos.services.delete_schema(service_id, kind).await
+            .into_status()?
+            .then_some(())
+            .ok_or_not_found("The schema is not found")?;
+        Ok(Response::new(()))
+    }
+}
+
+impl<U, S> GrpcServer<U, S>
+where
+    U: Users,
+    S: Services,
+{
+    async fn get_or_create_service(&self, service: &dto::Service) -> Result<i32, Status> {
+        let maybe_service_id = self.repos.services.get_id(service).await
+            .into_status()?;
+        let service_id = match maybe_service_id {
+            None => self.repos.services.create(service.service_type.clone(), &service.name).await
+                .into_status()?,
+            Some(id) => id
+        };
+        Ok(service_id)
+    }
+
+    async fn get_service_type(&self, service_type: &dto::ServiceType) -> Result<dto::ServiceTypeInfo, Status> {
+        self.repos.services.get_type(service_type).await
+            .into_status()?
+            .ok_or_invalid_argument(&format!("Unknown service type: {service_type}"))
+    }
+
+    /// Unlike [Self::get_or_create_service], doesn't register unknown services
+    async fn registered_service_id(&self, service: Option<generated::Service>) -> Result<i32, Status> {
+        let service = service_from_grpc(service)?;
+        self.repos.services.get_id(&service).await
+            .into_status()?
+            .ok_or_not_found("The service is not found")
+    }
+
+    /// Passes if the service hasn't registered a schema of the kind
+    async fn check_schema(&self, service_id: i32, kind: dto::SchemaKind, document: &serde_json::Value) -> Result<(), Status> {
+        let Some(schema) = self.repos.services.schema(service_id, kind).await.into_status()? else {
+            return Ok(());
+        };
+        dto::validate_document(&schema, document)
+            .map_err(schema_error_status)
+    }
+
+    /// `None` if the service is not set: the ban is global, the change is made by nobody in particular
+    async fn optional_service_id(&self, service: Option<generated::Service>) -> Result<Option<i32>, Status> {
+        match service {
+            None => Ok(None),
+            Some(service) => self.registered_service_id(Some(service)).await.map(Some),
+        }
+    }
+
+    /// The patch is applied only if the user still has the expected version (when set)
+    async fn patch_user(&self, id: i64, patch: UserPatch, expected_version: Option<i64>, actor_service_id: Option<i32>) -> Result<dto::SavedUser, Status> {
+        match self.repos.users.patch(id, patch, expected_version, actor_service_id).await.into_status()? {
+            PatchOutcome::Patched(user) => Ok(*user),
+            PatchOutcome::NotFound => None.ok_or_not_found("The user is not found"),
+            PatchOutcome::VersionMismatch(current_version) => {
+                tracing::warn!(?expected_version, current_version, "The user has been modified concurrently");
+                Err(Status::aborted(format!("The user has been modified, the current version is {current_version}")))
+            }
+        }
+    }
+}
+
+fn ensure_active(user: &dto::SavedUser) -> Result<(), Status> {
+    if user.deactivated() {
+        tracing::warn!(user_id = user.id, "The user is deactivated");
+        return Err(Status::failed_precondition("The user is deactivated"));
+    }
+    Ok(())
+}
+
+/// Types missing from the `ServiceType` enum of the protocol are passed by name in the `kind_name` field
+fn service_from_grpc(service: Option<generated::Service>) -> Result<dto::Service, Status> {
+    let service = service
+        .ok_or_invalid_argument("The 'service' field is not set")?;
+    let service_type: dto::ServiceType = if service.kind_name.is_empty() {
+        let grpc_service_type: ServiceType = service.kind.try_into()
+            .into_invalid_argument()?;
+        grpc_service_type.try_into()
+            .into_invalid_argument()?
+    } else {
+        service.kind_name.try_into()
+            .into_invalid_argument()?
+    };
+    Ok((service.name, service_type).into())
+}
+
+fn setting_from_grpc(key: &str, value: Option<prost_wkt_types::Value>) -> Result<serde_json::Value, Status> {
+    dto::validate_setting_key(key)
+        .into_invalid_argument()?;
+    let value = value
+        .and_then(|value| serde_json::to_value(value).ok())
+        .ok_or_invalid_argument("The 'value' field is not set or invalid")?;
+    dto::validate_setting_value(&value)
+        .into_invalid_argument()?;
+    Ok(value)
+}
+
+/// Fields listed in the mask but absent in the user are cleared
+fn patch_from_grpc(user: User, paths: &[String]) -> Result<UserPatch, Status> {
+    let options = user.options.unwrap_or_default();
+    let mut patch = UserPatch::default();
+    for path in paths {
+        match path.as_str() {
+            "name" => patch.name = Some(name_from_grpc(user.name.as_deref())?),
+            "options.language_code" => patch.language_code = Some(language_code_from_grpc(options.language_code.clone())?),
+            "options.location" => patch.location = Some(location_from_grpc(options.location)?),
+            "options.timezone" => patch.timezone = Some(timezone_from_grpc(options.timezone.clone())?),
+            "options" => {
+                patch.language_code = Some(language_code_from_grpc(options.language_code.clone())?);
+                patch.location = Some(location_from_grpc(options.location)?);
+                patch.timezone = Some(timezone_from_grpc(options.timezone.clone())?);
+            }
+            unknown => {
+                tracing::warn!(path = %unknown, "Unknown path in the update mask");
+                return Err(Status::invalid_argument(format!("Unknown path in the update mask: {unknown}")));
+            }
+        }
+    }
+    Ok(patch)
+}
+
+fn name_from_grpc(name: Option<&str>) -> Result<Option<String>, Status> {
+    name.map(dto::normalize_name)
+        .transpose()
+        .into_invalid_argument()
+}
+
+fn language_code_from_grpc(code: Option<String>) -> Result<Option<dto::LanguageTag>, Status> {
+    code.filter(|code| !code.is_empty())
+        .map(dto::LanguageTag::try_from)
+        .transpose()
+        .into_invalid_argument()
+}
+
+fn timezone_from_grpc(timezone: Option<String>) -> Result<Option<dto::Timezone>, Status> {
+    timezone.filter(|timezone| !timezone.is_empty())
+        .map(dto::Timezone::try_from)
+        .transpose()
+        .into_invalid_argument()
+}
+
+fn location_from_grpc(location: Option<generated::Location>) -> Result<Option<dto::Location>, Status> {
+    let location = location.map(|loc| dto::Location { latitude: loc.latitude, longitude: loc.longitude });
+    if let Some(ref location) = location {
+        location.validate()
+            .into_invalid_argument()?;
+    }
+    Ok(location)
+}
+
+/// Zero stands for the default limit since proto3 scalars are never absent; REST treats it the same way
+fn validate_limit(limit: u32, default: u32, max: u32) -> Result<u32, Status> {
+    match limit {
+        0 => Ok(default),
+        limit if limit > max => {
+            tracing::warn!(limit, "Invalid limit");
+            Err(Status::invalid_argument(format!("The limit must not exceed {max}")))
+        }
+        limit => Ok(limit),
+    }
+}
+
+fn external_user_from_grpc(user: Option<generated::ExternalUser>, service_type: &dto::ServiceTypeInfo) -> Result<dto::ExternalUser, Status> {
+    let external_user: dto::ExternalUser = user
+        .map(|ext_usr| ext_usr.into())
+        .ok_or_invalid_argument("The 'user' field is not set")?;
+    external_user.external_id.validate_for(service_type)
+        .into_invalid_argument()?;
+    Ok(external_user)
 }
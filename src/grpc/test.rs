@@ -8,8 +8,11 @@ use tracing::subscriber::set_default;
 use serde_json::json;
 use tokio::net::TcpListener;
 use tonic::Code;
+use tonic_types::StatusExt;
 use tonic::transport::{Channel, Server};
-use crate::grpc::generated::{ActivatePremiumRequest, ExternalUser, GetUserRequest, Location, PremiumVariant, RegistrationRequest, RegistrationStatus, Service, ServiceType, UpdateUserRequest};
+use crate::grpc::generated::{ActivatePremiumRequest, BanRequest, BatchGetRequest, BatchKey, CreateLinkCodeRequest, DeleteSchemaRequest, DeleteSettingDefaultRequest, DeleteSettingRequest, ExternalKey, ExternalUser, GetSchemaRequest, GetSettingsRequest, GetUserRequest, IssueTokenRequest, LinkRequest, ListBansRequest, ListChangesRequest, ListRequest, Location, NearbyRequest, PatchUserRequest, PremiumVariant, RegistrationRequest, RegistrationStatus, SearchRequest, Service, ServiceType, SetSchemaRequest, SetSettingDefaultRequest, SetSettingRequest, UnbanRequest, UnlinkRequest, UpdateUserRequest, User};
+use crate::grpc::generated::batch_key::Key;
+use crate::grpc::generated::user::Options;
 use crate::grpc::generated::update_user_request::Target;
 use crate::grpc::generated::user_service_client::UserServiceClient;
 use crate::grpc::generated::user_service_server::{UserService, UserServiceServer};
@@ -19,6 +22,9 @@ use crate::repo::test::mocks::mock_repositories;
 use crate::repo::test::otel::setup_otel_test;
 use crate::repo::users::Users;
 use crate::repo::services::Services;
+use crate::tokens::{TokenConfig, TokenIssuer, TEST_SECRET};
+
+const WEBSITE_USER_ID: &str = "0b5a2f6e-6a4c-4d8e-9a59-6f4e0c1d2b3a";
 
 #[tokio::test]
 async fn test_all() -> anyhow::Result<()> {
@@ -26,13 +32,20 @@ async fn test_all() -> anyhow::Result<()> {
     let mut client = UserServiceClient::connect(format!("http://{}", addr)).await?;
 
     let (ext_id, username, service_name) = (12345, "SadBot".to_owned(), "SadFavBot".to_owned());
+    let service = Service {
+        name: service_name,
+        kind: ServiceType::TelegramBot.into(),
+        kind_name: String::new(),
+    };
     let get_req_by_internal_id = GetUserRequest {
         id: 1,
-        by_external_id: false,
+        ..GetUserRequest::default()
     };
     let get_req_by_external_id = GetUserRequest {
         id: ext_id,
         by_external_id: true,
+        external_string_id: String::new(),
+        service: Some(service.clone()),
     };
     test_get_not_found(&mut client, get_req_by_internal_id.clone()).await;
     test_get_not_found(&mut client, get_req_by_external_id.clone()).await;
@@ -41,12 +54,11 @@ async fn test_all() -> anyhow::Result<()> {
         user: Some(ExternalUser {
             external_id: ext_id,
             name: Some(username.clone()),
+            external_string_id: String::new(),
         }),
-        service: Some(Service {
-            name: service_name.clone(),
-            kind: ServiceType::TelegramBot.into(),
-        }),
+        service: Some(service),
         consent_info: Some(serde_json::from_value(json!({"test": true}))?),
+        refresh_name: false,
     };
     test_registration(&mut client, registration_req.clone(), RegistrationStatus::Created).await?;
     test_registration(&mut client, registration_req, RegistrationStatus::AlreadyPresent).await?;
@@ -61,10 +73,14 @@ async fn test_all() -> anyhow::Result<()> {
     client.update(UpdateUserRequest {
         id: 1,
         target: Some(Target::Language(lang.clone())),
+        expected_version: None,
+        actor: None,
     }).await?;
     client.update(UpdateUserRequest {
         id: 1,
         target: Some(Target::Location(Location { latitude, longitude })),
+        expected_version: None,
+        actor: None,
     }).await?;
 
     let month_later = Utc::now().with_nanosecond(0).unwrap()
@@ -72,6 +88,7 @@ async fn test_all() -> anyhow::Result<()> {
     let resp = client.activate_premium(ActivatePremiumRequest {
         id: 1,
         variant: PremiumVariant::Month as i32,
+        actor: None,
     }).await?.into_inner();
     let till: SystemTime = resp.active_till
         .ok_or(anyhow!("active_till must be present in the response"))?
@@ -80,7 +97,7 @@ async fn test_all() -> anyhow::Result<()> {
     let till = till.with_nanosecond(0).unwrap();
     assert_eq!(till, month_later);
 
-    let user = client.get(get_req_by_internal_id).await?.into_inner();
+    let user = client.get(get_req_by_internal_id.clone()).await?.into_inner();
     assert_eq!(user.id, 1);
     assert_eq!(user.name, Some(username));
     assert!(user.is_premium);
@@ -88,6 +105,25 @@ async fn test_all() -> anyhow::Result<()> {
     assert_eq!(opts.language_code, Some(lang));
     assert_eq!(opts.location, Some(Location { latitude, longitude }));
 
+    client.update(UpdateUserRequest {
+        id: 1,
+        target: Some(Target::ClearLocation(())),
+        expected_version: None,
+        actor: None,
+    }).await?;
+    let user = client.get(get_req_by_internal_id.clone()).await?.into_inner();
+    let opts = user.options.unwrap();
+    assert!(opts.language_code.is_some());
+    assert_eq!(opts.location, None);
+    client.update(UpdateUserRequest {
+        id: 1,
+        target: Some(Target::ClearLanguage(())),
+        expected_version: None,
+        actor: None,
+    }).await?;
+    let user = client.get(get_req_by_internal_id).await?.into_inner();
+    assert_eq!(user.options.unwrap().language_code, None);
+
     Ok(())
 }
 
@@ -99,19 +135,833 @@ async fn invalid_arguments() -> anyhow::Result<()> {
     let resp = client.update(UpdateUserRequest {
         id: 1,
         target: None,
+        expected_version: None,
+        actor: None,
     }).await;
     assert_eq!(resp.err().map(|status| status.code()), Some(Code::InvalidArgument));
 
     let resp = client.activate_premium(ActivatePremiumRequest {
         id: 1,
         variant: 0,
+        actor: None,
+    }).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::InvalidArgument));
+
+    let resp = client.register(RegistrationRequest {
+        user: Some(ExternalUser {
+            external_id: 0,
+            name: None,
+            external_string_id: WEBSITE_USER_ID.to_owned(),
+        }),
+        service: Some(Service {
+            name: "SadFavBot".to_owned(),
+            kind: ServiceType::TelegramBot.into(),
+            kind_name: String::new(),
+        }),
+        consent_info: Some(serde_json::from_value(json!({"test": true}))?),
+        refresh_name: false,
+    }).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::InvalidArgument));
+
+    let resp = client.register(RegistrationRequest {
+        user: Some(ExternalUser {
+            external_id: 0,
+            name: None,
+            external_string_id: WEBSITE_USER_ID.to_owned(),
+        }),
+        service: Some(Service {
+            name: "SadDiscordBot".to_owned(),
+            kind: ServiceType::Unspecified.into(),
+            kind_name: "discord-bot".to_owned(),
+        }),
+        consent_info: Some(serde_json::from_value(json!({"test": true}))?),
+        refresh_name: false,
+    }).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::InvalidArgument));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_issue_token() -> anyhow::Result<()> {
+    let issuer = Arc::new(TokenIssuer::new(TokenConfig::new(TEST_SECRET.to_owned()))?);
+    let addr = start_test_server_with_issuer(mock_repositories(), issuer.clone()).await?;
+    let mut client = UserServiceClient::connect(format!("http://{}", addr)).await?;
+
+    let resp = client.issue_token(IssueTokenRequest { id: 1 }).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::NotFound));
+
+    client.register(RegistrationRequest {
+        user: Some(ExternalUser {
+            external_id: 12345,
+            name: Some("SadBot".to_owned()),
+            external_string_id: String::new(),
+        }),
+        service: Some(Service {
+            name: "SadFavBot".to_owned(),
+            kind: ServiceType::TelegramBot.into(),
+            kind_name: String::new(),
+        }),
+        consent_info: Some(serde_json::from_value(json!({"test": true}))?),
+        refresh_name: false,
+    }).await?;
+
+    let resp = client.issue_token(IssueTokenRequest { id: 1 }).await?.into_inner();
+    assert!(resp.expires_at.is_some());
+    let claims = issuer.verify(&resp.token)?;
+    assert_eq!(claims.user_id(), Some(1));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_linking() -> anyhow::Result<()> {
+    let addr = start_test_server(mock_repositories()).await?;
+    let mut client = UserServiceClient::connect(format!("http://{}", addr)).await?;
+
+    let resp = client.create_link_code(CreateLinkCodeRequest { id: 1 }).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::NotFound));
+
+    client.register(RegistrationRequest {
+        user: Some(ExternalUser {
+            external_id: 12345,
+            name: Some("SadBot".to_owned()),
+            external_string_id: String::new(),
+        }),
+        service: Some(Service {
+            name: "SadFavBot".to_owned(),
+            kind: ServiceType::TelegramBot.into(),
+            kind_name: String::new(),
+        }),
+        consent_info: Some(serde_json::from_value(json!({"test": true}))?),
+        refresh_name: false,
+    }).await?;
+    let link_code = client.create_link_code(CreateLinkCodeRequest { id: 1 }).await?.into_inner();
+    assert!(link_code.expires_at.is_some());
+
+    let link_req = LinkRequest {
+        code: link_code.code,
+        user: Some(ExternalUser {
+            external_id: 0,
+            name: None,
+            external_string_id: WEBSITE_USER_ID.to_owned(),
+        }),
+        service: Some(Service {
+            name: "kozalo.ru".to_owned(),
+            kind: ServiceType::Website.into(),
+            kind_name: String::new(),
+        }),
+        consent_info: Some(serde_json::from_value(json!({"test": true}))?),
+    };
+    let resp = client.link(link_req.clone()).await?.into_inner();
+    assert_eq!(resp.id, 1);
+    let resp = client.link(link_req).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::NotFound));
+
+    let get_website_user_req = GetUserRequest {
+        id: 0,
+        by_external_id: true,
+        external_string_id: WEBSITE_USER_ID.to_owned(),
+        service: Some(Service {
+            name: "kozalo.ru".to_owned(),
+            kind: ServiceType::Website.into(),
+            kind_name: String::new(),
+        }),
+    };
+    let user = client.get(get_website_user_req.clone()).await?.into_inner();
+    assert_eq!(user.id, 1);
+
+    let unlink_req = UnlinkRequest {
+        service: Some(Service {
+            name: "kozalo.ru".to_owned(),
+            kind: ServiceType::Website.into(),
+            kind_name: String::new(),
+        }),
+        external_id: 0,
+        external_string_id: WEBSITE_USER_ID.to_owned(),
+        withdraw_consent: true,
+    };
+    client.unlink(unlink_req.clone()).await?;
+    test_get_not_found(&mut client, get_website_user_req).await;
+    let resp = client.unlink(unlink_req).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::NotFound));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_batch_get() -> anyhow::Result<()> {
+    let addr = start_test_server(mock_repositories()).await?;
+    let mut client = UserServiceClient::connect(format!("http://{}", addr)).await?;
+
+    let service = Service {
+        name: "SadFavBot".to_owned(),
+        kind: ServiceType::TelegramBot.into(),
+        kind_name: String::new(),
+    };
+    client.register(RegistrationRequest {
+        user: Some(ExternalUser {
+            external_id: 12345,
+            name: Some("SadBot".to_owned()),
+            external_string_id: String::new(),
+        }),
+        service: Some(service.clone()),
+        consent_info: Some(serde_json::from_value(json!({"test": true}))?),
+        refresh_name: false,
+    }).await?;
+
+    let by_id = |id| BatchKey { key: Some(Key::Id(id)) };
+    let by_external_id = |external_id| BatchKey {
+        key: Some(Key::External(ExternalKey {
+            service: Some(service.clone()),
+            external_id,
+            external_string_id: String::new(),
+        })),
+    };
+    let resp = client.batch_get(BatchGetRequest {
+        keys: vec![by_id(1), by_id(2), by_external_id(12345), by_external_id(54321)],
+    }).await?.into_inner();
+    assert_eq!(resp.users.len(), 2);
+    assert_eq!(resp.users[0].key, Some(by_id(1)));
+    assert_eq!(resp.users[1].key, Some(by_external_id(12345)));
+    assert!(resp.users.iter().all(|found| found.user.as_ref().map(|user| user.id) == Some(1)));
+    assert_eq!(resp.missing, vec![by_id(2), by_external_id(54321)]);
+
+    let resp = client.batch_get(BatchGetRequest {
+        keys: vec![BatchKey { key: None }],
+    }).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::InvalidArgument));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_list() -> anyhow::Result<()> {
+    let addr = start_test_server(mock_repositories()).await?;
+    let mut client = UserServiceClient::connect(format!("http://{}", addr)).await?;
+
+    for external_id in 1..=3 {
+        client.register(RegistrationRequest {
+            user: Some(ExternalUser {
+                external_id,
+                name: None,
+                external_string_id: String::new(),
+            }),
+            service: Some(Service {
+                name: "SadFavBot".to_owned(),
+                kind: ServiceType::TelegramBot.into(),
+                kind_name: String::new(),
+            }),
+            consent_info: Some(serde_json::from_value(json!({"test": true}))?),
+            refresh_name: false,
+        }).await?;
+    }
+
+    let list_req = ListRequest {
+        limit: 2,
+        ..ListRequest::default()
+    };
+    let resp = client.list(list_req.clone()).await?.into_inner();
+    assert_eq!(resp.users.iter().map(|user| user.id).collect::<Vec<_>>(), vec![1, 2]);
+    assert!(!resp.next_cursor.is_empty());
+    let resp = client.list(ListRequest {
+        cursor: resp.next_cursor,
+        ..list_req
+    }).await?.into_inner();
+    assert_eq!(resp.users.iter().map(|user| user.id).collect::<Vec<_>>(), vec![3]);
+    assert!(resp.next_cursor.is_empty());
+
+    let resp = client.list(ListRequest {
+        cursor: "!!!".to_owned(),
+        ..ListRequest::default()
+    }).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::InvalidArgument));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_search() -> anyhow::Result<()> {
+    let addr = start_test_server(mock_repositories()).await?;
+    let mut client = UserServiceClient::connect(format!("http://{}", addr)).await?;
+
+    client.register(RegistrationRequest {
+        user: Some(ExternalUser {
+            external_id: 12345,
+            name: Some("SadBot".to_owned()),
+            external_string_id: String::new(),
+        }),
+        service: Some(Service {
+            name: "SadFavBot".to_owned(),
+            kind: ServiceType::TelegramBot.into(),
+            kind_name: String::new(),
+        }),
+        consent_info: Some(serde_json::from_value(json!({"test": true}))?),
+        refresh_name: false,
+    }).await?;
+
+    let resp = client.search(SearchRequest {
+        query: "sad".to_owned(),
+        ..SearchRequest::default()
+    }).await?.into_inner();
+    assert_eq!(resp.users.iter().map(|user| user.id).collect::<Vec<_>>(), vec![1]);
+
+    let resp = client.search(SearchRequest::default()).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::InvalidArgument));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_patch() -> anyhow::Result<()> {
+    let addr = start_test_server(mock_repositories()).await?;
+    let mut client = UserServiceClient::connect(format!("http://{}", addr)).await?;
+
+    client.register(RegistrationRequest {
+        user: Some(ExternalUser {
+            external_id: 12345,
+            name: Some("SadBot".to_owned()),
+            external_string_id: String::new(),
+        }),
+        service: Some(Service {
+            name: "SadFavBot".to_owned(),
+            kind: ServiceType::TelegramBot.into(),
+            kind_name: String::new(),
+        }),
+        consent_info: Some(serde_json::from_value(json!({"test": true}))?),
+        refresh_name: false,
+    }).await?;
+
+    let patch_req = |paths: &[&str]| PatchUserRequest {
+        id: 1,
+        user: Some(User {
+            name: Some("HappyBot".to_owned()),
+            options: Some(Options {
+                language_code: Some("ru".to_owned()),
+                location: None,
+                timezone: None,
+            }),
+            ..User::default()
+        }),
+        update_mask: Some(prost_types::FieldMask {
+            paths: paths.iter().map(|path| path.to_string()).collect(),
+        }),
+        expected_version: None,
+        actor: None,
+    };
+    let user = client.patch(patch_req(&["name", "options.language_code"])).await?.into_inner();
+    assert_eq!(user.name, Some("HappyBot".to_owned()));
+    assert_eq!(user.options.and_then(|opts| opts.language_code), Some("ru".to_owned()));
+
+    tracing::info!("fields in the mask but absent in the user are cleared");
+    let user = client.patch(PatchUserRequest {
+        user: None,
+        ..patch_req(&["name"])
+    }).await?.into_inner();
+    assert_eq!(user.name, None);
+    assert_eq!(user.options.and_then(|opts| opts.language_code), Some("ru".to_owned()));
+
+    let resp = client.patch(patch_req(&[])).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::InvalidArgument));
+    let resp = client.patch(patch_req(&["is_premium"])).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::InvalidArgument));
+    let resp = client.patch(PatchUserRequest {
+        id: 2,
+        ..patch_req(&["name"])
+    }).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::NotFound));
+
+    tracing::info!("changes based on a stale version are rejected");
+    let version = user.version;
+    let user = client.patch(PatchUserRequest {
+        expected_version: Some(version),
+        ..patch_req(&["name"])
+    }).await?.into_inner();
+    assert_eq!(user.version, version + 1);
+    let resp = client.patch(PatchUserRequest {
+        expected_version: Some(version),
+        ..patch_req(&["name"])
+    }).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::Aborted));
+    let resp = client.update(UpdateUserRequest {
+        id: 1,
+        target: Some(Target::ClearLanguage(())),
+        expected_version: Some(version),
+        actor: None,
+    }).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::Aborted));
+    client.update(UpdateUserRequest {
+        id: 1,
+        target: Some(Target::ClearLanguage(())),
+        expected_version: Some(version + 1),
+        actor: None,
+    }).await?;
+
+    tracing::info!("the acting service must be registered");
+    let sad_bot = Service {
+        name: "SadFavBot".to_owned(),
+        kind: ServiceType::TelegramBot.into(),
+        kind_name: String::new(),
+    };
+    client.patch(PatchUserRequest {
+        actor: Some(sad_bot.clone()),
+        ..patch_req(&["name"])
+    }).await?;
+    let resp = client.patch(PatchUserRequest {
+        actor: Some(Service { name: "UnknownBot".to_owned(), ..sad_bot }),
+        ..patch_req(&["name"])
     }).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::NotFound));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_names() -> anyhow::Result<()> {
+    let addr = start_test_server(mock_repositories()).await?;
+    let mut client = UserServiceClient::connect(format!("http://{}", addr)).await?;
+
+    let registration_req = |name: &str, refresh_name| RegistrationRequest {
+        user: Some(ExternalUser {
+            external_id: 12345,
+            name: Some(name.to_owned()),
+            external_string_id: String::new(),
+        }),
+        service: Some(Service {
+            name: "SadFavBot".to_owned(),
+            kind: ServiceType::TelegramBot.into(),
+            kind_name: String::new(),
+        }),
+        consent_info: Some(serde_json::from_value(json!({"test": true})).unwrap()),
+        refresh_name,
+    };
+    let get_req = GetUserRequest { id: 1, ..GetUserRequest::default() };
+
+    client.register(registration_req("  Sad\u{7}Bot ", false)).await?;
+    assert_eq!(client.get(get_req.clone()).await?.into_inner().name, Some("SadBot".to_owned()));
+
+    client.register(registration_req("HappyBot", false)).await?;
+    assert_eq!(client.get(get_req.clone()).await?.into_inner().name, Some("SadBot".to_owned()));
+    client.register(registration_req("HappyBot", true)).await?;
+    assert_eq!(client.get(get_req.clone()).await?.into_inner().name, Some("HappyBot".to_owned()));
+
+    client.update(UpdateUserRequest {
+        id: 1,
+        target: Some(Target::Name("\tMadBot".to_owned())),
+        expected_version: None,
+        actor: None,
+    }).await?;
+    assert_eq!(client.get(get_req).await?.into_inner().name, Some("MadBot".to_owned()));
+
+    for name in [" \u{0} ".to_owned(), "a".repeat(257)] {
+        let resp = client.update(UpdateUserRequest {
+            id: 1,
+            target: Some(Target::Name(name.clone())),
+            expected_version: None,
+            actor: None,
+        }).await;
+        assert_eq!(resp.err().map(|status| status.code()), Some(Code::InvalidArgument));
+        let resp = client.register(registration_req(&name, true)).await;
+        assert_eq!(resp.err().map(|status| status.code()), Some(Code::InvalidArgument));
+    }
+
+    tracing::info!("the renames are recorded in the history, the latest first");
+    let changes_req = |cursor: String| ListChangesRequest {
+        id: 1,
+        cursor,
+        limit: 1,
+    };
+    let resp = client.list_changes(changes_req(String::new())).await?.into_inner();
+    let change = &resp.changes[0];
+    assert_eq!(change.field, "name");
+    assert_eq!(change.old_value.clone().map(serde_json::to_value).transpose()?, Some(json!("HappyBot")));
+    assert_eq!(change.new_value.clone().map(serde_json::to_value).transpose()?, Some(json!("MadBot")));
+    let resp = client.list_changes(changes_req(resp.next_cursor)).await?.into_inner();
+    assert_eq!(resp.changes.len(), 1);
+    assert!(resp.next_cursor.is_empty());
+    let resp = client.list_changes(ListChangesRequest { id: 2, ..changes_req(String::new()) }).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::NotFound));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_deactivation() -> anyhow::Result<()> {
+    let repos = mock_repositories();
+    let user = crate::dto::ExternalUser {
+        external_id: crate::dto::ExternalId::Numeric(12345),
+        name: Some("SadBot".to_owned()),
+    };
+    let user_id = repos.users.register(user, 1, json!({"test": true})).await?;
+    repos.users.deactivate(user_id, None).await?;
+    let addr = start_test_server(repos).await?;
+    let mut client = UserServiceClient::connect(format!("http://{}", addr)).await?;
+
+    let resp = client.get(GetUserRequest { id: user_id, ..GetUserRequest::default() }).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::FailedPrecondition));
+    let resp = client.activate_premium(ActivatePremiumRequest {
+        id: user_id,
+        variant: PremiumVariant::Month as i32,
+        actor: None,
+    }).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::FailedPrecondition));
+    let resp = client.issue_token(IssueTokenRequest { id: user_id }).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::FailedPrecondition));
+
+    tracing::info!("deactivated users are still listed, but marked");
+    let resp = client.batch_get(BatchGetRequest {
+        keys: vec![BatchKey { key: Some(Key::Id(user_id)) }],
+    }).await?.into_inner();
+    assert!(resp.users[0].user.as_ref().is_some_and(|user| user.deactivated));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_contacts() -> anyhow::Result<()> {
+    let repos = mock_repositories();
+    let user = crate::dto::ExternalUser {
+        external_id: crate::dto::ExternalId::Numeric(12345),
+        name: Some("SadBot".to_owned()),
+    };
+    let user_id = repos.users.register(user, 1, json!({"test": true})).await?;
+    let email = crate::dto::NewContact::new(crate::dto::ContactKind::Email, "Sad.Bot@example.com")?;
+    let crate::dto::ContactRequestOutcome::Requested(verification) = repos.users.request_contact_verification(user_id, &email).await? else {
+        return Err(anyhow!("the verification must be requested"));
+    };
+    repos.users.confirm_contact(user_id, crate::dto::ContactKind::Email, &verification.code).await?;
+    let phone = crate::dto::NewContact::new(crate::dto::ContactKind::Phone, "+79001234567")?;
+    repos.users.request_contact_verification(user_id, &phone).await?;
+    let addr = start_test_server(repos).await?;
+    let mut client = UserServiceClient::connect(format!("http://{}", addr)).await?;
+
+    tracing::info!("only the verified contacts are returned");
+    let user = client.get(GetUserRequest { id: user_id, ..GetUserRequest::default() }).await?.into_inner();
+    assert_eq!(user.contacts.len(), 1);
+    assert_eq!(user.contacts[0].kind, "email");
+    assert_eq!(user.contacts[0].value, "sad.bot@example.com");
+    assert!(user.contacts[0].verified_at.is_some());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_bans() -> anyhow::Result<()> {
+    let addr = start_test_server(mock_repositories()).await?;
+    let mut client = UserServiceClient::connect(format!("http://{}", addr)).await?;
+
+    let service = Service {
+        name: "SadFavBot".to_owned(),
+        kind: ServiceType::TelegramBot.into(),
+        kind_name: String::new(),
+    };
+    client.register(RegistrationRequest {
+        user: Some(ExternalUser {
+            external_id: 12345,
+            name: Some("SadBot".to_owned()),
+            external_string_id: String::new(),
+        }),
+        service: Some(service.clone()),
+        consent_info: Some(serde_json::from_value(json!({"test": true}))?),
+        refresh_name: false,
+    }).await?;
+
+    let ban_req = |reason: &str| BanRequest {
+        id: 1,
+        service: Some(service.clone()),
+        reason: reason.to_owned(),
+        issuer: "moderator".to_owned(),
+        expires_at: None,
+    };
+    let resp = client.ban(ban_req("")).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::InvalidArgument));
+    let resp = client.ban(BanRequest { id: 2, ..ban_req("spam") }).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::NotFound));
+
+    let ban = client.ban(ban_req("spam")).await?.into_inner();
+    assert_eq!(ban.reason, "spam");
+    assert!(ban.issued_at.is_some());
+    let user = client.get(GetUserRequest { id: 1, ..GetUserRequest::default() }).await?.into_inner();
+    assert_eq!(user.bans.len(), 1);
+
+    let resp = client.unban(UnbanRequest { id: 1, service: None }).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::NotFound));
+    client.unban(UnbanRequest { id: 1, service: Some(service) }).await?;
+    let resp = client.list_bans(ListBansRequest { id: 1 }).await?.into_inner();
+    assert!(resp.bans.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_settings() -> anyhow::Result<()> {
+    let addr = start_test_server(mock_repositories()).await?;
+    let mut client = UserServiceClient::connect(format!("http://{}", addr)).await?;
+
+    let service = Service {
+        name: "SadFavBot".to_owned(),
+        kind: ServiceType::TelegramBot.into(),
+        kind_name: String::new(),
+    };
+    client.register(RegistrationRequest {
+        user: Some(ExternalUser {
+            external_id: 12345,
+            name: Some("SadBot".to_owned()),
+            external_string_id: String::new(),
+        }),
+        service: Some(service.clone()),
+        consent_info: Some(serde_json::from_value(json!({"test": true}))?),
+        refresh_name: false,
+    }).await?;
+
+    let set_req = |key: &str, value: serde_json::Value| -> anyhow::Result<SetSettingRequest> {
+        Ok(SetSettingRequest {
+            id: 1,
+            service: Some(service.clone()),
+            key: key.to_owned(),
+            value: Some(serde_json::from_value(value)?),
+        })
+    };
+    let resp = client.set_setting(set_req("Theme", json!("dark"))?).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::InvalidArgument));
+    let resp = client.set_setting(SetSettingRequest { id: 2, ..set_req("theme", json!("dark"))? }).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::NotFound));
+    let resp = client.set_setting(SetSettingRequest { value: None, ..set_req("theme", json!("dark"))? }).await;
     assert_eq!(resp.err().map(|status| status.code()), Some(Code::InvalidArgument));
 
+    client.set_setting(set_req("theme", json!("dark"))?).await?;
+    client.set_setting(set_req("limits", json!({"daily": 10}))?).await?;
+    let settings = client.get_settings(GetSettingsRequest { id: 1, service: Some(service.clone()) }).await?.into_inner();
+    assert_eq!(settings.values.len(), 2);
+    // google.protobuf.Value keeps all numbers as doubles
+    assert_eq!(serde_json::to_value(&settings.values["limits"])?, json!({"daily": 10.0}));
+
+    client.delete_setting(DeleteSettingRequest { id: 1, service: Some(service.clone()), key: "theme".to_owned() }).await?;
+    let resp = client.delete_setting(DeleteSettingRequest { id: 1, service: Some(service.clone()), key: "theme".to_owned() }).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::NotFound));
+
+    client.set_setting_default(SetSettingDefaultRequest {
+        service: Some(service.clone()),
+        key: "theme".to_owned(),
+        value: Some(serde_json::from_value(json!("light"))?),
+    }).await?;
+    client.delete_setting_default(DeleteSettingDefaultRequest { service: Some(service.clone()), key: "theme".to_owned() }).await?;
+    let resp = client.delete_setting_default(DeleteSettingDefaultRequest { service: Some(service), key: "theme".to_owned() }).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::NotFound));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_schemas() -> anyhow::Result<()> {
+    let addr = start_test_server(mock_repositories()).await?;
+    let mut client = UserServiceClient::connect(format!("http://{}", addr)).await?;
+
+    let service = Service {
+        name: "SadFavBot".to_owned(),
+        kind: ServiceType::TelegramBot.into(),
+        kind_name: String::new(),
+    };
+    let registration_req = |external_id: i64, consent_info: serde_json::Value| -> anyhow::Result<RegistrationRequest> {
+        Ok(RegistrationRequest {
+            user: Some(ExternalUser {
+                external_id,
+                name: Some("SadBot".to_owned()),
+                external_string_id: String::new(),
+            }),
+            service: Some(service.clone()),
+            consent_info: Some(serde_json::from_value(consent_info)?),
+            refresh_name: false,
+        })
+    };
+    client.register(registration_req(12345, json!({"version": 1}))?).await?;
+
+    let schema_req = |kind: &str, schema: serde_json::Value| -> anyhow::Result<SetSchemaRequest> {
+        Ok(SetSchemaRequest {
+            service: Some(service.clone()),
+            kind: kind.to_owned(),
+            schema: Some(serde_json::from_value(schema)?),
+        })
+    };
+    let resp = client.set_schema(schema_req("profile", json!({"type": "object"}))?).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::InvalidArgument));
+    let resp = client.set_schema(schema_req("consent", json!({"type": 42}))?).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::InvalidArgument));
+    client.set_schema(schema_req("consent", json!({"required": ["version"]}))?).await?;
+    let schema = client.get_schema(GetSchemaRequest { service: Some(service.clone()), kind: "consent".to_owned() }).await?.into_inner();
+    assert_eq!(serde_json::to_value(schema.schema)?, json!({"required": ["version"]}));
+
+    let resp = client.register(registration_req(54321, json!({"test": true}))?).await;
+    let status = resp.expect_err("the consent must be rejected");
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert!(status.message().contains("version"));
+    let violations = status.get_details_bad_request()
+        .expect("the violations must be attached")
+        .field_violations;
+    assert_eq!(violations.len(), 1);
+    assert_eq!(violations[0].field, "");
+    assert!(violations[0].description.contains("version"));
+    client.register(registration_req(54321, json!({"version": 1}))?).await?;
+
+    client.delete_schema(DeleteSchemaRequest { service: Some(service.clone()), kind: "consent".to_owned() }).await?;
+    let resp = client.delete_schema(DeleteSchemaRequest { service: Some(service), kind: "consent".to_owned() }).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::NotFound));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_timezones() -> anyhow::Result<()> {
+    let addr = start_test_server(mock_repositories()).await?;
+    let mut client = UserServiceClient::connect(format!("http://{}", addr)).await?;
+
+    client.register(RegistrationRequest {
+        user: Some(ExternalUser {
+            external_id: 12345,
+            name: Some("SadBot".to_owned()),
+            external_string_id: String::new(),
+        }),
+        service: Some(Service {
+            name: "SadFavBot".to_owned(),
+            kind: ServiceType::TelegramBot.into(),
+            kind_name: String::new(),
+        }),
+        consent_info: Some(serde_json::from_value(json!({"test": true}))?),
+        refresh_name: false,
+    }).await?;
+    let update_req = |target: Target| UpdateUserRequest {
+        id: 1,
+        target: Some(target),
+        expected_version: None,
+        actor: None,
+    };
+    let get_req = GetUserRequest { id: 1, ..GetUserRequest::default() };
+
+    client.update(update_req(Target::Location(Location { latitude: 55.7558, longitude: 37.6173 }))).await?;
+    let user = client.get(get_req.clone()).await?.into_inner();
+    assert_eq!(user.options.and_then(|opts| opts.timezone), Some("Europe/Moscow".to_owned()));
+    assert_eq!(user.utc_offset, Some(3 * 3600));
+    assert_eq!(user.place.map(|place| (place.city, place.country_code)), Some(("Moscow".to_owned(), "RU".to_owned())));
+
+    let resp = client.update(update_req(Target::Timezone("Asia/Tokio".to_owned()))).await;
+    assert_eq!(resp.err().map(|status| status.code()), Some(Code::InvalidArgument));
+    client.update(update_req(Target::Timezone("Asia/Tokyo".to_owned()))).await?;
+    let user = client.get(get_req.clone()).await?.into_inner();
+    assert_eq!(user.options.and_then(|opts| opts.timezone), Some("Asia/Tokyo".to_owned()));
+    assert_eq!(user.utc_offset, Some(9 * 3600));
+
+    client.update(update_req(Target::ClearTimezone(()))).await?;
+    let user = client.get(get_req).await?.into_inner();
+    assert_eq!(user.options.and_then(|opts| opts.timezone), None);
+    assert_eq!(user.utc_offset, None);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_language_tags() -> anyhow::Result<()> {
+    let addr = start_test_server(mock_repositories()).await?;
+    let mut client = UserServiceClient::connect(format!("http://{}", addr)).await?;
+
+    client.register(RegistrationRequest {
+        user: Some(ExternalUser {
+            external_id: 12345,
+            name: Some("SadBot".to_owned()),
+            external_string_id: String::new(),
+        }),
+        service: Some(Service {
+            name: "SadFavBot".to_owned(),
+            kind: ServiceType::TelegramBot.into(),
+            kind_name: String::new(),
+        }),
+        consent_info: Some(serde_json::from_value(json!({"test": true}))?),
+        refresh_name: false,
+    }).await?;
+    let update_req = |code: &str| UpdateUserRequest {
+        id: 1,
+        target: Some(Target::Language(code.to_owned())),
+        expected_version: None,
+        actor: None,
+    };
+    let get_req = GetUserRequest { id: 1, ..GetUserRequest::default() };
+
+    client.update(update_req("zh-hant-tw")).await?;
+    let user = client.get(get_req).await?.into_inner();
+    assert_eq!(user.options.and_then(|opts| opts.language_code), Some("zh-Hant-TW".to_owned()));
+
+    for code in ["zz", "en--US", "x-private"] {
+        let resp = client.update(update_req(code)).await;
+        assert_eq!(resp.err().map(|status| status.code()), Some(Code::InvalidArgument), "{code}");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_nearby() -> anyhow::Result<()> {
+    let addr = start_test_server(mock_repositories()).await?;
+    let mut client = UserServiceClient::connect(format!("http://{}", addr)).await?;
+
+    client.register(RegistrationRequest {
+        user: Some(ExternalUser {
+            external_id: 12345,
+            name: Some("SadBot".to_owned()),
+            external_string_id: String::new(),
+        }),
+        service: Some(Service {
+            name: "SadFavBot".to_owned(),
+            kind: ServiceType::TelegramBot.into(),
+            kind_name: String::new(),
+        }),
+        consent_info: Some(serde_json::from_value(json!({"test": true}))?),
+        refresh_name: false,
+    }).await?;
+    client.update(UpdateUserRequest {
+        id: 1,
+        target: Some(Target::Location(Location { latitude: 59.9343, longitude: 30.3351 })),
+        expected_version: None,
+        actor: None,
+    }).await?;
+    let nearby_req = |radius_km: f64| NearbyRequest {
+        center: Some(Location { latitude: 55.7558, longitude: 37.6173 }),
+        radius_km,
+        ..NearbyRequest::default()
+    };
+
+    let resp = client.nearby(nearby_req(700.0)).await?.into_inner();
+    assert_eq!(resp.users.len(), 1);
+    assert_eq!(resp.users[0].user.as_ref().map(|user| user.id), Some(1));
+    assert!((630.0..640.0).contains(&resp.users[0].distance_km), "{}", resp.users[0].distance_km);
+    assert!(resp.next_cursor.is_empty());
+    assert!(client.nearby(nearby_req(500.0)).await?.into_inner().users.is_empty());
+
+    let unknown_service = NearbyRequest {
+        service: Some(Service {
+            name: "UnknownBot".to_owned(),
+            kind: ServiceType::TelegramBot.into(),
+            kind_name: String::new(),
+        }),
+        ..nearby_req(700.0)
+    };
+    assert!(client.nearby(unknown_service).await?.into_inner().users.is_empty());
+
+    for req in [nearby_req(0.0), nearby_req(5000.0), NearbyRequest { center: None, ..nearby_req(700.0) }] {
+        let resp = client.nearby(req).await;
+        assert_eq!(resp.err().map(|status| status.code()), Some(Code::InvalidArgument));
+    }
+
     Ok(())
 }
 
 async fn start_test_server<U, S>(repos: repo::Repositories<U, S>) -> anyhow::Result<SocketAddr>
+where
+    U: Users + Send + Sync + 'static,
+    S: Services + Send + Sync + 'static,
+{
+    start_test_server_with_issuer(repos, Arc::new(TokenIssuer::new(TokenConfig::new(TEST_SECRET.to_owned()))?)).await
+}
+
+async fn start_test_server_with_issuer<U, S>(repos: repo::Repositories<U, S>, issuer: Arc<TokenIssuer>) -> anyhow::Result<SocketAddr>
 where
     U: Users + Send + Sync + 'static,
     S: Services + Send + Sync + 'static,
@@ -121,7 +971,7 @@ where
 
     tokio::spawn(async move {
         Server::builder()
-            .add_service(UserServiceServer::new(GrpcServer::new(Arc::new(repos))))
+            .add_service(UserServiceServer::new(GrpcServer::new(Arc::new(repos), issuer)))
             .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener))
             .await
             .expect("couldn't start a gRPC server");
@@ -150,8 +1000,8 @@ async fn test_span_hierarchy() -> anyhow::Result<()> {
 
     // Call the handler directly in-process — no network, no thread boundaries,
     // so the thread-local subscriber is reliably active for all spans
-    let server = GrpcServer::new(Arc::new(mock_repositories()));
-    let _ = server.get(tonic::Request::new(GetUserRequest { id: 1, by_external_id: false })).await;
+    let server = GrpcServer::new(Arc::new(mock_repositories()), Arc::new(TokenIssuer::new(TokenConfig::new(TEST_SECRET.to_owned()))?));
+    let _ = server.get(tonic::Request::new(GetUserRequest { id: 1, ..GetUserRequest::default() })).await;
 
     let _ = provider.force_flush();
     let spans = exporter.get_finished_spans().expect("Failed to get finished spans");
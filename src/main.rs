@@ -4,6 +4,8 @@ mod env;
 mod grpc;
 mod rest;
 mod observability;
+mod tokens;
+mod contacts;
 
 use std::net::Ipv6Addr;
 use std::sync::Arc;
@@ -17,8 +19,10 @@ use tokio::net::TcpListener;
 use tonic::transport::Server;
 use tonic_tracing_opentelemetry::middleware::server::OtelGrpcLayer;
 use tower::ServiceBuilder;
+use crate::contacts::CodeSender;
 use crate::grpc::generated::user_service_server::UserServiceServer;
 use crate::grpc::server::GrpcServer;
+use crate::tokens::{TokenConfig, TokenIssuer};
 
 const AXUM_PORT: u16 = 8080;
 const TONIC_PORT: u16 = 8090;
@@ -29,17 +33,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let tracer_provider = observability::init_tracing()?;
     autometrics::prometheus_exporter::init();
+    dto::load_geocoder();
+    dto::load_timezone_finder();
 
     let db_config = repo::DatabaseConfig::from_env()?;
     let db = repo::establish_database_connection(&db_config).await?;
     let rest_repos = Arc::new(repo::ProdRepositories::from_db(db));
+    rest_repos.users.backfill_places().await?;
     let grpc_repos = rest_repos.clone();
 
+    let rest_issuer = Arc::new(TokenIssuer::new(TokenConfig::from_env()?)?);
+    let grpc_issuer = rest_issuer.clone();
+    let code_sender = contacts::sender_from_env()?;
+
     let rest_srv_handle = tokio::spawn(async move {
-        run_rest_server(rest_repos).await
+        run_rest_server(rest_repos, rest_issuer, code_sender).await
     });
     let grpc_srv_handle = tokio::spawn(async move {
-        run_grpc_server(grpc_repos).await
+        run_grpc_server(grpc_repos, grpc_issuer).await
     });
 
     let (rest_res, grpc_res) = join!(rest_srv_handle, grpc_srv_handle);
@@ -49,12 +60,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-async fn run_rest_server(repos: Arc<repo::ProdRepositories>) -> anyhow::Result<()> {
+async fn run_rest_server(repos: Arc<repo::ProdRepositories>, issuer: Arc<TokenIssuer>, sender: Arc<dyn CodeSender>) -> anyhow::Result<()> {
     let prometheus = prometheus::Registry::new();
     let (prometheus_layer, metric_handle) = PrometheusMetricLayer::pair();
 
     let app = axum::Router::new()
-        .nest("/api/rest/v1/user", rest::router(repos))
+        .nest("/api/rest/v1/user", rest::router(repos.clone(), issuer.clone(), sender))
+        .nest("/api/rest/v1/admin", rest::admin_router(repos))
+        .nest("/.well-known", rest::well_known_router(issuer))
         .layer(prometheus_layer)
         .layer(OtelInResponseLayer)
         .layer(OtelAxumLayer::default())
@@ -85,10 +98,10 @@ async fn run_rest_server(repos: Arc<repo::ProdRepositories>) -> anyhow::Result<(
     Ok(())
 }
 
-async fn run_grpc_server(repos: Arc<repo::ProdRepositories>) -> anyhow::Result<()> {
+async fn run_grpc_server(repos: Arc<repo::ProdRepositories>, issuer: Arc<TokenIssuer>) -> anyhow::Result<()> {
     Server::builder()
         .layer(ServiceBuilder::new().layer(OtelGrpcLayer::default()))
-        .add_service(UserServiceServer::new(GrpcServer::new(repos)))
+        .add_service(UserServiceServer::new(GrpcServer::new(repos, issuer)))
         .serve_with_shutdown(([0,0,0,0], TONIC_PORT).into(), shutdown_signal())
         .await?;
     Ok(())
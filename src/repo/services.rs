@@ -1,18 +1,33 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use crate::dto::{Service, ServiceType};
+use crate::dto::{SchemaKind, Service, ServiceType, ServiceTypeInfo, Settings};
 use crate::dto::error::TypeConversionError;
 use crate::repo::error::RepoError;
 
 pub trait Services: Send + Sync {
     fn create(&self, service_type: ServiceType, name: &str) -> impl Future<Output = Result<i32, RepoError<TypeConversionError>>> + Send;
     fn get_id(&self, service: &Service) -> impl Future<Output = Result<Option<i32>, RepoError<TypeConversionError>>> + Send;
+    fn get_type(&self, service_type: &ServiceType) -> impl Future<Output = Result<Option<ServiceTypeInfo>, RepoError<TypeConversionError>>> + Send;
+    fn list_types(&self) -> impl Future<Output = Result<Vec<ServiceTypeInfo>, RepoError<TypeConversionError>>> + Send;
+    /// Returns `false` if a type with the same name is already registered
+    fn create_type(&self, info: &ServiceTypeInfo) -> impl Future<Output = Result<bool, RepoError<TypeConversionError>>> + Send;
+    /// Values of the settings for the users of the service who haven't set them
+    fn setting_defaults(&self, service_id: i32) -> impl Future<Output = Result<Settings, RepoError<TypeConversionError>>> + Send;
+    fn set_setting_default(&self, service_id: i32, key: &str, value: serde_json::Value) -> impl Future<Output = Result<(), RepoError<TypeConversionError>>> + Send;
+    /// Returns whether there was a default to delete
+    fn delete_setting_default(&self, service_id: i32, key: &str) -> impl Future<Output = Result<bool, RepoError<TypeConversionError>>> + Send;
+    /// JSON Schema the documents of the kind sent by the service must conform to
+    fn schema(&self, service_id: i32, kind: SchemaKind) -> impl Future<Output = Result<Option<serde_json::Value>, RepoError<TypeConversionError>>> + Send;
+    fn set_schema(&self, service_id: i32, kind: SchemaKind, schema: serde_json::Value) -> impl Future<Output = Result<(), RepoError<TypeConversionError>>> + Send;
+    /// Returns whether there was a schema to delete
+    fn delete_schema(&self, service_id: i32, kind: SchemaKind) -> impl Future<Output = Result<bool, RepoError<TypeConversionError>>> + Send;
 }
 
 pub struct ServicesPostgres {
     pool: sqlx::Pool<sqlx::Postgres>,
-    id_cache: Arc<RwLock<HashMap<ServiceKey, i32>>>
+    id_cache: Arc<RwLock<HashMap<ServiceKey, i32>>>,
+    type_cache: Arc<RwLock<HashMap<ServiceType, ServiceTypeInfo>>>,
 }
 
 #[derive(Hash, Eq, PartialEq)]
@@ -28,24 +43,25 @@ impl ServicesPostgres {
     pub fn new(pool: sqlx::Pool<sqlx::Postgres>) -> Self {
         Self {
             pool,
-            id_cache: Arc::new(RwLock::new(HashMap::new()))
+            id_cache: Arc::new(RwLock::new(HashMap::new())),
+            type_cache: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 }
 
 impl Services for ServicesPostgres {
-    #[tracing::instrument(skip(self), fields(service_type = ?service_type, name = %name))]
+    #[tracing::instrument(skip(self), fields(service_type = %service_type, name = %name))]
     async fn create(&self, service_type: ServiceType, name: &str) -> Result<i32, RepoError<TypeConversionError>> {
         tracing::info!("Creating new service");
         let result = sqlx::query_scalar!("INSERT INTO Services (type, name) VALUES ($1, $2) RETURNING id",
-                service_type as ServiceType, name)
+                service_type.as_str(), name)
             .fetch_one(&self.pool)
             .await?;
         tracing::info!(service_id = %result, "Service created successfully");
         Ok(result)
     }
 
-    #[tracing::instrument(skip(self), fields(service_name = %service.name, service_type = ?service.service_type))]
+    #[tracing::instrument(skip(self), fields(service_name = %service.name, service_type = %service.service_type))]
     async fn get_id(&self, service: &Service) -> Result<Option<i32>, RepoError<TypeConversionError>> {
         let cached_id = {
             self.id_cache
@@ -56,7 +72,7 @@ impl Services for ServicesPostgres {
         let id = if cached_id.is_none() {
             tracing::debug!("Cache miss - fetching service ID from database");
             let fetched_id = sqlx::query_scalar!("SELECT id FROM Services WHERE name = $1 AND type = $2",
-                    &service.name, service.service_type as ServiceType)
+                    &service.name, service.service_type.as_str())
                 .fetch_optional(&self.pool)
                 .await?;
             if let Some(id) = fetched_id {
@@ -74,4 +90,131 @@ impl Services for ServicesPostgres {
         };
         Ok(id)
     }
+
+    #[tracing::instrument(skip(self), fields(service_type = %service_type))]
+    async fn get_type(&self, service_type: &ServiceType) -> Result<Option<ServiceTypeInfo>, RepoError<TypeConversionError>> {
+        let cached_info = {
+            self.type_cache
+                .read().await
+                .get(service_type)
+                .cloned()
+        };
+        if cached_info.is_some() {
+            tracing::debug!("Cache hit - using cached service type");
+            return Ok(cached_info);
+        }
+
+        tracing::debug!("Cache miss - fetching service type from database");
+        let string_ids = sqlx::query_scalar!("SELECT string_ids FROM Service_Types WHERE name = $1",
+                service_type.as_str())
+            .fetch_optional(&self.pool)
+            .await?;
+        let info = string_ids.map(|string_ids| ServiceTypeInfo {
+            name: service_type.clone(),
+            string_ids,
+        });
+        if let Some(ref info) = info {
+            self.type_cache
+                .write().await
+                .insert(service_type.clone(), info.clone());
+        } else {
+            tracing::debug!("Service type is not registered");
+        }
+        Ok(info)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn list_types(&self) -> Result<Vec<ServiceTypeInfo>, RepoError<TypeConversionError>> {
+        sqlx::query!("SELECT name, string_ids FROM Service_Types ORDER BY name")
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|row| Ok(ServiceTypeInfo {
+                name: row.name.try_into()
+                    .map_err(|e| RepoError::Other(TypeConversionError::new(e)))?,
+                string_ids: row.string_ids,
+            }))
+            .collect()
+    }
+
+    #[tracing::instrument(skip(self), fields(service_type = %info.name, string_ids = %info.string_ids))]
+    async fn create_type(&self, info: &ServiceTypeInfo) -> Result<bool, RepoError<TypeConversionError>> {
+        let rows_affected = sqlx::query!(
+            "INSERT INTO Service_Types (name, string_ids) VALUES ($1, $2) ON CONFLICT (name) DO NOTHING",
+                info.name.as_str(), info.string_ids)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        let created = rows_affected > 0;
+        if created {
+            tracing::info!("Service type registered successfully");
+        } else {
+            tracing::warn!("Service type is already registered");
+        }
+        Ok(created)
+    }
+
+    #[tracing::instrument(skip(self), fields(service_id = %service_id))]
+    async fn setting_defaults(&self, service_id: i32) -> Result<Settings, RepoError<TypeConversionError>> {
+        Ok(sqlx::query!("SELECT key, value FROM Service_Setting_Defaults WHERE service_id = $1", service_id)
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|row| (row.key, row.value))
+            .collect())
+    }
+
+    #[tracing::instrument(skip(self, value), fields(service_id = %service_id, key = %key))]
+    async fn set_setting_default(&self, service_id: i32, key: &str, value: serde_json::Value) -> Result<(), RepoError<TypeConversionError>> {
+        sqlx::query!(
+            "INSERT INTO Service_Setting_Defaults (service_id, key, value) VALUES ($1, $2, $3)
+             ON CONFLICT (service_id, key) DO UPDATE SET value = EXCLUDED.value",
+                service_id, key, value)
+            .execute(&self.pool)
+            .await?;
+        tracing::info!("Default setting saved");
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self), fields(service_id = %service_id, key = %key))]
+    async fn delete_setting_default(&self, service_id: i32, key: &str) -> Result<bool, RepoError<TypeConversionError>> {
+        let rows_affected = sqlx::query!(
+            "DELETE FROM Service_Setting_Defaults WHERE service_id = $1 AND key = $2",
+                service_id, key)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        Ok(rows_affected > 0)
+    }
+
+    #[tracing::instrument(skip(self), fields(service_id = %service_id, kind = kind.as_str()))]
+    async fn schema(&self, service_id: i32, kind: SchemaKind) -> Result<Option<serde_json::Value>, RepoError<TypeConversionError>> {
+        Ok(sqlx::query_scalar!("SELECT schema FROM Service_Schemas WHERE service_id = $1 AND kind = $2",
+                service_id, kind.as_str())
+            .fetch_optional(&self.pool)
+            .await?)
+    }
+
+    #[tracing::instrument(skip(self, schema), fields(service_id = %service_id, kind = kind.as_str()))]
+    async fn set_schema(&self, service_id: i32, kind: SchemaKind, schema: serde_json::Value) -> Result<(), RepoError<TypeConversionError>> {
+        sqlx::query!(
+            "INSERT INTO Service_Schemas (service_id, kind, schema) VALUES ($1, $2, $3)
+             ON CONFLICT (service_id, kind) DO UPDATE SET schema = EXCLUDED.schema, updated_at = current_timestamp",
+                service_id, kind.as_str(), schema)
+            .execute(&self.pool)
+            .await?;
+        tracing::info!("Schema saved");
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self), fields(service_id = %service_id, kind = kind.as_str()))]
+    async fn delete_schema(&self, service_id: i32, kind: SchemaKind) -> Result<bool, RepoError<TypeConversionError>> {
+        let rows_affected = sqlx::query!(
+            "DELETE FROM Service_Schemas WHERE service_id = $1 AND kind = $2",
+                service_id, kind.as_str())
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        Ok(rows_affected > 0)
+    }
 }
@@ -1,24 +1,24 @@
 use std::collections::HashMap;
 use std::sync::Arc;
-use chrono::{DateTime, Utc};
-use num_traits::PrimInt;
+use chrono::Utc;
 use tokio::sync::Mutex;
-use crate::dto::{ExternalUser, PremiumVariant, SavedUser, Service, ServiceType};
+use crate::dto::{Ban, Contact, ContactConfirmation, ContactKind, ContactRequestOutcome, Credentials, CredentialsOutcome, Cursor, ExternalId, ExternalUser, LinkCode, LinkOutcome, Location, Login, LoginAttempt, NearbyUser, NewBan, Page, PremiumVariant, SavedUser, SchemaKind, Service, ServiceType, ServiceTypeInfo, Settings, UserChange, NewContact, NewCredentials, PasswordReset, VerificationCode};
 use crate::dto::error::TypeConversionError;
 use crate::repo::error::RepoError;
 use crate::repo::Repositories;
 use crate::repo::services::Services;
-use crate::repo::users::{UpdateTarget, UserId, Users};
+use crate::repo::users::{BatchKey, CredentialsKey, MergeOutcome, NearbyQuery, PatchOutcome, PremiumFilter, PremiumOutcome, UpdateTarget, UserFilter, UserId, UserPatch, Users, CONTACT_CODE_COOLDOWN, CONTACT_CODE_TTL, LOGIN_LOCKOUT, LOGIN_MAX_FAILED_ATTEMPTS};
 
-pub trait CtorWithData<K: PrimInt, V> {
+pub trait CtorWithData<K, V> {
     fn with_data(data: HashMap<K, V>) -> Self;
 }
 
 macro_rules! create_mock_struct {
-    ($type_name:ident, $id_type:ty, $key_type:ty, $value_type:ty, $data_field:ident) => {
+    ($type_name:ident, $id_type:ty, $key_type:ty, $value_type:ty, $data_field:ident $(, $extra_field:ident: $extra_type:ty)*) => {
         pub struct $type_name {
             id_seq: Arc<Mutex<dyn Iterator<Item=$id_type> + Send + Sync + 'static>>,
-            $data_field: Arc<Mutex<HashMap<$key_type, $value_type>>>
+            $data_field: Arc<Mutex<HashMap<$key_type, $value_type>>>,
+            $($extra_field: Arc<Mutex<$extra_type>>,)*
         }
 
         impl Default for $type_name {
@@ -26,6 +26,7 @@ macro_rules! create_mock_struct {
                 Self {
                     id_seq: Arc::new(Mutex::new((1..).into_iter())),
                     $data_field: Arc::new(Mutex::new(HashMap::new())),
+                    $($extra_field: Arc::new(Mutex::new(Default::default())),)*
                 }
             }
         }
@@ -48,12 +49,42 @@ macro_rules! create_mock_struct {
     };
 }
 
-create_mock_struct!(ServicesMock, i32, i32, Service, services);
-create_mock_struct!(UsersMock, i64, ExternalId, SavedUser, users);
+create_mock_struct!(ServicesMock, i32, i32, Service, services,
+    service_types: HashMap<ServiceType, ServiceTypeInfo>,
+    setting_defaults: HashMap<(i32, String), serde_json::Value>,
+    schemas: HashMap<(i32, SchemaKind), serde_json::Value>);
+
+/// The same types the migrations seed the `Service_Types` table with
+fn seeded_service_types() -> Vec<ServiceTypeInfo> {
+    [
+        (ServiceType::APPLICATION, true),
+        (ServiceType::TELEGRAM_BOT, false),
+        (ServiceType::TELEGRAM_CHANNEL, false),
+        (ServiceType::WEBSITE, true),
+    ]
+        .into_iter()
+        .map(|(name, string_ids)| ServiceTypeInfo { name, string_ids })
+        .collect()
+}
+/// The database compares external IDs as text, and so does the mock
+fn same_external_id(a: &ExternalId, b: &ExternalId) -> bool {
+    a.to_string() == b.to_string()
+}
+
+create_mock_struct!(UsersMock, i64, ExternalId, SavedUser, users,
+    link_codes: HashMap<String, i64>,
+    linked_accounts: HashMap<ExternalId, i64>,
+    merged_users: HashMap<i64, i64>,
+    changes: Vec<(i64, UserChange)>,
+    bans: HashMap<(i64, Option<i32>), Ban>,
+    settings: HashMap<(i64, i32, String), serde_json::Value>,
+    pending_contacts: HashMap<(i64, ContactKind), (NewContact, VerificationCode)>,
+    credentials: HashMap<i64, (Credentials, i32)>,
+    password_resets: HashMap<String, i64>);
 
 impl Services for ServicesMock {
     async fn create(&self, service_type: ServiceType, name: &str) -> Result<i32, RepoError<TypeConversionError>> {
-        tracing::info!("ServiceMock:create: {name} ({service_type:?})");
+        tracing::info!("ServiceMock:create: {name} ({service_type})");
         let id = self.gen_id().await;
         let service = (name.to_string(), service_type).into();
         self.services.lock().await
@@ -71,25 +102,100 @@ impl Services for ServicesMock {
             .next()
             .transpose()
     }
-}
 
-pub type ExternalId = i64;
+    async fn get_type(&self, service_type: &ServiceType) -> Result<Option<ServiceTypeInfo>, RepoError<TypeConversionError>> {
+        Ok(self.list_types().await?
+            .into_iter()
+            .find(|info| info.name == *service_type))
+    }
+
+    async fn list_types(&self) -> Result<Vec<ServiceTypeInfo>, RepoError<TypeConversionError>> {
+        let mut types = seeded_service_types();
+        types.extend(self.service_types.lock().await.values().cloned());
+        types.sort_by(|a, b| a.name.as_str().cmp(b.name.as_str()));
+        Ok(types)
+    }
+
+    async fn create_type(&self, info: &ServiceTypeInfo) -> Result<bool, RepoError<TypeConversionError>> {
+        tracing::info!("ServiceMock:create_type: {}", info.name);
+        if self.get_type(&info.name).await?.is_some() {
+            return Ok(false);
+        }
+        self.service_types.lock().await
+            .insert(info.name.clone(), info.clone());
+        Ok(true)
+    }
+
+    async fn setting_defaults(&self, service_id: i32) -> Result<Settings, RepoError<TypeConversionError>> {
+        Ok(self.setting_defaults.lock().await
+            .iter()
+            .filter(|((id, _), _)| *id == service_id)
+            .map(|((_, key), value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    async fn set_setting_default(&self, service_id: i32, key: &str, value: serde_json::Value) -> Result<(), RepoError<TypeConversionError>> {
+        tracing::info!("ServiceMock:set_setting_default: {key} for {service_id}");
+        self.setting_defaults.lock().await
+            .insert((service_id, key.to_owned()), value);
+        Ok(())
+    }
+
+    async fn delete_setting_default(&self, service_id: i32, key: &str) -> Result<bool, RepoError<TypeConversionError>> {
+        Ok(self.setting_defaults.lock().await
+            .remove(&(service_id, key.to_owned()))
+            .is_some())
+    }
+
+    async fn schema(&self, service_id: i32, kind: SchemaKind) -> Result<Option<serde_json::Value>, RepoError<TypeConversionError>> {
+        Ok(self.schemas.lock().await
+            .get(&(service_id, kind))
+            .cloned())
+    }
+
+    async fn set_schema(&self, service_id: i32, kind: SchemaKind, schema: serde_json::Value) -> Result<(), RepoError<TypeConversionError>> {
+        tracing::info!("ServiceMock:set_schema: {} for {service_id}", kind.as_str());
+        self.schemas.lock().await
+            .insert((service_id, kind), schema);
+        Ok(())
+    }
+
+    async fn delete_schema(&self, service_id: i32, kind: SchemaKind) -> Result<bool, RepoError<TypeConversionError>> {
+        Ok(self.schemas.lock().await
+            .remove(&(service_id, kind))
+            .is_some())
+    }
+}
 
 impl Users for UsersMock {
     #[tracing::instrument(skip(self), fields(user_id = %id))]
     async fn get(&self, id: UserId) -> Result<Option<SavedUser>, RepoError<TypeConversionError>> {
         let users = self.users.lock().await;
         match id {
-            UserId::Internal(internal_id) => users.values()
-                .filter(|&usr| usr.id == internal_id)
-                .cloned()
-                .map(Ok)
-                .take(1)
-                .next()
-                .transpose(),
-            UserId::External(external_id) => users.get(&external_id)
-                .map(|usr_ref| Ok(usr_ref.clone()))
-                .transpose()
+            UserId::Internal(internal_id) => {
+                let internal_id = self.merged_users.lock().await
+                    .get(&internal_id)
+                    .copied()
+                    .unwrap_or(internal_id);
+                users.values()
+                    .filter(|&usr| usr.id == internal_id)
+                    .cloned()
+                    .map(Ok)
+                    .take(1)
+                    .next()
+                    .transpose()
+            }
+            // the mock doesn't distinguish external IDs of different services
+            UserId::External(_, external_id) => match users.iter().find(|(id, _)| same_external_id(id, &external_id)) {
+                Some((_, usr)) => Ok(Some(usr.clone())),
+                None => {
+                    let linked_id = self.linked_accounts.lock().await
+                        .iter()
+                        .find(|(id, _)| same_external_id(id, &external_id))
+                        .map(|(_, &user_id)| user_id);
+                    Ok(linked_id.and_then(|id| users.values().find(|usr| usr.id == id).cloned()))
+                }
+            }
         }
     }
 
@@ -101,50 +207,566 @@ impl Users for UsersMock {
             name: user.name,
             language_code: None,
             location: None,
+            place: None,
+            timezone: None,
             premium_till: None,
+            version: 1,
+            deactivated_at: None,
+            bans: vec![],
+            contacts: vec![],
         };
 
         self.users.lock().await
-            .insert(user.external_id as ExternalId, saved_user)
+            .insert(user.external_id, saved_user)
             .map(|_| Ok(id))
             .unwrap_or(Ok(id))
     }
 
-    async fn get_user_id(&self, _: i32, external_id: i64) -> Result<Option<i64>, RepoError<TypeConversionError>> {
-        let user_id = external_id as ExternalId;
-        self.users.lock().await
-            .get(&user_id)
-            .map(|usr| Ok(usr.id))
-            .transpose()
+    async fn get_user_id(&self, _: i32, external_id: &ExternalId) -> Result<Option<i64>, RepoError<TypeConversionError>> {
+        let registered_id = self.users.lock().await
+            .get(external_id)
+            .map(|usr| usr.id);
+        let linked_id = self.linked_accounts.lock().await
+            .get(external_id)
+            .copied();
+        Ok(registered_id.or(linked_id))
     }
 
-    async fn update_value(&self, user_id: i64, target: UpdateTarget) -> Result<(), RepoError<TypeConversionError>> {
+    async fn update_value(&self, user_id: i64, target: UpdateTarget, _: Option<i32>) -> Result<(), RepoError<TypeConversionError>> {
         tracing::info!("UsersMock:update_value for {user_id} - {target:?}");
         self.modify_user(user_id, |user| {
             match target {
-                UpdateTarget::Language(code) => { user.language_code.replace(code); },
-                UpdateTarget::Location { latitude, longitude } => { user.location.replace((latitude, longitude).into()); },
+                UpdateTarget::Language(ref code) => { user.language_code.replace(code.clone()); },
+                UpdateTarget::Location { latitude, longitude } => {
+                    let location: Location = (latitude, longitude).into();
+                    user.timezone = location.timezone().or(user.timezone);
+                    user.place = location.place();
+                    user.location.replace(location);
+                },
+                UpdateTarget::Name(ref name) => { user.name.replace(name.clone()); },
+                UpdateTarget::ClearLanguage => { user.language_code.take(); },
+                UpdateTarget::ClearLocation => {
+                    user.location.take();
+                    user.place.take();
+                },
+                UpdateTarget::Timezone(timezone) => { user.timezone.replace(timezone); },
+                UpdateTarget::ClearTimezone => { user.timezone.take(); },
             }
         }).await.map_err(|e| RepoError::Database(e.into()))
     }
 
-    async fn activate_premium(&self, user_id: i64, variant: PremiumVariant) -> Result<Option<DateTime<Utc>>, RepoError<TypeConversionError>> {
+    async fn patch(&self, user_id: i64, patch: UserPatch, expected_version: Option<i64>, _: Option<i32>) -> Result<PatchOutcome, RepoError<TypeConversionError>> {
+        tracing::info!("UsersMock:patch for {user_id} - {patch:?} (expected_version = {expected_version:?})");
+        let current_version = match self.find_user(user_id).await {
+            Ok(user) => user.version,
+            Err(sqlx::Error::RowNotFound) => return Ok(PatchOutcome::NotFound),
+            Err(e) => return Err(RepoError::Database(e.into())),
+        };
+        if expected_version.is_some_and(|version| version != current_version) {
+            return Ok(PatchOutcome::VersionMismatch(current_version));
+        }
+        let patch = patch.with_derived_timezone();
+        self.modify_user(user_id, |user| {
+            let patch = patch.clone();
+            if let Some(name) = patch.name {
+                user.name = name;
+            }
+            if let Some(language_code) = patch.language_code {
+                user.language_code = language_code;
+            }
+            if let Some(location) = patch.location {
+                user.place = location.as_ref().and_then(Location::place);
+                user.location = location;
+            }
+            if let Some(timezone) = patch.timezone {
+                user.timezone = timezone;
+            }
+        }).await.map_err(|e| RepoError::Database(e.into()))?;
+        self.find_user(user_id).await
+            .map(|user| PatchOutcome::Patched(Box::new(user)))
+            .map_err(|e| RepoError::Database(e.into()))
+    }
+
+    async fn activate_premium(&self, user_id: i64, variant: PremiumVariant, _: Option<i32>) -> Result<PremiumOutcome, RepoError<TypeConversionError>> {
         tracing::info!("UsersMock:activate_premium for {user_id} for {}", variant as u32);
-        let premium_active = self.find_user(user_id).await
-            .map_err(|e| RepoError::Database(e.into()))?
-            .premium_till.is_some();
-        if premium_active {
-            return Ok(None)
+        let user = match self.find_user(user_id).await {
+            Ok(user) => user,
+            Err(sqlx::Error::RowNotFound) => return Ok(PremiumOutcome::NotFound),
+            Err(e) => return Err(RepoError::Database(e.into())),
+        };
+        if user.deactivated() {
+            return Ok(PremiumOutcome::Deactivated)
+        }
+        // the mock doesn't prolong the premium
+        if user.premium_till.is_some() {
+            return Ok(PremiumOutcome::NotFound)
         }
 
         self.modify_user(user_id, |user| {
             user.premium_till.replace(variant.into());
         }).await.map_err(|e| RepoError::Database(e.into()))?;
-        Ok(Some(variant.into()))
+        Ok(PremiumOutcome::Activated(variant.into()))
+    }
+
+    async fn get_services(&self, user_id: i64) -> Result<Vec<Service>, RepoError<TypeConversionError>> {
+        // the mock doesn't track the mappings between users and services
+        self.find_user(user_id).await
+            .map_err(|e| RepoError::Database(e.into()))?;
+        Ok(vec![])
+    }
+
+    async fn create_link_code(&self, user_id: i64) -> Result<Option<LinkCode>, RepoError<TypeConversionError>> {
+        if self.find_user(user_id).await.is_err() {
+            return Ok(None)
+        }
+        let link_code = LinkCode {
+            code: LinkCode::generate_code(),
+            expires_at: Utc::now() + chrono::TimeDelta::minutes(10),
+        };
+        self.link_codes.lock().await
+            .insert(link_code.code.clone(), user_id);
+        Ok(Some(link_code))
+    }
+
+    async fn redeem_link_code(&self, code: &str, user: ExternalUser, service_id: i32, _: serde_json::Value) -> Result<LinkOutcome, RepoError<TypeConversionError>> {
+        tracing::info!("UsersMock:redeem_link_code: {user:?} (service_id = {service_id})");
+        let Some(user_id) = self.link_codes.lock().await.remove(&LinkCode::normalize(code)) else {
+            return Ok(LinkOutcome::InvalidCode)
+        };
+        match self.get_user_id(service_id, &user.external_id).await? {
+            Some(mapped_id) if mapped_id != user_id => Ok(LinkOutcome::MappedToAnotherUser(mapped_id)),
+            _ => {
+                self.linked_accounts.lock().await
+                    .insert(user.external_id, user_id);
+                Ok(LinkOutcome::Linked(user_id))
+            }
+        }
+    }
+
+    async fn merge(&self, source_id: i64, target_id: i64, _: Option<i32>) -> Result<MergeOutcome, RepoError<TypeConversionError>> {
+        tracing::info!("UsersMock:merge {source_id} into {target_id}");
+        if source_id == target_id {
+            return Ok(MergeOutcome::SameUser)
+        }
+        let (Ok(source), Ok(_)) = (self.find_user(source_id).await, self.find_user(target_id).await) else {
+            return Ok(MergeOutcome::NotFound)
+        };
+        self.modify_user(target_id, |target| {
+            target.name = target.name.take().or(source.name.clone());
+            target.language_code = target.language_code.take().or(source.language_code.clone());
+            if target.location.is_none() {
+                target.location = source.location.clone();
+                target.place = source.place.clone();
+            }
+            target.timezone = target.timezone.or(source.timezone);
+            target.premium_till = target.premium_till.max(source.premium_till);
+            for contact in &source.contacts {
+                if target.contacts.iter().all(|existing| existing.kind != contact.kind) {
+                    target.contacts.push(contact.clone());
+                }
+            }
+        }).await.map_err(|e| RepoError::Database(e.into()))?;
+
+        let source_external_id = self.find_external_id(source_id).await
+            .map_err(|e| RepoError::Database(e.into()))?;
+        self.users.lock().await
+            .remove(&source_external_id);
+        let mut linked_accounts = self.linked_accounts.lock().await;
+        linked_accounts.insert(source_external_id, target_id);
+        linked_accounts.values_mut()
+            .filter(|id| **id == source_id)
+            .for_each(|id| *id = target_id);
+        self.merged_users.lock().await
+            .insert(source_id, target_id);
+
+        Ok(MergeOutcome::Merged(Box::new(self.find_user(target_id).await
+            .map_err(|e| RepoError::Database(e.into()))?)))
+    }
+
+    async fn unlink(&self, service_id: i32, external_id: &ExternalId, _: bool) -> Result<Option<i64>, RepoError<TypeConversionError>> {
+        tracing::info!("UsersMock:unlink: {external_id} (service_id = {service_id})");
+        let mut linked_accounts = self.linked_accounts.lock().await;
+        let linked_key = linked_accounts.keys().find(|id| same_external_id(id, external_id)).cloned();
+        if let Some(user_id) = linked_key.and_then(|key| linked_accounts.remove(&key)) {
+            return Ok(Some(user_id))
+        }
+        let mut users = self.users.lock().await;
+        let user_key = users.keys().find(|id| same_external_id(id, external_id)).cloned();
+        let Some(user) = user_key.and_then(|key| users.remove(&key)) else {
+            return Ok(None)
+        };
+        // keep the user under a key no lookup will use
+        let user_id = user.id;
+        users.insert(ExternalId::String(format!("detached:{user_id}")), user);
+        Ok(Some(user_id))
+    }
+
+    async fn get_many(&self, keys: &[BatchKey]) -> Result<HashMap<BatchKey, SavedUser>, RepoError<TypeConversionError>> {
+        let mut users = HashMap::new();
+        for key in keys {
+            let id = match key {
+                BatchKey::Internal(id) => UserId::Internal(*id),
+                BatchKey::External(service_id, external_id) => UserId::External(*service_id, external_id.clone()),
+            };
+            if let Some(user) = self.get(id).await? {
+                users.insert(key.clone(), user);
+            }
+        }
+        Ok(users)
+    }
+
+    async fn list(&self, filter: &UserFilter, after: Option<Cursor>, limit: u32) -> Result<Page<SavedUser>, RepoError<TypeConversionError>> {
+        // the mock knows neither the services of the users nor their registration time
+        let mut users: Vec<SavedUser> = self.users.lock().await
+            .values()
+            .filter(|usr| usr.deactivated_at.is_none() && after.is_none_or(|cursor| usr.id > cursor.0))
+            .filter(|usr| filter.premium.is_none_or(|premium| match premium {
+                PremiumFilter::Active => usr.premium(),
+                PremiumFilter::Expired => !usr.premium(),
+            }))
+            .filter(|usr| filter.language_code.as_ref().is_none_or(|code| usr.language_code.as_ref() == Some(code)))
+            .filter(|usr| filter.has_location.is_none_or(|has_location| usr.location.is_some() == has_location))
+            .cloned()
+            .collect();
+        users.sort_by_key(|usr| usr.id);
+        let next = if users.len() > limit as usize {
+            users.truncate(limit as usize);
+            users.last().map(|usr| Cursor(usr.id))
+        } else {
+            None
+        };
+        Ok(Page { items: users, next })
+    }
+
+    async fn search(&self, query: &str, _: Option<i32>, limit: u32) -> Result<Vec<SavedUser>, RepoError<TypeConversionError>> {
+        // a case-insensitive substring search is close enough for the mock
+        let query = query.to_lowercase();
+        let mut users: Vec<SavedUser> = self.users.lock().await
+            .values()
+            .filter(|usr| usr.deactivated_at.is_none())
+            .filter(|usr| usr.name.as_ref().is_some_and(|name| name.to_lowercase().contains(&query)))
+            .cloned()
+            .collect();
+        users.sort_by_key(|usr| usr.id);
+        users.truncate(limit as usize);
+        Ok(users)
+    }
+
+    async fn nearby(&self, query: &NearbyQuery, after: Option<Cursor>, limit: u32) -> Result<Page<NearbyUser>, RepoError<TypeConversionError>> {
+        // the mock knows no services of the users either
+        let mut users: Vec<NearbyUser> = self.users.lock().await
+            .values()
+            .filter(|usr| usr.deactivated_at.is_none() && after.is_none_or(|cursor| usr.id > cursor.0))
+            .filter_map(|usr| {
+                let distance_km = usr.location.as_ref()?.distance_km(&query.center);
+                (distance_km <= query.radius_km).then(|| NearbyUser { user: usr.clone(), distance_km })
+            })
+            .collect();
+        users.sort_by_key(|nearby| nearby.user.id);
+        let next = if users.len() > limit as usize {
+            users.truncate(limit as usize);
+            users.last().map(|nearby| Cursor(nearby.user.id))
+        } else {
+            None
+        };
+        Ok(Page { items: users, next })
+    }
+
+    async fn changes(&self, user_id: i64, after: Option<Cursor>, limit: u32) -> Result<Page<UserChange>, RepoError<TypeConversionError>> {
+        let mut changes: Vec<UserChange> = self.changes.lock().await.iter()
+            .rev()
+            .filter(|(id, change)| *id == user_id && after.is_none_or(|cursor| change.id < cursor.0))
+            .map(|(_, change)| change.clone())
+            .collect();
+        let next = if changes.len() > limit as usize {
+            changes.truncate(limit as usize);
+            changes.last().map(|change| Cursor(change.id))
+        } else {
+            None
+        };
+        Ok(Page { items: changes, next })
+    }
+
+    async fn deactivate(&self, user_id: i64, _: Option<i32>) -> Result<Option<SavedUser>, RepoError<TypeConversionError>> {
+        tracing::info!("UsersMock:deactivate {user_id}");
+        self.set_deactivated(user_id, true).await
+    }
+
+    async fn reactivate(&self, user_id: i64, _: Option<i32>) -> Result<Option<SavedUser>, RepoError<TypeConversionError>> {
+        tracing::info!("UsersMock:reactivate {user_id}");
+        self.set_deactivated(user_id, false).await
+    }
+
+    async fn ban(&self, user_id: i64, service_id: Option<i32>, ban: NewBan) -> Result<Option<Ban>, RepoError<TypeConversionError>> {
+        tracing::info!("UsersMock:ban {user_id} (service_id = {service_id:?})");
+        if self.find_user(user_id).await.is_err() {
+            return Ok(None)
+        }
+        // the mock doesn't know the services, so only the scope of the ban is kept
+        let ban = Ban {
+            service: None,
+            reason: ban.reason,
+            issuer: ban.issuer,
+            issued_at: Utc::now(),
+            expires_at: ban.expires_at,
+        };
+        self.bans.lock().await
+            .insert((user_id, service_id), ban.clone());
+        self.sync_bans(user_id).await;
+        Ok(Some(ban))
+    }
+
+    async fn unban(&self, user_id: i64, service_id: Option<i32>) -> Result<bool, RepoError<TypeConversionError>> {
+        tracing::info!("UsersMock:unban {user_id} (service_id = {service_id:?})");
+        let removed = self.bans.lock().await
+            .remove(&(user_id, service_id))
+            .is_some();
+        self.sync_bans(user_id).await;
+        Ok(removed)
+    }
+
+    async fn settings(&self, user_id: i64, service_id: i32) -> Result<Settings, RepoError<TypeConversionError>> {
+        // the mock doesn't know the defaults of the services
+        Ok(self.settings.lock().await
+            .iter()
+            .filter(|((uid, sid, _), _)| *uid == user_id && *sid == service_id)
+            .map(|((_, _, key), value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    async fn set_setting(&self, user_id: i64, service_id: i32, key: &str, value: serde_json::Value) -> Result<bool, RepoError<TypeConversionError>> {
+        tracing::info!("UsersMock:set_setting {key} for {user_id} (service_id = {service_id})");
+        if self.find_user(user_id).await.is_err() {
+            return Ok(false)
+        }
+        self.settings.lock().await
+            .insert((user_id, service_id, key.to_owned()), value);
+        Ok(true)
+    }
+
+    async fn delete_setting(&self, user_id: i64, service_id: i32, key: &str) -> Result<bool, RepoError<TypeConversionError>> {
+        Ok(self.settings.lock().await
+            .remove(&(user_id, service_id, key.to_owned()))
+            .is_some())
+    }
+
+    async fn request_contact_verification(&self, user_id: i64, contact: &NewContact) -> Result<ContactRequestOutcome, RepoError<TypeConversionError>> {
+        tracing::info!("UsersMock:request_contact_verification {} for {user_id}", contact.kind);
+        if self.find_user(user_id).await.is_err() {
+            return Ok(ContactRequestOutcome::NotFound)
+        }
+        if self.contact_owner(contact.kind, &contact.value).await.is_some_and(|owner_id| owner_id != user_id) {
+            return Ok(ContactRequestOutcome::TakenByAnotherUser)
+        }
+        // the mock throttles only the repeated requests of the same kind
+        let retry_after = self.pending_contacts.lock().await
+            .get(&(user_id, contact.kind))
+            .map(|(_, verification)| verification.expires_at - CONTACT_CODE_TTL + CONTACT_CODE_COOLDOWN)
+            .filter(|retry_after| *retry_after > Utc::now());
+        if let Some(retry_after) = retry_after {
+            return Ok(ContactRequestOutcome::TooManyRequests(retry_after))
+        }
+        let verification = VerificationCode {
+            code: VerificationCode::generate_code(),
+            expires_at: Utc::now() + CONTACT_CODE_TTL,
+        };
+        self.pending_contacts.lock().await
+            .insert((user_id, contact.kind), (contact.clone(), verification.clone()));
+        Ok(ContactRequestOutcome::Requested(verification))
+    }
+
+    async fn confirm_contact(&self, user_id: i64, kind: ContactKind, code: &str) -> Result<ContactConfirmation, RepoError<TypeConversionError>> {
+        tracing::info!("UsersMock:confirm_contact {kind} for {user_id}");
+        // the mock doesn't count the failed attempts
+        let mut pending_contacts = self.pending_contacts.lock().await;
+        let Some((contact, verification)) = pending_contacts.get(&(user_id, kind)) else {
+            return Ok(ContactConfirmation::NotPending)
+        };
+        if verification.code != code.trim() || verification.expires_at <= Utc::now() {
+            return Ok(ContactConfirmation::InvalidCode)
+        }
+        if self.contact_owner(kind, &contact.value).await.is_some_and(|owner_id| owner_id != user_id) {
+            return Ok(ContactConfirmation::TakenByAnotherUser)
+        }
+        let contact = Contact {
+            kind,
+            value: contact.value.clone(),
+            verified_at: Utc::now(),
+        };
+        pending_contacts.remove(&(user_id, kind));
+        if let Some(user) = self.users.lock().await.values_mut().find(|usr| usr.id == user_id) {
+            user.contacts.retain(|existing| existing.kind != kind);
+            user.contacts.push(contact.clone());
+        }
+        Ok(ContactConfirmation::Confirmed(contact))
+    }
+
+    async fn delete_contact(&self, user_id: i64, kind: ContactKind) -> Result<bool, RepoError<TypeConversionError>> {
+        tracing::info!("UsersMock:delete_contact {kind} for {user_id}");
+        let pending = self.pending_contacts.lock().await
+            .remove(&(user_id, kind))
+            .is_some();
+        let verified = match self.users.lock().await.values_mut().find(|usr| usr.id == user_id) {
+            Some(user) => {
+                let count = user.contacts.len();
+                user.contacts.retain(|existing| existing.kind != kind);
+                user.contacts.len() != count
+            }
+            None => false,
+        };
+        Ok(pending || verified)
+    }
+
+    async fn create_credentials(&self, user_id: i64, credentials: &NewCredentials) -> Result<CredentialsOutcome, RepoError<TypeConversionError>> {
+        tracing::info!("UsersMock:create_credentials for {user_id}");
+        if self.find_user(user_id).await.is_err() {
+            return Ok(CredentialsOutcome::NotFound)
+        }
+        let mut all_credentials = self.credentials.lock().await;
+        if all_credentials.contains_key(&user_id) {
+            return Ok(CredentialsOutcome::AlreadyExists)
+        }
+        let username = credentials.username.clone().map(String::from);
+        let taken = all_credentials.values().any(|(existing, _)|
+            (username.is_some() && existing.username == username) || (credentials.email.is_some() && existing.email == credentials.email));
+        if taken {
+            return Ok(CredentialsOutcome::LoginTaken)
+        }
+        if let Some(email) = &credentials.email && self.contact_owner(ContactKind::Email, email).await != Some(user_id) {
+            return Ok(CredentialsOutcome::EmailNotVerified)
+        }
+        let created = Credentials {
+            user_id,
+            username,
+            email: credentials.email.clone(),
+            password_hash: credentials.password_hash.clone(),
+            locked_until: None,
+        };
+        all_credentials.insert(user_id, (created.clone(), 0));
+        Ok(CredentialsOutcome::Created(created))
+    }
+
+    async fn credentials(&self, key: &CredentialsKey) -> Result<Option<Credentials>, RepoError<TypeConversionError>> {
+        Ok(self.find_credentials(key).await)
+    }
+
+    async fn start_login_attempt(&self, user_id: i64) -> Result<LoginAttempt, RepoError<TypeConversionError>> {
+        tracing::info!("UsersMock:start_login_attempt for {user_id}");
+        let mut all_credentials = self.credentials.lock().await;
+        let Some((credentials, failed_attempts)) = all_credentials.get_mut(&user_id) else {
+            return Ok(LoginAttempt::NotFound)
+        };
+        match credentials.locked_until {
+            Some(locked_until) if locked_until > Utc::now() => return Ok(LoginAttempt::Locked(locked_until)),
+            Some(_) => *failed_attempts = 1,
+            None => *failed_attempts += 1,
+        }
+        credentials.locked_until = (*failed_attempts >= LOGIN_MAX_FAILED_ATTEMPTS).then(|| Utc::now() + LOGIN_LOCKOUT);
+        Ok(LoginAttempt::Started(credentials.password_hash.clone()))
+    }
+
+    async fn reset_login_attempts(&self, user_id: i64) -> Result<(), RepoError<TypeConversionError>> {
+        tracing::info!("UsersMock:reset_login_attempts for {user_id}");
+        if let Some((credentials, failed_attempts)) = self.credentials.lock().await.get_mut(&user_id) {
+            *failed_attempts = 0;
+            credentials.locked_until = None;
+        }
+        Ok(())
+    }
+
+    async fn set_password(&self, user_id: i64, password_hash: &str) -> Result<bool, RepoError<TypeConversionError>> {
+        tracing::info!("UsersMock:set_password for {user_id}");
+        self.password_resets.lock().await
+            .retain(|_, id| *id != user_id);
+        let mut all_credentials = self.credentials.lock().await;
+        let Some((credentials, failed_attempts)) = all_credentials.get_mut(&user_id) else {
+            return Ok(false)
+        };
+        credentials.password_hash = password_hash.to_owned();
+        credentials.locked_until = None;
+        *failed_attempts = 0;
+        Ok(true)
+    }
+
+    async fn create_password_reset(&self, login: &Login) -> Result<Option<PasswordReset>, RepoError<TypeConversionError>> {
+        // the mock doesn't expire the tokens
+        let Some(credentials) = self.find_credentials(&CredentialsKey::Login(login.clone())).await else {
+            return Ok(None)
+        };
+        let email = self.find_user(credentials.user_id).await.ok()
+            .and_then(|user| user.contacts.into_iter().find(|contact| contact.kind == ContactKind::Email));
+        let Some(email) = email else {
+            return Ok(None)
+        };
+        let reset = PasswordReset {
+            user_id: credentials.user_id,
+            email: email.value,
+            token: PasswordReset::generate_token(),
+            expires_at: Utc::now() + chrono::TimeDelta::hours(1),
+        };
+        self.password_resets.lock().await
+            .insert(reset.token.clone(), reset.user_id);
+        Ok(Some(reset))
+    }
+
+    async fn redeem_password_reset(&self, token: &str, password_hash: &str) -> Result<Option<i64>, RepoError<TypeConversionError>> {
+        let Some(user_id) = self.password_resets.lock().await.remove(token.trim()) else {
+            return Ok(None)
+        };
+        self.set_password(user_id, password_hash).await?;
+        Ok(Some(user_id))
     }
 }
 
 impl UsersMock {
+    /// Copies the active bans to the user, so all the lookups return them
+    async fn sync_bans(&self, user_id: i64) {
+        let bans: Vec<Ban> = self.bans.lock().await
+            .iter()
+            .filter(|((id, _), ban)| *id == user_id && ban.expires_at.is_none_or(|till| till > Utc::now()))
+            .map(|(_, ban)| ban.clone())
+            .collect();
+        if let Some(user) = self.users.lock().await.values_mut().find(|usr| usr.id == user_id) {
+            user.bans = bans;
+        }
+    }
+
+    async fn find_credentials(&self, key: &CredentialsKey) -> Option<Credentials> {
+        self.credentials.lock().await
+            .values()
+            .map(|(credentials, _)| credentials)
+            .find(|credentials| match key {
+                CredentialsKey::User(id) => credentials.user_id == *id,
+                CredentialsKey::Login(login) => (login.username().is_some() && credentials.username.as_deref() == login.username())
+                    || (login.email().is_some() && credentials.email.as_deref() == login.email()),
+            })
+            .cloned()
+    }
+
+    /// The user who has verified the contact
+    async fn contact_owner(&self, kind: ContactKind, value: &str) -> Option<i64> {
+        self.users.lock().await
+            .values()
+            .find(|usr| usr.contacts.iter().any(|contact| contact.kind == kind && contact.value == value))
+            .map(|usr| usr.id)
+    }
+
+    async fn set_deactivated(&self, user_id: i64, deactivated: bool) -> Result<Option<SavedUser>, RepoError<TypeConversionError>> {
+        let user = match self.find_user(user_id).await {
+            Ok(user) => user,
+            Err(sqlx::Error::RowNotFound) => return Ok(None),
+            Err(e) => return Err(RepoError::Database(e.into())),
+        };
+        if user.deactivated() != deactivated {
+            self.modify_user(user_id, |user| {
+                user.deactivated_at = deactivated.then(Utc::now);
+            }).await.map_err(|e| RepoError::Database(e.into()))?;
+        }
+        self.find_user(user_id).await
+            .map(Some)
+            .map_err(|e| RepoError::Database(e.into()))
+    }
+
     async fn find_user(&self, id: i64) -> Result<SavedUser, sqlx::Error> {
         let external_id = self.find_external_id(id).await?;
         let user = self.users.lock().await
@@ -159,14 +781,30 @@ impl UsersMock {
         let mut users = self.users.lock().await;
         let user= users.get_mut(&external_id)
             .expect("user must be in the HashMap here!");
+        let old_user = user.clone();
         action(user);
+        user.version += 1;
+
+        // the mock doesn't know the actors of the changes
+        let mut changes = self.changes.lock().await;
+        for change in user.changes_since(&old_user) {
+            let change = UserChange {
+                id: changes.len() as i64 + 1,
+                field: change.field,
+                old_value: change.old_value,
+                new_value: change.new_value,
+                actor: None,
+                changed_at: Utc::now(),
+            };
+            changes.push((id, change));
+        }
         Ok(())
     }
 
     async fn find_external_id(&self, id: i64) -> Result<ExternalId, sqlx::Error> {
         self.users.lock().await.iter()
             .filter(|(_, u)| u.id == id)
-            .map(|(ext_id, _)| *ext_id)
+            .map(|(ext_id, _)| ext_id.clone())
             .take(1)
             .next()
             .ok_or(sqlx::Error::RowNotFound)
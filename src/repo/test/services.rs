@@ -1,4 +1,5 @@
-use crate::dto::{Service, ServiceType};
+use serde_json::json;
+use crate::dto::{SchemaKind, Service, ServiceType, ServiceTypeInfo};
 use crate::repo;
 use crate::repo::services::Services;
 use crate::repo::test::start_postgres;
@@ -12,12 +13,12 @@ async fn test_services() -> anyhow::Result<()> {
 
     let service = Service {
         name: TEST_NAME.to_owned(),
-        service_type: ServiceType::TelegramBot,
+        service_type: ServiceType::TELEGRAM_BOT,
     };
 
     assert!(services.get_id(&service).await?.is_none());
 
-    let created_id = services.create(ServiceType::TelegramBot, TEST_NAME).await?;
+    let created_id = services.create(ServiceType::TELEGRAM_BOT, TEST_NAME).await?;
     let fetched_id = services.get_id(&service).await?.expect("fetched_id must be");
     let fetched_cached_id = services.get_id(&service).await?.expect("fetched_cached_id must be");
 
@@ -26,3 +27,52 @@ async fn test_services() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_service_types() -> anyhow::Result<()> {
+    let (_container, db) = start_postgres().await;
+    let services = repo::ServicesPostgres::new(db);
+
+    let website = services.get_type(&ServiceType::WEBSITE).await?.expect("website must be seeded");
+    assert!(website.string_ids);
+    let telegram_bot = services.get_type(&ServiceType::TELEGRAM_BOT).await?.expect("telegram-bot must be seeded");
+    assert!(!telegram_bot.string_ids);
+
+    let discord_bot = ServiceTypeInfo {
+        name: "discord-bot".to_owned().try_into()?,
+        string_ids: true,
+    };
+    assert!(services.get_type(&discord_bot.name).await?.is_none());
+    assert!(services.create_type(&discord_bot).await?);
+    assert!(!services.create_type(&discord_bot).await?);
+    assert_eq!(services.get_type(&discord_bot.name).await?, Some(discord_bot.clone()));
+    assert_eq!(services.list_types().await?.len(), 5);
+
+    let created_id = services.create(discord_bot.name.clone(), TEST_NAME).await?;
+    let service = Service {
+        name: TEST_NAME.to_owned(),
+        service_type: discord_bot.name,
+    };
+    assert_eq!(services.get_id(&service).await?, Some(created_id));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_schemas() -> anyhow::Result<()> {
+    let (_container, db) = start_postgres().await;
+    let services = repo::ServicesPostgres::new(db);
+    let service_id = services.create(ServiceType::TELEGRAM_BOT, TEST_NAME).await?;
+
+    assert!(services.schema(service_id, SchemaKind::Consent).await?.is_none());
+    services.set_schema(service_id, SchemaKind::Consent, json!({"type": "object"})).await?;
+    services.set_schema(service_id, SchemaKind::Consent, json!({"type": "object", "required": ["version"]})).await?;
+    assert_eq!(services.schema(service_id, SchemaKind::Consent).await?, Some(json!({"type": "object", "required": ["version"]})));
+    assert!(services.schema(service_id, SchemaKind::Settings).await?.is_none());
+
+    assert!(services.delete_schema(service_id, SchemaKind::Consent).await?);
+    assert!(!services.delete_schema(service_id, SchemaKind::Consent).await?);
+    assert!(services.schema(service_id, SchemaKind::Consent).await?.is_none());
+
+    Ok(())
+}
@@ -1,42 +1,59 @@
-use chrono::{Months, Timelike, Utc};
+use chrono::{Months, TimeDelta, Timelike, Utc};
 use serde_json::json;
 use sqlx::{Pool, Postgres};
 use tokio::join;
-use crate::dto::{Code, ExternalUser, PremiumVariant, ServiceType};
+use crate::dto::{ChangedField, ContactConfirmation, ContactKind, ContactRequestOutcome, CredentialsOutcome, Cursor, ExternalId, ExternalUser, LanguageTag, LinkOutcome, Login, LoginAttempt, NewBan, NewContact, NewCredentials, PremiumVariant, Service, ServiceType, Timezone};
 use crate::repo;
 use crate::repo::services::Services;
 use crate::repo::test::start_postgres;
-use crate::repo::users::{UpdateTarget, UserId, Users};
+use crate::repo::users::{BatchKey, CredentialsKey, MergeOutcome, NearbyQuery, PatchOutcome, PremiumFilter, PremiumOutcome, UpdateTarget, UserFilter, UserId, UserPatch, Users, LOGIN_MAX_FAILED_ATTEMPTS};
 
 const TEST_UID_EXT: i64 = 1234567890;
 const TEST_NAME: &str = "kozalo";
 const TEST_LOCATION: (f64, f64) = (123.45, 67.890);
 const TEST_SERVICE: &str = "SadBot";
+const TEST_WEBSITE_UID: &str = "0b5a2f6e-6a4c-4d8e-9a59-6f4e0c1d2b3a";
 
 #[tokio::test]
 async fn test_users() -> anyhow::Result<()> {
     let (_container, db) = start_postgres().await;
 
     let users = repo::UsersPostgres::new(db.clone());
-    let external_id = UserId::External(TEST_UID_EXT);
-    let code = "ru".try_into()?;
+    let service_id = create_service(&db).await?;
+    let external_id = UserId::External(service_id, TEST_UID_EXT.into());
+    // a region subtag doesn't fit the two-letter codes of the past
+    let code: LanguageTag = "pt-BR".try_into()?;
 
-    assert!(users.get(external_id).await?.is_none());
+    assert!(users.get(external_id.clone()).await?.is_none());
 
-    let service_id = create_service(&db).await?;
     let created_user_id = create_user(&users, service_id).await?;
 
     test_get_user_id(&users, service_id, created_user_id).await;
     test_get_created_user(&users, external_id, created_user_id).await;
-    test_update_user(&users, created_user_id, code).await?;
-    test_fetch_updated_user(&users, created_user_id, code).await;
+    test_get_many(&users, service_id, created_user_id).await?;
+    test_get_services(&users, created_user_id).await;
+    test_link_account(&users, &db, created_user_id).await?;
+    test_update_user(&users, service_id, created_user_id, &code).await?;
+    test_fetch_updated_user(&users, created_user_id, &code).await;
+    test_list(&users, service_id, created_user_id, &code).await?;
+    test_search(&users, service_id, created_user_id).await?;
+    test_patch(&users, created_user_id).await?;
+    test_changes(&users, created_user_id).await?;
+    test_timezone(&users, created_user_id).await?;
+    test_place(&users, &db, created_user_id).await?;
+    test_nearby(&users, service_id, created_user_id).await?;
+    test_deactivation(&users, created_user_id).await?;
+    test_bans(&users, service_id, created_user_id).await?;
+    test_settings(&users, &db, service_id, created_user_id).await?;
+    test_contacts(&users, &db, service_id, created_user_id).await?;
+    test_credentials(&users, service_id, created_user_id).await?;
 
     Ok(())
 }
 
 async fn create_service(db: &Pool<Postgres>) -> anyhow::Result<i32> {
     repo::ServicesPostgres::new(db.clone())
-        .create(ServiceType::TelegramBot, TEST_SERVICE)
+        .create(ServiceType::TELEGRAM_BOT, TEST_SERVICE)
         .await
         .map_err(Into::into)
 }
@@ -44,7 +61,7 @@ async fn create_service(db: &Pool<Postgres>) -> anyhow::Result<i32> {
 async fn create_user(users: &repo::UsersPostgres, service_id: i32) -> anyhow::Result<i64> {
     let external_user = ExternalUser {
         name: Some(TEST_NAME.to_owned()),
-        external_id: TEST_UID_EXT,
+        external_id: TEST_UID_EXT.into(),
     };
     users.register(external_user, service_id, json!({"test": true}))
         .await
@@ -52,7 +69,7 @@ async fn create_user(users: &repo::UsersPostgres, service_id: i32) -> anyhow::Re
 }
 
 async fn test_get_user_id(users: &repo::UsersPostgres, service_id: i32, user_id: i64) {
-    let fetched_user_id = users.get_user_id(service_id, TEST_UID_EXT)
+    let fetched_user_id = users.get_user_id(service_id, &TEST_UID_EXT.into())
         .await
         .expect("fetched_user_id must be");
     assert_eq!(fetched_user_id, Some(user_id));
@@ -70,23 +87,90 @@ async fn test_get_created_user(users: &repo::UsersPostgres, external_id: UserId,
     assert!(fetched_user.premium_till.is_none());
 }
 
-async fn test_update_user(users: &repo::UsersPostgres, created_user_id: i64, code: Code) -> anyhow::Result<()> {
+async fn test_get_many(users: &repo::UsersPostgres, service_id: i32, user_id: i64) -> anyhow::Result<()> {
+    let found_keys = [
+        BatchKey::Internal(user_id),
+        BatchKey::External(service_id, TEST_UID_EXT.into()),
+        // the same ID in the string form is a distinct key
+        BatchKey::External(service_id, ExternalId::String(TEST_UID_EXT.to_string())),
+    ];
+    let missing_keys = [
+        BatchKey::Internal(user_id + 1),
+        BatchKey::External(service_id, (TEST_UID_EXT + 1).into()),
+        BatchKey::External(service_id + 1, TEST_UID_EXT.into()),
+    ];
+    let keys: Vec<_> = found_keys.iter().chain(&missing_keys).cloned().collect();
+
+    let users = users.get_many(&keys).await?;
+    assert_eq!(users.len(), found_keys.len());
+    for key in &found_keys {
+        assert_eq!(users.get(key).map(|user| user.id), Some(user_id));
+    }
+    assert!(missing_keys.iter().all(|key| !users.contains_key(key)));
+    Ok(())
+}
+
+async fn test_get_services(users: &repo::UsersPostgres, user_id: i64) {
+    let services = users.get_services(user_id)
+        .await
+        .expect("services must be");
+    assert_eq!(services, vec![Service {
+        name: TEST_SERVICE.to_owned(),
+        service_type: ServiceType::TELEGRAM_BOT,
+    }]);
+}
+
+async fn test_link_account(users: &repo::UsersPostgres, db: &Pool<Postgres>, user_id: i64) -> anyhow::Result<()> {
+    assert!(users.create_link_code(user_id + 1).await?.is_none());
+
+    let website_id = repo::ServicesPostgres::new(db.clone())
+        .create(ServiceType::WEBSITE, TEST_SERVICE)
+        .await?;
+    let website_user = || ExternalUser {
+        external_id: TEST_WEBSITE_UID.into(),
+        name: None,
+    };
+    let link_code = users.create_link_code(user_id).await?
+        .expect("link_code must be");
+
+    let outcome = users.redeem_link_code(&link_code.code, website_user(), website_id, json!({"test": true})).await?;
+    assert_eq!(outcome, LinkOutcome::Linked(user_id));
+    let outcome = users.redeem_link_code(&link_code.code, website_user(), website_id, json!({"test": true})).await?;
+    assert_eq!(outcome, LinkOutcome::InvalidCode);
+
+    let website_uid = ExternalId::from(TEST_WEBSITE_UID);
+    assert_eq!(users.get_user_id(website_id, &website_uid).await?, Some(user_id));
+    assert_eq!(users.get_services(user_id).await?.len(), 2);
+    let fetched_user = users.get(UserId::External(website_id, website_uid.clone())).await?
+        .expect("user must be found by the string ID");
+    assert_eq!(fetched_user.id, user_id);
+    // the external IDs are scoped by the service
+    assert!(users.get(UserId::External(website_id, TEST_UID_EXT.into())).await?.is_none());
+
+    assert_eq!(users.unlink(website_id, &website_uid, true).await?, Some(user_id));
+    assert_eq!(users.unlink(website_id, &website_uid, true).await?, None);
+    assert_eq!(users.get_user_id(website_id, &website_uid).await?, None);
+    assert_eq!(users.get_services(user_id).await?.len(), 1);
+    Ok(())
+}
+
+async fn test_update_user(users: &repo::UsersPostgres, service_id: i32, created_user_id: i64, code: &LanguageTag) -> anyhow::Result<()> {
     let (r1, r2, r3) = join!(
-        users.update_value(created_user_id, UpdateTarget::Language(code)),
-        users.update_value(created_user_id, TEST_LOCATION.into()),
-        users.activate_premium(created_user_id, PremiumVariant::Month)
+        users.update_value(created_user_id, UpdateTarget::Language(code.clone()), None),
+        users.update_value(created_user_id, TEST_LOCATION.into(), None),
+        users.activate_premium(created_user_id, PremiumVariant::Month, Some(service_id))
     );
     r1?; r2?;
-    assert!(r3?.is_some());
+    assert!(matches!(r3?, PremiumOutcome::Activated(_)));
     Ok(())
 }
 
-async fn test_fetch_updated_user(users: &repo::UsersPostgres, created_user_id: i64, code: Code) {
+async fn test_fetch_updated_user(users: &repo::UsersPostgres, created_user_id: i64, code: &LanguageTag) {
     let fetched_user = users.get(UserId::Internal(created_user_id))
         .await
         .expect("couldn't fetch the updated user")
         .expect("updated user must be");
-    assert_eq!(fetched_user.language_code, Some(code));
+    assert_eq!(fetched_user.language_code.as_ref(), Some(code));
     assert_eq!(fetched_user.location, Some(TEST_LOCATION.into()));
 
     let now = Utc::now()
@@ -97,3 +181,470 @@ async fn test_fetch_updated_user(users: &repo::UsersPostgres, created_user_id: i
         .and_then(|till| till.with_nanosecond(0));
     assert_eq!(fetched_date, now);
 }
+
+async fn test_list(users: &repo::UsersPostgres, service_id: i32, user_id: i64, code: &LanguageTag) -> anyhow::Result<()> {
+    let another_user = ExternalUser {
+        external_id: (TEST_UID_EXT + 1).into(),
+        name: None,
+    };
+    let another_user_id = users.register(another_user, service_id, json!({"test": true})).await?;
+
+    let page = users.list(&UserFilter::default(), None, 1).await?;
+    assert_eq!(page.items.iter().map(|user| user.id).collect::<Vec<_>>(), vec![user_id]);
+    let page = users.list(&UserFilter::default(), page.next, 1).await?;
+    assert_eq!(page.items.iter().map(|user| user.id).collect::<Vec<_>>(), vec![another_user_id]);
+    assert!(page.next.is_none());
+
+    let filter = UserFilter {
+        service_id: Some(service_id),
+        premium: Some(PremiumFilter::Active),
+        language_code: Some(code.clone()),
+        registered_after: Some(Utc::now() - Months::new(1)),
+        has_location: Some(true),
+    };
+    let page = users.list(&filter, None, 10).await?;
+    assert_eq!(page.items.iter().map(|user| user.id).collect::<Vec<_>>(), vec![user_id]);
+
+    let filter = UserFilter {
+        premium: Some(PremiumFilter::Expired),
+        ..UserFilter::default()
+    };
+    let page = users.list(&filter, None, 10).await?;
+    assert_eq!(page.items.iter().map(|user| user.id).collect::<Vec<_>>(), vec![another_user_id]);
+    let filter = UserFilter {
+        service_id: Some(service_id + 100),
+        ..UserFilter::default()
+    };
+    assert!(users.list(&filter, None, 10).await?.items.is_empty());
+    Ok(())
+}
+
+async fn test_search(users: &repo::UsersPostgres, service_id: i32, user_id: i64) -> anyhow::Result<()> {
+    let found = users.search("koza", None, 10).await?;
+    assert_eq!(found.iter().map(|user| user.id).collect::<Vec<_>>(), vec![user_id]);
+    let found = users.search(TEST_NAME, Some(service_id), 10).await?;
+    assert_eq!(found.iter().map(|user| user.id).collect::<Vec<_>>(), vec![user_id]);
+    assert!(users.search(TEST_NAME, Some(service_id + 100), 10).await?.is_empty());
+    assert!(users.search("nobody", None, 10).await?.is_empty());
+    Ok(())
+}
+
+async fn test_patch(users: &repo::UsersPostgres, user_id: i64) -> anyhow::Result<()> {
+    let patch = UserPatch {
+        name: Some(Some("SadBot".to_owned())),
+        language_code: Some(Some("en".try_into()?)),
+        location: Some(None),
+        timezone: None,
+    };
+    let PatchOutcome::Patched(patched) = users.patch(user_id, patch.clone(), None, None).await? else {
+        panic!("patched user must be");
+    };
+    assert_eq!(patched.name, Some("SadBot".to_owned()));
+    assert_eq!(patched.language_code, Some("en".try_into()?));
+    assert!(patched.location.is_none());
+    assert!(patched.premium_till.is_some());
+
+    let version = patched.version;
+    let name_patch = UserPatch { name: Some(None), ..UserPatch::default() };
+    let PatchOutcome::Patched(patched) = users.patch(user_id, name_patch.clone(), Some(version), None).await? else {
+        panic!("patched user must be");
+    };
+    assert!(patched.name.is_none());
+    assert_eq!(patched.language_code, Some("en".try_into()?));
+    assert_eq!(patched.version, version + 1);
+
+    let outcome = users.patch(user_id, name_patch, Some(version), None).await?;
+    assert!(matches!(outcome, PatchOutcome::VersionMismatch(current) if current == version + 1));
+    assert!(matches!(users.patch(user_id + 100, patch.clone(), None, None).await?, PatchOutcome::NotFound));
+    assert!(matches!(users.patch(user_id + 100, patch, Some(version), None).await?, PatchOutcome::NotFound));
+
+    users.update_value(user_id, UpdateTarget::ClearLanguage, None).await?;
+    users.update_value(user_id, UpdateTarget::Name(TEST_NAME.to_owned()), None).await?;
+    let fetched_user = users.get(UserId::Internal(user_id)).await?
+        .expect("user must be");
+    assert!(fetched_user.language_code.is_none());
+    assert_eq!(fetched_user.name, Some(TEST_NAME.to_owned()));
+    assert_eq!(fetched_user.version, version + 3);
+    Ok(())
+}
+
+async fn test_changes(users: &repo::UsersPostgres, user_id: i64) -> anyhow::Result<()> {
+    let changes = users.changes(user_id, None, 100).await?.items;
+    let registration = changes.last().expect("the registration must be recorded");
+    assert_eq!(registration.field, ChangedField::Name);
+    assert_eq!(registration.old_value, None);
+    assert_eq!(registration.new_value, Some(json!(TEST_NAME)));
+    assert_eq!(registration.actor.as_ref().map(|service| service.name.as_str()), Some(TEST_SERVICE));
+    let premium = changes.iter().find(|change| change.field == ChangedField::PremiumTill)
+        .expect("the premium activation must be recorded");
+    assert_eq!(premium.actor.as_ref().map(|service| service.name.as_str()), Some(TEST_SERVICE));
+    assert!(changes.iter().any(|change| change.field == ChangedField::LanguageCode && change.actor.is_none()));
+
+    tracing::info!("the latest changes go first");
+    assert_eq!(changes[0].field, ChangedField::Name);
+    assert_eq!(changes[0].old_value, None);
+    assert_eq!(changes[0].new_value, Some(json!(TEST_NAME)));
+    assert_eq!(changes[1].field, ChangedField::LanguageCode);
+    assert_eq!(changes[1].old_value, Some(json!("en")));
+    assert_eq!(changes[1].new_value, None);
+
+    let page = users.changes(user_id, None, 1).await?;
+    assert_eq!(page.items.iter().map(|change| change.id).collect::<Vec<_>>(), vec![changes[0].id]);
+    let page = users.changes(user_id, page.next, 1).await?;
+    assert_eq!(page.items.iter().map(|change| change.id).collect::<Vec<_>>(), vec![changes[1].id]);
+    assert!(users.changes(user_id + 100, None, 10).await?.items.is_empty());
+    Ok(())
+}
+
+async fn test_timezone(users: &repo::UsersPostgres, user_id: i64) -> anyhow::Result<()> {
+    let moscow = Timezone::try_from("Europe/Moscow")?;
+    let tokyo = Timezone::try_from("Asia/Tokyo")?;
+
+    tracing::info!("the timezone is derived from the location");
+    users.update_value(user_id, (55.7558, 37.6173).into(), None).await?;
+    let fetched_user = users.get(UserId::Internal(user_id)).await?
+        .expect("user must be");
+    assert_eq!(fetched_user.timezone, Some(moscow));
+    let changes = users.changes(user_id, None, 2).await?.items;
+    assert_eq!(changes[0].field, ChangedField::Timezone);
+    assert_eq!(changes[0].new_value, Some(json!("Europe/Moscow")));
+
+    tracing::info!("an explicit timezone takes precedence");
+    let patch = UserPatch {
+        location: Some(Some((55.7558, 37.6173).into())),
+        timezone: Some(Some(tokyo)),
+        ..UserPatch::default()
+    };
+    let PatchOutcome::Patched(patched) = users.patch(user_id, patch, None, None).await? else {
+        panic!("patched user must be");
+    };
+    assert_eq!(patched.timezone, Some(tokyo));
+
+    users.update_value(user_id, UpdateTarget::ClearLocation, None).await?;
+    let fetched_user = users.get(UserId::Internal(user_id)).await?
+        .expect("user must be");
+    assert_eq!(fetched_user.timezone, Some(tokyo));
+    users.update_value(user_id, UpdateTarget::ClearTimezone, None).await?;
+    let fetched_user = users.get(UserId::Internal(user_id)).await?
+        .expect("user must be");
+    assert!(fetched_user.timezone.is_none());
+    Ok(())
+}
+
+async fn test_place(users: &repo::UsersPostgres, db: &Pool<Postgres>, user_id: i64) -> anyhow::Result<()> {
+    users.update_value(user_id, (59.9343, 30.3351).into(), None).await?;
+    let fetched_user = users.get(UserId::Internal(user_id)).await?
+        .expect("user must be");
+    let place = fetched_user.place.expect("the place must be found");
+    assert_eq!(place.city, "Saint Petersburg");
+    assert_eq!(place.country_code, "RU");
+
+    users.update_value(user_id, UpdateTarget::ClearLocation, None).await?;
+    let fetched_user = users.get(UserId::Internal(user_id)).await?
+        .expect("user must be");
+    assert!(fetched_user.place.is_none());
+
+    tracing::info!("the locations saved before the geocoding are looked up by the backfill");
+    sqlx::query("UPDATE Users SET location = ARRAY[59.9343, 30.3351] WHERE id = $1")
+        .bind(user_id)
+        .execute(db)
+        .await?;
+    let fetched_user = users.get(UserId::Internal(user_id)).await?
+        .expect("user must be");
+    assert!(fetched_user.place.is_none());
+    assert!(users.backfill_places().await? >= 1);
+    let fetched_user = users.get(UserId::Internal(user_id)).await?
+        .expect("user must be");
+    assert_eq!(fetched_user.place.map(|place| place.city), Some("Saint Petersburg".to_owned()));
+
+    users.update_value(user_id, UpdateTarget::ClearLocation, None).await?;
+    Ok(())
+}
+
+async fn test_nearby(users: &repo::UsersPostgres, service_id: i32, user_id: i64) -> anyhow::Result<()> {
+    users.update_value(user_id, (59.9343, 30.3351).into(), None).await?;
+    let moscow = (55.7558, 37.6173).into();
+    let query = NearbyQuery { center: moscow, radius_km: 700.0, service_id: Some(service_id) };
+    let page = users.nearby(&query, None, 10).await?;
+    assert_eq!(page.items.len(), 1);
+    assert_eq!(page.items[0].user.id, user_id);
+    assert!((page.items[0].distance_km - 634.0).abs() < 5.0, "{}", page.items[0].distance_km);
+    assert!(page.next.is_none());
+    assert!(users.nearby(&query, Some(Cursor(user_id)), 10).await?.items.is_empty());
+
+    let too_far = NearbyQuery { radius_km: 500.0, ..query.clone() };
+    assert!(users.nearby(&too_far, None, 10).await?.items.is_empty());
+    let other_service = NearbyQuery { service_id: Some(service_id + 100), ..query };
+    assert!(users.nearby(&other_service, None, 10).await?.items.is_empty());
+
+    users.update_value(user_id, UpdateTarget::ClearLocation, None).await?;
+    let query = NearbyQuery { center: (59.9343, 30.3351).into(), radius_km: 1.0, service_id: None };
+    assert!(users.nearby(&query, None, 10).await?.items.is_empty());
+    Ok(())
+}
+
+async fn test_deactivation(users: &repo::UsersPostgres, user_id: i64) -> anyhow::Result<()> {
+    let deactivated = users.deactivate(user_id, None).await?
+        .expect("deactivated user must be");
+    let deactivated_at = deactivated.deactivated_at.expect("deactivated_at must be set");
+    let again = users.deactivate(user_id, None).await?
+        .expect("deactivated user must be");
+    assert_eq!(again.deactivated_at, Some(deactivated_at));
+    assert_eq!(again.version, deactivated.version);
+
+    let fetched_user = users.get(UserId::Internal(user_id)).await?
+        .expect("deactivated user must still be fetched");
+    assert!(fetched_user.deactivated());
+    assert!(users.list(&UserFilter::default(), None, 10).await?.items.iter().all(|user| user.id != user_id));
+    let name = fetched_user.name.as_deref().unwrap_or(TEST_NAME);
+    assert!(users.search(name, None, 10).await?.iter().all(|user| user.id != user_id));
+    assert!(matches!(users.activate_premium(user_id, PremiumVariant::Month, None).await?, PremiumOutcome::Deactivated));
+    assert!(matches!(users.activate_premium(user_id + 100, PremiumVariant::Month, None).await?, PremiumOutcome::NotFound));
+
+    let reactivated = users.reactivate(user_id, None).await?
+        .expect("reactivated user must be");
+    assert!(!reactivated.deactivated());
+    assert_eq!(reactivated.version, deactivated.version + 1);
+    assert!(users.reactivate(user_id + 100, None).await?.is_none());
+
+    let changes = users.changes(user_id, None, 2).await?.items;
+    assert!(changes.iter().all(|change| change.field == ChangedField::DeactivatedAt));
+    assert_eq!(changes[0].new_value, None);
+    assert_eq!(changes[1].old_value, None);
+    Ok(())
+}
+
+async fn test_bans(users: &repo::UsersPostgres, service_id: i32, user_id: i64) -> anyhow::Result<()> {
+    let new_ban = |reason: &str, expires_at| NewBan {
+        reason: reason.to_owned(),
+        issuer: "moderator".to_owned(),
+        expires_at,
+    };
+    let ban = users.ban(user_id, Some(service_id), new_ban("spam", None)).await?
+        .expect("the ban must be issued");
+    assert_eq!(ban.service.as_ref().map(|service| service.name.as_str()), Some(TEST_SERVICE));
+    users.ban(user_id, None, new_ban("fraud", Some(Utc::now() + chrono::TimeDelta::days(1)))).await?;
+    assert!(users.ban(user_id + 100, None, new_ban("fraud", None)).await?.is_none());
+
+    tracing::info!("a ban of the same scope is replaced");
+    users.ban(user_id, Some(service_id), new_ban("flood", None)).await?;
+    let user = users.get(UserId::Internal(user_id)).await?
+        .expect("the user must be");
+    assert_eq!(user.bans.iter().map(|ban| ban.reason.as_str()).collect::<Vec<_>>(), vec!["flood", "fraud"]);
+    assert_eq!(user.bans[1].service, None);
+
+    tracing::info!("expired bans are not returned");
+    users.ban(user_id, None, new_ban("fraud", Some(Utc::now() - chrono::TimeDelta::days(1)))).await?;
+    let batch = users.get_many(&[BatchKey::Internal(user_id)]).await?;
+    assert_eq!(batch[&BatchKey::Internal(user_id)].bans.len(), 1);
+
+    assert!(users.unban(user_id, Some(service_id)).await?);
+    assert!(!users.unban(user_id, Some(service_id)).await?);
+    assert!(users.unban(user_id, None).await?);
+    let user = users.get(UserId::Internal(user_id)).await?
+        .expect("the user must be");
+    assert!(user.bans.is_empty());
+    Ok(())
+}
+
+async fn test_settings(users: &repo::UsersPostgres, db: &Pool<Postgres>, service_id: i32, user_id: i64) -> anyhow::Result<()> {
+    let services = repo::ServicesPostgres::new(db.clone());
+    services.set_setting_default(service_id, "theme", json!("light")).await?;
+    services.set_setting_default(service_id, "notifications", json!(true)).await?;
+    assert_eq!(users.settings(user_id, service_id).await?.len(), 2);
+
+    tracing::info!("user values override the defaults");
+    assert!(users.set_setting(user_id, service_id, "theme", json!("dark")).await?);
+    assert!(users.set_setting(user_id, service_id, "limits", json!({"daily": 10})).await?);
+    assert!(users.set_setting(user_id, service_id, "theme", json!("sepia")).await?);
+    assert!(!users.set_setting(user_id + 100, service_id, "theme", json!("dark")).await?);
+    let settings = users.settings(user_id, service_id).await?;
+    assert_eq!(settings.len(), 3);
+    assert_eq!(settings["theme"], json!("sepia"));
+    assert_eq!(settings["notifications"], json!(true));
+    assert_eq!(settings["limits"], json!({"daily": 10}));
+
+    tracing::info!("deletion restores the default value");
+    assert!(users.delete_setting(user_id, service_id, "theme").await?);
+    assert!(!users.delete_setting(user_id, service_id, "theme").await?);
+    assert_eq!(users.settings(user_id, service_id).await?["theme"], json!("light"));
+
+    assert!(services.delete_setting_default(service_id, "notifications").await?);
+    assert!(!services.setting_defaults(service_id).await?.contains_key("notifications"));
+    assert!(!users.settings(user_id, service_id).await?.contains_key("notifications"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_merge_users() -> anyhow::Result<()> {
+    let (_container, db) = start_postgres().await;
+    let users = repo::UsersPostgres::new(db.clone());
+    let service_id = create_service(&db).await?;
+
+    let target_id = create_user(&users, service_id).await?;
+    let source = ExternalUser {
+        external_id: (TEST_UID_EXT + 1).into(),
+        name: None,
+    };
+    let source_id = users.register(source, service_id, json!({"test": true})).await?;
+    users.update_value(source_id, TEST_LOCATION.into(), None).await?;
+    let PremiumOutcome::Activated(premium_till) = users.activate_premium(source_id, PremiumVariant::Year, None).await? else {
+        anyhow::bail!("premium must be activated");
+    };
+
+    assert!(matches!(users.merge(source_id, target_id + 100, None).await?, MergeOutcome::NotFound));
+    assert!(matches!(users.merge(target_id, target_id, None).await?, MergeOutcome::SameUser));
+    let MergeOutcome::Merged(merged) = users.merge(source_id, target_id, None).await? else {
+        anyhow::bail!("the users must be merged");
+    };
+    assert_eq!(merged.id, target_id);
+    assert_eq!(merged.name, Some(TEST_NAME.to_owned()));
+    assert_eq!(merged.location, Some(TEST_LOCATION.into()));
+    assert_eq!(merged.premium_till.map(|till| till.timestamp()), Some(premium_till.timestamp()));
+
+    let redirected = users.get(UserId::Internal(source_id)).await?
+        .expect("redirected user must be");
+    assert_eq!(redirected.id, target_id);
+    let batch = users.get_many(&[BatchKey::Internal(source_id)]).await?;
+    assert_eq!(batch.get(&BatchKey::Internal(source_id)).map(|user| user.id), Some(target_id));
+    assert_eq!(users.get_user_id(service_id, &(TEST_UID_EXT + 1).into()).await?, Some(target_id));
+
+    tracing::info!("the changes by the old ID go to the target");
+    assert!(matches!(users.merge(source_id, target_id, None).await?, MergeOutcome::SameUser));
+    users.update_value(source_id, UpdateTarget::Name("merged".to_owned()), None).await?;
+    assert!(matches!(users.activate_premium(source_id, PremiumVariant::Month, None).await?, PremiumOutcome::Activated(_)));
+    assert!(users.set_setting(source_id, service_id, "theme", json!("dark")).await?);
+    let target = users.get(UserId::Internal(target_id)).await?
+        .expect("target user must be");
+    assert_eq!(target.name, Some("merged".to_owned()));
+    assert!(target.premium_till > merged.premium_till);
+    assert_eq!(users.settings(target_id, service_id).await?.get("theme"), Some(&json!("dark")));
+    Ok(())
+}
+
+async fn test_contacts(users: &repo::UsersPostgres, db: &Pool<Postgres>, service_id: i32, user_id: i64) -> anyhow::Result<()> {
+    let email = NewContact::new(ContactKind::Email, "Sad.Bot@example.com")?;
+    assert_eq!(users.request_contact_verification(user_id + 100, &email).await?, ContactRequestOutcome::NotFound);
+    assert_eq!(users.confirm_contact(user_id, ContactKind::Email, "123456").await?, ContactConfirmation::NotPending);
+
+    let ContactRequestOutcome::Requested(verification) = users.request_contact_verification(user_id, &email).await? else {
+        panic!("the verification must be requested");
+    };
+    assert!(matches!(users.request_contact_verification(user_id, &email).await?, ContactRequestOutcome::TooManyRequests(_)));
+    assert_eq!(users.confirm_contact(user_id, ContactKind::Email, "wrong").await?, ContactConfirmation::InvalidCode);
+    let ContactConfirmation::Confirmed(contact) = users.confirm_contact(user_id, ContactKind::Email, &verification.code).await? else {
+        panic!("the contact must be confirmed");
+    };
+    assert_eq!(contact.value, "sad.bot@example.com");
+
+    tracing::info!("pending contacts are not returned");
+    let phone = NewContact::new(ContactKind::Phone, "+79001234567")?;
+    users.request_contact_verification(user_id, &phone).await?;
+    let user = users.get(UserId::Internal(user_id)).await?
+        .expect("the user must be");
+    assert_eq!(user.contacts, vec![contact]);
+
+    tracing::info!("the pending contact is dropped after too many wrong codes");
+    for _ in 0..5 {
+        assert_eq!(users.confirm_contact(user_id, ContactKind::Phone, "wrong").await?, ContactConfirmation::InvalidCode);
+    }
+    assert_eq!(users.confirm_contact(user_id, ContactKind::Phone, "wrong").await?, ContactConfirmation::NotPending);
+
+    tracing::info!("the wrong codes are counted until the last code expires");
+    sqlx::query("UPDATE Contact_Verification_Requests SET requested_at = requested_at - interval '2 minutes' WHERE user_id = $1")
+        .bind(user_id)
+        .execute(db)
+        .await?;
+    let ContactRequestOutcome::TooManyRequests(retry_after) = users.request_contact_verification(user_id, &phone).await? else {
+        panic!("the request must be throttled");
+    };
+    assert!(retry_after > Utc::now() + TimeDelta::minutes(10), "{retry_after}");
+
+    tracing::info!("a verified contact belongs to a single user");
+    let another_user = ExternalUser {
+        name: None,
+        external_id: (TEST_UID_EXT + 2).into(),
+    };
+    let another_user_id = users.register(another_user, service_id, json!({"test": true})).await?;
+    assert_eq!(users.request_contact_verification(another_user_id, &email).await?, ContactRequestOutcome::TakenByAnotherUser);
+
+    assert!(users.delete_contact(user_id, ContactKind::Email).await?);
+    assert!(!users.delete_contact(user_id, ContactKind::Email).await?);
+    assert!(matches!(users.request_contact_verification(another_user_id, &email).await?, ContactRequestOutcome::Requested(_)));
+    let user = users.get(UserId::Internal(user_id)).await?
+        .expect("the user must be");
+    assert!(user.contacts.is_empty());
+    Ok(())
+}
+
+async fn test_credentials(users: &repo::UsersPostgres, service_id: i32, user_id: i64) -> anyhow::Result<()> {
+    // the repository doesn't hash anything, so fake hashes are enough
+    let credentials = NewCredentials {
+        username: Some("SadBot".try_into()?),
+        email: Some("sad.bot@example.org".to_owned()),
+        password_hash: "hash".to_owned(),
+    };
+    assert_eq!(users.create_credentials(user_id + 100, &credentials).await?, CredentialsOutcome::NotFound);
+    assert_eq!(users.create_credentials(user_id, &credentials).await?, CredentialsOutcome::EmailNotVerified);
+    let email = NewContact::new(ContactKind::Email, "sad.bot@example.org")?;
+    let ContactRequestOutcome::Requested(verification) = users.request_contact_verification(user_id, &email).await? else {
+        panic!("the verification must be requested");
+    };
+    users.confirm_contact(user_id, ContactKind::Email, &verification.code).await?;
+    let CredentialsOutcome::Created(created) = users.create_credentials(user_id, &credentials).await? else {
+        panic!("the credentials must be created");
+    };
+    assert_eq!(created.username.as_deref(), Some("sadbot"));
+    assert_eq!(users.create_credentials(user_id, &credentials).await?, CredentialsOutcome::AlreadyExists);
+
+    let another_user = ExternalUser {
+        name: None,
+        external_id: (TEST_UID_EXT + 3).into(),
+    };
+    let another_user_id = users.register(another_user, service_id, json!({"test": true})).await?;
+    let same_username = NewCredentials {
+        email: None,
+        ..credentials.clone()
+    };
+    assert_eq!(users.create_credentials(another_user_id, &same_username).await?, CredentialsOutcome::LoginTaken);
+
+    let login = Login::try_from("SADBOT")?;
+    let found = users.credentials(&CredentialsKey::Login(login.clone())).await?;
+    assert_eq!(found.as_ref(), Some(&created));
+    assert_eq!(users.credentials(&CredentialsKey::User(user_id)).await?, found);
+    assert_eq!(users.credentials(&CredentialsKey::Login(Login::try_from("sad.bot@example.com")?)).await?, None);
+
+    tracing::info!("the attempts are counted before the password is checked and lock the login when too many in a row");
+    assert_eq!(users.start_login_attempt(user_id + 100).await?, LoginAttempt::NotFound);
+    assert_eq!(users.start_login_attempt(user_id).await?, LoginAttempt::Started("hash".to_owned()));
+    users.reset_login_attempts(user_id).await?;
+    for _ in 1..LOGIN_MAX_FAILED_ATTEMPTS {
+        assert!(matches!(users.start_login_attempt(user_id).await?, LoginAttempt::Started(_)));
+    }
+    assert!(!users.credentials(&CredentialsKey::User(user_id)).await?.expect("the credentials must be").locked());
+    assert!(matches!(users.start_login_attempt(user_id).await?, LoginAttempt::Started(_)));
+    assert!(users.credentials(&CredentialsKey::User(user_id)).await?.expect("the credentials must be").locked());
+    assert!(matches!(users.start_login_attempt(user_id).await?, LoginAttempt::Locked(_)));
+
+    tracing::info!("the reset token is redeemed once and unlocks the login");
+    assert!(users.create_password_reset(&Login::try_from("nobody")?).await?.is_none());
+    let reset = users.create_password_reset(&login).await?
+        .expect("the reset token must be created");
+    assert_eq!(reset.user_id, user_id);
+    assert_eq!(reset.email, "sad.bot@example.org");
+    assert_eq!(users.redeem_password_reset("wrong", "new hash").await?, None);
+    assert_eq!(users.redeem_password_reset(&reset.token, "new hash").await?, Some(user_id));
+    assert_eq!(users.redeem_password_reset(&reset.token, "newer hash").await?, None);
+    let found = users.credentials(&CredentialsKey::User(user_id)).await?
+        .expect("the credentials must be");
+    assert_eq!(found.password_hash, "new hash");
+    assert!(!found.locked());
+
+    tracing::info!("changing the password invalidates the pending reset tokens");
+    let reset = users.create_password_reset(&login).await?
+        .expect("the reset token must be created");
+    assert!(users.set_password(user_id, "newer hash").await?);
+    assert!(!users.set_password(another_user_id, "newer hash").await?);
+    assert_eq!(users.redeem_password_reset(&reset.token, "newest hash").await?, None);
+    Ok(())
+}
@@ -1,17 +1,64 @@
-use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use chrono::{DateTime, TimeDelta, Utc};
 use derive_more::{Constructor, From};
 use num_traits::Zero;
-use sqlx::postgres::PgQueryResult;
-use crate::dto::{SavedUser, Code, ExternalUser, error::TypeConversionError, Location, PremiumVariant};
+use serde_json::json;
+use crate::dto::{SavedUser, Ban, ChangedField, Contact, ContactConfirmation, ContactKind, ContactRequestOutcome, Credentials, CredentialsOutcome, Cursor, ExternalId, ExternalUser, error::TypeConversionError, FieldChange, LanguageTag, LinkCode, LinkOutcome, Location, Login, LoginAttempt, NearbyUser, NewBan, NewCredentials, PasswordReset, Page, Settings, Place, PremiumVariant, Service, ServiceType, Timezone, UserChange, NewContact, VerificationCode};
 use crate::repo::error::RepoError;
 
+const LINK_CODE_TTL: TimeDelta = TimeDelta::minutes(10);
+pub const CONTACT_CODE_TTL: TimeDelta = TimeDelta::minutes(15);
+/// The pending contact is dropped after this many wrong codes; they are counted until the last code expires,
+/// so requesting a new one doesn't restore the attempts
+const CONTACT_CODE_MAX_ATTEMPTS: i32 = 5;
+/// A new code for a contact of the same kind can't be requested sooner than this
+pub const CONTACT_CODE_COOLDOWN: TimeDelta = TimeDelta::minutes(1);
+/// At most so many codes are sent to a user and to a contact value within [CONTACT_REQUEST_WINDOW]
+const CONTACT_MAX_REQUESTS_PER_USER: i64 = 10;
+const CONTACT_MAX_REQUESTS_PER_VALUE: i64 = 5;
+const CONTACT_REQUEST_WINDOW: TimeDelta = TimeDelta::hours(1);
+const PASSWORD_RESET_TTL: TimeDelta = TimeDelta::hours(1);
+/// Logging in is locked for [LOGIN_LOCKOUT] after this many failed attempts in a row
+pub const LOGIN_MAX_FAILED_ATTEMPTS: i32 = 5;
+pub const LOGIN_LOCKOUT: TimeDelta = TimeDelta::minutes(15);
+/// The maximum number of keys in a batch lookup
+pub const BATCH_MAX_SIZE: usize = 1000;
+pub const LIST_DEFAULT_LIMIT: u32 = 50;
+pub const LIST_MAX_LIMIT: u32 = 500;
+pub const SEARCH_DEFAULT_LIMIT: u32 = 20;
+pub const SEARCH_MAX_LIMIT: u32 = 100;
+pub const SEARCH_QUERY_MAX_LENGTH: usize = 256;
+
 #[derive(sqlx::FromRow)]
 struct UserInternal {
     id: i64,
     name: Option<String>,
     language_code: Option<String>,
     location: Option<Vec<f64>>,
-    premium_till: Option<DateTime<Utc>>
+    city: Option<String>,
+    country_code: Option<String>,
+    timezone: Option<String>,
+    premium_till: Option<DateTime<Utc>>,
+    version: i64,
+    deactivated_at: Option<DateTime<Utc>>,
+}
+
+/// Builds a [UserInternal] out of a row of an ad hoc query that selects the user columns along with some others
+macro_rules! user_internal {
+    ($row:expr) => {
+        UserInternal {
+            id: $row.id,
+            name: $row.name,
+            language_code: $row.language_code,
+            location: $row.location,
+            city: $row.city,
+            country_code: $row.country_code,
+            timezone: $row.timezone,
+            premium_till: $row.premium_till,
+            version: $row.version,
+            deactivated_at: $row.deactivated_at,
+        }
+    };
 }
 
 impl TryFrom<UserInternal> for SavedUser {
@@ -19,44 +66,191 @@ impl TryFrom<UserInternal> for SavedUser {
 
     fn try_from(value: UserInternal) -> Result<Self, Self::Error> {
         let language_code = value.language_code
-            .map(|code| code.try_into())
+            .map(LanguageTag::try_from)
             .transpose()
             .map_err(TypeConversionError::new)?;
 
-        let location = value.location
+        let location: Option<Location> = value.location
             .map(|loc| loc.try_into())
             .transpose()
             .map_err(TypeConversionError::new)?;
 
+        let place = match (value.city, value.country_code) {
+            (Some(city), Some(country_code)) => Some(Place { city, country_code }),
+            _ => None,
+        };
+
+        let timezone = value.timezone
+            .map(Timezone::try_from)
+            .transpose()
+            .map_err(TypeConversionError::new)?;
+
         Ok(Self {
             id: value.id,
             name: value.name,
             language_code,
             location,
+            place,
+            timezone,
             premium_till: value.premium_till,
+            version: value.version,
+            deactivated_at: value.deactivated_at,
+            bans: Vec::new(),
+            contacts: Vec::new(),
         })
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub enum UserId {
     Internal(i64),
-    External(i64),
+    /// External IDs are unique only within a service, hence the service ID
+    External(i32, ExternalId),
 }
 
 impl std::fmt::Display for UserId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             UserId::Internal(id) => write!(f, "internal:{id}"),
-            UserId::External(id) => write!(f, "external:{id}"),
+            UserId::External(service_id, id) => write!(f, "external:{service_id}:{id}"),
         }
     }
 }
 
+/// Key of a batch lookup: either an internal ID or an external one in the scope of a service
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub enum BatchKey {
+    Internal(i64),
+    External(i32, ExternalId),
+}
+
+/// Credentials are looked up either by their owner or by one of the logins
+#[derive(Debug, Clone)]
+pub enum CredentialsKey {
+    User(i64),
+    Login(Login),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PremiumFilter {
+    Active,
+    /// No active premium, including the users who have never had one
+    Expired,
+}
+
+/// Filters of the listing; unset fields don't restrict anything
+#[derive(Debug, Default, Clone)]
+pub struct UserFilter {
+    pub service_id: Option<i32>,
+    pub premium: Option<PremiumFilter>,
+    pub language_code: Option<LanguageTag>,
+    pub registered_after: Option<DateTime<Utc>>,
+    pub has_location: Option<bool>,
+}
+
+/// Users located within `radius_km` of the center, optionally restricted to the users of a service
+#[derive(Debug, Clone)]
+pub struct NearbyQuery {
+    pub center: Location,
+    pub radius_km: f64,
+    pub service_id: Option<i32>,
+}
+
 #[derive(Debug, From)]
 pub enum UpdateTarget {
-    Language(Code),
+    Language(LanguageTag),
     Location { latitude: f64, longitude: f64 },
+    /// Expected to be normalized by [crate::dto::normalize_name]
+    #[from(skip)]
+    Name(String),
+    #[from(skip)]
+    ClearLanguage,
+    #[from(skip)]
+    ClearLocation,
+    Timezone(Timezone),
+    #[from(skip)]
+    ClearTimezone,
+}
+
+/// Changes of several fields applied at once: `None` leaves a field intact, `Some(None)` clears it
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct UserPatch {
+    pub name: Option<Option<String>>,
+    pub language_code: Option<Option<LanguageTag>>,
+    pub location: Option<Option<Location>>,
+    pub timezone: Option<Option<Timezone>>,
+}
+
+impl UserPatch {
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.language_code.is_none() && self.location.is_none() && self.timezone.is_none()
+    }
+
+    /// A new location brings the timezone along unless the patch sets it explicitly
+    pub fn with_derived_timezone(mut self) -> Self {
+        if self.timezone.is_none()
+            && let Some(Some(location)) = &self.location {
+            self.timezone = location.timezone().map(Some);
+        }
+        self
+    }
+}
+
+pub enum PatchOutcome {
+    Patched(Box<SavedUser>),
+    NotFound,
+    /// The user has been changed since the expected version; holds the current one
+    VersionMismatch(i64),
+}
+
+pub enum MergeOutcome {
+    Merged(Box<SavedUser>),
+    NotFound,
+    /// Both IDs lead to the same user, maybe through an earlier merge
+    SameUser,
+}
+
+#[derive(Debug)]
+pub enum PremiumOutcome {
+    /// Holds the new expiry of the premium
+    Activated(DateTime<Utc>),
+    NotFound,
+    Deactivated,
+}
+
+impl From<UpdateTarget> for UserPatch {
+    fn from(value: UpdateTarget) -> Self {
+        match value {
+            UpdateTarget::Language(code) => Self {
+                language_code: Some(Some(code)),
+                ..Self::default()
+            },
+            UpdateTarget::Location { latitude, longitude } => Self {
+                location: Some(Some(Location { latitude, longitude })),
+                ..Self::default()
+            },
+            UpdateTarget::Name(name) => Self {
+                name: Some(Some(name)),
+                ..Self::default()
+            },
+            UpdateTarget::ClearLanguage => Self {
+                language_code: Some(None),
+                ..Self::default()
+            },
+            UpdateTarget::ClearLocation => Self {
+                location: Some(None),
+                ..Self::default()
+            },
+            UpdateTarget::Timezone(timezone) => Self {
+                timezone: Some(Some(timezone)),
+                ..Self::default()
+            },
+            UpdateTarget::ClearTimezone => Self {
+                timezone: Some(None),
+                ..Self::default()
+            },
+        }
+    }
 }
 
 impl From<Location> for UpdateTarget {
@@ -66,12 +260,65 @@ impl From<Location> for UpdateTarget {
     }
 }
 
+/// `actor_service_id` is the service on whose behalf a change is recorded in the history, if any.
+/// The internal IDs of the merged users are redirected to the users they were merged into.
 pub trait Users: Send + Sync {
     fn get(&self, id: UserId) -> impl Future<Output = Result<Option<SavedUser>, RepoError<TypeConversionError>>> + Send;
     fn register(&self, user: ExternalUser, service_id: i32, consent_info: serde_json::Value) -> impl Future<Output = Result<i64, RepoError<TypeConversionError>>> + Send;
-    fn get_user_id(&self, service_id: i32, external_id: i64) -> impl Future<Output = Result<Option<i64>, RepoError<TypeConversionError>>> + Send;
-    fn update_value(&self, user_id: i64, target: UpdateTarget) -> impl Future<Output = Result<(), RepoError<TypeConversionError>>> + Send;
-    fn activate_premium(&self, user_id: i64, variant: PremiumVariant) -> impl Future<Output = Result<Option<DateTime<Utc>>, RepoError<TypeConversionError>>> + Send;
+    fn get_user_id(&self, service_id: i32, external_id: &ExternalId) -> impl Future<Output = Result<Option<i64>, RepoError<TypeConversionError>>> + Send;
+    fn update_value(&self, user_id: i64, target: UpdateTarget, actor_service_id: Option<i32>) -> impl Future<Output = Result<(), RepoError<TypeConversionError>>> + Send;
+    /// Applies the patch only if the current version of the user equals `expected_version` (when set)
+    fn patch(&self, user_id: i64, patch: UserPatch, expected_version: Option<i64>, actor_service_id: Option<i32>) -> impl Future<Output = Result<PatchOutcome, RepoError<TypeConversionError>>> + Send;
+    fn activate_premium(&self, user_id: i64, variant: PremiumVariant, actor_service_id: Option<i32>) -> impl Future<Output = Result<PremiumOutcome, RepoError<TypeConversionError>>> + Send;
+    fn get_services(&self, user_id: i64) -> impl Future<Output = Result<Vec<Service>, RepoError<TypeConversionError>>> + Send;
+    fn create_link_code(&self, user_id: i64) -> impl Future<Output = Result<Option<LinkCode>, RepoError<TypeConversionError>>> + Send;
+    fn redeem_link_code(&self, code: &str, user: ExternalUser, service_id: i32, consent_info: serde_json::Value) -> impl Future<Output = Result<LinkOutcome, RepoError<TypeConversionError>>> + Send;
+    fn merge(&self, source_id: i64, target_id: i64, actor_service_id: Option<i32>) -> impl Future<Output = Result<MergeOutcome, RepoError<TypeConversionError>>> + Send;
+    fn unlink(&self, service_id: i32, external_id: &ExternalId, withdraw_consent: bool) -> impl Future<Output = Result<Option<i64>, RepoError<TypeConversionError>>> + Send;
+    /// Found users keyed by the requested keys; missing keys are just absent in the map
+    fn get_many(&self, keys: &[BatchKey]) -> impl Future<Output = Result<HashMap<BatchKey, SavedUser>, RepoError<TypeConversionError>>> + Send;
+    /// Active users ordered by their IDs, starting after the cursor
+    fn list(&self, filter: &UserFilter, after: Option<Cursor>, limit: u32) -> impl Future<Output = Result<Page<SavedUser>, RepoError<TypeConversionError>>> + Send;
+    /// Fuzzy search of the active users by name; the best matches go first
+    fn search(&self, query: &str, service_id: Option<i32>, limit: u32) -> impl Future<Output = Result<Vec<SavedUser>, RepoError<TypeConversionError>>> + Send;
+    /// Active users within the radius ordered by their IDs, starting after the cursor
+    fn nearby(&self, query: &NearbyQuery, after: Option<Cursor>, limit: u32) -> impl Future<Output = Result<Page<NearbyUser>, RepoError<TypeConversionError>>> + Send;
+    /// Change history of the user, the latest changes go first
+    fn changes(&self, user_id: i64, after: Option<Cursor>, limit: u32) -> impl Future<Output = Result<Page<UserChange>, RepoError<TypeConversionError>>> + Send;
+    /// Deactivates the user keeping all the data; does nothing if it's already deactivated
+    fn deactivate(&self, user_id: i64, actor_service_id: Option<i32>) -> impl Future<Output = Result<Option<SavedUser>, RepoError<TypeConversionError>>> + Send;
+    /// Reverses [Users::deactivate]; does nothing if the user is active
+    fn reactivate(&self, user_id: i64, actor_service_id: Option<i32>) -> impl Future<Output = Result<Option<SavedUser>, RepoError<TypeConversionError>>> + Send;
+    /// Bans the user in the service, or globally if it's not set, replacing the previous ban of the same scope
+    fn ban(&self, user_id: i64, service_id: Option<i32>, ban: NewBan) -> impl Future<Output = Result<Option<Ban>, RepoError<TypeConversionError>>> + Send;
+    /// Returns whether there was a ban to lift
+    fn unban(&self, user_id: i64, service_id: Option<i32>) -> impl Future<Output = Result<bool, RepoError<TypeConversionError>>> + Send;
+    /// Settings of the user in the service on top of the defaults of the service
+    fn settings(&self, user_id: i64, service_id: i32) -> impl Future<Output = Result<Settings, RepoError<TypeConversionError>>> + Send;
+    /// Returns `false` if the user is not found
+    fn set_setting(&self, user_id: i64, service_id: i32, key: &str, value: serde_json::Value) -> impl Future<Output = Result<bool, RepoError<TypeConversionError>>> + Send;
+    /// Returns whether there was a value to delete; the default of the service takes effect again, if any
+    fn delete_setting(&self, user_id: i64, service_id: i32, key: &str) -> impl Future<Output = Result<bool, RepoError<TypeConversionError>>> + Send;
+    /// Replaces the pending contact of the same kind, if any, and returns a fresh code to be sent to it;
+    /// the requests are throttled per contact kind, per user and per contact value
+    fn request_contact_verification(&self, user_id: i64, contact: &NewContact) -> impl Future<Output = Result<ContactRequestOutcome, RepoError<TypeConversionError>>> + Send;
+    /// Makes the pending contact verified, replacing the previous one of the same kind
+    fn confirm_contact(&self, user_id: i64, kind: ContactKind, code: &str) -> impl Future<Output = Result<ContactConfirmation, RepoError<TypeConversionError>>> + Send;
+    fn delete_contact(&self, user_id: i64, kind: ContactKind) -> impl Future<Output = Result<bool, RepoError<TypeConversionError>>> + Send;
+    /// The email, if any, must be a verified contact of the user
+    fn create_credentials(&self, user_id: i64, credentials: &NewCredentials) -> impl Future<Output = Result<CredentialsOutcome, RepoError<TypeConversionError>>> + Send;
+    fn credentials(&self, key: &CredentialsKey) -> impl Future<Output = Result<Option<Credentials>, RepoError<TypeConversionError>>> + Send;
+    /// Counts the attempt as a failed one before the password is checked, so that the concurrent guesses can't outrun
+    /// the lock; logging in is locked after too many failures in a row
+    fn start_login_attempt(&self, user_id: i64) -> impl Future<Output = Result<LoginAttempt, RepoError<TypeConversionError>>> + Send;
+    /// Called once the password has matched
+    fn reset_login_attempts(&self, user_id: i64) -> impl Future<Output = Result<(), RepoError<TypeConversionError>>> + Send;
+    /// Also lifts the lock and invalidates the reset tokens
+    fn set_password(&self, user_id: i64, password_hash: &str) -> impl Future<Output = Result<bool, RepoError<TypeConversionError>>> + Send;
+    /// Nothing is created unless the user has a verified email to send the token to
+    fn create_password_reset(&self, login: &Login) -> impl Future<Output = Result<Option<PasswordReset>, RepoError<TypeConversionError>>> + Send;
+    /// Sets the password of the owner of the token and returns their ID
+    fn redeem_password_reset(&self, token: &str, password_hash: &str) -> impl Future<Output = Result<Option<i64>, RepoError<TypeConversionError>>> + Send;
 }
 
 #[derive(Clone, Constructor)]
@@ -87,12 +334,12 @@ impl Users for UsersPostgres {
                 tracing::debug!(id, "Fetching user by internal ID");
                 Self::get_user_internal(&self.pool, id).await
             }
-            UserId::External(external_id) => {
-                tracing::debug!(external_id, "Fetching user by external ID");
+            UserId::External(service_id, external_id) => {
+                tracing::debug!(service_id, %external_id, "Fetching user by external ID");
                 sqlx::query_as!(UserInternal,
-                    "SELECT id, name, language_code, location, premium_till FROM Users u
+                    "SELECT id, name, language_code, location, city, country_code, timezone, premium_till, version, deactivated_at FROM Users u
                     JOIN User_Service_Mappings usm ON u.id = usm.user_id
-                    WHERE external_id = $1", external_id)
+                    WHERE usm.service_id = $1 AND usm.external_id = $2", service_id, external_id.to_string())
                 .fetch_optional(&self.pool)
                 .await
             }
@@ -100,7 +347,9 @@ impl Users for UsersPostgres {
         match result {
             Ok(Some(user)) => {
                 tracing::debug!("User found in database");
-                user.try_into().map_err(RepoError::Other).map(Some)
+                let mut user: SavedUser = user.try_into().map_err(RepoError::Other)?;
+                self.load_relations([&mut user]).await?;
+                Ok(Some(user))
             }
             Ok(None) => {
                 tracing::debug!("User not found in database");
@@ -126,10 +375,11 @@ impl Users for UsersPostgres {
         tracing::debug!(user_id, "User created with ID");
 
         tracing::debug!("Inserting user-service mapping");
+        let external_id = user.external_id.to_string();
         let rows = sqlx::query!(
             "INSERT INTO User_Service_Mappings (user_id, service_id, external_id)
              VALUES ($1, $2, $3) ON CONFLICT (service_id, external_id) DO NOTHING",
-            user_id, service_id, user.external_id
+            user_id, service_id, external_id
         )
             .execute(&mut *tx)
             .await?;
@@ -140,7 +390,7 @@ impl Users for UsersPostgres {
             return Ok(sqlx::query_scalar!(
                 "SELECT user_id FROM User_Service_Mappings
                  WHERE service_id = $1 AND external_id = $2",
-                service_id, user.external_id
+                service_id, external_id
             )
                 .fetch_one(&self.pool)
                 .await?);
@@ -152,6 +402,11 @@ impl Users for UsersPostgres {
             .execute(&mut *tx)
             .await?;
 
+        if let Some(name) = user.name {
+            let change = FieldChange { field: ChangedField::Name, old_value: None, new_value: Some(name.into()) };
+            Self::record_changes(&mut tx, user_id, &[change], Some(service_id)).await?;
+        }
+
         tracing::debug!("Committing transaction");
         tx.commit().await?;
         tracing::info!(user_id, "User registered successfully");
@@ -159,11 +414,11 @@ impl Users for UsersPostgres {
     }
 
     #[tracing::instrument(skip(self), fields(service_id = %service_id, external_id = %external_id))]
-    async fn get_user_id(&self, service_id: i32, external_id: i64) -> Result<Option<i64>, RepoError<TypeConversionError>> {
+    async fn get_user_id(&self, service_id: i32, external_id: &ExternalId) -> Result<Option<i64>, RepoError<TypeConversionError>> {
         tracing::debug!("Querying user ID by service and external ID");
         let result = sqlx::query_scalar!("SELECT user_id FROM User_Service_Mappings
                 WHERE service_id = $1 AND external_id = $2",
-                service_id, external_id)
+                service_id, external_id.to_string())
             .fetch_optional(&self.pool)
             .await?;
         if let Some(user_id) = result {
@@ -174,37 +429,89 @@ impl Users for UsersPostgres {
         Ok(result)
     }
 
-    #[tracing::instrument(skip(self), fields(user_id = %user_id, update_target = ?target))]
-    async fn update_value(&self, user_id: i64, target: UpdateTarget) -> Result<(), RepoError<TypeConversionError>> {
+    #[tracing::instrument(skip(self), fields(user_id = %user_id, update_target = ?target, actor_service_id = ?actor_service_id))]
+    async fn update_value(&self, user_id: i64, target: UpdateTarget, actor_service_id: Option<i32>) -> Result<(), RepoError<TypeConversionError>> {
         tracing::debug!("Updating user value");
-        let rows_affected = match target {
-            UpdateTarget::Language(code) => self.update_language(user_id, code).await,
-            UpdateTarget::Location { latitude, longitude } => self.update_location(user_id, latitude, longitude).await,
-        }?.rows_affected();
+        match self.patch(user_id, target.into(), None, actor_service_id).await? {
+            PatchOutcome::Patched(_) => {
+                tracing::info!("User value updated successfully");
+                Ok(())
+            }
+            PatchOutcome::NotFound | PatchOutcome::VersionMismatch(_) => {
+                tracing::warn!("No rows affected - user not found");
+                Err(sqlx::Error::RowNotFound.into())
+            }
+        }
+    }
 
-        if rows_affected.is_zero() {
-            tracing::warn!("No rows affected - user not found");
-            Err(sqlx::Error::RowNotFound.into())
-        } else {
-            tracing::info!(rows_affected, "User value updated successfully");
-            Ok(())
+    #[tracing::instrument(skip(self), fields(user_id = %user_id, patch = ?patch, expected_version = ?expected_version, actor_service_id = ?actor_service_id))]
+    async fn patch(&self, user_id: i64, patch: UserPatch, expected_version: Option<i64>, actor_service_id: Option<i32>) -> Result<PatchOutcome, RepoError<TypeConversionError>> {
+        tracing::debug!("Patching user");
+        let mut tx = self.pool.begin().await?;
+        let user_id = Self::resolve_user_id(&mut *tx, user_id).await?;
+        let Some(old_user) = Self::lock_user_internal(&mut *tx, user_id).await? else {
+            tracing::warn!("User not found");
+            return Ok(PatchOutcome::NotFound);
+        };
+        if expected_version.is_some_and(|version| version != old_user.version) {
+            tracing::warn!(current_version = old_user.version, "Version mismatch");
+            return Ok(PatchOutcome::VersionMismatch(old_user.version));
         }
+
+        let patch = patch.with_derived_timezone();
+        let (set_name, set_language_code, set_location, set_timezone) = (patch.name.is_some(), patch.language_code.is_some(), patch.location.is_some(), patch.timezone.is_some());
+        let name = patch.name.flatten();
+        let language_code: Option<String> = patch.language_code.flatten().map(Into::into);
+        let location = patch.location.flatten();
+        let place = location.as_ref().and_then(Location::place);
+        let location = location.map(|loc| vec![loc.latitude, loc.longitude]);
+        let timezone: Option<String> = patch.timezone.flatten().map(Into::into);
+        let mut user: SavedUser = sqlx::query_as!(UserInternal,
+                "UPDATE Users SET
+                    name = CASE WHEN $2 THEN $3 ELSE name END,
+                    language_code = CASE WHEN $4 THEN $5 ELSE language_code END,
+                    location = CASE WHEN $6 THEN $7::float8[] ELSE location END,
+                    city = CASE WHEN $6 THEN $10 ELSE city END,
+                    country_code = CASE WHEN $6 THEN $11 ELSE country_code END,
+                    timezone = CASE WHEN $8 THEN $9 ELSE timezone END,
+                    version = version + 1
+                WHERE id = $1
+                RETURNING id, name, language_code, location, city, country_code, timezone, premium_till, version, deactivated_at",
+                user_id, set_name, name, set_language_code, language_code, set_location, location.as_deref(), set_timezone, timezone,
+                place.as_ref().map(|place| place.city.as_str()), place.as_ref().map(|place| place.country_code.as_str()))
+            .fetch_one(&mut *tx)
+            .await?
+            .try_into()
+            .map_err(RepoError::Other)?;
+        let old_user: SavedUser = old_user.try_into()
+            .map_err(RepoError::Other)?;
+        Self::record_changes(&mut tx, user_id, &user.changes_since(&old_user), actor_service_id).await?;
+        tx.commit().await?;
+        self.load_relations([&mut user]).await?;
+
+        tracing::info!(version = user.version, "User patched successfully");
+        Ok(PatchOutcome::Patched(Box::new(user)))
     }
 
-    #[tracing::instrument(skip(self), fields(user_id = %user_id, variant = ?variant))]
-    async fn activate_premium(&self, user_id: i64, variant: PremiumVariant) -> Result<Option<DateTime<Utc>>, RepoError<TypeConversionError>> {
+    #[tracing::instrument(skip(self), fields(user_id = %user_id, variant = ?variant, actor_service_id = ?actor_service_id))]
+    async fn activate_premium(&self, user_id: i64, variant: PremiumVariant, actor_service_id: Option<i32>) -> Result<PremiumOutcome, RepoError<TypeConversionError>> {
+        let user_id = Self::resolve_user_id(&self.pool, user_id).await?;
         tracing::debug!("Fetching current premium status");
-        let Some(current_premium_till) = sqlx::query!(
-            "SELECT premium_till FROM Users WHERE id = $1",
+        let Some(row) = sqlx::query!(
+            "SELECT premium_till, deactivated_at FROM Users WHERE id = $1",
             user_id
         )
             .fetch_optional(&self.pool)
             .await?
-            .map(|row| row.premium_till)
         else {
             tracing::warn!("User not found");
-            return Ok(None);
+            return Ok(PremiumOutcome::NotFound);
         };
+        if row.deactivated_at.is_some() {
+            tracing::warn!("The user is deactivated");
+            return Ok(PremiumOutcome::Deactivated);
+        }
+        let current_premium_till = row.premium_till;
 
         let start_datetime = current_premium_till.unwrap_or_else(|| {
             tracing::debug!("No existing premium - starting from now");
@@ -213,35 +520,1127 @@ impl Users for UsersPostgres {
         let till = variant + start_datetime;
         tracing::debug!(premium_till = %till, "Calculated new premium expiry");
 
+        let mut tx = self.pool.begin().await?;
         let rows_affected = sqlx::query!(
-            "UPDATE Users SET premium_till = $2
-             WHERE id = $1 AND premium_till IS NOT DISTINCT FROM $3",
+            "UPDATE Users SET premium_till = $2, version = version + 1
+             WHERE id = $1 AND premium_till IS NOT DISTINCT FROM $3 AND deactivated_at IS NULL",
             user_id, till, current_premium_till
-        ).execute(&self.pool).await?.rows_affected();
+        ).execute(&mut *tx).await?.rows_affected();
 
         if rows_affected == 0 {
-            tracing::warn!("Concurrent premium activation detected");
+            tracing::warn!("Concurrent premium activation or deactivation detected");
             return Err(sqlx::Error::RowNotFound.into());
         }
+        let change = FieldChange {
+            field: ChangedField::PremiumTill,
+            old_value: current_premium_till.map(|till| json!(till)),
+            new_value: Some(json!(till)),
+        };
+        Self::record_changes(&mut tx, user_id, &[change], actor_service_id).await?;
+        tx.commit().await?;
 
         tracing::info!(premium_till = %till, "Premium activated successfully");
-        Ok(Some(till))
+        Ok(PremiumOutcome::Activated(till))
+    }
+
+    #[tracing::instrument(skip(self), fields(user_id = %user_id))]
+    async fn get_services(&self, user_id: i64) -> Result<Vec<Service>, RepoError<TypeConversionError>> {
+        tracing::debug!("Fetching services of the user");
+        let user_id = Self::resolve_user_id(&self.pool, user_id).await?;
+        let services = sqlx::query!(
+                "SELECT s.name, s.type FROM Services s
+                JOIN User_Service_Mappings usm ON s.id = usm.service_id
+                WHERE usm.user_id = $1", user_id)
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|row| Ok(Service {
+                name: row.name,
+                service_type: ServiceType::try_from(row.r#type)
+                    .map_err(|e| RepoError::Other(TypeConversionError::new(e)))?,
+            }))
+            .collect::<Result<Vec<_>, RepoError<TypeConversionError>>>()?;
+        tracing::debug!(count = services.len(), "Services fetched");
+        Ok(services)
+    }
+
+    #[tracing::instrument(skip(self), fields(user_id = %user_id))]
+    async fn create_link_code(&self, user_id: i64) -> Result<Option<LinkCode>, RepoError<TypeConversionError>> {
+        tracing::debug!("Removing expired link codes");
+        sqlx::query!("DELETE FROM Link_Codes WHERE expires_at <= current_timestamp")
+            .execute(&self.pool)
+            .await?;
+
+        let user_id = Self::resolve_user_id(&self.pool, user_id).await?;
+        let link_code = LinkCode {
+            code: LinkCode::generate_code(),
+            expires_at: Utc::now() + LINK_CODE_TTL,
+        };
+        let rows_affected = sqlx::query!(
+            "INSERT INTO Link_Codes (code, user_id, expires_at)
+             SELECT $1, id, $3 FROM Users WHERE id = $2",
+            link_code.code, user_id, link_code.expires_at
+        )
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+
+        if rows_affected.is_zero() {
+            tracing::warn!("User not found");
+            Ok(None)
+        } else {
+            tracing::info!(expires_at = %link_code.expires_at, "Link code created");
+            Ok(Some(link_code))
+        }
+    }
+
+    #[tracing::instrument(skip(self, code, user, consent_info), fields(external_id = %user.external_id, service_id = %service_id))]
+    async fn redeem_link_code(&self, code: &str, user: ExternalUser, service_id: i32, consent_info: serde_json::Value) -> Result<LinkOutcome, RepoError<TypeConversionError>> {
+        let mut tx = self.pool.begin().await?;
+
+        let code = LinkCode::normalize(code);
+        let Some(user_id) = sqlx::query_scalar!(
+            "DELETE FROM Link_Codes WHERE code = $1 AND expires_at > current_timestamp RETURNING user_id",
+            code
+        )
+            .fetch_optional(&mut *tx)
+            .await?
+        else {
+            tracing::warn!("Invalid or expired link code");
+            return Ok(LinkOutcome::InvalidCode);
+        };
+        tracing::debug!(user_id, "Link code redeemed");
+
+        let external_id = user.external_id.to_string();
+        let mapped_user_id = sqlx::query_scalar!(
+            "SELECT user_id FROM User_Service_Mappings
+             WHERE service_id = $1 AND external_id = $2",
+            service_id, external_id
+        )
+            .fetch_optional(&mut *tx)
+            .await?;
+        match mapped_user_id {
+            Some(mapped_id) if mapped_id != user_id => {
+                tracing::warn!(mapped_user_id = mapped_id, "The external account is mapped to another user");
+                tx.rollback().await?;
+                return Ok(LinkOutcome::MappedToAnotherUser(mapped_id));
+            }
+            Some(_) => tracing::debug!("The external account is already linked to the user"),
+            None => {
+                tracing::debug!("Inserting user-service mapping");
+                sqlx::query!(
+                    "INSERT INTO User_Service_Mappings (user_id, service_id, external_id) VALUES ($1, $2, $3)",
+                    user_id, service_id, external_id
+                )
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query!(
+                    "INSERT INTO Consents (uid, service_id, info) VALUES ($1, $2, $3)
+                     ON CONFLICT (uid, service_id) DO NOTHING",
+                    user_id, service_id, consent_info
+                )
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+
+        tx.commit().await?;
+        tracing::info!(user_id, "External account linked successfully");
+        Ok(LinkOutcome::Linked(user_id))
+    }
+
+    /// Moves all mappings and consents of the source user to the target one, fills in the
+    /// absent values of the target from the source (keeping the later premium expiry),
+    /// and deletes the source user leaving a redirect record in its place.
+    #[tracing::instrument(skip(self), fields(source_id = %source_id, target_id = %target_id, actor_service_id = ?actor_service_id))]
+    async fn merge(&self, source_id: i64, target_id: i64, actor_service_id: Option<i32>) -> Result<MergeOutcome, RepoError<TypeConversionError>> {
+        tracing::debug!("Starting user merge transaction");
+        let mut tx = self.pool.begin().await?;
+
+        let source_id = Self::resolve_user_id(&mut *tx, source_id).await?;
+        let target_id = Self::resolve_user_id(&mut *tx, target_id).await?;
+        if source_id == target_id {
+            tracing::warn!("Attempt to merge the user into itself");
+            return Ok(MergeOutcome::SameUser);
+        }
+        let Some(old_target) = Self::lock_user_internal(&mut *tx, target_id).await? else {
+            tracing::warn!("Target user not found");
+            return Ok(MergeOutcome::NotFound);
+        };
+        let rows_affected = sqlx::query!(
+            "UPDATE Users t SET
+                name = COALESCE(t.name, s.name),
+                language_code = COALESCE(t.language_code, s.language_code),
+                location = COALESCE(t.location, s.location),
+                city = CASE WHEN t.location IS NULL THEN s.city ELSE t.city END,
+                country_code = CASE WHEN t.location IS NULL THEN s.country_code ELSE t.country_code END,
+                timezone = COALESCE(t.timezone, s.timezone),
+                premium_till = GREATEST(t.premium_till, s.premium_till),
+                version = t.version + 1
+             FROM Users s WHERE t.id = $1 AND s.id = $2",
+            target_id, source_id
+        )
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        if rows_affected.is_zero() {
+            tracing::warn!("Source user not found");
+            tx.rollback().await?;
+            return Ok(MergeOutcome::NotFound);
+        }
+
+        tracing::debug!("Moving user-service mappings");
+        sqlx::query!("UPDATE User_Service_Mappings SET user_id = $1 WHERE user_id = $2", target_id, source_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tracing::debug!("Moving consents");
+        sqlx::query!(
+            "UPDATE Consents c SET uid = $1 WHERE uid = $2
+             AND NOT EXISTS (SELECT 1 FROM Consents tc WHERE tc.uid = $1 AND tc.service_id = c.service_id)",
+            target_id, source_id
+        )
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("DELETE FROM Consents WHERE uid = $1", source_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("DELETE FROM Link_Codes WHERE user_id = $1", source_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tracing::debug!("Moving settings");
+        sqlx::query!(
+            "UPDATE User_Settings us SET user_id = $1 WHERE user_id = $2
+             AND NOT EXISTS (SELECT 1 FROM User_Settings ts WHERE ts.user_id = $1 AND ts.service_id = us.service_id AND ts.key = us.key)",
+            target_id, source_id
+        )
+            .execute(&mut *tx)
+            .await?;
+
+        tracing::debug!("Moving bans");
+        sqlx::query!(
+            "UPDATE User_Bans b SET user_id = $1 WHERE user_id = $2
+             AND NOT EXISTS (SELECT 1 FROM User_Bans tb WHERE tb.user_id = $1 AND tb.service_id IS NOT DISTINCT FROM b.service_id)",
+            target_id, source_id
+        )
+            .execute(&mut *tx)
+            .await?;
+
+        tracing::debug!("Moving contacts");
+        sqlx::query!(
+            "UPDATE User_Contacts c SET user_id = $1 WHERE user_id = $2
+             AND NOT EXISTS (SELECT 1 FROM User_Contacts tc WHERE tc.user_id = $1 AND tc.kind = c.kind)",
+            target_id, source_id
+        )
+            .execute(&mut *tx)
+            .await?;
+
+        tracing::debug!("Moving credentials");
+        sqlx::query!(
+            "UPDATE User_Credentials SET user_id = $1 WHERE user_id = $2
+             AND NOT EXISTS (SELECT 1 FROM User_Credentials WHERE user_id = $1)",
+            target_id, source_id
+        )
+            .execute(&mut *tx)
+            .await?;
+
+        tracing::debug!("Recording the merge");
+        sqlx::query!("UPDATE User_Merges SET target_id = $1 WHERE target_id = $2", target_id, source_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("INSERT INTO User_Merges (source_id, target_id) VALUES ($1, $2)", source_id, target_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("DELETE FROM Users WHERE id = $1", source_id)
+            .execute(&mut *tx)
+            .await?;
+
+        let mut merged_user: SavedUser = Self::get_user_internal(&mut *tx, target_id).await?
+            .ok_or(sqlx::Error::RowNotFound)?
+            .try_into()
+            .map_err(RepoError::Other)?;
+        let old_target: SavedUser = old_target.try_into()
+            .map_err(RepoError::Other)?;
+        Self::record_changes(&mut tx, target_id, &merged_user.changes_since(&old_target), actor_service_id).await?;
+        tx.commit().await?;
+        self.load_relations([&mut merged_user]).await?;
+        tracing::info!("Users merged successfully");
+        Ok(MergeOutcome::Merged(Box::new(merged_user)))
+    }
+
+    /// Removes the mapping and returns the ID of the user it belonged to. The consent is
+    /// withdrawn only if the user has no other accounts in the same service.
+    #[tracing::instrument(skip(self), fields(service_id = %service_id, external_id = %external_id, withdraw_consent = %withdraw_consent))]
+    async fn unlink(&self, service_id: i32, external_id: &ExternalId, withdraw_consent: bool) -> Result<Option<i64>, RepoError<TypeConversionError>> {
+        let mut tx = self.pool.begin().await?;
+
+        let Some(user_id) = sqlx::query_scalar!(
+            "DELETE FROM User_Service_Mappings WHERE service_id = $1 AND external_id = $2 RETURNING user_id",
+            service_id, external_id.to_string()
+        )
+            .fetch_optional(&mut *tx)
+            .await?
+        else {
+            tracing::warn!("Mapping not found");
+            return Ok(None);
+        };
+
+        if withdraw_consent {
+            tracing::debug!(user_id, "Withdrawing consent");
+            sqlx::query!(
+                "DELETE FROM Consents WHERE uid = $1 AND service_id = $2
+                 AND NOT EXISTS (SELECT 1 FROM User_Service_Mappings WHERE user_id = $1 AND service_id = $2)",
+                user_id, service_id
+            )
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        tracing::info!(user_id, "Service mapping removed");
+        Ok(Some(user_id))
+    }
+
+    #[tracing::instrument(skip(self, keys), fields(keys_count = keys.len()))]
+    async fn get_many(&self, keys: &[BatchKey]) -> Result<HashMap<BatchKey, SavedUser>, RepoError<TypeConversionError>> {
+        // one row per key: either the internal ID or the pair of the service and the external ID is set;
+        // the found rows are matched back to the keys by their positions, not by the values,
+        // so the numeric and the string forms of the same external ID don't collapse into one key
+        let mut internal_ids = Vec::with_capacity(keys.len());
+        let mut service_ids = Vec::with_capacity(keys.len());
+        let mut external_ids = Vec::with_capacity(keys.len());
+        for key in keys {
+            match key {
+                BatchKey::Internal(id) => {
+                    internal_ids.push(Some(*id));
+                    service_ids.push(None);
+                    external_ids.push(None);
+                }
+                BatchKey::External(service_id, external_id) => {
+                    internal_ids.push(None);
+                    service_ids.push(Some(*service_id));
+                    external_ids.push(Some(external_id.to_string()));
+                }
+            }
+        }
+
+        tracing::debug!(count = keys.len(), "Fetching users by keys");
+        let rows = sqlx::query!(
+            r#"SELECT r.position AS "position!", u.id, u.name, u.language_code, u.location, u.city, u.country_code, u.timezone, u.premium_till, u.version, u.deactivated_at
+            FROM UNNEST($1::bigint[], $2::int[], $3::text[]) WITH ORDINALITY AS r(internal_id, service_id, external_id, position)
+            JOIN Users u ON u.id = CASE
+                WHEN r.internal_id IS NOT NULL
+                    THEN COALESCE((SELECT target_id FROM User_Merges WHERE source_id = r.internal_id), r.internal_id)
+                ELSE (SELECT usm.user_id FROM User_Service_Mappings usm
+                      WHERE usm.service_id = r.service_id AND usm.external_id = r.external_id)
+            END"#,
+            // the type overrides let the arrays carry NULLs
+            &internal_ids as &[Option<i64>], &service_ids as &[Option<i32>], &external_ids as &[Option<String>])
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut users = HashMap::with_capacity(rows.len());
+        for row in rows {
+            // ordinality is 1-based
+            let Some(key) = usize::try_from(row.position - 1).ok().and_then(|index| keys.get(index)) else {
+                continue;
+            };
+            let user = user_internal!(row);
+            users.insert(key.clone(), user.try_into().map_err(RepoError::Other)?);
+        }
+
+        self.load_relations(users.values_mut()).await?;
+        tracing::debug!(found = users.len(), "Batch lookup finished");
+        Ok(users)
+    }
+
+    #[tracing::instrument(skip(self), fields(filter = ?filter, after = ?after, limit = %limit))]
+    async fn list(&self, filter: &UserFilter, after: Option<Cursor>, limit: u32) -> Result<Page<SavedUser>, RepoError<TypeConversionError>> {
+        let premium_active = filter.premium.map(|premium| premium == PremiumFilter::Active);
+        let language_code = filter.language_code.as_ref().map(LanguageTag::as_str);
+        // one extra row tells whether there is a next page
+        let mut users = sqlx::query_as!(UserInternal,
+                "SELECT id, name, language_code, location, city, country_code, timezone, premium_till, version, deactivated_at FROM Users u
+                WHERE ($1::bigint IS NULL OR id > $1)
+                    AND ($2::int IS NULL OR EXISTS (
+                        SELECT 1 FROM User_Service_Mappings usm WHERE usm.user_id = u.id AND usm.service_id = $2))
+                    AND ($3::bool IS NULL OR COALESCE(premium_till > current_timestamp, false) = $3)
+                    AND ($4::text IS NULL OR language_code = $4)
+                    AND ($5::timestamptz IS NULL OR registered_at > $5)
+                    AND ($6::bool IS NULL OR (location IS NOT NULL) = $6)
+                    AND deactivated_at IS NULL
+                ORDER BY id
+                LIMIT $7",
+                after.map(|cursor| cursor.0), filter.service_id, premium_active, language_code,
+                filter.registered_after, filter.has_location, i64::from(limit) + 1)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let next = if users.len() > limit as usize {
+            users.truncate(limit as usize);
+            users.last().map(|user| Cursor(user.id))
+        } else {
+            None
+        };
+        let mut items = users.into_iter()
+            .map(SavedUser::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(RepoError::Other)?;
+        self.load_relations(&mut items).await?;
+        tracing::debug!(count = items.len(), has_next = next.is_some(), "Users listed");
+        Ok(Page { items, next })
+    }
+
+    #[tracing::instrument(skip(self), fields(query = %query, service_id = ?service_id, limit = %limit))]
+    async fn search(&self, query: &str, service_id: Option<i32>, limit: u32) -> Result<Vec<SavedUser>, RepoError<TypeConversionError>> {
+        // word similarity matches the query against any part of the name, so a first name alone is enough
+        let users = sqlx::query_as!(UserInternal,
+                "SELECT id, name, language_code, location, city, country_code, timezone, premium_till, version, deactivated_at FROM Users u
+                WHERE $1 <% name
+                    AND deactivated_at IS NULL
+                    AND ($2::int IS NULL OR EXISTS (
+                        SELECT 1 FROM User_Service_Mappings usm WHERE usm.user_id = u.id AND usm.service_id = $2))
+                ORDER BY word_similarity($1, name) DESC, id
+                LIMIT $3",
+                query, service_id, i64::from(limit))
+            .fetch_all(&self.pool)
+            .await?;
+        tracing::debug!(count = users.len(), "Users found");
+        let mut users = users.into_iter()
+            .map(SavedUser::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(RepoError::Other)?;
+        self.load_relations(&mut users).await?;
+        Ok(users)
+    }
+
+    #[tracing::instrument(skip(self), fields(query = ?query, after = ?after, limit = %limit))]
+    async fn nearby(&self, query: &NearbyQuery, after: Option<Cursor>, limit: u32) -> Result<Page<NearbyUser>, RepoError<TypeConversionError>> {
+        let radius_m = query.radius_km * 1000.0;
+        // the bounding box is served by the index, the exact distance check cuts its corners off;
+        // one extra row tells whether there is a next page
+        let rows = sqlx::query!(
+                r#"SELECT id, name, language_code, location, city, country_code, timezone, premium_till, version, deactivated_at,
+                    earth_distance(ll_to_earth($1, $2), ll_to_earth(location[1], location[2])) AS "distance_m!"
+                FROM Users u
+                WHERE location IS NOT NULL
+                    AND earth_box(ll_to_earth($1, $2), $3) @> ll_to_earth(location[1], location[2])
+                    AND earth_distance(ll_to_earth($1, $2), ll_to_earth(location[1], location[2])) <= $3
+                    AND deactivated_at IS NULL
+                    AND ($4::bigint IS NULL OR id > $4)
+                    AND ($5::int IS NULL OR EXISTS (
+                        SELECT 1 FROM User_Service_Mappings usm WHERE usm.user_id = u.id AND usm.service_id = $5))
+                ORDER BY id
+                LIMIT $6"#,
+                query.center.latitude, query.center.longitude, radius_m,
+                after.map(|cursor| cursor.0), query.service_id, i64::from(limit) + 1)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut rows = rows.into_iter()
+            .map(|row| -> Result<NearbyUser, TypeConversionError> {
+                let distance_km = row.distance_m / 1000.0;
+                let user = SavedUser::try_from(user_internal!(row))?;
+                Ok(NearbyUser { user, distance_km })
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(RepoError::Other)?;
+        let next = if rows.len() > limit as usize {
+            rows.truncate(limit as usize);
+            rows.last().map(|nearby| Cursor(nearby.user.id))
+        } else {
+            None
+        };
+        self.load_relations(rows.iter_mut().map(|nearby| &mut nearby.user)).await?;
+        tracing::debug!(count = rows.len(), has_next = next.is_some(), "Nearby users found");
+        Ok(Page { items: rows, next })
+    }
+
+    #[tracing::instrument(skip(self), fields(user_id = %user_id, after = ?after, limit = %limit))]
+    async fn changes(&self, user_id: i64, after: Option<Cursor>, limit: u32) -> Result<Page<UserChange>, RepoError<TypeConversionError>> {
+        let user_id = Self::resolve_user_id(&self.pool, user_id).await?;
+        // one extra row tells whether there is a next page
+        let rows = sqlx::query!(
+                r#"SELECT c.id, c.field, c.old_value, c.new_value, c.changed_at,
+                    s.name AS "service_name?", s.type AS "service_type?"
+                FROM User_Changes c
+                LEFT JOIN Services s ON s.id = c.actor_service_id
+                WHERE c.user_id = $1 AND ($2::bigint IS NULL OR c.id < $2)
+                ORDER BY c.id DESC
+                LIMIT $3"#,
+                user_id, after.map(|cursor| cursor.0), i64::from(limit) + 1)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut changes = rows.into_iter()
+            .map(|row| Ok(UserChange {
+                id: row.id,
+                field: ChangedField::try_from(row.field.as_str()).map_err(TypeConversionError::new)?,
+                old_value: row.old_value,
+                new_value: row.new_value,
+                actor: service_from_row(row.service_name, row.service_type)?,
+                changed_at: row.changed_at,
+            }))
+            .collect::<Result<Vec<_>, TypeConversionError>>()
+            .map_err(RepoError::Other)?;
+        let next = if changes.len() > limit as usize {
+            changes.truncate(limit as usize);
+            changes.last().map(|change| Cursor(change.id))
+        } else {
+            None
+        };
+        tracing::debug!(count = changes.len(), has_next = next.is_some(), "Changes fetched");
+        Ok(Page { items: changes, next })
+    }
+    #[tracing::instrument(skip(self), fields(user_id = %user_id, actor_service_id = ?actor_service_id))]
+    async fn deactivate(&self, user_id: i64, actor_service_id: Option<i32>) -> Result<Option<SavedUser>, RepoError<TypeConversionError>> {
+        self.set_deactivated(user_id, true, actor_service_id).await
+    }
+
+    #[tracing::instrument(skip(self), fields(user_id = %user_id, actor_service_id = ?actor_service_id))]
+    async fn reactivate(&self, user_id: i64, actor_service_id: Option<i32>) -> Result<Option<SavedUser>, RepoError<TypeConversionError>> {
+        self.set_deactivated(user_id, false, actor_service_id).await
+    }
+
+    #[tracing::instrument(skip(self, ban), fields(user_id = %user_id, service_id = ?service_id, issuer = %ban.issuer))]
+    async fn ban(&self, user_id: i64, service_id: Option<i32>, ban: NewBan) -> Result<Option<Ban>, RepoError<TypeConversionError>> {
+        let user_id = Self::resolve_user_id(&self.pool, user_id).await?;
+        let row = sqlx::query!(
+                r#"WITH b AS (
+                    INSERT INTO User_Bans (user_id, service_id, reason, issuer, expires_at)
+                    SELECT id, $2, $3, $4, $5 FROM Users WHERE id = $1
+                    ON CONFLICT (user_id, COALESCE(service_id, 0)) DO UPDATE SET
+                        reason = EXCLUDED.reason,
+                        issuer = EXCLUDED.issuer,
+                        issued_at = current_timestamp,
+                        expires_at = EXCLUDED.expires_at
+                    RETURNING service_id, reason, issuer, issued_at, expires_at
+                )
+                SELECT b.reason AS "reason!", b.issuer AS "issuer!", b.issued_at AS "issued_at!", b.expires_at,
+                    s.name AS "service_name?", s.type AS "service_type?"
+                FROM b
+                LEFT JOIN Services s ON s.id = b.service_id"#,
+                user_id, service_id, ban.reason, ban.issuer, ban.expires_at)
+            .fetch_optional(&self.pool)
+            .await?;
+        let Some(row) = row else {
+            tracing::warn!("User not found");
+            return Ok(None);
+        };
+        tracing::info!(expires_at = ?row.expires_at, "User banned");
+        Ok(Some(Ban {
+            service: service_from_row(row.service_name, row.service_type).map_err(RepoError::Other)?,
+            reason: row.reason,
+            issuer: row.issuer,
+            issued_at: row.issued_at,
+            expires_at: row.expires_at,
+        }))
+    }
+
+    #[tracing::instrument(skip(self), fields(user_id = %user_id, service_id = ?service_id))]
+    async fn unban(&self, user_id: i64, service_id: Option<i32>) -> Result<bool, RepoError<TypeConversionError>> {
+        let user_id = Self::resolve_user_id(&self.pool, user_id).await?;
+        let rows_affected = sqlx::query!(
+                "DELETE FROM User_Bans WHERE user_id = $1 AND service_id IS NOT DISTINCT FROM $2",
+                user_id, service_id)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        if rows_affected.is_zero() {
+            tracing::warn!("Ban not found");
+            Ok(false)
+        } else {
+            tracing::info!("User unbanned");
+            Ok(true)
+        }
+    }
+    #[tracing::instrument(skip(self), fields(user_id = %user_id, service_id = %service_id))]
+    async fn settings(&self, user_id: i64, service_id: i32) -> Result<Settings, RepoError<TypeConversionError>> {
+        let user_id = Self::resolve_user_id(&self.pool, user_id).await?;
+        let settings: Settings = sqlx::query!(
+                r#"SELECT COALESCE(us.key, d.key) AS "key!", COALESCE(us.value, d.value) AS "value!"
+                FROM (SELECT key, value FROM User_Settings WHERE user_id = $1 AND service_id = $2) us
+                FULL JOIN (SELECT key, value FROM Service_Setting_Defaults WHERE service_id = $2) d ON d.key = us.key"#,
+                user_id, service_id)
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|row| (row.key, row.value))
+            .collect();
+        tracing::debug!(count = settings.len(), "Settings fetched");
+        Ok(settings)
+    }
+
+    #[tracing::instrument(skip(self, value), fields(user_id = %user_id, service_id = %service_id, key = %key))]
+    async fn set_setting(&self, user_id: i64, service_id: i32, key: &str, value: serde_json::Value) -> Result<bool, RepoError<TypeConversionError>> {
+        let user_id = Self::resolve_user_id(&self.pool, user_id).await?;
+        let rows_affected = sqlx::query!(
+                "INSERT INTO User_Settings (user_id, service_id, key, value)
+                 SELECT id, $2, $3, $4 FROM Users WHERE id = $1
+                 ON CONFLICT (user_id, service_id, key) DO UPDATE SET value = EXCLUDED.value, updated_at = current_timestamp",
+                user_id, service_id, key, value)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        if rows_affected.is_zero() {
+            tracing::warn!("User not found");
+            Ok(false)
+        } else {
+            tracing::info!("Setting saved");
+            Ok(true)
+        }
+    }
+
+    #[tracing::instrument(skip(self), fields(user_id = %user_id, service_id = %service_id, key = %key))]
+    async fn delete_setting(&self, user_id: i64, service_id: i32, key: &str) -> Result<bool, RepoError<TypeConversionError>> {
+        let user_id = Self::resolve_user_id(&self.pool, user_id).await?;
+        let rows_affected = sqlx::query!(
+                "DELETE FROM User_Settings WHERE user_id = $1 AND service_id = $2 AND key = $3",
+                user_id, service_id, key)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        tracing::info!(deleted = !rows_affected.is_zero(), "Setting deletion finished");
+        Ok(!rows_affected.is_zero())
+    }
+
+    #[tracing::instrument(skip(self, contact), fields(user_id = %user_id, kind = %contact.kind))]
+    async fn request_contact_verification(&self, user_id: i64, contact: &NewContact) -> Result<ContactRequestOutcome, RepoError<TypeConversionError>> {
+        // the user is locked, so that the concurrent requests of the same user are counted one after another
+        let mut tx = self.pool.begin().await?;
+        let user_id = Self::resolve_user_id(&mut *tx, user_id).await?;
+        if Self::lock_user_internal(&mut *tx, user_id).await?.is_none() {
+            tracing::warn!("User not found");
+            return Ok(ContactRequestOutcome::NotFound);
+        }
+
+        let taken = sqlx::query_scalar!(
+                "SELECT user_id FROM User_Contacts WHERE kind = $1 AND value = $2 AND user_id <> $3",
+                contact.kind.as_str(), contact.value, user_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if let Some(owner_id) = taken {
+            tracing::warn!(owner_id, "The contact is verified by another user");
+            return Ok(ContactRequestOutcome::TakenByAnotherUser);
+        }
+
+        let now = Utc::now();
+        let attempts = sqlx::query!(
+                "SELECT failed_attempts, code_expires_at FROM User_Contacts WHERE user_id = $1 AND kind = $2",
+                user_id, contact.kind.as_str())
+            .fetch_optional(&mut *tx)
+            .await?;
+        let exhausted_till = attempts
+            .filter(|attempts| attempts.failed_attempts >= CONTACT_CODE_MAX_ATTEMPTS)
+            .and_then(|attempts| attempts.code_expires_at);
+        let requests = sqlx::query!(
+                r#"SELECT
+                    MAX(requested_at) FILTER (WHERE user_id = $1 AND kind = $2) AS last_requested_at,
+                    MIN(requested_at) FILTER (WHERE user_id = $1) AS first_user_request_at,
+                    COUNT(*) FILTER (WHERE user_id = $1) AS "user_requests!",
+                    MIN(requested_at) FILTER (WHERE kind = $2 AND value = $3) AS first_value_request_at,
+                    COUNT(*) FILTER (WHERE kind = $2 AND value = $3) AS "value_requests!"
+                FROM Contact_Verification_Requests
+                WHERE requested_at > $4 AND (user_id = $1 OR (kind = $2 AND value = $3))"#,
+                user_id, contact.kind.as_str(), contact.value, now - CONTACT_REQUEST_WINDOW)
+            .fetch_one(&mut *tx)
+            .await?;
+        let retry_after = [
+            exhausted_till,
+            requests.last_requested_at.map(|at| at + CONTACT_CODE_COOLDOWN),
+            requests.first_user_request_at
+                .filter(|_| requests.user_requests >= CONTACT_MAX_REQUESTS_PER_USER)
+                .map(|at| at + CONTACT_REQUEST_WINDOW),
+            requests.first_value_request_at
+                .filter(|_| requests.value_requests >= CONTACT_MAX_REQUESTS_PER_VALUE)
+                .map(|at| at + CONTACT_REQUEST_WINDOW),
+        ].into_iter().flatten().filter(|at| *at > now).max();
+        if let Some(retry_after) = retry_after {
+            tracing::warn!(%retry_after, user_requests = requests.user_requests, value_requests = requests.value_requests,
+                "Too many contact verification requests");
+            return Ok(ContactRequestOutcome::TooManyRequests(retry_after));
+        }
+
+        let verification = VerificationCode {
+            code: VerificationCode::generate_code(),
+            expires_at: now + CONTACT_CODE_TTL,
+        };
+        sqlx::query!(
+                "INSERT INTO User_Contacts (user_id, kind, pending_value, code_hash, code_expires_at)
+                 VALUES ($1, $2, $3, $4, $5)
+                 ON CONFLICT (user_id, kind) DO UPDATE SET
+                    pending_value = EXCLUDED.pending_value,
+                    code_hash = EXCLUDED.code_hash,
+                    code_expires_at = EXCLUDED.code_expires_at,
+                    failed_attempts = CASE WHEN User_Contacts.code_expires_at > $6 THEN User_Contacts.failed_attempts ELSE 0 END",
+                user_id, contact.kind.as_str(), contact.value, VerificationCode::digest(&verification.code), verification.expires_at, now)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!(
+                "INSERT INTO Contact_Verification_Requests (user_id, kind, value, requested_at) VALUES ($1, $2, $3, $4)",
+                user_id, contact.kind.as_str(), contact.value, now)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("DELETE FROM Contact_Verification_Requests WHERE requested_at <= $1", now - CONTACT_REQUEST_WINDOW)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        tracing::info!(expires_at = %verification.expires_at, "Contact verification requested");
+        Ok(ContactRequestOutcome::Requested(verification))
+    }
+
+    #[tracing::instrument(skip(self, code), fields(user_id = %user_id, kind = %kind))]
+    async fn confirm_contact(&self, user_id: i64, kind: ContactKind, code: &str) -> Result<ContactConfirmation, RepoError<TypeConversionError>> {
+        let mut tx = self.pool.begin().await?;
+        let user_id = Self::resolve_user_id(&mut *tx, user_id).await?;
+        let pending = sqlx::query!(
+                "SELECT pending_value, code_hash, code_expires_at, failed_attempts FROM User_Contacts
+                 WHERE user_id = $1 AND kind = $2 AND pending_value IS NOT NULL
+                 FOR UPDATE",
+                user_id, kind.as_str())
+            .fetch_optional(&mut *tx)
+            .await?;
+        let Some(pending) = pending else {
+            tracing::warn!("No pending contact");
+            return Ok(ContactConfirmation::NotPending);
+        };
+
+        let expired = pending.code_expires_at.is_none_or(|expires_at| expires_at <= Utc::now());
+        if expired || pending.code_hash.as_deref() != Some(VerificationCode::digest(code).as_slice()) {
+            let failed_attempts = pending.failed_attempts + 1;
+            let drop_pending = expired || failed_attempts >= CONTACT_CODE_MAX_ATTEMPTS;
+            // the expiration time and the attempts are kept to hold off the new requests, see [CONTACT_CODE_MAX_ATTEMPTS]
+            sqlx::query!(
+                    "UPDATE User_Contacts SET
+                        pending_value = CASE WHEN $3 THEN NULL ELSE pending_value END,
+                        code_hash = CASE WHEN $3 THEN NULL ELSE code_hash END,
+                        failed_attempts = failed_attempts + 1
+                    WHERE user_id = $1 AND kind = $2",
+                    user_id, kind.as_str(), drop_pending)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            tracing::warn!(expired, failed_attempts, dropped = drop_pending, "Invalid contact verification code");
+            return Ok(ContactConfirmation::InvalidCode);
+        }
+
+        let taken = sqlx::query_scalar!(
+                "SELECT user_id FROM User_Contacts WHERE kind = $1 AND value = $2 AND user_id <> $3",
+                kind.as_str(), pending.pending_value, user_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if let Some(owner_id) = taken {
+            tracing::warn!(owner_id, "The contact has been verified by another user in the meantime");
+            tx.rollback().await?;
+            return Ok(ContactConfirmation::TakenByAnotherUser);
+        }
+
+        let row = sqlx::query!(
+                r#"UPDATE User_Contacts SET
+                    value = pending_value,
+                    verified_at = current_timestamp,
+                    pending_value = NULL,
+                    code_hash = NULL,
+                    code_expires_at = NULL,
+                    failed_attempts = 0
+                WHERE user_id = $1 AND kind = $2
+                RETURNING value AS "value!", verified_at AS "verified_at!""#,
+                user_id, kind.as_str())
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        tracing::info!("Contact verified");
+        Ok(ContactConfirmation::Confirmed(Contact {
+            kind,
+            value: row.value,
+            verified_at: row.verified_at,
+        }))
+    }
+
+    #[tracing::instrument(skip(self), fields(user_id = %user_id, kind = %kind))]
+    async fn delete_contact(&self, user_id: i64, kind: ContactKind) -> Result<bool, RepoError<TypeConversionError>> {
+        let user_id = Self::resolve_user_id(&self.pool, user_id).await?;
+        let rows_affected = sqlx::query!(
+                "DELETE FROM User_Contacts WHERE user_id = $1 AND kind = $2",
+                user_id, kind.as_str())
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        tracing::info!(deleted = !rows_affected.is_zero(), "Contact deletion finished");
+        Ok(!rows_affected.is_zero())
+    }
+
+    #[tracing::instrument(skip(self, credentials), fields(user_id = %user_id))]
+    async fn create_credentials(&self, user_id: i64, credentials: &NewCredentials) -> Result<CredentialsOutcome, RepoError<TypeConversionError>> {
+        let mut tx = self.pool.begin().await?;
+        let user_id = Self::resolve_user_id(&mut *tx, user_id).await?;
+        if Self::lock_user_internal(&mut *tx, user_id).await?.is_none() {
+            tracing::warn!("User not found");
+            return Ok(CredentialsOutcome::NotFound);
+        }
+        let exists = sqlx::query_scalar!(
+                r#"SELECT EXISTS(SELECT 1 FROM User_Credentials WHERE user_id = $1) AS "exists!""#,
+                user_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        if exists {
+            tracing::warn!("The user has credentials already");
+            return Ok(CredentialsOutcome::AlreadyExists);
+        }
+        if let Some(email) = &credentials.email {
+            let verified = sqlx::query_scalar!(
+                    r#"SELECT EXISTS(SELECT 1 FROM User_Contacts WHERE user_id = $1 AND kind = $2 AND value = $3) AS "exists!""#,
+                    user_id, ContactKind::Email.as_str(), email)
+                .fetch_one(&mut *tx)
+                .await?;
+            if !verified {
+                tracing::warn!("The email is not verified");
+                return Ok(CredentialsOutcome::EmailNotVerified);
+            }
+        }
+
+        let username = credentials.username.as_ref().map(|username| username.as_str());
+        let row = sqlx::query!(
+                "INSERT INTO User_Credentials (user_id, username, email, password_hash) VALUES ($1, $2, $3, $4)
+                 ON CONFLICT DO NOTHING
+                 RETURNING user_id, username, email, password_hash, locked_until",
+                user_id, username, credentials.email, credentials.password_hash)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let Some(row) = row else {
+            tracing::warn!("The login is taken by another user");
+            return Ok(CredentialsOutcome::LoginTaken);
+        };
+        tx.commit().await?;
+        tracing::info!("Credentials created");
+        Ok(CredentialsOutcome::Created(Credentials {
+            user_id: row.user_id,
+            username: row.username,
+            email: row.email,
+            password_hash: row.password_hash,
+            locked_until: row.locked_until,
+        }))
+    }
+
+    #[tracing::instrument(skip(self, key))]
+    async fn credentials(&self, key: &CredentialsKey) -> Result<Option<Credentials>, RepoError<TypeConversionError>> {
+        let (user_id, username, email) = match key {
+            CredentialsKey::User(id) => (Some(Self::resolve_user_id(&self.pool, *id).await?), None, None),
+            CredentialsKey::Login(login) => (None, login.username(), login.email()),
+        };
+        let credentials = sqlx::query!(
+                "SELECT user_id, username, email, password_hash, locked_until FROM User_Credentials
+                 WHERE user_id = $1 OR username = $2 OR email = $3",
+                user_id, username, email)
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|row| Credentials {
+                user_id: row.user_id,
+                username: row.username,
+                email: row.email,
+                password_hash: row.password_hash,
+                locked_until: row.locked_until,
+            });
+        tracing::debug!(found = credentials.is_some(), "Credentials fetched");
+        Ok(credentials)
+    }
+
+    #[tracing::instrument(skip(self), fields(user_id = %user_id))]
+    async fn start_login_attempt(&self, user_id: i64) -> Result<LoginAttempt, RepoError<TypeConversionError>> {
+        let user_id = Self::resolve_user_id(&self.pool, user_id).await?;
+        // the counting starts over once the previous lock has passed
+        let now = Utc::now();
+        let password_hash = sqlx::query_scalar!(
+                "UPDATE User_Credentials SET
+                    failed_attempts = CASE WHEN locked_until IS NULL THEN failed_attempts + 1 ELSE 1 END,
+                    locked_until = CASE WHEN (CASE WHEN locked_until IS NULL THEN failed_attempts + 1 ELSE 1 END) >= $2 THEN $3::timestamptz END
+                WHERE user_id = $1 AND (locked_until IS NULL OR locked_until <= $4)
+                RETURNING password_hash",
+                user_id, LOGIN_MAX_FAILED_ATTEMPTS, now + LOGIN_LOCKOUT, now)
+            .fetch_optional(&self.pool)
+            .await?;
+        if let Some(password_hash) = password_hash {
+            return Ok(LoginAttempt::Started(password_hash));
+        }
+
+        let locked_until = sqlx::query_scalar!(
+                r#"SELECT locked_until AS "locked_until!" FROM User_Credentials WHERE user_id = $1 AND locked_until IS NOT NULL"#,
+                user_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        match locked_until {
+            Some(locked_until) => {
+                tracing::warn!(%locked_until, "Logging in is locked");
+                Ok(LoginAttempt::Locked(locked_until))
+            }
+            None => {
+                tracing::warn!("Credentials not found");
+                Ok(LoginAttempt::NotFound)
+            }
+        }
+    }
+
+    #[tracing::instrument(skip(self), fields(user_id = %user_id))]
+    async fn reset_login_attempts(&self, user_id: i64) -> Result<(), RepoError<TypeConversionError>> {
+        let user_id = Self::resolve_user_id(&self.pool, user_id).await?;
+        sqlx::query!(
+                "UPDATE User_Credentials SET failed_attempts = 0, locked_until = NULL WHERE user_id = $1",
+                user_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self, password_hash), fields(user_id = %user_id))]
+    async fn set_password(&self, user_id: i64, password_hash: &str) -> Result<bool, RepoError<TypeConversionError>> {
+        let mut tx = self.pool.begin().await?;
+        let user_id = Self::resolve_user_id(&mut *tx, user_id).await?;
+        let rows_affected = Self::update_password(&mut tx, user_id, password_hash).await?;
+        tx.commit().await?;
+        if rows_affected.is_zero() {
+            tracing::warn!("Credentials not found");
+            Ok(false)
+        } else {
+            tracing::info!("Password changed");
+            Ok(true)
+        }
+    }
+
+    #[tracing::instrument(skip(self, login))]
+    async fn create_password_reset(&self, login: &Login) -> Result<Option<PasswordReset>, RepoError<TypeConversionError>> {
+        tracing::debug!("Removing expired password reset tokens");
+        sqlx::query!("DELETE FROM Password_Reset_Tokens WHERE expires_at <= current_timestamp")
+            .execute(&self.pool)
+            .await?;
+
+        let owner = sqlx::query!(
+                r#"SELECT cr.user_id, c.value AS "email!" FROM User_Credentials cr
+                JOIN User_Contacts c ON c.user_id = cr.user_id AND c.kind = $3 AND c.value IS NOT NULL
+                WHERE cr.username = $1 OR cr.email = $2"#,
+                login.username(), login.email(), ContactKind::Email.as_str())
+            .fetch_optional(&self.pool)
+            .await?;
+        let Some(owner) = owner else {
+            tracing::warn!("Credentials with a verified email not found");
+            return Ok(None);
+        };
+
+        let token = PasswordReset::generate_token();
+        let expires_at = Utc::now() + PASSWORD_RESET_TTL;
+        sqlx::query!(
+                "INSERT INTO Password_Reset_Tokens (token_hash, user_id, expires_at) VALUES ($1, $2, $3)",
+                PasswordReset::digest(&token), owner.user_id, expires_at)
+            .execute(&self.pool)
+            .await?;
+        tracing::info!(user_id = owner.user_id, %expires_at, "Password reset token created");
+        Ok(Some(PasswordReset {
+            user_id: owner.user_id,
+            email: owner.email,
+            token,
+            expires_at,
+        }))
+    }
+
+    #[tracing::instrument(skip(self, token, password_hash))]
+    async fn redeem_password_reset(&self, token: &str, password_hash: &str) -> Result<Option<i64>, RepoError<TypeConversionError>> {
+        let mut tx = self.pool.begin().await?;
+        let Some(user_id) = sqlx::query_scalar!(
+                "DELETE FROM Password_Reset_Tokens WHERE token_hash = $1 AND expires_at > current_timestamp RETURNING user_id",
+                PasswordReset::digest(token))
+            .fetch_optional(&mut *tx)
+            .await?
+        else {
+            tracing::warn!("Invalid or expired password reset token");
+            return Ok(None);
+        };
+        Self::update_password(&mut tx, user_id, password_hash).await?;
+        tx.commit().await?;
+        tracing::info!(user_id, "Password reset");
+        Ok(Some(user_id))
     }
 }
 
+/// Users looked up per query of [UsersPostgres::backfill_places]
+const PLACES_BACKFILL_BATCH_SIZE: i64 = 1000;
+
 impl UsersPostgres {
-    async fn update_language(&self, user_id: i64, language: Code) -> Result<PgQueryResult, sqlx::Error> {
-        tracing::debug!(?language, "Updating language");
-        let lang_code: String = language.into();
-        sqlx::query!("UPDATE Users SET language_code = $2 WHERE id = $1", user_id, lang_code)
-            .execute(&self.pool)
+    /// Looks up the places of the locations saved before the geocoding was introduced and returns their number.
+    /// The locations in the middle of nowhere stay without a place, so they are looked up again on every run.
+    #[tracing::instrument(skip(self))]
+    pub async fn backfill_places(&self) -> Result<u64, RepoError<TypeConversionError>> {
+        let mut last_id = 0;
+        let mut backfilled = 0;
+        loop {
+            let rows = sqlx::query!(
+                    r#"SELECT id, location AS "location!" FROM Users
+                    WHERE id > $1 AND location IS NOT NULL AND city IS NULL
+                    ORDER BY id
+                    LIMIT $2"#,
+                    last_id, PLACES_BACKFILL_BATCH_SIZE)
+                .fetch_all(&self.pool)
+                .await?;
+            let Some(last_row) = rows.last() else {
+                break;
+            };
+            last_id = last_row.id;
+
+            for row in rows {
+                let location = Location::try_from(row.location.clone())
+                    .map_err(|e| RepoError::Other(TypeConversionError::new(e)))?;
+                let Some(place) = location.place() else {
+                    continue;
+                };
+                // the location might have been changed since it was fetched
+                backfilled += sqlx::query!(
+                        "UPDATE Users SET city = $2, country_code = $3
+                        WHERE id = $1 AND location = $4 AND city IS NULL",
+                        row.id, place.city, place.country_code, &row.location)
+                    .execute(&self.pool)
+                    .await?
+                    .rows_affected();
+            }
+        }
+        tracing::info!(backfilled, "Places backfilled");
+        Ok(backfilled)
+    }
+
+    async fn set_deactivated(&self, user_id: i64, deactivated: bool, actor_service_id: Option<i32>) -> Result<Option<SavedUser>, RepoError<TypeConversionError>> {
+        let mut tx = self.pool.begin().await?;
+        let user_id = Self::resolve_user_id(&mut *tx, user_id).await?;
+        let Some(old_user) = Self::lock_user_internal(&mut *tx, user_id).await? else {
+            tracing::warn!("User not found");
+            return Ok(None);
+        };
+        let old_user: SavedUser = old_user.try_into()
+            .map_err(RepoError::Other)?;
+        if old_user.deactivated() == deactivated {
+            tracing::debug!(deactivated, "The user is already in the requested state");
+            return Ok(Some(old_user));
+        }
+
+        let mut user: SavedUser = sqlx::query_as!(UserInternal,
+                "UPDATE Users SET
+                    deactivated_at = CASE WHEN $2 THEN current_timestamp END,
+                    version = version + 1
+                WHERE id = $1
+                RETURNING id, name, language_code, location, city, country_code, timezone, premium_till, version, deactivated_at",
+                user_id, deactivated)
+            .fetch_one(&mut *tx)
+            .await?
+            .try_into()
+            .map_err(RepoError::Other)?;
+        Self::record_changes(&mut tx, user_id, &user.changes_since(&old_user), actor_service_id).await?;
+        tx.commit().await?;
+        self.load_relations([&mut user]).await?;
+
+        tracing::info!(deactivated, "User activity changed");
+        Ok(Some(user))
+    }
+
+    /// Fills in the active bans and the verified contacts of the users with a query for each
+    async fn load_relations<'u>(&self, users: impl IntoIterator<Item = &'u mut SavedUser>) -> Result<(), RepoError<TypeConversionError>> {
+        let mut users: Vec<&mut SavedUser> = users.into_iter().collect();
+        if users.is_empty() {
+            return Ok(());
+        }
+        let ids: Vec<i64> = users.iter().map(|user| user.id).collect();
+        let rows = sqlx::query!(
+                r#"SELECT b.user_id, b.reason, b.issuer, b.issued_at, b.expires_at,
+                    s.name AS "service_name?", s.type AS "service_type?"
+                FROM User_Bans b
+                LEFT JOIN Services s ON s.id = b.service_id
+                WHERE b.user_id = ANY($1) AND (b.expires_at IS NULL OR b.expires_at > current_timestamp)
+                ORDER BY b.id"#,
+                &ids)
+            .fetch_all(&self.pool)
+            .await?;
+        for row in rows {
+            let ban = Ban {
+                service: service_from_row(row.service_name, row.service_type).map_err(RepoError::Other)?,
+                reason: row.reason,
+                issuer: row.issuer,
+                issued_at: row.issued_at,
+                expires_at: row.expires_at,
+            };
+            users.iter_mut()
+                .filter(|user| user.id == row.user_id)
+                .for_each(|user| user.bans.push(ban.clone()));
+        }
+
+        let rows = sqlx::query!(
+                r#"SELECT user_id, kind, value AS "value!", verified_at AS "verified_at!"
+                FROM User_Contacts
+                WHERE user_id = ANY($1) AND value IS NOT NULL
+                ORDER BY kind"#,
+                &ids)
+            .fetch_all(&self.pool)
+            .await?;
+        for row in rows {
+            let contact = Contact {
+                kind: ContactKind::try_from(row.kind.as_str())
+                    .map_err(|e| RepoError::Other(TypeConversionError::new(e)))?,
+                value: row.value,
+                verified_at: row.verified_at,
+            };
+            users.iter_mut()
+                .filter(|user| user.id == row.user_id)
+                .for_each(|user| user.contacts.push(contact.clone()));
+        }
+        Ok(())
+    }
+
+    async fn update_password(conn: &mut sqlx::PgConnection, user_id: i64, password_hash: &str) -> Result<u64, sqlx::Error> {
+        let rows_affected = sqlx::query!(
+                "UPDATE User_Credentials SET
+                    password_hash = $2,
+                    failed_attempts = 0,
+                    locked_until = NULL,
+                    updated_at = current_timestamp
+                WHERE user_id = $1",
+                user_id, password_hash)
+            .execute(&mut *conn)
+            .await?
+            .rows_affected();
+        sqlx::query!("DELETE FROM Password_Reset_Tokens WHERE user_id = $1", user_id)
+            .execute(&mut *conn)
+            .await?;
+        Ok(rows_affected)
+    }
+
+    async fn record_changes(conn: &mut sqlx::PgConnection, user_id: i64, changes: &[FieldChange], actor_service_id: Option<i32>) -> Result<(), sqlx::Error> {
+        for change in changes {
+            tracing::debug!(field = change.field.as_str(), "Recording the change");
+            sqlx::query!(
+                "INSERT INTO User_Changes (user_id, field, old_value, new_value, actor_service_id)
+                 VALUES ($1, $2, $3, $4, $5)",
+                user_id, change.field.as_str(), change.old_value, change.new_value, actor_service_id
+            )
+                .execute(&mut *conn)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// ID of the user the given one was merged into, or the given one if it wasn't merged
+    async fn resolve_user_id<'a, E>(executor: E, id: i64) -> Result<i64, sqlx::Error>
+    where E: sqlx::Executor<'a, Database = sqlx::Postgres>
+    {
+        sqlx::query_scalar!(
+                r#"SELECT COALESCE((SELECT target_id FROM User_Merges WHERE source_id = $1), $1) AS "id!""#, id)
+            .fetch_one(executor)
             .await
     }
 
-    async fn update_location(&self, user_id: i64, latitude: f64, longitude: f64) -> Result<PgQueryResult, sqlx::Error> {
-        tracing::debug!(latitude, longitude, "Updating location");
-        sqlx::query!("UPDATE Users SET location = ARRAY[$2::float8, $3::float8] WHERE id = $1", user_id, latitude, longitude)
-            .execute(&self.pool)
+    /// Doesn't follow the merges, unlike [Self::get_user_internal]
+    async fn lock_user_internal<'a, E>(executor: E, id: i64) -> Result<Option<UserInternal>, sqlx::Error>
+    where E: sqlx::Executor<'a, Database = sqlx::Postgres>
+    {
+        sqlx::query_as!(UserInternal,
+                    "SELECT id, name, language_code, location, city, country_code, timezone, premium_till, version, deactivated_at FROM Users
+                    WHERE id = $1 FOR UPDATE", id)
+            .fetch_optional(executor)
             .await
     }
 
@@ -249,8 +1648,20 @@ impl UsersPostgres {
     where E: sqlx::Executor<'a, Database = sqlx::Postgres>
     {
         sqlx::query_as!(UserInternal,
-                    "SELECT id, name, language_code, location, premium_till FROM Users WHERE id = $1", id)
+                    "SELECT id, name, language_code, location, city, country_code, timezone, premium_till, version, deactivated_at FROM Users
+                    WHERE id = COALESCE((SELECT target_id FROM User_Merges WHERE source_id = $1), $1)", id)
             .fetch_optional(executor)
             .await
     }
 }
+
+/// The service of a LEFT JOIN, if there is any
+fn service_from_row(name: Option<String>, service_type: Option<String>) -> Result<Option<Service>, TypeConversionError> {
+    match (name, service_type) {
+        (Some(name), Some(service_type)) => Ok(Some(Service {
+            name,
+            service_type: ServiceType::try_from(service_type).map_err(TypeConversionError::new)?,
+        })),
+        _ => Ok(None),
+    }
+}
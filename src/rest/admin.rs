@@ -0,0 +1,224 @@
+use std::sync::Arc;
+use axum::{Extension, Json};
+use axum::extract::{Path, Query};
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::{get, post, put};
+use axum_route_error::RouteError;
+use crate::dto::{validate_schema, validate_setting_key, validate_setting_value, SchemaKind, ServiceTypeInfo, Settings};
+use crate::repo;
+use crate::repo::services::Services;
+use crate::repo::users::{MergeOutcome, Users};
+use crate::rest::error::{not_found_error, RestErrorExt};
+use crate::rest::{MergeRequest, RestError, ServiceQuery, Success, UserView};
+use crate::rest::service::{actor_service_id, registered_service_id};
+
+/// Router for the administrative operations, to be nested at `/api/rest/v1/admin`
+pub fn admin_router<U, S>(repos: Arc<repo::Repositories<U, S>>) -> axum::Router
+where
+    U: Users + Send + Sync + 'static,
+    S: Services + Send + Sync + 'static,
+{
+    axum::Router::new()
+        .route("/users/merge", post(merge_users::<U, S>))
+        .route("/users/{id}/deactivate", post(deactivate_user::<U, S>))
+        .route("/users/{id}/reactivate", post(reactivate_user::<U, S>))
+        .route("/service-types", get(list_service_types::<U, S>).post(create_service_type::<U, S>))
+        .route("/services/settings", get(list_setting_defaults::<U, S>))
+        .route("/services/settings/{key}", put(set_setting_default::<U, S>).delete(delete_setting_default::<U, S>))
+        .route("/services/schemas/{kind}", get(get_schema::<U, S>).put(set_schema::<U, S>).delete(delete_schema::<U, S>))
+        .layer(Extension(repos))
+}
+
+#[tracing::instrument(skip(repos, headers), fields(source_id = %req.source_id, target_id = %req.target_id))]
+async fn merge_users<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    headers: HeaderMap,
+    Json(req): Json<MergeRequest>,
+) -> Result<Json<UserView>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let actor_service_id = actor_service_id(&repos, &headers).await?;
+    let merged_user = match repos.users.merge(req.source_id, req.target_id, actor_service_id).await
+        .log_route_error("Failed to merge users")? {
+        MergeOutcome::Merged(user) => user,
+        MergeOutcome::NotFound => return Err(not_found_error()),
+        MergeOutcome::SameUser => return Err(RouteError::new_bad_request()
+            .set_error_data(RestError::new("source_id and target_id must belong to different users"))),
+    };
+    tracing::info!("Users merged successfully");
+    Ok(Json((*merged_user).into()))
+}
+
+#[tracing::instrument(skip(repos, headers), fields(user_id = %id))]
+async fn deactivate_user<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+) -> Result<Json<UserView>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let actor_service_id = actor_service_id(&repos, &headers).await?;
+    let user = repos.users.deactivate(id, actor_service_id).await
+        .log_route_error("Failed to deactivate the user")?
+        .ok_or_else(not_found_error)?;
+    tracing::info!("User deactivated");
+    Ok(Json(user.into()))
+}
+
+#[tracing::instrument(skip(repos, headers), fields(user_id = %id))]
+async fn reactivate_user<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+) -> Result<Json<UserView>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let actor_service_id = actor_service_id(&repos, &headers).await?;
+    let user = repos.users.reactivate(id, actor_service_id).await
+        .log_route_error("Failed to reactivate the user")?
+        .ok_or_else(not_found_error)?;
+    tracing::info!("User reactivated");
+    Ok(Json(user.into()))
+}
+
+#[tracing::instrument(skip(repos))]
+async fn list_service_types<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+) -> Result<Json<Vec<ServiceTypeInfo>>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let service_types = repos.services.list_types().await
+        .log_route_error("Failed to list service types")?;
+    Ok(Json(service_types))
+}
+
+#[tracing::instrument(skip(repos), fields(service_type = %req.name, string_ids = %req.string_ids))]
+async fn create_service_type<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Json(req): Json<ServiceTypeInfo>,
+) -> Result<(StatusCode, Json<ServiceTypeInfo>), RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let created = repos.services.create_type(&req).await
+        .log_route_error("Failed to register service type")?;
+    if !created {
+        return Err(RouteError::new_from_status(StatusCode::CONFLICT)
+            .set_error_data(RestError::new("the service type is already registered")));
+    }
+    Ok((StatusCode::CREATED, Json(req)))
+}
+
+#[tracing::instrument(skip(repos), fields(service_name = %query.service_name))]
+async fn list_setting_defaults<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Query(query): Query<ServiceQuery>,
+) -> Result<Json<Settings>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let service_id = registered_service_id(&repos, &query.into()).await?;
+    let defaults = repos.services.setting_defaults(service_id).await
+        .log_route_error("Failed to get the default settings")?;
+    Ok(Json(defaults))
+}
+
+#[tracing::instrument(skip(repos, value), fields(key = %key, service_name = %query.service_name))]
+async fn set_setting_default<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path(key): Path<String>,
+    Query(query): Query<ServiceQuery>,
+    Json(value): Json<serde_json::Value>,
+) -> Result<Success, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    validate_setting_key(&key)
+        .and_then(|_| validate_setting_value(&value))
+        .log_route_warn("Invalid setting")?;
+    let service_id = registered_service_id(&repos, &query.into()).await?;
+    repos.services.set_setting_default(service_id, &key, value).await
+        .log_route_error("Failed to save the default setting")?;
+    Ok(Success)
+}
+
+#[tracing::instrument(skip(repos), fields(key = %key, service_name = %query.service_name))]
+async fn delete_setting_default<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path(key): Path<String>,
+    Query(query): Query<ServiceQuery>,
+) -> Result<Success, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let service_id = registered_service_id(&repos, &query.into()).await?;
+    if !repos.services.delete_setting_default(service_id, &key).await.log_route_error("Failed to delete the default setting")? {
+        return Err(not_found_error());
+    }
+    Ok(Success)
+}
+
+#[tracing::instrument(skip(repos), fields(kind = kind.as_str(), service_name = %query.service_name))]
+async fn get_schema<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path(kind): Path<SchemaKind>,
+    Query(query): Query<ServiceQuery>,
+) -> Result<Json<serde_json::Value>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let service_id = registered_service_id(&repos, &query.into()).await?;
+    let schema = repos.services.schema(service_id, kind).await
+        .log_route_error("Failed to get the schema")?
+        .ok_or_else(not_found_error)?;
+    Ok(Json(schema))
+}
+
+#[tracing::instrument(skip(repos, schema), fields(kind = kind.as_str(), service_name = %query.service_name))]
+async fn set_schema<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path(kind): Path<SchemaKind>,
+    Query(query): Query<ServiceQuery>,
+    Json(schema): Json<serde_json::Value>,
+) -> Result<Success, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    validate_schema(&schema)
+        .log_route_warn("Invalid schema")?;
+    let service_id = registered_service_id(&repos, &query.into()).await?;
+    repos.services.set_schema(service_id, kind, schema).await
+        .log_route_error("Failed to save the schema")?;
+    Ok(Success)
+}
+
+#[tracing::instrument(skip(repos), fields(kind = kind.as_str(), service_name = %query.service_name))]
+async fn delete_schema<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path(kind): Path<SchemaKind>,
+    Query(query): Query<ServiceQuery>,
+) -> Result<Success, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let service_id = registered_service_id(&repos, &query.into()).await?;
+    if !repos.services.delete_schema(service_id, kind).await.log_route_error("Failed to delete the schema")? {
+        return Err(not_found_error());
+    }
+    Ok(Success)
+}
@@ -2,18 +2,35 @@ mod user;
 
 pub use user::*;
 
+use axum::http::header;
 use axum::response::{IntoResponse, Response};
 use chrono::{DateTime, Utc};
 use derive_more::FromStr;
 use serde_derive::{Deserialize, Serialize};
 use serde_json::json;
-use crate::dto::{ExternalUser, PremiumVariant, Service};
+use crate::dto::{ContactKind, Credentials, ExternalId, ExternalUser, NewBan, PremiumVariant, SchemaViolation, Service, ServiceType, UserChange};
+use crate::dto::error::SchemaError;
+use crate::repo::users::PremiumFilter;
+use crate::tokens::IssuedToken;
 
 #[derive(Deserialize)]
 pub struct RegistrationRequest {
     pub user: ExternalUser,
     pub service: Service,
     pub consent_info: serde_json::Value,
+    /// Update the name of an already registered user
+    #[serde(default)]
+    pub refresh_name: bool,
+}
+
+#[derive(Deserialize)]
+pub struct NameUpdate {
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+pub struct TimezoneUpdate {
+    pub timezone: String,
 }
 
 #[derive(Clone, FromStr)]
@@ -24,17 +41,203 @@ pub enum PremiumVariantRest {
     Year,
 }
 
-impl Into<PremiumVariant> for PremiumVariantRest {
-    fn into(self) -> PremiumVariant {
-        match self {
-            Self::Month => PremiumVariant::Month,
-            Self::Quarter => PremiumVariant::Quarter,
-            Self::HalfYear => PremiumVariant::HalfYear,
-            Self::Year => PremiumVariant::Year,
+impl From<PremiumVariantRest> for PremiumVariant {
+    fn from(value: PremiumVariantRest) -> Self {
+        match value {
+            PremiumVariantRest::Month => Self::Month,
+            PremiumVariantRest::Quarter => Self::Quarter,
+            PremiumVariantRest::HalfYear => Self::HalfYear,
+            PremiumVariantRest::Year => Self::Year,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PremiumFilterRest {
+    Active,
+    Expired,
+}
+
+impl From<PremiumFilterRest> for PremiumFilter {
+    fn from(value: PremiumFilterRest) -> Self {
+        match value {
+            PremiumFilterRest::Active => Self::Active,
+            PremiumFilterRest::Expired => Self::Expired,
         }
     }
 }
 
+/// Query of `GET /`: the service is identified by both `service_name` and `service_type`
+#[derive(Debug, Deserialize)]
+pub struct ListQuery {
+    pub service_name: Option<String>,
+    pub service_type: Option<ServiceType>,
+    pub premium: Option<PremiumFilterRest>,
+    pub language_code: Option<String>,
+    pub registered_after: Option<DateTime<Utc>>,
+    pub has_location: Option<bool>,
+    pub cursor: Option<String>,
+    pub limit: Option<u32>,
+}
+
+/// Query of `GET /search`: the service is identified by both `service_name` and `service_type`
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    pub service_name: Option<String>,
+    pub service_type: Option<ServiceType>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ListResponse {
+    pub users: Vec<UserView>,
+    pub next_cursor: Option<String>,
+}
+
+/// Query of `GET /nearby`: the service is identified by both `service_name` and `service_type`
+#[derive(Debug, Deserialize)]
+pub struct NearbyUsersQuery {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius_km: f64,
+    pub service_name: Option<String>,
+    pub service_type: Option<ServiceType>,
+    pub cursor: Option<String>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NearbyResponse {
+    pub users: Vec<NearbyUserView>,
+    pub next_cursor: Option<String>,
+}
+
+/// Query of `GET /{id}/changes`
+#[derive(Debug, Deserialize)]
+pub struct ChangesQuery {
+    pub cursor: Option<String>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChangesResponse {
+    pub changes: Vec<UserChange>,
+    pub next_cursor: Option<String>,
+}
+
+/// Body of `POST /{id}/bans`; the ban is global if the service is not set
+#[derive(Deserialize)]
+pub struct BanRequest {
+    pub service: Option<Service>,
+    #[serde(flatten)]
+    pub ban: NewBan,
+}
+
+/// Query of `DELETE /{id}/bans`: the service is identified by both `service_name` and `service_type`;
+/// the global ban is lifted if neither is set
+#[derive(Debug, Deserialize)]
+pub struct BanScopeQuery {
+    pub service_name: Option<String>,
+    pub service_type: Option<ServiceType>,
+}
+
+/// Query of the settings endpoints, which are scoped by a service
+#[derive(Debug, Deserialize)]
+pub struct ServiceQuery {
+    pub service_name: String,
+    pub service_type: ServiceType,
+}
+
+impl From<ServiceQuery> for Service {
+    fn from(value: ServiceQuery) -> Self {
+        Self {
+            name: value.service_name,
+            service_type: value.service_type,
+        }
+    }
+}
+
+/// Body of `POST /{id}/contacts`
+#[derive(Deserialize)]
+pub struct ContactRequest {
+    pub kind: ContactKind,
+    pub value: String,
+}
+
+/// The code itself is sent to the contact only
+#[derive(Serialize, Deserialize)]
+pub struct ContactVerificationResponse {
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Body of `POST /{id}/contacts/{kind}/confirm`
+#[derive(Deserialize)]
+pub struct ContactConfirmationRequest {
+    pub code: String,
+}
+
+/// Body of `POST /{id}/credentials`: at least one of the logins must be set
+#[derive(Deserialize)]
+pub struct CredentialsRequest {
+    pub username: Option<String>,
+    pub email: Option<String>,
+    pub password: String,
+}
+
+/// The hash of the password is never exposed
+#[derive(Serialize, Deserialize)]
+pub struct CredentialsView {
+    pub username: Option<String>,
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+impl From<Credentials> for CredentialsView {
+    fn from(value: Credentials) -> Self {
+        let locked_until = value.locked_until.filter(|_| value.locked());
+        Self {
+            username: value.username,
+            email: value.email,
+            locked_until,
+        }
+    }
+}
+
+/// Body of `POST /credentials/login`; the login is either a username or an email
+#[derive(Deserialize)]
+pub struct LoginRequest {
+    pub login: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LoginResponse {
+    pub id: i64,
+}
+
+/// Body of `POST /{id}/credentials/password`
+#[derive(Deserialize)]
+pub struct PasswordChangeRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+/// Body of `POST /credentials/reset`
+#[derive(Deserialize)]
+pub struct PasswordResetRequest {
+    pub login: String,
+}
+
+/// Body of `POST /credentials/reset/confirm`
+#[derive(Deserialize)]
+pub struct PasswordResetConfirmation {
+    pub token: String,
+    pub new_password: String,
+}
+
 #[derive(Serialize)]
 pub struct PremiumActivationResult {
     success: bool,
@@ -50,6 +253,76 @@ impl From<Option<DateTime<Utc>>> for PremiumActivationResult {
     }
 }
 
+#[derive(Deserialize)]
+pub struct LinkRequest {
+    pub code: String,
+    pub user: ExternalUser,
+    pub service: Service,
+    pub consent_info: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LinkResponse {
+    pub id: i64,
+}
+
+/// Query of `DELETE /external/{external_id}`: the service is identified the same way as in the lookups
+#[derive(Debug, Deserialize)]
+pub struct UnlinkQuery {
+    #[serde(flatten)]
+    pub service: ServiceQuery,
+    #[serde(default)]
+    pub withdraw_consent: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MergeRequest {
+    pub source_id: i64,
+    pub target_id: i64,
+}
+
+/// Either `{"id": 1}` or `{"service": {...}, "external_id": 12345}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum BatchKeyView {
+    Internal { id: i64 },
+    External { service: Service, external_id: ExternalId },
+}
+
+#[derive(Deserialize)]
+pub struct BatchGetRequest {
+    pub keys: Vec<BatchKeyView>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BatchUser {
+    #[serde(flatten)]
+    pub key: BatchKeyView,
+    pub user: UserView,
+}
+
+/// Found users and missing keys, both in the order of the request
+#[derive(Default, Serialize, Deserialize)]
+pub struct BatchGetResponse {
+    pub users: Vec<BatchUser>,
+    pub missing: Vec<BatchKeyView>,
+}
+
+#[derive(Serialize)]
+pub struct TokenResponse {
+    token: String,
+    expires_at: DateTime<Utc>,
+}
+
+impl From<IssuedToken> for TokenResponse {
+    fn from(value: IssuedToken) -> Self {
+        Self {
+            token: value.token,
+            expires_at: value.expires_at,
+        }
+    }
+}
+
 pub struct Success;
 
 impl IntoResponse for Success {
@@ -58,13 +331,41 @@ impl IntoResponse for Success {
     }
 }
 
+/// Adds the version of the user to the response as a strong `ETag`
+pub struct Versioned<T>(pub i64, pub T);
+
+impl<T: IntoResponse> IntoResponse for Versioned<T> {
+    fn into_response(self) -> Response {
+        ([(header::ETAG, format!("\"{}\"", self.0))], self.1).into_response()
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RestError {
-    reason: String
+    reason: String,
+    /// Failing paths of a document rejected by the JSON Schema of the service
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    violations: Vec<SchemaViolation>,
+}
+
+impl RestError {
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self { reason: reason.into(), violations: vec![] }
+    }
+
+    /// Keeps the violations structured instead of only listing them in the reason
+    pub fn from_schema_error(error: SchemaError) -> Self {
+        let reason = error.to_string();
+        let violations = match error {
+            SchemaError::Violations(violations) => violations,
+            SchemaError::InvalidSchema(_) => vec![],
+        };
+        Self { reason, violations }
+    }
 }
 
 impl <T: std::error::Error> From<T> for RestError {
     fn from(value: T) -> Self {
-        Self { reason: value.to_string() }
+        Self::new(value.to_string())
     }
 }
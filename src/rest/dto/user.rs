@@ -1,5 +1,7 @@
+use chrono::{DateTime, Utc};
+use serde::Deserializer;
 use serde_derive::{Deserialize, Serialize};
-use crate::dto::{Location, SavedUser};
+use crate::dto::{Ban, Contact, Language, LanguageTag, Location, NearbyUser, Place, SavedUser, Timezone};
 
 /// DTO for JSON response
 #[derive(Serialize, Deserialize)]
@@ -7,30 +9,109 @@ pub struct UserView {
     id: i64,
     name: Option<String>,
     options: Options,
-    is_premium: bool
+    is_premium: bool,
+    /// The nearest city to the location
+    place: Option<Place>,
+    /// Current offset of the timezone of the user from UTC in seconds
+    utc_offset: Option<i32>,
+    /// English and native names of the primary language of the tag
+    #[serde(skip_deserializing)]
+    language: Option<&'static Language>,
+    /// Lookup chain of the language tag (RFC 4647), from the most specific tag to the bare language
+    #[serde(default)]
+    language_fallbacks: Vec<String>,
+    version: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deactivated_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    bans: Vec<Ban>,
+    /// Verified contacts only
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    contacts: Vec<Contact>,
+}
+
+/// [UserView] with the distance from the center of the nearby users search
+#[derive(Serialize, Deserialize)]
+pub struct NearbyUserView {
+    #[serde(flatten)]
+    pub user: UserView,
+    pub distance_km: f64,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Options {
     pub language_code: Option<String>,
     pub location: Option<Location>,
+    #[serde(default)]
+    pub timezone: Option<Timezone>,
+}
+
+/// JSON Merge Patch (RFC 7396) of [UserView]: absent fields stay intact, `null` clears them
+#[derive(Debug, Default, Deserialize)]
+pub struct UserMergePatch {
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub options: Option<Option<OptionsMergePatch>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct OptionsMergePatch {
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub language_code: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub location: Option<Option<Location>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub timezone: Option<Option<String>>,
 }
 
 
 // IMPLEMENTATIONS
 
 
+/// Distinguishes explicit `null` values (`Some(None)`) from absent fields (`None`)
+fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: serde::Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+
 impl From<SavedUser> for UserView {
     fn from(value: SavedUser) -> Self {
         let is_premium = value.premium();
+        let language = value.language_code.as_ref().map(LanguageTag::language);
+        let language_fallbacks = value.language_code.as_ref()
+            .map(|tag| tag.fallback_chain().into_iter().map(ToOwned::to_owned).collect())
+            .unwrap_or_default();
         Self {
             id: value.id,
             name: value.name,
             options: Options {
                 language_code: value.language_code.map(Into::into),
-                location: value.location.map(Into::into),
+                location: value.location,
+                timezone: value.timezone,
             },
             is_premium,
+            place: value.place,
+            utc_offset: value.timezone.map(|timezone| timezone.utc_offset()),
+            language,
+            language_fallbacks,
+            version: value.version,
+            deactivated_at: value.deactivated_at,
+            bans: value.bans,
+            contacts: value.contacts,
+        }
+    }
+}
+
+impl From<NearbyUser> for NearbyUserView {
+    fn from(value: NearbyUser) -> Self {
+        Self {
+            user: value.user.into(),
+            distance_km: value.distance_km,
         }
     }
 }
@@ -28,3 +28,8 @@ where
         })
     }
 }
+
+/// Not found error with the REST error payload, for the handlers returning `RouteError<RestError>`
+pub fn not_found_error() -> RouteError<RestError> {
+    RouteError::new_not_found().set_error_data(RestError::new("not found"))
+}
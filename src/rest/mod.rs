@@ -1,9 +1,13 @@
+mod admin;
 mod dto;
 mod error;
 mod service;
+mod well_known;
 
 #[cfg(test)]
 mod test;
 
+pub use admin::admin_router;
 pub use dto::*;
 pub use service::router;
+pub use well_known::well_known_router;
@@ -1,37 +1,185 @@
+use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::Arc;
 use axum::{Extension, Json};
 use axum::extract::{Path, Query};
-use axum::routing::{get, patch, post};
+use axum::routing::{delete, get, patch, post};
 use axum_route_error::RouteError;
-use axum::http::StatusCode;
-use crate::dto::{Code, Location, RegistrationResponse, RegistrationStatus};
-use crate::rest::error::RestErrorExt;
+use axum::http::{header, HeaderMap, StatusCode};
+use crate::dto::{normalize_name, hash_password, verify_dummy_password, verify_password, Contact, ContactConfirmation, ContactKind, ContactRequestOutcome, CredentialsOutcome, Login, LoginAttempt, NewContact, NewCredentials, Password, Username, supported_languages, validate_document, validate_radius, validate_setting_key, validate_setting_value, Ban, Cursor, Language, LanguageTag, LinkCode, LinkOutcome, Location, RegistrationResponse, RegistrationStatus, SavedUser, SchemaKind, Service, ServiceType, ServiceTypeInfo, Settings, Timezone, VerificationCode};
+use crate::dto::error::CredentialsError;
+use crate::rest::error::{not_found_error, RestErrorExt};
 use crate::repo;
-use crate::repo::users::{UpdateTarget, UserId, Users};
+use crate::repo::users::{BatchKey, CredentialsKey, NearbyQuery, PatchOutcome, PremiumOutcome, UpdateTarget, UserFilter, UserId, UserPatch, Users, BATCH_MAX_SIZE, LIST_DEFAULT_LIMIT, LIST_MAX_LIMIT, SEARCH_DEFAULT_LIMIT, SEARCH_MAX_LIMIT, SEARCH_QUERY_MAX_LENGTH};
 use crate::repo::services::Services;
-use crate::rest::{PremiumActivationResult, PremiumVariantRest, RegistrationRequest, RestError, Success, UserView};
+use crate::rest::{BanRequest, BanScopeQuery, BatchGetRequest, BatchGetResponse, BatchKeyView, BatchUser, ChangesQuery, ChangesResponse, ContactConfirmationRequest, ContactRequest, ContactVerificationResponse, CredentialsRequest, CredentialsView, LoginRequest, LoginResponse, PasswordChangeRequest, PasswordResetConfirmation, PasswordResetRequest, LinkRequest, LinkResponse, ListQuery, ListResponse, NameUpdate, NearbyResponse, NearbyUsersQuery, OptionsMergePatch, PremiumActivationResult, PremiumVariantRest, RegistrationRequest, RestError, SearchQuery, ServiceQuery, Success, TimezoneUpdate, TokenResponse, UnlinkQuery, UserMergePatch, UserView, Versioned};
+use crate::contacts::{CodeSender, SendError};
+use crate::tokens::TokenIssuer;
 
-pub fn router<U, S>(repos: Arc<repo::Repositories<U, S>>) -> axum::Router
+const ACTOR_NAME_HEADER: &str = "x-service-name";
+const ACTOR_TYPE_HEADER: &str = "x-service-type";
+
+pub fn router<U, S>(repos: Arc<repo::Repositories<U, S>>, issuer: Arc<TokenIssuer>, sender: Arc<dyn CodeSender>) -> axum::Router
 where
     U: Users + Send + Sync + 'static,
     S: Services + Send + Sync + 'static,
 {
     axum::Router::new()
-        .route("/{id}", get(get_user::<U, S>))
-        .route("/external/{external_id}", get(get_external_user::<U, S>))
+        .route("/", get(list_users::<U, S>))
+        .route("/search", get(search_users::<U, S>))
+        .route("/nearby", get(nearby_users::<U, S>))
+        .route("/languages", get(list_languages))
+        .route("/{id}", get(get_user::<U, S>).patch(patch_user::<U, S>))
+        .route("/batch", post(batch_get::<U, S>))
+        .route("/external/{external_id}", get(get_external_user::<U, S>).delete(unlink_user::<U, S>))
         .route("/external", post(register_user::<U, S>))
         .route("/{id}/language/{code}", patch(update_language::<U, S>))
-        .route("/{id}/location/", patch(update_location::<U, S>))
+        .route("/{id}/name", patch(update_name::<U, S>))
+        .route("/{id}/timezone", patch(update_timezone::<U, S>).delete(clear_timezone::<U, S>))
+        .route("/{id}/language", delete(clear_language::<U, S>))
+        .route("/{id}/location/", patch(update_location::<U, S>).delete(clear_location::<U, S>))
         .route("/{id}/premium/activate/{variant}", post(activate_premium::<U, S>))
+        .route("/{id}/token", post(issue_token::<U, S>))
+        .route("/{id}/link-code", post(create_link_code::<U, S>))
+        .route("/{id}/changes", get(list_changes::<U, S>))
+        .route("/{id}/bans", get(list_bans::<U, S>).post(ban_user::<U, S>).delete(unban_user::<U, S>))
+        .route("/{id}/settings", get(list_settings::<U, S>))
+        .route("/{id}/settings/{key}", get(get_setting::<U, S>).put(set_setting::<U, S>).delete(delete_setting::<U, S>))
+        .route("/{id}/contacts", post(request_contact_verification::<U, S>))
+        .route("/{id}/contacts/{kind}", delete(delete_contact::<U, S>))
+        .route("/{id}/contacts/{kind}/confirm", post(confirm_contact::<U, S>))
+        .route("/{id}/credentials", get(get_credentials::<U, S>).post(create_credentials::<U, S>))
+        .route("/{id}/credentials/password", post(change_password::<U, S>))
+        .route("/credentials/login", post(log_in::<U, S>))
+        .route("/credentials/reset", post(create_password_reset::<U, S>))
+        .route("/credentials/reset/confirm", post(reset_password::<U, S>))
+        .route("/link", post(link_account::<U, S>))
         .layer(Extension(repos))
+        .layer(Extension(issuer))
+        .layer(Extension(sender))
+}
+
+#[tracing::instrument(skip(repos))]
+async fn list_users<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Query(query): Query<ListQuery>,
+) -> Result<Json<ListResponse>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let limit = validate_limit(query.limit, LIST_DEFAULT_LIMIT, LIST_MAX_LIMIT)?;
+    let after = query.cursor.as_deref()
+        .map(Cursor::decode)
+        .transpose()
+        .log_route_warn("Invalid cursor")?;
+    let language_code = query.language_code
+        .map(LanguageTag::try_from)
+        .transpose()
+        .log_route_warn("Invalid language tag")?;
+
+    let service_id = match service_scope(&repos, query.service_name, query.service_type).await? {
+        ServiceScope::All => None,
+        ServiceScope::Known(id) => Some(id),
+        ServiceScope::Unknown => {
+            tracing::debug!("Unknown service, nobody to list");
+            return Ok(Json(ListResponse { users: vec![], next_cursor: None }));
+        }
+    };
+
+    let filter = UserFilter {
+        service_id,
+        premium: query.premium.map(Into::into),
+        language_code,
+        registered_after: query.registered_after,
+        has_location: query.has_location,
+    };
+    let page = repos.users.list(&filter, after, limit).await
+        .log_route_error("Failed to list users")?;
+    Ok(Json(ListResponse {
+        users: page.items.into_iter().map(Into::into).collect(),
+        next_cursor: page.next.map(|cursor| cursor.encode()),
+    }))
+}
+
+#[tracing::instrument(skip(repos), fields(query = %query.q))]
+async fn search_users<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<Vec<UserView>>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let limit = validate_limit(query.limit, SEARCH_DEFAULT_LIMIT, SEARCH_MAX_LIMIT)?;
+    let search_query = query.q.trim();
+    if search_query.is_empty() || search_query.chars().count() > SEARCH_QUERY_MAX_LENGTH {
+        tracing::warn!("Invalid search query");
+        return Err(RouteError::new_bad_request()
+            .set_error_data(RestError::new(format!("the query must be a non-empty string of at most {SEARCH_QUERY_MAX_LENGTH} characters"))));
+    }
+
+    let service_id = match service_scope(&repos, query.service_name, query.service_type).await? {
+        ServiceScope::All => None,
+        ServiceScope::Known(id) => Some(id),
+        ServiceScope::Unknown => {
+            tracing::debug!("Unknown service, nobody to find");
+            return Ok(Json(vec![]));
+        }
+    };
+    let users = repos.users.search(search_query, service_id, limit).await
+        .log_route_error("Failed to search users")?;
+    Ok(Json(users.into_iter().map(Into::into).collect()))
+}
+
+#[tracing::instrument(skip(repos), fields(latitude = %query.latitude, longitude = %query.longitude, radius_km = %query.radius_km))]
+async fn nearby_users<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Query(query): Query<NearbyUsersQuery>,
+) -> Result<Json<NearbyResponse>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let limit = validate_limit(query.limit, LIST_DEFAULT_LIMIT, LIST_MAX_LIMIT)?;
+    let after = query.cursor.as_deref()
+        .map(Cursor::decode)
+        .transpose()
+        .log_route_warn("Invalid cursor")?;
+    let center = Location { latitude: query.latitude, longitude: query.longitude };
+    center.validate()
+        .log_route_warn("Invalid location coordinates")?;
+    validate_radius(query.radius_km)
+        .log_route_warn("Invalid radius")?;
+
+    let service_id = match service_scope(&repos, query.service_name, query.service_type).await? {
+        ServiceScope::All => None,
+        ServiceScope::Known(id) => Some(id),
+        ServiceScope::Unknown => {
+            tracing::debug!("Unknown service, nobody to find");
+            return Ok(Json(NearbyResponse { users: vec![], next_cursor: None }));
+        }
+    };
+
+    let nearby_query = NearbyQuery { center, radius_km: query.radius_km, service_id };
+    let page = repos.users.nearby(&nearby_query, after, limit).await
+        .log_route_error("Failed to find nearby users")?;
+    Ok(Json(NearbyResponse {
+        users: page.items.into_iter().map(Into::into).collect(),
+        next_cursor: page.next.map(|cursor| cursor.encode()),
+    }))
+}
+
+#[tracing::instrument]
+async fn list_languages() -> Json<&'static [Language]> {
+    Json(supported_languages())
 }
 
 #[tracing::instrument(skip(repos), fields(user_id = %id))]
 async fn get_user<U, S>(
     Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
     Path(id): Path<i64>,
-) -> Result<Json<UserView>, RouteError>
+) -> Result<Versioned<Json<UserView>>, RouteError<RestError>>
 where
     U: Users,
     S: Services,
@@ -39,34 +187,540 @@ where
     get_user_impl(repos, UserId::Internal(id)).await
 }
 
-#[tracing::instrument(skip(repos), fields(external_id = %id))]
+/// External IDs are unique only within a service, so the service is a mandatory part of the lookup
+#[tracing::instrument(skip(repos), fields(external_id = %id, service_name = %query.service_name))]
 async fn get_external_user<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path(id): Path<String>,
+    Query(query): Query<ServiceQuery>,
+) -> Result<Versioned<Json<UserView>>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let service_id = registered_service_id(&repos, &query.into()).await?;
+    get_user_impl(repos, UserId::External(service_id, id.as_str().into())).await
+}
+
+#[tracing::instrument(skip(repos), fields(user_id = %id))]
+async fn list_changes<U, S>(
     Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
     Path(id): Path<i64>,
-) -> Result<Json<UserView>, RouteError>
+    Query(query): Query<ChangesQuery>,
+) -> Result<Json<ChangesResponse>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let limit = validate_limit(query.limit, LIST_DEFAULT_LIMIT, LIST_MAX_LIMIT)?;
+    let after = query.cursor.as_deref()
+        .map(Cursor::decode)
+        .transpose()
+        .log_route_warn("Invalid cursor")?;
+    // merged users are redirected, so their history is the one of the target
+    let user = repos.users.get(UserId::Internal(id)).await
+        .log_route_error("Failed to get user")?
+        .ok_or_else(not_found_error)?;
+    let page = repos.users.changes(user.id, after, limit).await
+        .log_route_error("Failed to get the change history")?;
+    Ok(Json(ChangesResponse {
+        changes: page.items,
+        next_cursor: page.next.map(|cursor| cursor.encode()),
+    }))
+}
+
+#[tracing::instrument(skip(repos), fields(user_id = %id))]
+async fn list_bans<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path(id): Path<i64>,
+) -> Result<Json<Vec<Ban>>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let user = repos.users.get(UserId::Internal(id)).await
+        .log_route_error("Failed to get user")?
+        .ok_or_else(not_found_error)?;
+    Ok(Json(user.bans))
+}
+
+#[tracing::instrument(skip(repos, req), fields(user_id = %id, issuer = %req.ban.issuer))]
+async fn ban_user<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path(id): Path<i64>,
+    Json(req): Json<BanRequest>,
+) -> Result<(StatusCode, Json<Ban>), RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let ban = req.ban.validated()
+        .log_route_warn("Invalid ban")?;
+    let service_id = match req.service {
+        None => None,
+        Some(service) => Some(registered_service_id(&repos, &service).await?),
+    };
+    let ban = repos.users.ban(id, service_id, ban).await
+        .log_route_error("Failed to ban the user")?
+        .ok_or_else(not_found_error)?;
+    tracing::info!(?service_id, "User banned");
+    Ok((StatusCode::CREATED, Json(ban)))
+}
+
+#[tracing::instrument(skip(repos), fields(user_id = %id))]
+async fn unban_user<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path(id): Path<i64>,
+    Query(query): Query<BanScopeQuery>,
+) -> Result<Success, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let service_id = match service_scope(&repos, query.service_name, query.service_type).await? {
+        ServiceScope::All => None,
+        ServiceScope::Known(id) => Some(id),
+        ServiceScope::Unknown => return Err(not_found_error()),
+    };
+    if !repos.users.unban(id, service_id).await.log_route_error("Failed to unban the user")? {
+        return Err(not_found_error());
+    }
+    tracing::info!(?service_id, "User unbanned");
+    Ok(Success)
+}
+
+#[tracing::instrument(skip(repos), fields(user_id = %id, service_name = %query.service_name))]
+async fn list_settings<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path(id): Path<i64>,
+    Query(query): Query<ServiceQuery>,
+) -> Result<Json<Settings>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let service_id = registered_service_id(&repos, &query.into()).await?;
+    let user = repos.users.get(UserId::Internal(id)).await
+        .log_route_error("Failed to get user")?
+        .ok_or_else(not_found_error)?;
+    let settings = repos.users.settings(user.id, service_id).await
+        .log_route_error("Failed to get settings")?;
+    Ok(Json(settings))
+}
+
+#[tracing::instrument(skip(repos), fields(user_id = %id, key = %key, service_name = %query.service_name))]
+async fn get_setting<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path((id, key)): Path<(i64, String)>,
+    Query(query): Query<ServiceQuery>,
+) -> Result<Json<serde_json::Value>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let service_id = registered_service_id(&repos, &query.into()).await?;
+    let user = repos.users.get(UserId::Internal(id)).await
+        .log_route_error("Failed to get user")?
+        .ok_or_else(not_found_error)?;
+    let value = repos.users.settings(user.id, service_id).await
+        .log_route_error("Failed to get settings")?
+        .remove(&key)
+        .ok_or_else(not_found_error)?;
+    Ok(Json(value))
+}
+
+#[tracing::instrument(skip(repos, value), fields(user_id = %id, key = %key, service_name = %query.service_name))]
+async fn set_setting<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path((id, key)): Path<(i64, String)>,
+    Query(query): Query<ServiceQuery>,
+    Json(value): Json<serde_json::Value>,
+) -> Result<Success, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    validate_setting_key(&key)
+        .and_then(|_| validate_setting_value(&value))
+        .log_route_warn("Invalid setting")?;
+    let service_id = registered_service_id(&repos, &query.into()).await?;
+    let mut settings = repos.users.settings(id, service_id).await
+        .log_route_error("Failed to get settings")?;
+    settings.insert(key.clone(), value.clone());
+    let settings = serde_json::Value::Object(settings.into_iter().collect());
+    check_schema(&repos, service_id, SchemaKind::Settings, &settings).await?;
+    if !repos.users.set_setting(id, service_id, &key, value).await.log_route_error("Failed to save the setting")? {
+        return Err(not_found_error());
+    }
+    Ok(Success)
+}
+
+#[tracing::instrument(skip(repos), fields(user_id = %id, key = %key, service_name = %query.service_name))]
+async fn delete_setting<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path((id, key)): Path<(i64, String)>,
+    Query(query): Query<ServiceQuery>,
+) -> Result<Success, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let service_id = registered_service_id(&repos, &query.into()).await?;
+    if !repos.users.delete_setting(id, service_id, &key).await.log_route_error("Failed to delete the setting")? {
+        return Err(not_found_error());
+    }
+    Ok(Success)
+}
+
+#[tracing::instrument(skip(repos, sender, req), fields(user_id = %id, kind = %req.kind))]
+async fn request_contact_verification<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Extension(sender): Extension<Arc<dyn CodeSender>>,
+    Path(id): Path<i64>,
+    Json(req): Json<ContactRequest>,
+) -> Result<Json<ContactVerificationResponse>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let contact = NewContact::new(req.kind, &req.value)
+        .log_route_warn("Invalid contact")?;
+    if !sender.supports(contact.kind) {
+        return Err(SendError::Unsupported(contact.kind)).log_route_warn("Unsupported contact kind");
+    }
+    let outcome = repos.users.request_contact_verification(id, &contact).await
+        .log_route_error("Failed to request the contact verification")?;
+    let verification = match outcome {
+        ContactRequestOutcome::Requested(verification) => verification,
+        ContactRequestOutcome::NotFound => return Err(not_found_error()),
+        ContactRequestOutcome::TooManyRequests(retry_after) => return Err(RouteError::new_from_status(StatusCode::TOO_MANY_REQUESTS)
+            .set_error_data(RestError::new(format!("too many verification codes requested, try again after {retry_after}")))),
+        ContactRequestOutcome::TakenByAnotherUser => return Err(contact_taken_error()),
+    };
+    match sender.send_code(&contact, &verification).await {
+        Ok(()) => Ok(Json(ContactVerificationResponse { expires_at: verification.expires_at })),
+        Err(e @ SendError::Unsupported(_)) => Err(e).log_route_warn("Unsupported contact kind"),
+        Err(e) => Err(e).log_route_error("Failed to send the verification code"),
+    }
+}
+
+#[tracing::instrument(skip(repos, req), fields(user_id = %id, kind = %kind))]
+async fn confirm_contact<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path((id, kind)): Path<(i64, ContactKind)>,
+    Json(req): Json<ContactConfirmationRequest>,
+) -> Result<Json<Contact>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let outcome = repos.users.confirm_contact(id, kind, &req.code).await
+        .log_route_error("Failed to confirm the contact")?;
+    match outcome {
+        ContactConfirmation::Confirmed(contact) => Ok(Json(contact)),
+        ContactConfirmation::InvalidCode => Err(RouteError::new_bad_request()
+            .set_error_data(RestError::new("the code is invalid or expired"))),
+        ContactConfirmation::NotPending => Err(RouteError::new_not_found()
+            .set_error_data(RestError::new("no contact of this kind is pending verification"))),
+        ContactConfirmation::TakenByAnotherUser => Err(contact_taken_error()),
+    }
+}
+
+#[tracing::instrument(skip(repos), fields(user_id = %id, kind = %kind))]
+async fn delete_contact<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path((id, kind)): Path<(i64, ContactKind)>,
+) -> Result<Success, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    if !repos.users.delete_contact(id, kind).await.log_route_error("Failed to delete the contact")? {
+        return Err(not_found_error());
+    }
+    Ok(Success)
+}
+
+fn contact_taken_error() -> RouteError<RestError> {
+    tracing::warn!("The contact is verified by another user");
+    RouteError::new_from_status(StatusCode::CONFLICT)
+        .set_error_data(RestError::new("the contact is already verified by another user"))
+}
+
+#[tracing::instrument(skip(repos), fields(user_id = %id))]
+async fn get_credentials<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path(id): Path<i64>,
+) -> Result<Json<CredentialsView>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let credentials = repos.users.credentials(&CredentialsKey::User(id)).await
+        .log_route_error("Failed to get credentials")?
+        .ok_or_else(not_found_error)?;
+    Ok(Json(credentials.into()))
+}
+
+#[tracing::instrument(skip(repos, req), fields(user_id = %id))]
+async fn create_credentials<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path(id): Path<i64>,
+    Json(req): Json<CredentialsRequest>,
+) -> Result<(StatusCode, Json<CredentialsView>), RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let username = req.username.as_deref()
+        .map(Username::try_from)
+        .transpose()
+        .log_route_warn("Invalid username")?;
+    let email = req.email.as_deref()
+        .map(|email| NewContact::new(ContactKind::Email, email)
+            .map(|contact| contact.value)
+            .map_err(|_| CredentialsError::InvalidEmail))
+        .transpose()
+        .log_route_warn("Invalid email")?;
+    if username.is_none() && email.is_none() {
+        return Err(CredentialsError::NoLogin).log_route_warn("No login");
+    }
+    let password = Password::try_from(req.password)
+        .log_route_warn("Invalid password")?;
+
+    let credentials = NewCredentials {
+        username,
+        email,
+        password_hash: hash_in_background(password).await?,
+    };
+    let outcome = repos.users.create_credentials(id, &credentials).await
+        .log_route_error("Failed to create credentials")?;
+    match outcome {
+        CredentialsOutcome::Created(credentials) => Ok((StatusCode::CREATED, Json(credentials.into()))),
+        CredentialsOutcome::NotFound => Err(not_found_error()),
+        CredentialsOutcome::AlreadyExists => Err(RouteError::new_from_status(StatusCode::CONFLICT)
+            .set_error_data(RestError::new("the user has credentials already"))),
+        CredentialsOutcome::LoginTaken => Err(RouteError::new_from_status(StatusCode::CONFLICT)
+            .set_error_data(RestError::new("the username or the email is already taken"))),
+        CredentialsOutcome::EmailNotVerified => {
+            tracing::warn!("The email is not verified");
+            Err(RouteError::new_bad_request()
+                .set_error_data(RestError::new("the email must be verified as a contact of the user first")))
+        }
+    }
+}
+
+#[tracing::instrument(skip(repos, req))]
+async fn log_in<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Json(req): Json<LoginRequest>,
+) -> Result<Json<LoginResponse>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    // a malformed login can't be registered, so it's reported the same way as an unknown one
+    let credentials = match Login::try_from(req.login.as_str()) {
+        Ok(login) => repos.users.credentials(&CredentialsKey::Login(login)).await
+            .log_route_error("Failed to get credentials")?,
+        Err(_) => None,
+    };
+    let Some(credentials) = credentials else {
+        tokio::task::spawn_blocking(move || verify_dummy_password(&req.password)).await
+            .log_route_error("Failed to verify the password")?;
+        return Err(invalid_credentials_error());
+    };
+    let user_id = credentials.user_id;
+
+    // checked first, so that the attempts to log in as a deactivated user aren't counted
+    let user = repos.users.get(UserId::Internal(user_id)).await
+        .log_route_error("Failed to get user")?
+        .ok_or_else(not_found_error)?;
+    if user.deactivated() {
+        return Err(deactivated_error());
+    }
+    check_password(&repos, user_id, req.password).await?;
+    tracing::info!(user_id, "User logged in");
+    Ok(Json(LoginResponse { id: user.id }))
+}
+
+#[tracing::instrument(skip(repos, req), fields(user_id = %id))]
+async fn change_password<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path(id): Path<i64>,
+    Json(req): Json<PasswordChangeRequest>,
+) -> Result<Success, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let new_password = Password::try_from(req.new_password)
+        .log_route_warn("Invalid password")?;
+    repos.users.credentials(&CredentialsKey::User(id)).await
+        .log_route_error("Failed to get credentials")?
+        .ok_or_else(not_found_error)?;
+    check_password(&repos, id, req.current_password).await?;
+
+    let password_hash = hash_in_background(new_password).await?;
+    if !repos.users.set_password(id, &password_hash).await.log_route_error("Failed to change the password")? {
+        return Err(not_found_error());
+    }
+    Ok(Success)
+}
+
+/// Answers the same whether the login exists or not; the token is sent to the verified email of the user, if any
+#[tracing::instrument(skip(repos, sender, req))]
+async fn create_password_reset<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Extension(sender): Extension<Arc<dyn CodeSender>>,
+    Json(req): Json<PasswordResetRequest>,
+) -> Result<(StatusCode, Success), RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let login = Login::try_from(req.login.as_str())
+        .log_route_warn("Invalid login")?;
+    let reset = repos.users.create_password_reset(&login).await
+        .log_route_error("Failed to create a password reset token")?;
+    if let Some(reset) = reset {
+        let email = NewContact { kind: ContactKind::Email, value: reset.email };
+        let verification = VerificationCode { code: reset.token, expires_at: reset.expires_at };
+        // a failure is only logged, since reporting it would tell that the account exists
+        if let Err(e) = sender.send_code(&email, &verification).await {
+            tracing::error!(user_id = reset.user_id, error = %e, "Failed to send the password reset token");
+        }
+    }
+    Ok((StatusCode::ACCEPTED, Success))
+}
+
+#[tracing::instrument(skip(repos, req))]
+async fn reset_password<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Json(req): Json<PasswordResetConfirmation>,
+) -> Result<Success, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let new_password = Password::try_from(req.new_password)
+        .log_route_warn("Invalid password")?;
+    let password_hash = hash_in_background(new_password).await?;
+    let user_id = repos.users.redeem_password_reset(&req.token, &password_hash).await
+        .log_route_error("Failed to reset the password")?
+        .ok_or_else(|| RouteError::new_not_found()
+            .set_error_data(RestError::new("the token is invalid or expired")))?;
+    tracing::info!(user_id, "Password reset");
+    Ok(Success)
+}
+
+/// The attempt is counted before the password is checked, so guessing can't go on during the lockout
+async fn check_password<U, S>(repos: &repo::Repositories<U, S>, user_id: i64, password: String) -> Result<(), RouteError<RestError>>
 where
     U: Users,
     S: Services,
 {
-    get_user_impl(repos, UserId::External(id)).await
+    let attempt = repos.users.start_login_attempt(user_id).await
+        .log_route_error("Failed to start the login attempt")?;
+    let password_hash = match attempt {
+        LoginAttempt::Started(password_hash) => password_hash,
+        LoginAttempt::Locked(locked_until) => {
+            tracing::warn!(%locked_until, "Logging in is locked");
+            return Err(RouteError::new_from_status(StatusCode::TOO_MANY_REQUESTS)
+                .set_error_data(RestError::new(format!("too many failed attempts, try again after {locked_until}"))));
+        }
+        LoginAttempt::NotFound => return Err(invalid_credentials_error()),
+    };
+    let valid = tokio::task::spawn_blocking(move || verify_password(&password, &password_hash)).await
+        .log_route_error("Failed to verify the password")?;
+    if !valid {
+        return Err(invalid_credentials_error());
+    }
+    repos.users.reset_login_attempts(user_id).await
+        .log_route_error("Failed to reset the login attempts")
+}
+
+async fn hash_in_background(password: Password) -> Result<String, RouteError<RestError>> {
+    tokio::task::spawn_blocking(move || hash_password(&password)).await
+        .log_route_error("Failed to hash the password")?
+        .log_route_error("Failed to hash the password")
+}
+
+fn invalid_credentials_error() -> RouteError<RestError> {
+    tracing::warn!("Invalid credentials");
+    RouteError::new_from_status(StatusCode::UNAUTHORIZED)
+        .set_error_data(RestError::new("the login or the password is invalid"))
+}
+
+#[tracing::instrument(skip(repos, req), fields(keys_count = req.keys.len()))]
+async fn batch_get<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Json(req): Json<BatchGetRequest>,
+) -> Result<Json<BatchGetResponse>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    if req.keys.len() > BATCH_MAX_SIZE {
+        tracing::warn!("Too many keys in the batch");
+        return Err(RouteError::new_bad_request()
+            .set_error_data(RestError::new(format!("at most {BATCH_MAX_SIZE} keys are allowed"))));
+    }
+
+    // each service is looked up once; keys of unknown services can't match anyone
+    let mut service_ids: HashMap<&Service, Option<i32>> = HashMap::new();
+    let mut keys = Vec::with_capacity(req.keys.len());
+    for key in &req.keys {
+        let key = match key {
+            BatchKeyView::Internal { id } => Some(BatchKey::Internal(*id)),
+            BatchKeyView::External { service, external_id } => {
+                let service_id = match service_ids.get(service) {
+                    Some(service_id) => *service_id,
+                    None => {
+                        let service_id = repos.services.get_id(service).await
+                            .log_route_error("Failed to get service ID")?;
+                        service_ids.insert(service, service_id);
+                        service_id
+                    }
+                };
+                service_id.map(|service_id| BatchKey::External(service_id, external_id.clone()))
+            }
+        };
+        keys.push(key);
+    }
+    let known_keys: Vec<BatchKey> = keys.iter().flatten().cloned().collect();
+    let found = repos.users.get_many(&known_keys).await
+        .log_route_error("Failed to get users")?;
+
+    let mut response = BatchGetResponse::default();
+    for (view, key) in req.keys.into_iter().zip(keys) {
+        match key.and_then(|key| found.get(&key)) {
+            Some(user) => response.users.push(BatchUser { key: view, user: user.clone().into() }),
+            None => response.missing.push(view),
+        }
+    }
+    tracing::info!(found = response.users.len(), missing = response.missing.len(), "Batch lookup finished");
+    Ok(Json(response))
 }
 
 async fn get_user_impl<U, S>(
     repos: Arc<repo::Repositories<U, S>>,
     id: UserId,
-) -> Result<Json<UserView>, RouteError>
+) -> Result<Versioned<Json<UserView>>, RouteError<RestError>>
 where
     U: Users,
     S: Services,
 {
-    let user = repos.users.get(id)
-        .await?
-        .ok_or(RouteError::new_not_found())?
-        .into();
-    Ok(Json(user))
+    let user = repos.users.get(id).await
+        .log_route_error("Failed to get user")?
+        .ok_or_else(not_found_error)?;
+    if user.deactivated() {
+        return Err(deactivated_error());
+    }
+    Ok(Versioned(user.version, Json(user.into())))
 }
 
-#[tracing::instrument(skip(repos, req), fields(external_id = %req.user.external_id, service_type = ?req.service.service_type))]
+#[tracing::instrument(skip(repos, req), fields(external_id = %req.user.external_id, service_type = %req.service.service_type))]
 async fn register_user<U, S>(
     Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
     Json(req): Json<RegistrationRequest>,
@@ -75,22 +729,28 @@ where
     U: Users,
     S: Services,
 {
-    let maybe_service = repos.services.get_id(&req.service).await
-        .log_route_error("Failed to get service ID")?;
-    let service_id = match maybe_service {
-        Some(id) => id,
-        None => repos.services.create(req.service.service_type, &req.service.name).await?
-    };
+    let service_type = get_service_type(&repos, &req.service.service_type).await?;
+    req.user.external_id.validate_for(&service_type)
+        .log_route_warn("Invalid external ID")?;
+    let user = req.user.normalized()
+        .log_route_warn("Invalid name")?;
+    let service_id = get_or_create_service(&repos, &req.service).await?;
 
-    let user_id = repos.users.get_user_id(service_id, req.user.external_id).await
+    let user_id = repos.users.get_user_id(service_id, &user.external_id).await
         .log_route_error("Failed to get user ID")?;
     let status = match user_id {
         Some(id) => {
             tracing::info!(user_id = %id, "User already registered");
+            if let Some(name) = user.name.filter(|_| req.refresh_name) {
+                repos.users.update_value(id, UpdateTarget::Name(name), Some(service_id)).await
+                    .log_route_error("Failed to refresh the name")?;
+                tracing::info!(user_id = %id, "Name refreshed");
+            }
             (StatusCode::FOUND, RegistrationStatus::AlreadyPresent.with_id(id))
         }
         None => {
-            let id = repos.users.register(req.user, service_id, req.consent_info).await
+            check_schema(&repos, service_id, SchemaKind::Consent, &req.consent_info).await?;
+            let id = repos.users.register(user, service_id, req.consent_info).await
                 .log_route_error("Failed to register user")?;
             tracing::info!(user_id = %id, "User registered successfully");
             (StatusCode::CREATED, RegistrationStatus::Created.with_id(id))
@@ -99,49 +759,344 @@ where
     Ok((status.0, Json(status.1)))
 }
 
+/// Passes if the service hasn't registered a schema of the kind
+pub(super) async fn check_schema<U, S>(repos: &repo::Repositories<U, S>, service_id: i32, kind: SchemaKind, document: &serde_json::Value) -> Result<(), RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let Some(schema) = repos.services.schema(service_id, kind).await.log_route_error("Failed to get the schema")? else {
+        return Ok(());
+    };
+    validate_document(&schema, document).map_err(|e| {
+        tracing::warn!(error = %e, kind = kind.as_str(), "The document doesn't match the schema");
+        RouteError::new_bad_request().set_error_data(RestError::from_schema_error(e))
+    })
+}
+
+/// Unlike [get_or_create_service], doesn't register unknown services
+pub(super) async fn registered_service_id<U, S>(repos: &repo::Repositories<U, S>, service: &Service) -> Result<i32, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    repos.services.get_id(service).await
+        .log_route_error("Failed to get service ID")?
+        .ok_or_else(|| {
+            tracing::warn!("The service is not registered");
+            RouteError::new_not_found()
+                .set_error_data(RestError::new("the service is not registered"))
+        })
+}
+
+async fn get_or_create_service<U, S>(repos: &repo::Repositories<U, S>, service: &Service) -> Result<i32, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let maybe_service = repos.services.get_id(service).await
+        .log_route_error("Failed to get service ID")?;
+    let service_id = match maybe_service {
+        Some(id) => id,
+        None => repos.services.create(service.service_type.clone(), &service.name).await?
+    };
+    Ok(service_id)
+}
+
+enum ServiceScope {
+    All,
+    Known(i32),
+    Unknown,
+}
+
+/// Resolves an optional service filter given by its name and type, which must be set together
+async fn service_scope<U, S>(repos: &repo::Repositories<U, S>, name: Option<String>, service_type: Option<ServiceType>) -> Result<ServiceScope, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    match (name, service_type) {
+        (None, None) => Ok(ServiceScope::All),
+        (Some(name), Some(service_type)) => {
+            let service_id = repos.services.get_id(&Service { name, service_type }).await
+                .log_route_error("Failed to get service ID")?;
+            Ok(service_id.map_or(ServiceScope::Unknown, ServiceScope::Known))
+        }
+        _ => {
+            tracing::warn!("Incomplete service filter");
+            Err(RouteError::new_bad_request()
+                .set_error_data(RestError::new("service_name and service_type must be set together")))
+        }
+    }
+}
+
+/// The service on whose behalf the change is made, named by the `X-Service-Name` and `X-Service-Type` headers
+/// which must be set together; the change is made by nobody in particular if neither is set
+pub(super) async fn actor_service_id<U, S>(repos: &repo::Repositories<U, S>, headers: &HeaderMap) -> Result<Option<i32>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let header = |name: &str| headers.get(name)
+        .map(|value| value.to_str().map(ToOwned::to_owned))
+        .transpose()
+        .log_route_warn("Invalid actor header");
+    let name = header(ACTOR_NAME_HEADER)?;
+    let service_type = header(ACTOR_TYPE_HEADER)?
+        .map(ServiceType::try_from)
+        .transpose()
+        .log_route_warn("Invalid actor service type")?;
+    match service_scope(repos, name, service_type).await? {
+        ServiceScope::All => Ok(None),
+        ServiceScope::Known(id) => Ok(Some(id)),
+        ServiceScope::Unknown => {
+            tracing::warn!("The actor service is not registered");
+            Err(RouteError::new_not_found()
+                .set_error_data(RestError::new("the service is not registered")))
+        }
+    }
+}
+
+/// An absent or zero limit stands for the default one, the same as in the gRPC API
+fn validate_limit(limit: Option<u32>, default: u32, max: u32) -> Result<u32, RouteError<RestError>> {
+    match limit.unwrap_or(0) {
+        0 => Ok(default),
+        limit if limit > max => {
+            tracing::warn!(limit, "Invalid limit");
+            Err(RouteError::new_bad_request()
+                .set_error_data(RestError::new(format!("the limit must not exceed {max}"))))
+        }
+        limit => Ok(limit),
+    }
+}
+
+async fn get_service_type<U, S>(repos: &repo::Repositories<U, S>, service_type: &ServiceType) -> Result<ServiceTypeInfo, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    repos.services.get_type(service_type).await
+        .log_route_error("Failed to get service type")?
+        .ok_or_else(|| {
+            tracing::warn!(service_type = %service_type, "Unknown service type");
+            RouteError::new_bad_request()
+                .set_error_data(RestError::new(format!("unknown service type: {service_type}")))
+        })
+}
+
 #[tracing::instrument(skip(repos), fields(user_id = %id, language_code = %code))]
 async fn update_language<U, S>(
     Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
     Path((id, code)): Path<(i64, String)>,
-) -> Result<Success, RouteError<RestError>>
+    headers: HeaderMap,
+) -> Result<Versioned<Success>, RouteError<RestError>>
 where
     U: Users,
     S: Services,
 {
-    let lang_code: Code = code.try_into()
-        .log_route_warn("Invalid language code format")?;
-    update_impl(repos, id, lang_code.into()).await
+    let lang_code: LanguageTag = code.try_into()
+        .log_route_warn("Invalid language tag")?;
+    update_impl(repos, id, &headers, lang_code.into()).await
 }
 
 #[tracing::instrument(skip(repos), fields(user_id = %id, lat = %location.latitude, lon = %location.longitude))]
 async fn update_location<U, S>(
     Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
     Path(id): Path<i64>,
+    headers: HeaderMap,
     Query(location): Query<Location>,
-) -> Result<Success, RouteError<RestError>>
+) -> Result<Versioned<Success>, RouteError<RestError>>
 where
     U: Users,
     S: Services,
 {
     location.validate()
         .log_route_warn("Invalid location coordinates")?;
-    update_impl(repos, id, location.into()).await
+    update_impl(repos, id, &headers, location.into()).await
 }
 
-async fn update_impl<U, S>(repos: Arc<repo::Repositories<U, S>>, id: i64, target: UpdateTarget) -> Result<Success, RouteError<RestError>>
+#[tracing::instrument(skip(repos, merge_patch), fields(user_id = %id))]
+async fn patch_user<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+    Json(merge_patch): Json<UserMergePatch>,
+) -> Result<Versioned<Json<UserView>>, RouteError<RestError>>
 where
     U: Users,
     S: Services,
 {
-    repos.users.update_value(id, target).await
-        .log_route_error("Failed to update user")?;
-    Ok(Success)
+    // `"options": null` clears all the options
+    let options = merge_patch.options
+        .map(|options| options.unwrap_or(OptionsMergePatch {
+            language_code: Some(None),
+            location: Some(None),
+            timezone: Some(None),
+        }))
+        .unwrap_or_default();
+    let language_code = options.language_code
+        .map(|code| code.map(LanguageTag::try_from).transpose())
+        .transpose()
+        .log_route_warn("Invalid language tag")?;
+    if let Some(Some(location)) = &options.location {
+        location.validate()
+            .log_route_warn("Invalid location coordinates")?;
+    }
+    let timezone = options.timezone
+        .map(|timezone| timezone.map(Timezone::try_from).transpose())
+        .transpose()
+        .log_route_warn("Invalid timezone")?;
+    let name = merge_patch.name
+        .map(|name| name.as_deref().map(normalize_name).transpose())
+        .transpose()
+        .log_route_warn("Invalid name")?;
+    let patch = UserPatch {
+        name,
+        language_code,
+        location: options.location,
+        timezone,
+    };
+    if patch.is_empty() {
+        tracing::warn!("Empty patch");
+        return Err(RouteError::new_bad_request()
+            .set_error_data(RestError::new("nothing to update")));
+    }
+
+    let user = patch_impl(&repos, id, &headers, patch).await?;
+    tracing::info!("User patched successfully");
+    Ok(Versioned(user.version, Json(user.into())))
 }
 
-#[tracing::instrument(skip(repos), fields(user_id = %id, variant = %till))]
+#[tracing::instrument(skip(repos, req), fields(user_id = %id))]
+async fn update_name<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+    Json(req): Json<NameUpdate>,
+) -> Result<Versioned<Success>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let name = normalize_name(&req.name)
+        .log_route_warn("Invalid name")?;
+    update_impl(repos, id, &headers, UpdateTarget::Name(name)).await
+}
+
+#[tracing::instrument(skip(repos, req), fields(user_id = %id, timezone = %req.timezone))]
+async fn update_timezone<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+    Json(req): Json<TimezoneUpdate>,
+) -> Result<Versioned<Success>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let timezone = Timezone::try_from(req.timezone)
+        .log_route_warn("Invalid timezone")?;
+    update_impl(repos, id, &headers, timezone.into()).await
+}
+
+#[tracing::instrument(skip(repos), fields(user_id = %id))]
+async fn clear_language<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+) -> Result<Versioned<Success>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    update_impl(repos, id, &headers, UpdateTarget::ClearLanguage).await
+}
+
+#[tracing::instrument(skip(repos), fields(user_id = %id))]
+async fn clear_location<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+) -> Result<Versioned<Success>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    update_impl(repos, id, &headers, UpdateTarget::ClearLocation).await
+}
+
+#[tracing::instrument(skip(repos), fields(user_id = %id))]
+async fn clear_timezone<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+) -> Result<Versioned<Success>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    update_impl(repos, id, &headers, UpdateTarget::ClearTimezone).await
+}
+
+async fn update_impl<U, S>(repos: Arc<repo::Repositories<U, S>>, id: i64, headers: &HeaderMap, target: UpdateTarget) -> Result<Versioned<Success>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let user = patch_impl(&repos, id, headers, target.into()).await?;
+    Ok(Versioned(user.version, Success))
+}
+
+/// Honors the `If-Match` header: the patch is applied only to the version of the user it names
+async fn patch_impl<U, S>(repos: &repo::Repositories<U, S>, id: i64, headers: &HeaderMap, patch: UserPatch) -> Result<SavedUser, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let expected_version = expected_version(headers)?;
+    let actor_service_id = actor_service_id(repos, headers).await?;
+    match repos.users.patch(id, patch, expected_version, actor_service_id).await.log_route_error("Failed to update user")? {
+        PatchOutcome::Patched(user) => Ok(*user),
+        PatchOutcome::NotFound => Err(not_found_error()),
+        PatchOutcome::VersionMismatch(current_version) => {
+            tracing::warn!(?expected_version, current_version, "The user has been modified concurrently");
+            Err(RouteError::new_from_status(StatusCode::PRECONDITION_FAILED)
+                .set_error_data(RestError::new(format!("the user has been modified, the current version is {current_version}"))))
+        }
+    }
+}
+
+/// `None` if there is no `If-Match` header or it's `*`; otherwise a single strong ETag is expected
+fn expected_version(headers: &HeaderMap) -> Result<Option<i64>, RouteError<RestError>> {
+    let Some(if_match) = headers.get(header::IF_MATCH) else {
+        return Ok(None);
+    };
+    let if_match = if_match.to_str().unwrap_or_default().trim();
+    if if_match == "*" {
+        return Ok(None);
+    }
+    if_match.strip_prefix('"')
+        .and_then(|tag| tag.strip_suffix('"'))
+        .and_then(|version| version.parse().ok())
+        .map(Some)
+        .ok_or_else(|| {
+            tracing::warn!(if_match, "Invalid If-Match header");
+            RouteError::new_bad_request()
+                .set_error_data(RestError::new("If-Match must be a single ETag returned by the service"))
+        })
+}
+
+fn deactivated_error() -> RouteError<RestError> {
+    tracing::warn!("The user is deactivated");
+    RouteError::new_from_status(StatusCode::GONE)
+        .set_error_data(RestError::new("the user is deactivated"))
+}
+
+#[tracing::instrument(skip(repos, headers), fields(user_id = %id, variant = %till))]
 async fn activate_premium<U, S>(
     Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
     Path((id, till)): Path<(i64, String)>,
+    headers: HeaderMap,
 ) -> Result<Json<PremiumActivationResult>, RouteError<RestError>>
 where
     U: Users,
@@ -149,8 +1104,98 @@ where
 {
     let variant = PremiumVariantRest::from_str(&till)
         .log_route_warn("Invalid premium variant")?;
-    let activation_result = repos.users.activate_premium(id, variant.into()).await
-        .log_route_error("Failed to activate premium")?;
+    let actor_service_id = actor_service_id(&repos, &headers).await?;
+    let activation_result = match repos.users.activate_premium(id, variant.into(), actor_service_id).await
+        .log_route_error("Failed to activate premium")? {
+        PremiumOutcome::Activated(till) => Some(till),
+        PremiumOutcome::NotFound => None,
+        PremiumOutcome::Deactivated => return Err(deactivated_error()),
+    };
     tracing::info!(?activation_result, "Premium activation completed");
     Ok(Json(PremiumActivationResult::from(activation_result)))
 }
+
+#[tracing::instrument(skip(repos, issuer), fields(user_id = %id))]
+async fn issue_token<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Extension(issuer): Extension<Arc<TokenIssuer>>,
+    Path(id): Path<i64>,
+) -> Result<Json<TokenResponse>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let user = repos.users.get(UserId::Internal(id)).await
+        .log_route_error("Failed to get user")?
+        .ok_or_else(not_found_error)?;
+    if user.deactivated() {
+        return Err(deactivated_error());
+    }
+    // merged users are redirected, so the token is issued for the target
+    let services = repos.users.get_services(user.id).await
+        .log_route_error("Failed to get services of the user")?;
+    let issued = issuer.issue(&user, services)
+        .log_route_error("Failed to issue a token")?;
+    Ok(Json(issued.into()))
+}
+
+#[tracing::instrument(skip(repos), fields(user_id = %id))]
+async fn create_link_code<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path(id): Path<i64>,
+) -> Result<Json<LinkCode>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let link_code = repos.users.create_link_code(id).await
+        .log_route_error("Failed to create a link code")?
+        .ok_or_else(not_found_error)?;
+    Ok(Json(link_code))
+}
+
+#[tracing::instrument(skip(repos, req), fields(external_id = %req.user.external_id, service_type = %req.service.service_type))]
+async fn link_account<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Json(req): Json<LinkRequest>,
+) -> Result<Json<LinkResponse>, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let service_type = get_service_type(&repos, &req.service.service_type).await?;
+    req.user.external_id.validate_for(&service_type)
+        .log_route_warn("Invalid external ID")?;
+    let service_id = get_or_create_service(&repos, &req.service).await?;
+    check_schema(&repos, service_id, SchemaKind::Consent, &req.consent_info).await?;
+    let outcome = repos.users.redeem_link_code(&req.code, req.user, service_id, req.consent_info).await
+        .log_route_error("Failed to link the account")?;
+    match outcome {
+        LinkOutcome::Linked(id) => Ok(Json(LinkResponse { id })),
+        LinkOutcome::InvalidCode => Err(RouteError::new_not_found()
+            .set_error_data(RestError::new("the code is invalid or expired"))),
+        LinkOutcome::MappedToAnotherUser(_) => Err(RouteError::new_from_status(StatusCode::CONFLICT)
+            .set_error_data(RestError::new("the account is already registered as another user"))),
+    }
+}
+
+#[tracing::instrument(skip(repos), fields(external_id = %external_id, service_name = %query.service.service_name, service_type = %query.service.service_type))]
+async fn unlink_user<U, S>(
+    Extension(repos): Extension<Arc<repo::Repositories<U, S>>>,
+    Path(external_id): Path<String>,
+    Query(query): Query<UnlinkQuery>,
+) -> Result<Success, RouteError<RestError>>
+where
+    U: Users,
+    S: Services,
+{
+    let service = Service::from(query.service);
+    let service_id = repos.services.get_id(&service).await
+        .log_route_error("Failed to get service ID")?
+        .ok_or_else(not_found_error)?;
+    let user_id = repos.users.unlink(service_id, &external_id.as_str().into(), query.withdraw_consent).await
+        .log_route_error("Failed to unlink the account")?
+        .ok_or_else(not_found_error)?;
+    tracing::info!(user_id, "Account unlinked successfully");
+    Ok(Success)
+}
@@ -11,15 +11,49 @@ use opentelemetry::trace::SpanId;
 use tracing::subscriber::set_default;
 use serde_json::json;
 use tower::ServiceExt;
-use crate::dto::{Code, ExternalUser, SavedUser, Service, ServiceType};
-use crate::repo::test::mocks::{mock_repositories, ServicesMock, CtorWithData, UsersMock, ExternalId, MockRepositories};
+use crate::contacts::{CodeSender, SendFuture};
+use crate::dto::{ContactKind, ExternalId, ExternalUser, Location, NewContact, SavedUser, Service, ServiceType, VerificationCode};
+use crate::repo::test::mocks::{mock_repositories, ServicesMock, CtorWithData, UsersMock, MockRepositories};
 use crate::repo::test::otel::setup_otel_test;
 use crate::{repo, rest};
-use crate::repo::users::{UserId, Users};
+use crate::repo::users::{Users, LOGIN_MAX_FAILED_ATTEMPTS};
 use crate::repo::services::Services;
+use crate::tokens::{self, TokenConfig, TokenIssuer, TEST_SECRET};
 
 struct UserServiceClient {
     router: axum::Router,
+    admin_router: axum::Router,
+    issuer: Arc<TokenIssuer>,
+    sender: Arc<RecordingSender>,
+}
+
+/// Keeps the codes instead of delivering them
+#[derive(Default)]
+struct RecordingSender {
+    codes: std::sync::Mutex<Vec<(String, String)>>,
+}
+
+impl RecordingSender {
+    fn last_code(&self, value: &str) -> Option<String> {
+        self.codes.lock().expect("the lock is poisoned")
+            .iter()
+            .rev()
+            .find(|(contact, _)| contact == value)
+            .map(|(_, code)| code.clone())
+    }
+}
+
+impl CodeSender for RecordingSender {
+    /// Like the SMTP sender
+    fn supports(&self, kind: ContactKind) -> bool {
+        kind == ContactKind::Email
+    }
+
+    fn send_code<'a>(&'a self, contact: &'a NewContact, verification: &'a VerificationCode) -> SendFuture<'a> {
+        self.codes.lock().expect("the lock is poisoned")
+            .push((contact.value.clone(), verification.code.clone()));
+        Box::pin(async { Ok(()) })
+    }
 }
 
 impl Default for UserServiceClient {
@@ -34,29 +68,46 @@ impl UserServiceClient {
         U: Users + Send + Sync + 'static,
         S: Services + Send + Sync + 'static,
     {
+        let repos = Arc::new(repos);
+        let issuer = Arc::new(TokenIssuer::new(TokenConfig::new(TEST_SECRET.to_owned())).expect("couldn't create a token issuer"));
+        let sender = Arc::new(RecordingSender::default());
         Self {
-            router: rest::router(Arc::new(repos)),
+            router: rest::router(repos.clone(), issuer.clone(), sender.clone()),
+            admin_router: rest::admin_router(repos),
+            issuer,
+            sender,
         }
     }
 }
 
 impl UserServiceClient {
-    async fn get_user(&self, uid: UserId) -> anyhow::Result<Response> {
+    async fn get_user(&self, user_id: i64) -> anyhow::Result<Response> {
+        let app = self.router.clone();
+        let response = app.oneshot(
+            Request::builder()
+                .method(http::Method::GET)
+                .uri(format!("/{user_id}"))
+                .body(Body::empty())?
+        ).await?;
+        Ok(response)
+    }
+
+    async fn get_external_user(&self, external_id: &ExternalId, service: &Service) -> anyhow::Result<Response> {
         let app = self.router.clone();
-        let path = match uid {
-            UserId::Internal(internal_id) => format!("/{internal_id}"),
-            UserId::External(external_id) => format!("/external/{external_id}"),
-        };
         let response = app.oneshot(
             Request::builder()
                 .method(http::Method::GET)
-                .uri(path)
+                .uri(format!("/external/{external_id}?service_name={}&service_type={}", service.name, service.service_type))
                 .body(Body::empty())?
         ).await?;
         Ok(response)
     }
 
     async fn create_user(&self, user: &ExternalUser, service: &Service) -> anyhow::Result<Response> {
+        self.register_user(user, service, false).await
+    }
+
+    async fn register_user(&self, user: &ExternalUser, service: &Service, refresh_name: bool) -> anyhow::Result<Response> {
         let app = self.router.clone();
         let response = app.oneshot(
             Request::builder()
@@ -73,14 +124,57 @@ impl UserServiceClient {
                             "name": service.name,
                             "type": service.service_type
                         },
-                        "consent_info": {"test": true}
+                        "consent_info": {"test": true},
+                        "refresh_name": refresh_name
+                    }))?
+                ))?
+        ).await?;
+        Ok(response)
+    }
+
+    async fn register_with_consent(&self, user: &ExternalUser, service: &Service, consent_info: serde_json::Value) -> anyhow::Result<Response> {
+        let app = self.router.clone();
+        let response = app.oneshot(
+            Request::builder()
+                .method(http::Method::POST)
+                .uri("/external")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(Body::from(
+                    serde_json::to_vec(&json!({
+                        "user": user,
+                        "service": service,
+                        "consent_info": consent_info
                     }))?
                 ))?
         ).await?;
         Ok(response)
     }
 
-    async fn update_user_language(&self, user_id: i64, code: Code) -> anyhow::Result<Response> {
+    async fn update_user_name(&self, user_id: i64, name: &str) -> anyhow::Result<Response> {
+        let app = self.router.clone();
+        let response = app.oneshot(
+            Request::builder()
+                .method(http::Method::PATCH)
+                .uri(format!("/{user_id}/name"))
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(Body::from(serde_json::to_vec(&json!({"name": name}))?))?
+        ).await?;
+        Ok(response)
+    }
+
+    async fn update_user_timezone(&self, user_id: i64, timezone: &str) -> anyhow::Result<Response> {
+        let app = self.router.clone();
+        let response = app.oneshot(
+            Request::builder()
+                .method(http::Method::PATCH)
+                .uri(format!("/{user_id}/timezone"))
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(Body::from(serde_json::to_vec(&json!({"timezone": timezone}))?))?
+        ).await?;
+        Ok(response)
+    }
+
+    async fn update_user_language(&self, user_id: i64, code: &str) -> anyhow::Result<Response> {
         let app = self.router.clone();
         let response = app.oneshot(
             Request::builder()
@@ -103,123 +197,1471 @@ impl UserServiceClient {
         Ok(response)
     }
 
-    async fn activate_user_premium(&self, user_id: i64, variant: &str) -> anyhow::Result<Response> {
+    async fn clear_user_value(&self, user_id: i64, path: &str) -> anyhow::Result<Response> {
+        let app = self.router.clone();
+        let response = app.oneshot(
+            Request::builder()
+                .method(http::Method::DELETE)
+                .uri(format!("/{user_id}/{path}"))
+                .body(Body::empty())?
+        ).await?;
+        Ok(response)
+    }
+
+    async fn issue_token(&self, user_id: i64) -> anyhow::Result<Response> {
         let app = self.router.clone();
         let response = app.oneshot(
             Request::builder()
                 .method(http::Method::POST)
-                .uri(format!("/{user_id}/premium/activate/{variant}"))
+                .uri(format!("/{user_id}/token"))
                 .body(Body::empty())?
         ).await?;
         Ok(response)
     }
-}
 
-#[tokio::test]
-async fn test_get_and_create() -> anyhow::Result<()> {
-    let _ = tracing_subscriber::fmt().with_test_writer().try_init();
+    async fn get_jwks(&self) -> anyhow::Result<Response> {
+        let app = rest::well_known_router(self.issuer.clone());
+        let response = app.oneshot(
+            Request::builder()
+                .method(http::Method::GET)
+                .uri("/jwks.json")
+                .body(Body::empty())?
+        ).await?;
+        Ok(response)
+    }
 
-    let client = UserServiceClient::default();
-    let external_user = ExternalUser {
-        external_id: 1234567890,
-        name: Some("SadBot".to_owned()),
-    };
-    let service = Service {
-        name: "SadFavBot".to_string(),
-        service_type: ServiceType::TelegramBot,
-    };
+    async fn create_link_code(&self, user_id: i64) -> anyhow::Result<Response> {
+        let app = self.router.clone();
+        let response = app.oneshot(
+            Request::builder()
+                .method(http::Method::POST)
+                .uri(format!("/{user_id}/link-code"))
+                .body(Body::empty())?
+        ).await?;
+        Ok(response)
+    }
 
-    tracing::info!("ensure nobody is in the database");
-    let response = client.get_user(UserId::Internal(1)).await?;
-    assert_eq!(response.status(), StatusCode::NOT_FOUND);
-    let response = client.get_user(UserId::External(external_user.external_id)).await?;
-    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    async fn link_account(&self, code: &str, user: &ExternalUser, service: &Service) -> anyhow::Result<Response> {
+        let app = self.router.clone();
+        let response = app.oneshot(
+            Request::builder()
+                .method(http::Method::POST)
+                .uri("/link")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(Body::from(
+                    serde_json::to_vec(&json!({
+                        "code": code,
+                        "user": {
+                            "external_id": user.external_id,
+                            "name": user.name
+                        },
+                        "service": {
+                            "name": service.name,
+                            "type": service.service_type
+                        },
+                        "consent_info": {"test": true}
+                    }))?
+                ))?
+        ).await?;
+        Ok(response)
+    }
 
-    tracing::info!("create the first user");
-    let response = client.create_user(&external_user, &service).await?;
-    assert_eq!(response.status(), StatusCode::CREATED);
-    let body = to_json_value(response).await?;
-    assert_eq!(body, json!({
-        "status": "created",
-        "id": 1
-    }));
+    async fn unlink_user(&self, external_id: &ExternalId, service: &Service) -> anyhow::Result<Response> {
+        let app = self.router.clone();
+        let response = app.oneshot(
+            Request::builder()
+                .method(http::Method::DELETE)
+                .uri(format!("/external/{external_id}?service_name={}&service_type={}&withdraw_consent=true", service.name, service.service_type))
+                .body(Body::empty())?
+        ).await?;
+        Ok(response)
+    }
 
-    tracing::info!("try to create the same user again");
-    let response = client.create_user(&external_user, &service).await?;
-    assert_eq!(response.status(), StatusCode::FOUND);
-    let body = to_json_value(response).await?;
-    assert_eq!(body, json!({
-        "status": "already_present",
-        "id": 1
-    }));
+    async fn patch_user(&self, user_id: i64, merge_patch: serde_json::Value) -> anyhow::Result<Response> {
+        self.patch_user_if_match(user_id, None, merge_patch).await
+    }
 
-    tracing::info!("test the output of the GET method");
-    let response = client.get_user(UserId::Internal(1)).await?;
-    assert_eq!(response.status(), StatusCode::OK);
-    let response = client.get_user(UserId::External(external_user.external_id)).await?;
-    assert_eq!(response.status(), StatusCode::OK);
-    let body = to_json_value(response).await?;
-    assert_eq!(body, json!({
-        "id": 1,
-        "name": external_user.name.unwrap(),
-        "options": {
-            "language_code": null,
-            "location": null
-        },
-        "is_premium": false
-    }));
+    async fn patch_user_if_match(&self, user_id: i64, if_match: Option<&str>, merge_patch: serde_json::Value) -> anyhow::Result<Response> {
+        let app = self.router.clone();
+        let mut request = Request::builder()
+            .method(http::Method::PATCH)
+            .uri(format!("/{user_id}"))
+            .header(http::header::CONTENT_TYPE, "application/merge-patch+json");
+        if let Some(if_match) = if_match {
+            request = request.header(http::header::IF_MATCH, if_match);
+        }
+        let response = app.oneshot(
+            request.body(Body::from(serde_json::to_vec(&merge_patch)?))?
+        ).await?;
+        Ok(response)
+    }
 
-    Ok(())
-}
+    async fn patch_user_as(&self, user_id: i64, actor: &Service, merge_patch: serde_json::Value) -> anyhow::Result<Response> {
+        let app = self.router.clone();
+        let response = app.oneshot(
+            Request::builder()
+                .method(http::Method::PATCH)
+                .uri(format!("/{user_id}"))
+                .header(http::header::CONTENT_TYPE, "application/merge-patch+json")
+                .header("X-Service-Name", &actor.name)
+                .header("X-Service-Type", actor.service_type.to_string())
+                .body(Body::from(serde_json::to_vec(&merge_patch)?))?
+        ).await?;
+        Ok(response)
+    }
 
-#[tokio::test]
-async fn test_updates() -> anyhow::Result<()> {
-    let client = UserServiceClient::new(build_repos_with_test_user());
-    let username = build_external_user().name.unwrap();
-    let (latitude, longitude) = (12.345, 67.89);
+    async fn list_users(&self, query: &str) -> anyhow::Result<Response> {
+        let app = self.router.clone();
+        let response = app.oneshot(
+            Request::builder()
+                .method(http::Method::GET)
+                .uri(format!("/?{query}"))
+                .body(Body::empty())?
+        ).await?;
+        Ok(response)
+    }
 
-    let response = client.update_user_language(1, "ru".try_into()?).await?;
-    ensure_success(response).await?;
+    async fn list_changes(&self, user_id: i64, query: &str) -> anyhow::Result<Response> {
+        let app = self.router.clone();
+        let response = app.oneshot(
+            Request::builder()
+                .method(http::Method::GET)
+                .uri(format!("/{user_id}/changes?{query}"))
+                .body(Body::empty())?
+        ).await?;
+        Ok(response)
+    }
 
-    let response = client.update_user_location(1, latitude, longitude).await?;
-    ensure_success(response).await?;
+    async fn list_bans(&self, user_id: i64) -> anyhow::Result<Response> {
+        let app = self.router.clone();
+        let response = app.oneshot(
+            Request::builder()
+                .method(http::Method::GET)
+                .uri(format!("/{user_id}/bans"))
+                .body(Body::empty())?
+        ).await?;
+        Ok(response)
+    }
 
-    let response = client.activate_user_premium(1, "week").await?;
-    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
-    let response = client.activate_user_premium(1, "month").await?;
-    assert_eq!(response.status(), StatusCode::OK);
-    let body = to_json_value(response).await?;
-    let date_part_str_range = 0..10;
-    let date_in_month = &Utc::now()
-        .checked_add_months(Months::new(1))
-        .unwrap()
-        .with_nanosecond(0)
-        .unwrap()
-        .to_string()
-        [date_part_str_range.clone()];
-    assert_eq!(body["success"], true);
-    let active_till = body["active_till"].as_str()
-        .map(|s| &s[date_part_str_range])
-        .expect("active_till must be present here");
-    assert_eq!(active_till, date_in_month);
+    async fn ban_user(&self, user_id: i64, ban: serde_json::Value) -> anyhow::Result<Response> {
+        let app = self.router.clone();
+        let response = app.oneshot(
+            Request::builder()
+                .method(http::Method::POST)
+                .uri(format!("/{user_id}/bans"))
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(Body::from(serde_json::to_vec(&ban)?))?
+        ).await?;
+        Ok(response)
+    }
 
-    let response = client.get_user(UserId::Internal(1)).await?;
-    assert_eq!(response.status(), StatusCode::OK);
-    let body = to_json_value(response).await?;
-    assert_eq!(body, json!({
-        "id": 1,
-        "name": username,
-        "options": {
-            "language_code": "ru",
-            "location": {
-                "latitude": latitude,
-                "longitude": longitude
-            }
-        },
-        "is_premium": true
-    }));
+    async fn unban_user(&self, user_id: i64, query: &str) -> anyhow::Result<Response> {
+        let app = self.router.clone();
+        let response = app.oneshot(
+            Request::builder()
+                .method(http::Method::DELETE)
+                .uri(format!("/{user_id}/bans?{query}"))
+                .body(Body::empty())?
+        ).await?;
+        Ok(response)
+    }
 
-    Ok(())
+    async fn user_setting(&self, method: http::Method, path: &str, value: Option<serde_json::Value>) -> anyhow::Result<Response> {
+        let app = self.router.clone();
+        let response = app.oneshot(build_json_request(method, path, value)?).await?;
+        Ok(response)
+    }
+
+    async fn contact_request(&self, path: &str, value: serde_json::Value) -> anyhow::Result<Response> {
+        let app = self.router.clone();
+        let response = app.oneshot(build_json_request(http::Method::POST, path, Some(value))?).await?;
+        Ok(response)
+    }
+
+    async fn verify_email(&self, user_id: i64, email: &str) -> anyhow::Result<()> {
+        let response = self.contact_request(&format!("/{user_id}/contacts"), json!({"kind": "email", "value": email})).await?;
+        anyhow::ensure!(response.status() == StatusCode::OK, "the code must be requested");
+        let code = self.sender.last_code(email).ok_or_else(|| anyhow::anyhow!("the code must be sent"))?;
+        let response = self.contact_request(&format!("/{user_id}/contacts/email/confirm"), json!({"code": code})).await?;
+        anyhow::ensure!(response.status() == StatusCode::OK, "the email must be confirmed");
+        Ok(())
+    }
+
+    async fn delete_contact(&self, user_id: i64, kind: &str) -> anyhow::Result<Response> {
+        let app = self.router.clone();
+        let response = app.oneshot(
+            Request::builder()
+                .method(http::Method::DELETE)
+                .uri(format!("/{user_id}/contacts/{kind}"))
+                .body(Body::empty())?
+        ).await?;
+        Ok(response)
+    }
+
+    async fn credentials_request(&self, path: &str, value: serde_json::Value) -> anyhow::Result<Response> {
+        let app = self.router.clone();
+        let response = app.oneshot(build_json_request(http::Method::POST, path, Some(value))?).await?;
+        Ok(response)
+    }
+
+    async fn admin_request(&self, method: http::Method, path: &str, value: Option<serde_json::Value>) -> anyhow::Result<Response> {
+        let app = self.admin_router.clone();
+        let response = app.oneshot(build_json_request(method, path, value)?).await?;
+        Ok(response)
+    }
+
+    async fn search_users(&self, query: &str) -> anyhow::Result<Response> {
+        let app = self.router.clone();
+        let response = app.oneshot(
+            Request::builder()
+                .method(http::Method::GET)
+                .uri(format!("/search?{query}"))
+                .body(Body::empty())?
+        ).await?;
+        Ok(response)
+    }
+
+    async fn nearby_users(&self, query: &str) -> anyhow::Result<Response> {
+        let app = self.router.clone();
+        let response = app.oneshot(
+            Request::builder()
+                .method(http::Method::GET)
+                .uri(format!("/nearby?{query}"))
+                .body(Body::empty())?
+        ).await?;
+        Ok(response)
+    }
+
+    async fn list_languages(&self) -> anyhow::Result<Response> {
+        let app = self.router.clone();
+        let response = app.oneshot(
+            Request::builder()
+                .method(http::Method::GET)
+                .uri("/languages")
+                .body(Body::empty())?
+        ).await?;
+        Ok(response)
+    }
+
+    async fn batch_get(&self, keys: serde_json::Value) -> anyhow::Result<Response> {
+        let app = self.router.clone();
+        let response = app.oneshot(
+            Request::builder()
+                .method(http::Method::POST)
+                .uri("/batch")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(Body::from(
+                    serde_json::to_vec(&json!({"keys": keys}))?
+                ))?
+        ).await?;
+        Ok(response)
+    }
+
+    async fn list_service_types(&self) -> anyhow::Result<Response> {
+        let app = self.admin_router.clone();
+        let response = app.oneshot(
+            Request::builder()
+                .method(http::Method::GET)
+                .uri("/service-types")
+                .body(Body::empty())?
+        ).await?;
+        Ok(response)
+    }
+
+    async fn create_service_type(&self, name: &str, string_ids: bool) -> anyhow::Result<Response> {
+        let app = self.admin_router.clone();
+        let response = app.oneshot(
+            Request::builder()
+                .method(http::Method::POST)
+                .uri("/service-types")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(Body::from(
+                    serde_json::to_vec(&json!({
+                        "name": name,
+                        "string_ids": string_ids
+                    }))?
+                ))?
+        ).await?;
+        Ok(response)
+    }
+
+    async fn merge_users(&self, source_id: i64, target_id: i64) -> anyhow::Result<Response> {
+        let app = self.admin_router.clone();
+        let response = app.oneshot(
+            Request::builder()
+                .method(http::Method::POST)
+                .uri("/users/merge")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(Body::from(
+                    serde_json::to_vec(&json!({
+                        "source_id": source_id,
+                        "target_id": target_id
+                    }))?
+                ))?
+        ).await?;
+        Ok(response)
+    }
+
+    /// `action` is either `deactivate` or `reactivate`
+    async fn change_user_activity(&self, user_id: i64, action: &str) -> anyhow::Result<Response> {
+        let app = self.admin_router.clone();
+        let response = app.oneshot(
+            Request::builder()
+                .method(http::Method::POST)
+                .uri(format!("/users/{user_id}/{action}"))
+                .body(Body::empty())?
+        ).await?;
+        Ok(response)
+    }
+
+    async fn activate_user_premium(&self, user_id: i64, variant: &str) -> anyhow::Result<Response> {
+        let app = self.router.clone();
+        let response = app.oneshot(
+            Request::builder()
+                .method(http::Method::POST)
+                .uri(format!("/{user_id}/premium/activate/{variant}"))
+                .body(Body::empty())?
+        ).await?;
+        Ok(response)
+    }
+}
+
+#[tokio::test]
+async fn test_get_and_create() -> anyhow::Result<()> {
+    let _ = tracing_subscriber::fmt().with_test_writer().try_init();
+
+    let client = UserServiceClient::default();
+    let external_user = ExternalUser {
+        external_id: ExternalId::Numeric(1234567890),
+        name: Some("SadBot".to_owned()),
+    };
+    let service = Service {
+        name: "SadFavBot".to_string(),
+        service_type: ServiceType::TELEGRAM_BOT,
+    };
+
+    tracing::info!("ensure nobody is in the database");
+    let response = client.get_user(1).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = client.get_external_user(&external_user.external_id, &service).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    tracing::info!("create the first user");
+    let response = client.create_user(&external_user, &service).await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = to_json_value(response).await?;
+    assert_eq!(body, json!({
+        "status": "created",
+        "id": 1
+    }));
+
+    tracing::info!("try to create the same user again");
+    let response = client.create_user(&external_user, &service).await?;
+    assert_eq!(response.status(), StatusCode::FOUND);
+    let body = to_json_value(response).await?;
+    assert_eq!(body, json!({
+        "status": "already_present",
+        "id": 1
+    }));
+
+    tracing::info!("test the output of the GET method");
+    let response = client.get_user(1).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.get_external_user(&external_user.external_id, &service).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_json_value(response).await?;
+    assert_eq!(body, json!({
+        "id": 1,
+        "name": external_user.name.unwrap(),
+        "options": {
+            "language_code": null,
+            "location": null,
+            "timezone": null
+        },
+        "is_premium": false,
+        "place": null,
+        "utc_offset": null,
+        "language": null,
+        "language_fallbacks": [],
+        "version": 1
+    }));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_string_external_ids() -> anyhow::Result<()> {
+    let client = UserServiceClient::default();
+    let vendor_user = ExternalUser {
+        external_id: ExternalId::String("vendor-account-42".to_owned()),
+        name: None,
+    };
+    let application = Service {
+        name: "SadApp".to_owned(),
+        service_type: ServiceType::APPLICATION,
+    };
+
+    let response = client.create_user(&vendor_user, &build_service()).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = client.create_user(&vendor_user, &application).await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = client.get_external_user(&vendor_user.external_id, &application).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(to_json_value(response).await?["id"], 1);
+
+    let padded_user = ExternalUser {
+        external_id: ExternalId::String("007".to_owned()),
+        name: None,
+    };
+    let response = client.create_user(&padded_user, &application).await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = client.get_external_user(&padded_user.external_id, &application).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(to_json_value(response).await?["id"], 2);
+    let response = client.get_external_user(&ExternalId::Numeric(7), &application).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_service_types() -> anyhow::Result<()> {
+    let client = UserServiceClient::default();
+    let discord_user = ExternalUser {
+        external_id: ExternalId::String("discord-user".to_owned()),
+        name: None,
+    };
+    let discord_bot = Service {
+        name: "SadDiscordBot".to_owned(),
+        service_type: "discord-bot".to_owned().try_into()?,
+    };
+
+    let response = client.create_user(&discord_user, &discord_bot).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = client.create_service_type("discord-bot", true).await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = client.create_service_type("discord-bot", true).await?;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = client.create_service_type("Discord Bot", true).await?;
+    assert!(response.status().is_client_error());
+
+    let response = client.list_service_types().await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let names: Vec<_> = to_json_value(response).await?
+        .as_array()
+        .expect("an array of service types")
+        .iter()
+        .map(|info| info["name"].as_str().unwrap_or_default().to_owned())
+        .collect();
+    assert_eq!(names, ["application", "discord-bot", "telegram-bot", "telegram-channel", "website"]);
+
+    let response = client.create_user(&discord_user, &discord_bot).await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_updates() -> anyhow::Result<()> {
+    let client = UserServiceClient::new(build_repos_with_test_user());
+    let username = build_external_user().name.unwrap();
+    let (latitude, longitude) = (12.345, 67.89);
+    let location = Location::from((latitude, longitude));
+    let timezone = location.timezone();
+
+    let response = client.update_user_language(1, "ru").await?;
+    ensure_success(response).await?;
+
+    let response = client.update_user_location(1, latitude, longitude).await?;
+    ensure_success(response).await?;
+
+    let response = client.activate_user_premium(1, "week").await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.activate_user_premium(1, "month").await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_json_value(response).await?;
+    let date_part_str_range = 0..10;
+    let date_in_month = &Utc::now()
+        .checked_add_months(Months::new(1))
+        .unwrap()
+        .with_nanosecond(0)
+        .unwrap()
+        .to_string()
+        [date_part_str_range.clone()];
+    assert_eq!(body["success"], true);
+    let active_till = body["active_till"].as_str()
+        .map(|s| &s[date_part_str_range])
+        .expect("active_till must be present here");
+    assert_eq!(active_till, date_in_month);
+
+    let response = client.get_user(1).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_json_value(response).await?;
+    assert_eq!(body, json!({
+        "id": 1,
+        "name": username,
+        "options": {
+            "language_code": "ru",
+            "location": {
+                "latitude": latitude,
+                "longitude": longitude
+            },
+            "timezone": timezone
+        },
+        "is_premium": true,
+        "place": location.place(),
+        "utc_offset": timezone.map(|timezone| timezone.utc_offset()),
+        "language": {"code": "ru", "name": "Russian", "native_name": "русский"},
+        "language_fallbacks": ["ru"],
+        "version": 4
+    }));
+
+    let response = client.clear_user_value(1, "location/").await?;
+    ensure_success(response).await?;
+    let response = client.clear_user_value(1, "language").await?;
+    ensure_success(response).await?;
+    let body = to_json_value(client.get_user(1).await?).await?;
+    assert_eq!(body["options"], json!({
+        "language_code": null,
+        "location": null,
+        "timezone": timezone
+    }));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_timezones() -> anyhow::Result<()> {
+    let client = UserServiceClient::new(build_repos_with_test_user());
+
+    let response = client.update_user_timezone(1, "Mars/Olympus_Mons").await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.update_user_timezone(2, "Asia/Tokyo").await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = client.update_user_timezone(1, "Asia/Tokyo").await?;
+    ensure_success(response).await?;
+    let body = to_json_value(client.get_user(1).await?).await?;
+    assert_eq!(body["options"]["timezone"], "Asia/Tokyo");
+    assert_eq!(body["utc_offset"], 9 * 3600);
+
+    tracing::info!("a new location brings its timezone");
+    let response = client.update_user_location(1, 55.7558, 37.6173).await?;
+    ensure_success(response).await?;
+    let body = to_json_value(client.get_user(1).await?).await?;
+    assert_eq!(body["options"]["timezone"], "Europe/Moscow");
+    assert_eq!(body["utc_offset"], 3 * 3600);
+
+    let response = client.patch_user(1, json!({"options": {"timezone": "Asia/Tokio"}})).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.patch_user(1, json!({"options": {"timezone": "Asia/Tokyo"}})).await?;
+    assert_eq!(to_json_value(response).await?["options"]["timezone"], "Asia/Tokyo");
+
+    let response = client.clear_user_value(1, "timezone").await?;
+    ensure_success(response).await?;
+    let body = to_json_value(client.get_user(1).await?).await?;
+    assert_eq!(body["options"]["timezone"], serde_json::Value::Null);
+    assert_eq!(body["utc_offset"], serde_json::Value::Null);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_places() -> anyhow::Result<()> {
+    let client = UserServiceClient::new(build_repos_with_test_user());
+
+    let response = client.update_user_location(1, 55.7558, 37.6173).await?;
+    ensure_success(response).await?;
+    let body = to_json_value(client.get_user(1).await?).await?;
+    assert_eq!(body["place"], json!({"city": "Moscow", "country_code": "RU"}));
+
+    tracing::info!("no place is found in the open ocean");
+    let response = client.patch_user(1, json!({"options": {"location": {"latitude": -48.8767, "longitude": -123.3933}}})).await?;
+    assert_eq!(to_json_value(response).await?["place"], serde_json::Value::Null);
+
+    let response = client.update_user_location(1, 59.9343, 30.3351).await?;
+    ensure_success(response).await?;
+    let response = client.clear_user_value(1, "location/").await?;
+    ensure_success(response).await?;
+    let body = to_json_value(client.get_user(1).await?).await?;
+    assert_eq!(body["place"], serde_json::Value::Null);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_language_tags() -> anyhow::Result<()> {
+    let client = UserServiceClient::new(build_repos_with_test_user());
+
+    let response = client.update_user_language(1, "pt-BR").await?;
+    ensure_success(response).await?;
+    let body = to_json_value(client.get_user(1).await?).await?;
+    assert_eq!(body["options"]["language_code"], "pt-BR");
+    assert_eq!(body["language_fallbacks"], json!(["pt-BR", "pt"]));
+
+    tracing::info!("the tags are canonicalized");
+    let response = client.patch_user(1, json!({"options": {"language_code": "zh-hant-tw"}})).await?;
+    let body = to_json_value(response).await?;
+    assert_eq!(body["options"]["language_code"], "zh-Hant-TW");
+    assert_eq!(body["language_fallbacks"], json!(["zh-Hant-TW", "zh-Hant", "zh"]));
+
+    let response = client.list_users("language_code=zh-Hant-TW").await?;
+    assert_eq!(to_json_value(response).await?["users"][0]["id"], 1);
+    let response = client.list_users("language_code=zh-Hans").await?;
+    assert_eq!(to_json_value(response).await?["users"], json!([]));
+
+    for code in ["zz", "12", "🦀🦀", "en--US", "x-private"] {
+        let response = client.patch_user(1, json!({"options": {"language_code": code}})).await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{code}");
+    }
+
+    let response = client.clear_user_value(1, "language").await?;
+    ensure_success(response).await?;
+    let body = to_json_value(client.get_user(1).await?).await?;
+    assert_eq!(body["language_fallbacks"], json!([]));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_languages() -> anyhow::Result<()> {
+    let client = UserServiceClient::new(build_repos_with_test_user());
+
+    let response = client.list_languages().await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_json_value(response).await?;
+    let languages = body.as_array().expect("languages must be an array");
+    assert_eq!(languages.len(), 183);
+    assert!(languages.contains(&json!({"code": "uk", "name": "Ukrainian", "native_name": "українська"})));
+
+    for code in ["zz", "1f", "e", "x-private"] {
+        let response = client.update_user_language(1, code).await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{code}");
+    }
+    let response = client.update_user_language(1, "uk-UA").await?;
+    ensure_success(response).await?;
+    let body = to_json_value(client.get_user(1).await?).await?;
+    assert_eq!(body["language"], json!({"code": "uk", "name": "Ukrainian", "native_name": "українська"}));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_names() -> anyhow::Result<()> {
+    let client = UserServiceClient::new(build_repos_with_test_user());
+    let service = build_service();
+    let renamed_user = |name: &str| ExternalUser {
+        name: Some(name.to_owned()),
+        ..build_external_user()
+    };
+    let fetch_name = || async {
+        let body = to_json_value(client.get_user(1).await?).await?;
+        anyhow::Ok(body["name"].clone())
+    };
+
+    let response = client.update_user_name(1, " Happy\u{7}Bot\n").await?;
+    ensure_success(response).await?;
+    assert_eq!(fetch_name().await?, "HappyBot");
+
+    for name in ["", " \t ", &"a".repeat(257)] {
+        let response = client.update_user_name(1, name).await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    tracing::info!("the name is kept on re-registration unless asked otherwise");
+    let response = client.create_user(&renamed_user("MadBot"), &service).await?;
+    assert_eq!(response.status(), StatusCode::FOUND);
+    assert_eq!(fetch_name().await?, "HappyBot");
+    let response = client.register_user(&renamed_user("MadBot"), &service, true).await?;
+    assert_eq!(response.status(), StatusCode::FOUND);
+    assert_eq!(fetch_name().await?, "MadBot");
+
+    let response = client.register_user(&renamed_user("\u{0}"), &service, true).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_changes() -> anyhow::Result<()> {
+    let client = UserServiceClient::new(build_repos_with_test_user());
+    ensure_success(client.update_user_language(1, "ru").await?).await?;
+    ensure_success(client.update_user_name(1, "HappyBot").await?).await?;
+
+    let response = client.list_changes(1, "").await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_json_value(response).await?;
+    let changes = body["changes"].as_array().expect("changes must be an array");
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[0]["field"], "name");
+    assert_eq!(changes[0]["old_value"], "SadBot");
+    assert_eq!(changes[0]["new_value"], "HappyBot");
+    assert_eq!(changes[1]["field"], "language_code");
+    assert_eq!(changes[1]["old_value"], serde_json::Value::Null);
+    assert_eq!(changes[1]["new_value"], "ru");
+    assert_eq!(body["next_cursor"], serde_json::Value::Null);
+
+    let body = to_json_value(client.list_changes(1, "limit=1").await?).await?;
+    assert_eq!(body["changes"][0]["field"], "name");
+    let cursor = body["next_cursor"].as_str().expect("next_cursor must be present");
+    let body = to_json_value(client.list_changes(1, &format!("limit=1&cursor={cursor}")).await?).await?;
+    assert_eq!(body["changes"][0]["field"], "language_code");
+    assert_eq!(body["next_cursor"], serde_json::Value::Null);
+
+    let response = client.list_changes(1, "cursor=!!!").await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.list_changes(2, "").await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_tokens() -> anyhow::Result<()> {
+    let client = UserServiceClient::new(build_repos_with_test_user());
+
+    let response = client.issue_token(2).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = client.issue_token(1).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_json_value(response).await?;
+    let token = body["token"].as_str()
+        .expect("token must be present here");
+
+    let response = client.get_jwks().await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let jwks = serde_json::from_value(to_json_value(response).await?)?;
+    let claims = tokens::verify(token, &jwks, "user-service")?;
+    assert_eq!(claims.user_id(), Some(1));
+    assert!(!claims.premium);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_linking() -> anyhow::Result<()> {
+    let client = UserServiceClient::default();
+    let response = client.create_user(&build_external_user(), &build_service()).await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let website = Service {
+        name: "kozalo.ru".to_owned(),
+        service_type: ServiceType::WEBSITE,
+    };
+    let website_user = ExternalUser {
+        external_id: ExternalId::String("0b5a2f6e-6a4c-4d8e-9a59-6f4e0c1d2b3a".to_owned()),
+        name: None,
+    };
+
+    let response = client.create_link_code(2).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = client.create_link_code(1).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_json_value(response).await?;
+    let code = body["code"].as_str()
+        .expect("code must be present here")
+        .to_owned();
+
+    let response = client.link_account(&code.to_lowercase(), &website_user, &website).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(to_json_value(response).await?, json!({"id": 1}));
+
+    tracing::info!("the code is one-time");
+    let response = client.link_account(&code, &website_user, &website).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = client.get_external_user(&website_user.external_id, &website).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(to_json_value(response).await?["id"], 1);
+
+    tracing::info!("an account of another user cannot be linked");
+    let another_user = ExternalUser {
+        external_id: ExternalId::Numeric(43),
+        name: None,
+    };
+    let response = client.create_user(&another_user, &website).await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = to_json_value(client.create_link_code(1).await?).await?;
+    let code = body["code"].as_str()
+        .expect("code must be present here");
+    let response = client.link_account(code, &another_user, &website).await?;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_merge() -> anyhow::Result<()> {
+    let client = UserServiceClient::default();
+    let telegram_user = build_external_user();
+    let website_user = ExternalUser {
+        external_id: ExternalId::Numeric(42),
+        name: Some("kozalo".to_owned()),
+    };
+    let website = Service {
+        name: "kozalo.ru".to_owned(),
+        service_type: ServiceType::WEBSITE,
+    };
+    client.create_user(&telegram_user, &build_service()).await?;
+    client.create_user(&website_user, &website).await?;
+    ensure_success(client.update_user_language(2, "ru").await?).await?;
+    let response = client.activate_user_premium(2, "month").await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client.merge_users(2, 2).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.merge_users(3, 1).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = client.merge_users(2, 1).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let expected_user = json!({
+        "id": 1,
+        "name": telegram_user.name,
+        "options": {
+            "language_code": "ru",
+            "location": null,
+            "timezone": null
+        },
+        "is_premium": true,
+        "place": null,
+        "utc_offset": null,
+        "language": {"code": "ru", "name": "Russian", "native_name": "русский"},
+        "language_fallbacks": ["ru"],
+        "version": 2
+    });
+    assert_eq!(to_json_value(response).await?, expected_user);
+
+    tracing::info!("lookups of the merged user are redirected");
+    let response = client.get_user(2).await?;
+    assert_eq!(to_json_value(response).await?, expected_user);
+    let response = client.get_external_user(&website_user.external_id, &website).await?;
+    assert_eq!(to_json_value(response).await?, expected_user);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_deactivation() -> anyhow::Result<()> {
+    let client = UserServiceClient::new(build_repos_with_test_user());
+    let external_id = build_external_user().external_id;
+
+    let response = client.change_user_activity(2, "deactivate").await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = client.change_user_activity(1, "deactivate").await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_json_value(response).await?;
+    assert!(body["deactivated_at"].is_string());
+    assert_eq!(body["version"], 2);
+
+    tracing::info!("the data is kept, but the user is gone for the lookups");
+    let response = client.get_user(1).await?;
+    assert_eq!(response.status(), StatusCode::GONE);
+    let response = client.get_external_user(&external_id, &build_service()).await?;
+    assert_eq!(response.status(), StatusCode::GONE);
+    let response = client.activate_user_premium(1, "month").await?;
+    assert_eq!(response.status(), StatusCode::GONE);
+    let response = client.issue_token(1).await?;
+    assert_eq!(response.status(), StatusCode::GONE);
+
+    let response = client.change_user_activity(1, "reactivate").await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_json_value(response).await?;
+    assert!(body.get("deactivated_at").is_none());
+    assert_eq!(body["version"], 3);
+
+    let response = client.get_external_user(&external_id, &build_service()).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.activate_user_premium(1, "month").await?;
+    assert_eq!(to_json_value(response).await?["success"], true);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_bans() -> anyhow::Result<()> {
+    let client = UserServiceClient::new(build_repos_with_test_user());
+    let service = build_service();
+
+    let response = client.ban_user(1, json!({"reason": " ", "issuer": "moderator"})).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.ban_user(1, json!({"reason": "spam", "issuer": "moderator", "expires_at": "2020-01-01T00:00:00Z"})).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.ban_user(2, json!({"reason": "spam", "issuer": "moderator"})).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let unknown_service = json!({"name": "UnknownBot", "type": "telegram-bot"});
+    let response = client.ban_user(1, json!({"service": unknown_service, "reason": "spam", "issuer": "moderator"})).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = client.ban_user(1, json!({"service": service, "reason": " spam ", "issuer": "moderator"})).await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = to_json_value(response).await?;
+    assert_eq!(body["reason"], "spam");
+    assert_eq!(body["expires_at"], serde_json::Value::Null);
+
+    tracing::info!("the bans are reflected in the user");
+    let response = client.get_user(1).await?;
+    let body = to_json_value(response).await?;
+    assert_eq!(body["bans"].as_array().map(Vec::len), Some(1));
+    let response = client.list_bans(1).await?;
+    assert_eq!(to_json_value(response).await?.as_array().map(Vec::len), Some(1));
+
+    tracing::info!("the global ban is lifted separately");
+    let response = client.unban_user(1, "").await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = client.unban_user(1, "service_name=SadFavBot").await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.unban_user(1, &format!("service_name={}&service_type={}", service.name, service.service_type)).await?;
+    ensure_success(response).await?;
+
+    let response = client.get_user(1).await?;
+    assert!(to_json_value(response).await?.get("bans").is_none());
+    let response = client.list_bans(2).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_settings() -> anyhow::Result<()> {
+    let client = UserServiceClient::new(build_repos_with_test_user());
+    let service = build_service();
+    let query = format!("service_name={}&service_type={}", service.name, service.service_type);
+
+    let response = client.user_setting(http::Method::PUT, &format!("/1/settings/Theme?{query}"), Some(json!("dark"))).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.user_setting(http::Method::PUT, "/1/settings/theme?service_name=UnknownBot&service_type=telegram-bot", Some(json!("dark"))).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = client.user_setting(http::Method::PUT, &format!("/2/settings/theme?{query}"), Some(json!("dark"))).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = client.user_setting(http::Method::GET, &format!("/1/settings/theme?{query}"), None).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = client.user_setting(http::Method::PUT, &format!("/1/settings/theme?{query}"), Some(json!("dark"))).await?;
+    ensure_success(response).await?;
+    let response = client.user_setting(http::Method::PUT, &format!("/1/settings/limits.daily?{query}"), Some(json!({"max": 10}))).await?;
+    ensure_success(response).await?;
+    let response = client.user_setting(http::Method::GET, &format!("/1/settings/theme?{query}"), None).await?;
+    assert_eq!(to_json_value(response).await?, json!("dark"));
+    let response = client.user_setting(http::Method::GET, &format!("/1/settings?{query}"), None).await?;
+    assert_eq!(to_json_value(response).await?, json!({"theme": "dark", "limits.daily": {"max": 10}}));
+
+    let response = client.user_setting(http::Method::DELETE, &format!("/1/settings/theme?{query}"), None).await?;
+    ensure_success(response).await?;
+    let response = client.user_setting(http::Method::DELETE, &format!("/1/settings/theme?{query}"), None).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    tracing::info!("the defaults are managed by the admins");
+    let response = client.admin_request(http::Method::PUT, &format!("/services/settings/theme?{query}"), Some(json!("light"))).await?;
+    ensure_success(response).await?;
+    let response = client.admin_request(http::Method::GET, &format!("/services/settings?{query}"), None).await?;
+    assert_eq!(to_json_value(response).await?, json!({"theme": "light"}));
+    let response = client.admin_request(http::Method::DELETE, &format!("/services/settings/theme?{query}"), None).await?;
+    ensure_success(response).await?;
+    let response = client.admin_request(http::Method::DELETE, &format!("/services/settings/theme?{query}"), None).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_contacts() -> anyhow::Result<()> {
+    let client = UserServiceClient::default();
+    let another_user = ExternalUser {
+        external_id: ExternalId::Numeric(42),
+        name: Some("kozalo".to_owned()),
+    };
+    client.create_user(&build_external_user(), &build_service()).await?;
+    client.create_user(&another_user, &build_service()).await?;
+
+    let response = client.contact_request("/1/contacts", json!({"kind": "email", "value": "sadbot"})).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.contact_request("/1/contacts", json!({"kind": "fax", "value": "+79001234567"})).await?;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let response = client.contact_request("/3/contacts", json!({"kind": "email", "value": "sad.bot@example.com"})).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = client.contact_request("/1/contacts/email/confirm", json!({"code": "123456"})).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = client.contact_request("/1/contacts", json!({"kind": "email", "value": " Sad.Bot@Example.COM "})).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(to_json_value(response).await?["expires_at"].is_string());
+    let code = client.sender.last_code("sad.bot@example.com").expect("the code must be sent to the normalized email");
+
+    let response = client.contact_request("/1/contacts/email/confirm", json!({"code": "wrong"})).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.contact_request("/1/contacts/email/confirm", json!({"code": code})).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_json_value(response).await?;
+    assert_eq!(body["kind"], "email");
+    assert_eq!(body["value"], "sad.bot@example.com");
+
+    tracing::info!("the contacts the codes can't be delivered to are rejected before a code is issued");
+    let response = client.contact_request("/1/contacts", json!({"kind": "phone", "value": "+7 900 123-45-67"})).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.contact_request("/1/contacts/phone/confirm", json!({"code": "123456"})).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    tracing::info!("a new code can't be requested right away");
+    let response = client.contact_request("/1/contacts", json!({"kind": "email", "value": "happy.bot@example.com"})).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.contact_request("/1/contacts", json!({"kind": "email", "value": "happy.bot@example.com"})).await?;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    tracing::info!("only the verified contacts are shown");
+    let response = client.get_user(1).await?;
+    let body = to_json_value(response).await?;
+    assert_eq!(body["contacts"].as_array().map(Vec::len), Some(1));
+    assert_eq!(body["contacts"][0]["value"], "sad.bot@example.com");
+
+    tracing::info!("a verified contact belongs to a single user");
+    let response = client.contact_request("/2/contacts", json!({"kind": "email", "value": "sad.bot@example.com"})).await?;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let response = client.delete_contact(1, "email").await?;
+    ensure_success(response).await?;
+    let response = client.delete_contact(1, "email").await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = client.get_user(1).await?;
+    assert!(to_json_value(response).await?.get("contacts").is_none());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_credentials() -> anyhow::Result<()> {
+    let client = UserServiceClient::default();
+    let another_user = ExternalUser {
+        external_id: ExternalId::Numeric(42),
+        name: Some("kozalo".to_owned()),
+    };
+    client.create_user(&build_external_user(), &build_service()).await?;
+    client.create_user(&another_user, &build_service()).await?;
+
+    let response = client.credentials_request("/1/credentials", json!({"password": "correct horse"})).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.credentials_request("/1/credentials", json!({"username": "sad bot", "password": "correct horse"})).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.credentials_request("/1/credentials", json!({"email": "sadbot", "password": "correct horse"})).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.credentials_request("/1/credentials", json!({"username": "sadbot", "password": "short"})).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.credentials_request("/3/credentials", json!({"username": "sadbot", "password": "correct horse"})).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    tracing::info!("the email must be verified first");
+    let response = client.credentials_request("/1/credentials", json!({"username": "SadBot", "email": "Sad.Bot@Example.com", "password": "correct horse"})).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    client.verify_email(1, "sad.bot@example.com").await?;
+    client.verify_email(2, "kozalo@example.com").await?;
+
+    let response = client.credentials_request("/1/credentials", json!({"username": "SadBot", "email": "Sad.Bot@Example.com", "password": "correct horse"})).await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = to_json_value(response).await?;
+    assert_eq!(body, json!({"username": "sadbot", "email": "sad.bot@example.com"}));
+    let response = client.credentials_request("/1/credentials", json!({"username": "another", "password": "correct horse"})).await?;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = client.credentials_request("/2/credentials", json!({"username": "sadbot", "password": "correct horse"})).await?;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = client.credentials_request("/2/credentials", json!({"email": "kozalo@example.com", "password": "battery staple"})).await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = client.user_setting(http::Method::GET, "/2/credentials", None).await?;
+    assert_eq!(to_json_value(response).await?, json!({"username": null, "email": "kozalo@example.com"}));
+
+    tracing::info!("both the username and the email are logins");
+    let response = client.credentials_request("/credentials/login", json!({"login": "SADBOT", "password": "correct horse"})).await?;
+    assert_eq!(to_json_value(response).await?, json!({"id": 1}));
+    let response = client.credentials_request("/credentials/login", json!({"login": "sad.bot@example.com", "password": "correct horse"})).await?;
+    assert_eq!(to_json_value(response).await?, json!({"id": 1}));
+    let response = client.credentials_request("/credentials/login", json!({"login": "nobody", "password": "correct horse"})).await?;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    tracing::info!("the login is locked after too many failed attempts in a row");
+    for _ in 0..LOGIN_MAX_FAILED_ATTEMPTS {
+        let response = client.credentials_request("/credentials/login", json!({"login": "sadbot", "password": "wrong password"})).await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+    let response = client.credentials_request("/credentials/login", json!({"login": "sadbot", "password": "correct horse"})).await?;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let response = client.user_setting(http::Method::GET, "/1/credentials", None).await?;
+    assert!(to_json_value(response).await?["locked_until"].is_string());
+
+    tracing::info!("a reset of the password lifts the lock");
+    let response = client.credentials_request("/credentials/reset", json!({"login": "nobody"})).await?;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let response = client.credentials_request("/credentials/reset", json!({"login": "sadbot"})).await?;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    assert_eq!(to_json_value(response).await?, json!({"success": true}), "the token must not be returned");
+    let token = client.sender.last_code("sad.bot@example.com").expect("the token must be sent to the verified email");
+    let response = client.credentials_request("/credentials/reset/confirm", json!({"token": token, "new_password": "short"})).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.credentials_request("/credentials/reset/confirm", json!({"token": token, "new_password": "tr0ub4dor&3"})).await?;
+    ensure_success(response).await?;
+    let response = client.credentials_request("/credentials/reset/confirm", json!({"token": token, "new_password": "tr0ub4dor&3"})).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = client.credentials_request("/credentials/login", json!({"login": "sadbot", "password": "tr0ub4dor&3"})).await?;
+    assert_eq!(to_json_value(response).await?, json!({"id": 1}));
+
+    tracing::info!("the current password is required to change it");
+    let response = client.credentials_request("/1/credentials/password", json!({"current_password": "correct horse", "new_password": "correct horse"})).await?;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = client.credentials_request("/1/credentials/password", json!({"current_password": "tr0ub4dor&3", "new_password": "correct horse"})).await?;
+    ensure_success(response).await?;
+    let response = client.credentials_request("/credentials/login", json!({"login": "sadbot", "password": "tr0ub4dor&3"})).await?;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    tracing::info!("deactivated users can't log in");
+    let response = client.change_user_activity(1, "deactivate").await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.credentials_request("/credentials/login", json!({"login": "sadbot", "password": "correct horse"})).await?;
+    assert_eq!(response.status(), StatusCode::GONE);
+
+    tracing::info!("the attempts to log in as a deactivated user aren't counted");
+    for _ in 0..LOGIN_MAX_FAILED_ATTEMPTS {
+        let response = client.credentials_request("/credentials/login", json!({"login": "sadbot", "password": "wrong password"})).await?;
+        assert_eq!(response.status(), StatusCode::GONE);
+    }
+    let response = client.change_user_activity(1, "reactivate").await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.credentials_request("/credentials/login", json!({"login": "sadbot", "password": "correct horse"})).await?;
+    assert_eq!(to_json_value(response).await?, json!({"id": 1}));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_schemas() -> anyhow::Result<()> {
+    let client = UserServiceClient::new(build_repos_with_test_user());
+    let service = build_service();
+    let query = format!("service_name={}&service_type={}", service.name, service.service_type);
+    let consent_schema = json!({
+        "type": "object",
+        "properties": {
+            "version": {"type": "integer"},
+            "accepted": {"const": true}
+        },
+        "required": ["version", "accepted"]
+    });
+
+    let response = client.admin_request(http::Method::PUT, &format!("/services/schemas/consent?{query}"), Some(json!({"type": 42}))).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.admin_request(http::Method::PUT, &format!("/services/schemas/consent?{query}"), Some(consent_schema.clone())).await?;
+    ensure_success(response).await?;
+    let response = client.admin_request(http::Method::GET, &format!("/services/schemas/consent?{query}"), None).await?;
+    assert_eq!(to_json_value(response).await?, consent_schema);
+
+    tracing::info!("the violations are listed by their paths");
+    let new_user = ExternalUser {
+        external_id: ExternalId::Numeric(987654321),
+        name: Some("SadCat".to_owned()),
+    };
+    let response = client.register_with_consent(&new_user, &service, json!({"version": "1", "accepted": false})).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = to_json_value(response).await?;
+    let mut paths: Vec<&str> = body["violations"].as_array()
+        .map(|violations| violations.iter().filter_map(|v| v["path"].as_str()).collect())
+        .unwrap_or_default();
+    paths.sort();
+    assert_eq!(paths, vec!["/accepted", "/version"]);
+    let response = client.register_with_consent(&new_user, &service, json!({"version": 1, "accepted": true})).await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    tracing::info!("the settings are validated as a whole object");
+    let settings_schema = json!({"properties": {"theme": {"enum": ["light", "dark"]}}});
+    let response = client.admin_request(http::Method::PUT, &format!("/services/schemas/settings?{query}"), Some(settings_schema)).await?;
+    ensure_success(response).await?;
+    let response = client.user_setting(http::Method::PUT, &format!("/1/settings/theme?{query}"), Some(json!("sepia"))).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(to_json_value(response).await?["violations"][0]["path"], "/theme");
+    let response = client.user_setting(http::Method::PUT, &format!("/1/settings/theme?{query}"), Some(json!("dark"))).await?;
+    ensure_success(response).await?;
+
+    let response = client.admin_request(http::Method::DELETE, &format!("/services/schemas/consent?{query}"), None).await?;
+    ensure_success(response).await?;
+    let response = client.admin_request(http::Method::GET, &format!("/services/schemas/consent?{query}"), None).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unlink() -> anyhow::Result<()> {
+    let client = UserServiceClient::default();
+    let external_user = build_external_user();
+    let service = build_service();
+
+    let response = client.unlink_user(&external_user.external_id, &service).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    client.create_user(&external_user, &service).await?;
+    let response = client.unlink_user(&external_user.external_id, &service).await?;
+    ensure_success(response).await?;
+
+    let response = client.get_external_user(&external_user.external_id, &service).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = client.get_user(1).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client.unlink_user(&external_user.external_id, &service).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_patch() -> anyhow::Result<()> {
+    let client = UserServiceClient::new(build_repos_with_test_user());
+    let location = Location::from((12.345, 67.89));
+    let timezone = location.timezone();
+
+    let response = client.patch_user(1, json!({
+        "name": "HappyBot",
+        "options": {
+            "language_code": "ru",
+            "location": {"latitude": 12.345, "longitude": 67.89}
+        }
+    })).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(to_json_value(response).await?, json!({
+        "id": 1,
+        "name": "HappyBot",
+        "options": {
+            "language_code": "ru",
+            "location": {"latitude": 12.345, "longitude": 67.89},
+            "timezone": timezone
+        },
+        "is_premium": false,
+        "place": location.place(),
+        "utc_offset": timezone.map(|timezone| timezone.utc_offset()),
+        "language": {"code": "ru", "name": "Russian", "native_name": "русский"},
+        "language_fallbacks": ["ru"],
+        "version": 2
+    }));
+
+    tracing::info!("null clears a field, absent fields stay intact");
+    let response = client.patch_user(1, json!({"options": {"location": null}})).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_json_value(response).await?;
+    assert_eq!(body["name"], "HappyBot");
+    assert_eq!(body["options"], json!({"language_code": "ru", "location": null, "timezone": timezone}));
+
+    let response = client.patch_user(1, json!({"options": null, "name": null})).await?;
+    let body = to_json_value(response).await?;
+    assert_eq!(body["name"], serde_json::Value::Null);
+    assert_eq!(body["options"], json!({"language_code": null, "location": null, "timezone": null}));
+
+    let response = client.patch_user(1, json!({})).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.patch_user(1, json!({"options": {"language_code": "rus"}})).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.patch_user(1, json!({"options": {"location": {"latitude": 100.0, "longitude": 0.0}}})).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.patch_user(2, json!({"name": "HappyBot"})).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    tracing::info!("the acting service must be registered");
+    let response = client.patch_user_as(1, &build_service(), json!({"name": "HappyBot"})).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let unknown_service = Service {
+        name: "UnknownBot".to_string(),
+        ..build_service()
+    };
+    let response = client.patch_user_as(1, &unknown_service, json!({"name": "HappyBot"})).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_versions() -> anyhow::Result<()> {
+    let client = UserServiceClient::new(build_repos_with_test_user());
+
+    let response = client.get_user(1).await?;
+    assert_eq!(response.headers()[http::header::ETAG], "\"1\"");
+
+    let response = client.patch_user_if_match(1, Some("\"1\""), json!({"name": "HappyBot"})).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[http::header::ETAG], "\"2\"");
+    assert_eq!(to_json_value(response).await?["version"], 2);
+
+    tracing::info!("a stale ETag doesn't match anymore");
+    let response = client.patch_user_if_match(1, Some("\"1\""), json!({"name": "MadBot"})).await?;
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+    let response = client.patch_user_if_match(1, Some("*"), json!({"name": "MadBot"})).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.patch_user_if_match(1, Some("W/\"3\""), json!({"name": "SadBot"})).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = client.update_user_language(1, "ru").await?;
+    assert_eq!(response.headers()[http::header::ETAG], "\"4\"");
+    let response = client.patch_user_if_match(2, Some("\"1\""), json!({"name": "SadBot"})).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_list() -> anyhow::Result<()> {
+    let client = UserServiceClient::default();
+    let service = build_service();
+    for external_id in 1..=3 {
+        let user = ExternalUser {
+            external_id: ExternalId::Numeric(external_id),
+            name: None,
+        };
+        client.create_user(&user, &service).await?;
+    }
+    ensure_success(client.update_user_language(2, "ru").await?).await?;
+
+    let response = client.list_users("limit=2").await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_json_value(response).await?;
+    assert_eq!(body["users"].as_array().map(Vec::len), Some(2));
+    let cursor = body["next_cursor"].as_str()
+        .expect("next_cursor must be present here")
+        .to_owned();
+    let response = client.list_users(&format!("limit=2&cursor={cursor}")).await?;
+    let body = to_json_value(response).await?;
+    assert_eq!(body["users"][0]["id"], 3);
+    assert_eq!(body["next_cursor"], serde_json::Value::Null);
+
+    let response = client.list_users("language_code=ru&has_location=false").await?;
+    let body = to_json_value(response).await?;
+    assert_eq!(body["users"].as_array().map(Vec::len), Some(1));
+    assert_eq!(body["users"][0]["id"], 2);
+
+    let response = client.list_users("premium=active").await?;
+    assert_eq!(to_json_value(response).await?["users"], json!([]));
+    let response = client.list_users("service_name=UnknownBot&service_type=telegram-bot").await?;
+    assert_eq!(to_json_value(response).await?["users"], json!([]));
+
+    let response = client.list_users("service_name=SadFavBot").await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.list_users("cursor=!!!").await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.list_users(&format!("limit={}", repo::users::LIST_MAX_LIMIT + 1)).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    // zero stands for the default limit, as in the gRPC API
+    let response = client.list_users("limit=0").await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(to_json_value(response).await?["users"].as_array().map(Vec::len), Some(3));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_search() -> anyhow::Result<()> {
+    let client = UserServiceClient::new(build_repos_with_test_user());
+
+    let response = client.search_users("q=sad").await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_json_value(response).await?;
+    assert_eq!(body.as_array().map(Vec::len), Some(1));
+    assert_eq!(body[0]["name"], "SadBot");
+
+    let response = client.search_users("q=sad&service_name=UnknownBot&service_type=telegram-bot").await?;
+    assert_eq!(to_json_value(response).await?, json!([]));
+    let response = client.search_users("q=nobody").await?;
+    assert_eq!(to_json_value(response).await?, json!([]));
+
+    let response = client.search_users("q=%20").await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.search_users("q=sad&limit=1000").await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_nearby() -> anyhow::Result<()> {
+    let client = UserServiceClient::new(build_repos_with_test_user());
+    let moscow = "latitude=55.7558&longitude=37.6173";
+
+    let response = client.nearby_users(&format!("{moscow}&radius_km=700")).await?;
+    assert_eq!(to_json_value(response).await?, json!({"users": [], "next_cursor": null}));
+
+    let response = client.update_user_location(1, 59.9343, 30.3351).await?;
+    ensure_success(response).await?;
+    let response = client.nearby_users(&format!("{moscow}&radius_km=700")).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_json_value(response).await?;
+    let users = body["users"].as_array().expect("users must be an array");
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0]["id"], 1);
+    assert_eq!(users[0]["place"]["city"], "Saint Petersburg");
+    let distance = users[0]["distance_km"].as_f64().expect("distance must be a number");
+    assert!((630.0..640.0).contains(&distance), "{distance}");
+    assert_eq!(body["next_cursor"], serde_json::Value::Null);
+
+    let response = client.nearby_users(&format!("{moscow}&radius_km=500")).await?;
+    assert_eq!(to_json_value(response).await?["users"], json!([]));
+    let response = client.nearby_users(&format!("{moscow}&radius_km=700&service_name=UnknownBot&service_type=telegram-bot")).await?;
+    assert_eq!(to_json_value(response).await?["users"], json!([]));
+
+    for query in ["radius_km=700", "latitude=91&longitude=0&radius_km=700", &format!("{moscow}&radius_km=0"),
+            &format!("{moscow}&radius_km=5000"), &format!("{moscow}&radius_km=700&limit=1000"),
+            &format!("{moscow}&radius_km=700&service_name=SadFavBot")] {
+        let response = client.nearby_users(query).await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{query}");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_batch_get() -> anyhow::Result<()> {
+    let client = UserServiceClient::default();
+    let external_user = build_external_user();
+    let service = build_service();
+    client.create_user(&external_user, &service).await?;
+
+    let unknown_service = Service {
+        name: "UnknownBot".to_owned(),
+        service_type: ServiceType::TELEGRAM_BOT,
+    };
+    let response = client.batch_get(json!([
+        {"id": 1},
+        {"id": 2},
+        {"service": service, "external_id": external_user.external_id},
+        {"service": unknown_service, "external_id": external_user.external_id}
+    ])).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_json_value(response).await?;
+    let users = body["users"].as_array().expect("users must be an array");
+    assert_eq!(users.len(), 2);
+    assert_eq!(users[0]["id"], 1);
+    assert_eq!(users[0]["user"]["id"], 1);
+    assert_eq!(users[1]["external_id"], json!(external_user.external_id));
+    assert_eq!(users[1]["user"]["id"], 1);
+    assert_eq!(body["missing"], json!([
+        {"id": 2},
+        {"service": unknown_service, "external_id": external_user.external_id}
+    ]));
+
+    let too_many_keys: Vec<_> = (0..=repo::users::BATCH_MAX_SIZE as i64)
+        .map(|id| json!({"id": id}))
+        .collect();
+    let response = client.batch_get(json!(too_many_keys)).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+fn build_json_request(method: http::Method, path: &str, value: Option<serde_json::Value>) -> anyhow::Result<Request<Body>> {
+    let builder = Request::builder()
+        .method(method)
+        .uri(path);
+    let request = match value {
+        Some(value) => builder
+            .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from(serde_json::to_vec(&value)?))?,
+        None => builder.body(Body::empty())?,
+    };
+    Ok(request)
 }
 
 async fn to_json_value<T>(response: http::Response<T>) -> anyhow::Result<serde_json::Value>
@@ -245,13 +1687,19 @@ async fn ensure_success<T>(response: http::Response<T>) -> anyhow::Result<()>
 
 fn build_repos_with_test_user() -> MockRepositories {
     let usr = build_external_user();
-    let external_id = usr.external_id as ExternalId;
+    let external_id = usr.external_id;
     let usr = SavedUser {
         id: 1,
         name: usr.name,
         language_code: None,
         location: None,
+        place: None,
+        timezone: None,
         premium_till: None,
+        version: 1,
+        deactivated_at: None,
+        bans: vec![],
+        contacts: vec![],
     };
 
     let services = ServicesMock::with_data(HashMap::from([(1, build_service())]));
@@ -261,7 +1709,7 @@ fn build_repos_with_test_user() -> MockRepositories {
 
 fn build_external_user() -> ExternalUser {
     ExternalUser {
-        external_id: 1234567890,
+        external_id: ExternalId::Numeric(1234567890),
         name: Some("SadBot".to_owned()),
     }
 }
@@ -269,7 +1717,7 @@ fn build_external_user() -> ExternalUser {
 fn build_service() -> Service {
     Service {
         name: "SadFavBot".to_string(),
-        service_type: ServiceType::TelegramBot,
+        service_type: ServiceType::TELEGRAM_BOT,
     }
 }
 
@@ -279,7 +1727,8 @@ async fn test_span_hierarchy() -> anyhow::Result<()> {
     let _guard = set_default(subscriber);
 
     let repos = Arc::new(mock_repositories());
-    let app = rest::router(repos)
+    let issuer = Arc::new(TokenIssuer::new(TokenConfig::new(TEST_SECRET.to_owned()))?);
+    let app = rest::router(repos, issuer, Arc::new(RecordingSender::default()))
         .layer(OtelInResponseLayer)
         .layer(OtelAxumLayer::default());
 
@@ -0,0 +1,17 @@
+use std::sync::Arc;
+use axum::{Extension, Json};
+use axum::routing::get;
+use jsonwebtoken::jwk::JwkSet;
+use crate::tokens::TokenIssuer;
+
+/// Router to be nested at `/.well-known`
+pub fn well_known_router(issuer: Arc<TokenIssuer>) -> axum::Router {
+    axum::Router::new()
+        .route("/jwks.json", get(jwks))
+        .layer(Extension(issuer))
+}
+
+#[tracing::instrument(skip(issuer))]
+async fn jwks(Extension(issuer): Extension<Arc<TokenIssuer>>) -> Json<JwkSet> {
+    Json(issuer.jwks())
+}
@@ -0,0 +1,21 @@
+use serde_derive::{Deserialize, Serialize};
+use crate::dto::Service;
+
+/// Payload of the session tokens. `sub` is the internal ID of the user.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserClaims {
+    pub iss: String,
+    pub sub: String,
+    pub iat: i64,
+    pub exp: i64,
+    pub services: Vec<Service>,
+    pub premium: bool,
+    pub premium_till: Option<i64>,
+}
+
+impl UserClaims {
+    #[allow(dead_code, reason = "the verification helper is for the consumers of the tokens")]
+    pub fn user_id(&self) -> Option<i64> {
+        self.sub.parse().ok()
+    }
+}
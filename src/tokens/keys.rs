@@ -0,0 +1,55 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use jsonwebtoken::EncodingKey;
+use jsonwebtoken::jwk::{AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse};
+use ring::{digest, hmac};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use crate::tokens::TokenError;
+
+/// PKCS#8 v1 prefix of an Ed25519 private key, followed by its 32-byte seed
+const ED25519_PKCS8_PREFIX: [u8; 16] = [0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20];
+
+/// Ed25519 key pair; `kid` is derived from the SHA-256 hash of the public key.
+pub struct SigningKey {
+    pub kid: String,
+    pub encoding: EncodingKey,
+    pub jwk: Jwk,
+}
+
+impl SigningKey {
+    pub fn from_seed(seed: &[u8]) -> Result<Self, TokenError> {
+        let key_pair = Ed25519KeyPair::from_seed_unchecked(seed)
+            .map_err(|_| TokenError::KeyGeneration)?;
+        let public_key = key_pair.public_key().as_ref();
+        let kid = URL_SAFE_NO_PAD.encode(&digest::digest(&digest::SHA256, public_key).as_ref()[..12]);
+
+        let jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: Some(KeyAlgorithm::EdDSA),
+                key_id: Some(kid.clone()),
+                ..CommonParameters::default()
+            },
+            algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: URL_SAFE_NO_PAD.encode(public_key),
+            }),
+        };
+        let pkcs8 = [ED25519_PKCS8_PREFIX.as_slice(), seed].concat();
+        Ok(Self {
+            kid,
+            encoding: EncodingKey::from_ed_der(&pkcs8),
+            jwk,
+        })
+    }
+}
+
+/// Derives the signing key from the shared secret, so all the instances of the service sign with
+/// the same key and publish the same JWKS without any coordination. The key lives as long as
+/// the secret does: changing the secret replaces the key and invalidates the issued tokens.
+pub fn derive_signing_key(secret: &[u8]) -> Result<SigningKey, TokenError> {
+    let secret = hmac::Key::new(hmac::HMAC_SHA256, secret);
+    let seed = hmac::sign(&secret, b"signing key");
+    SigningKey::from_seed(&seed.as_ref()[..32])
+}
@@ -0,0 +1,149 @@
+//! Signed session tokens (JWT) for users authenticated by one of the services of the ecosystem
+
+mod claims;
+mod keys;
+
+#[cfg(test)]
+mod test;
+
+use std::time::Duration;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use derive_more::Display;
+use jsonwebtoken::{Algorithm, Header, Validation};
+use jsonwebtoken::jwk::JwkSet;
+use thiserror::Error;
+use crate::dto::{SavedUser, Service};
+use crate::env::{get_mandatory_value, get_value_or_default};
+
+pub use claims::*;
+use keys::{derive_signing_key, SigningKey};
+
+const DEFAULT_ISSUER: &str = "user-service";
+const DEFAULT_TTL_SECS: u64 = 60 * 60;
+
+/// Minimal length of `JWT_SECRET`: it's the only input of the signing key
+pub const SECRET_MIN_LENGTH: usize = 32;
+
+#[cfg(test)]
+pub(crate) const TEST_SECRET: &str = "0123456789abcdef0123456789abcdef";
+
+#[derive(Clone)]
+pub struct TokenConfig {
+    pub issuer: String,
+    pub ttl: Duration,
+    /// Shared by all the instances of the service to derive the same signing key
+    pub secret: String,
+}
+
+impl TokenConfig {
+    /// Default issuer and TTL; there is no default secret, since a random one would differ between the instances
+    pub fn new(secret: String) -> Self {
+        Self {
+            issuer: DEFAULT_ISSUER.to_owned(),
+            ttl: Duration::from_secs(DEFAULT_TTL_SECS),
+            secret,
+        }
+    }
+
+    pub fn from_env() -> anyhow::Result<Self> {
+        let secret: String = get_mandatory_value("JWT_SECRET")
+            .context("JWT_SECRET must be set to derive the signing key")?;
+        if secret.len() < SECRET_MIN_LENGTH {
+            anyhow::bail!("JWT_SECRET must be at least {SECRET_MIN_LENGTH} bytes long");
+        }
+        Ok(Self {
+            issuer: get_value_or_default("JWT_ISSUER", DEFAULT_ISSUER.to_owned()),
+            ttl: Duration::from_secs(get_value_or_default("JWT_TTL_SECONDS", DEFAULT_TTL_SECS)),
+            ..Self::new(secret)
+        })
+    }
+}
+
+#[derive(Debug, Display, Error)]
+pub enum TokenError {
+    Jwt(jsonwebtoken::errors::Error),
+    #[display("couldn't generate a signing key")]
+    KeyGeneration,
+    #[display("the secret must be at least {SECRET_MIN_LENGTH} bytes long")]
+    WeakSecret,
+    #[display("the token is signed by an unknown key")]
+    #[allow(dead_code, reason = "the verification helper is for the consumers of the tokens")]
+    UnknownKey,
+}
+
+impl From<jsonwebtoken::errors::Error> for TokenError {
+    fn from(value: jsonwebtoken::errors::Error) -> Self {
+        Self::Jwt(value)
+    }
+}
+
+pub struct IssuedToken {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Issues tokens with a single long-lived signing key and publishes its public part. The key is
+/// derived from the shared secret, so the tokens survive restarts and can be verified by the JWKS
+/// of any instance. There is no automatic rotation: the key is replaced by changing the secret.
+pub struct TokenIssuer {
+    config: TokenConfig,
+    key: SigningKey,
+}
+
+impl TokenIssuer {
+    pub fn new(config: TokenConfig) -> Result<Self, TokenError> {
+        if config.secret.len() < SECRET_MIN_LENGTH {
+            return Err(TokenError::WeakSecret);
+        }
+        let key = derive_signing_key(config.secret.as_bytes())?;
+        Ok(Self { config, key })
+    }
+
+    #[tracing::instrument(skip(self, user, services), fields(user_id = %user.id))]
+    pub fn issue(&self, user: &SavedUser, services: Vec<Service>) -> Result<IssuedToken, TokenError> {
+        let now = Utc::now();
+        let expires_at = now + self.config.ttl;
+        let claims = UserClaims {
+            iss: self.config.issuer.clone(),
+            sub: user.id.to_string(),
+            iat: now.timestamp(),
+            exp: expires_at.timestamp(),
+            services,
+            premium: user.premium(),
+            premium_till: user.premium_till.map(|till| till.timestamp()),
+        };
+
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(self.key.kid.clone());
+        let token = jsonwebtoken::encode(&header, &claims, &self.key.encoding)?;
+        tracing::debug!(kid = %self.key.kid, %expires_at, "Token issued");
+        Ok(IssuedToken { token, expires_at })
+    }
+
+    #[allow(dead_code, reason = "the verification helper is for the consumers of the tokens")]
+    pub fn verify(&self, token: &str) -> Result<UserClaims, TokenError> {
+        verify(token, &self.jwks(), &self.config.issuer)
+    }
+
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet { keys: vec![self.key.jwk.clone()] }
+    }
+}
+
+/// Verification helper for the consumers of the tokens: checks the signature against a key
+/// from the JWKS document (`/.well-known/jwks.json`), the expiration time and the issuer.
+#[allow(dead_code, reason = "the verification helper is for the consumers of the tokens")]
+pub fn verify(token: &str, jwks: &JwkSet, issuer: &str) -> Result<UserClaims, TokenError> {
+    let kid = jsonwebtoken::decode_header(token)?
+        .kid
+        .ok_or(TokenError::UnknownKey)?;
+    let jwk = jwks.find(&kid)
+        .ok_or(TokenError::UnknownKey)?;
+    let key = jsonwebtoken::DecodingKey::from_jwk(jwk)?;
+
+    let mut validation = Validation::new(Algorithm::EdDSA);
+    validation.set_issuer(&[issuer]);
+    let data = jsonwebtoken::decode::<UserClaims>(token, &key, &validation)?;
+    Ok(data.claims)
+}
@@ -0,0 +1,71 @@
+use crate::dto::{SavedUser, Service, ServiceType};
+use crate::tokens::{verify, TokenConfig, TokenError, TokenIssuer, TEST_SECRET};
+use crate::tokens::keys::derive_signing_key;
+
+const ANOTHER_SECRET: &str = "fedcba9876543210fedcba9876543210";
+
+fn build_user() -> SavedUser {
+    SavedUser {
+        id: 1,
+        name: Some("kozalo".to_owned()),
+        language_code: None,
+        location: None,
+        place: None,
+        timezone: None,
+        premium_till: None,
+        version: 1,
+        deactivated_at: None,
+        bans: vec![],
+        contacts: vec![],
+    }
+}
+
+fn build_services() -> Vec<Service> {
+    vec![Service {
+        name: "SadFavBot".to_owned(),
+        service_type: ServiceType::TELEGRAM_BOT,
+    }]
+}
+
+#[test]
+fn test_issue_and_verify() -> anyhow::Result<()> {
+    let issuer = TokenIssuer::new(TokenConfig::new(TEST_SECRET.to_owned()))?;
+    let issued = issuer.issue(&build_user(), build_services())?;
+
+    let claims = verify(&issued.token, &issuer.jwks(), "user-service")?;
+    assert_eq!(claims.user_id(), Some(1));
+    assert_eq!(claims.services, build_services());
+    assert!(!claims.premium);
+    assert_eq!(claims.exp, issued.expires_at.timestamp());
+
+    assert!(matches!(verify(&issued.token, &issuer.jwks(), "another-issuer"), Err(TokenError::Jwt(_))));
+    Ok(())
+}
+
+#[test]
+fn test_shared_secret() -> anyhow::Result<()> {
+    let config = TokenConfig::new(TEST_SECRET.to_owned());
+    let issuer = TokenIssuer::new(config.clone())?;
+    let token = issuer.issue(&build_user(), vec![])?.token;
+
+    tracing::info!("another instance with the same secret accepts the token");
+    let another_instance = TokenIssuer::new(config)?;
+    assert_eq!(another_instance.verify(&token)?.user_id(), Some(1));
+
+    let another_service = TokenIssuer::new(TokenConfig::new(ANOTHER_SECRET.to_owned()))?;
+    assert!(matches!(another_service.verify(&token), Err(TokenError::UnknownKey)));
+    assert!(matches!(TokenIssuer::new(TokenConfig::new("short".to_owned())), Err(TokenError::WeakSecret)));
+    Ok(())
+}
+
+#[test]
+fn test_signing_key() -> anyhow::Result<()> {
+    let key = derive_signing_key(TEST_SECRET.as_bytes())?;
+    assert_eq!(derive_signing_key(TEST_SECRET.as_bytes())?.kid, key.kid);
+    assert_ne!(derive_signing_key(ANOTHER_SECRET.as_bytes())?.kid, key.kid);
+
+    let jwks = TokenIssuer::new(TokenConfig::new(TEST_SECRET.to_owned()))?.jwks();
+    let kids: Vec<_> = jwks.keys.into_iter().filter_map(|key| key.common.key_id).collect();
+    assert_eq!(kids, vec![key.kid]);
+    Ok(())
+}